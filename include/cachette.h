@@ -0,0 +1,145 @@
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#ifndef CACHETTE_H
+#define CACHETTE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Hide the message in a chunk of its own
+#define CACHETTE_MODE_CHUNK 0
+
+// Hide the message in the low bits of the pixels
+#define CACHETTE_MODE_LSB 1
+
+// Hide the message in the paired colours of the palette of an
+// indexed-colour image
+#define CACHETTE_MODE_PALETTE 2
+
+// Hide the message in how the image data is compressed
+#define CACHETTE_MODE_DEFLATE 3
+
+// Hide the message in the colour parity of the pixels, so that it survives
+// lossless re-encoding
+#define CACHETTE_MODE_ROBUST 4
+
+// Hide the message in the DCT coefficients of a JPEG image
+#define CACHETTE_MODE_F5 5
+
+#define CACHETTE_ECC_NONE 0
+
+#define CACHETTE_ECC_LOW 1
+
+#define CACHETTE_ECC_MEDIUM 2
+
+#define CACHETTE_ECC_HIGH 3
+
+// The outcome of every `cachette_*` call. Anything other than
+// `CACHETTE_STATUS_OK` comes with a description from `cachette_last_error`.
+typedef enum CachetteStatus {
+  CACHETTE_STATUS_OK = 0,
+  // A required pointer was null
+  CACHETTE_STATUS_NULL_POINTER = 1,
+  // A string was not valid UTF-8
+  CACHETTE_STATUS_INVALID_UTF8 = 2,
+  // The input is not a valid PNG
+  CACHETTE_STATUS_INVALID_PNG = 3,
+  // The chunk type is not four valid ASCII letters
+  CACHETTE_STATUS_INVALID_CHUNK_TYPE = 4,
+  // The options are out of range or do not suit the image
+  CACHETTE_STATUS_INVALID_OPTIONS = 5,
+  // The message does not fit in the image
+  CACHETTE_STATUS_MESSAGE_TOO_LONG = 6,
+  // No message was found, or it could not be decrypted with the password
+  CACHETTE_STATUS_DECODE_FAILED = 7,
+  // cachette panicked; this is a bug. Only builds that unwind on panic,
+  // such as the `ffi` profile, return it: the others abort the process
+  CACHETTE_STATUS_PANIC = 8,
+} CachetteStatus;
+
+// Where and how messages are hidden
+typedef struct CachetteOptions {
+  // One of the `CACHETTE_MODE_*` constants
+  uint32_t mode;
+  // How many low bits of each colour sample LSB mode uses
+  uint8_t bits_per_channel;
+  // One of the `CACHETTE_ECC_*` constants
+  uint32_t ecc;
+} CachetteOptions;
+
+// Bytes allocated by cachette. `data` is followed by a NUL byte that `len`
+// does not count, so text can be used as a C string. Release it with
+// `cachette_free`.
+typedef struct CachetteBuffer {
+  uint8_t *data;
+  size_t len;
+} CachetteBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Chunk mode, one bit per channel and no error correction
+struct CachetteOptions cachette_default_options(void);
+
+// Hides `message` in the PNG held by `png`, writing the resulting PNG to
+// `out`. `options` may be null to use `cachette_default_options`.
+//
+// # Safety
+//
+// `png` must point to `png_len` readable bytes, the strings must be
+// NUL-terminated, `options` must be valid or null and `out` must be valid.
+enum CachetteStatus cachette_encode_buffer(const uint8_t *png,
+                                           size_t png_len,
+                                           const char *chunk_type,
+                                           const char *message,
+                                           const char *password,
+                                           const struct CachetteOptions *options,
+                                           struct CachetteBuffer *out);
+
+// Recovers the message hidden in the PNG held by `png`, writing it to `out`
+// as a NUL-terminated string. `options` may be null to use
+// `cachette_default_options`; only `mode` and `bits_per_channel` are used.
+//
+// # Safety
+//
+// `png` must point to `png_len` readable bytes, the strings must be
+// NUL-terminated, `options` must be valid or null and `out` must be valid.
+enum CachetteStatus cachette_decode_buffer(const uint8_t *png,
+                                           size_t png_len,
+                                           const char *chunk_type,
+                                           const char *password,
+                                           const struct CachetteOptions *options,
+                                           struct CachetteBuffer *out);
+
+// Lists the chunks of the PNG held by `png` in `out`, one
+// `<type> <length>` line per chunk
+//
+// # Safety
+//
+// `png` must point to `png_len` readable bytes and `out` must be valid.
+enum CachetteStatus cachette_inspect(const uint8_t *png,
+                                     size_t png_len,
+                                     struct CachetteBuffer *out);
+
+// Releases a buffer returned by cachette and resets it to empty. Freeing an
+// empty buffer does nothing.
+//
+// # Safety
+//
+// `buffer` must be null or hold a buffer returned by cachette that has not
+// been modified.
+void cachette_free(struct CachetteBuffer *buffer);
+
+// Describes why the last call on this thread failed, or returns an empty
+// string if it succeeded. The string stays valid until the next call.
+const char *cachette_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CACHETTE_H */
@@ -1,3 +1,4 @@
+use crate::envelope::WRAPPED_KEY_LEN;
 use crate::Result;
 use aes_gcm::aead::{
   generic_array::{typenum::U12, GenericArray},
@@ -7,14 +8,49 @@ use aes_gcm::{Aes256Gcm, Nonce};
 use argon2::PasswordHasher;
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, Version};
 
+use crate::kdf::KdfParams;
+
 use rand::Rng;
 
+/// An AES-256 key. Keys derived from passwords are slow to derive on
+/// purpose, so derive a key once and reuse it for every image.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; 32]);
+
+impl Key {
+  /// Derives the key for `password` the way it was derived before KDF
+  /// parameters were stored in payloads, with `KdfParams::LEGACY` and a
+  /// fixed salt
+  pub fn derive(password: &str) -> Self {
+    let hash =
+      compute_password_hash(password).expect("Failed to hash password");
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hash.as_bytes()[32..64]);
+    Self(key)
+  }
+
+  /// A random key, for messages whose key is wrapped for recipients
+  pub(crate) fn generate() -> Self {
+    Self(rand::thread_rng().gen())
+  }
+
+  pub(crate) fn from_bytes(bytes: [u8; 32]) -> Self {
+    Self(bytes)
+  }
+}
+
+/// The key itself is left out so that it never ends up in logs
+impl std::fmt::Debug for Key {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("Key(..)")
+  }
+}
+
 pub fn encrypt_message(
   message: &str,
-  password: &str,
+  key: &Key,
 ) -> (Vec<u8>, GenericArray<u8, U12>) {
-  let hash = compute_password_hash(password).expect("Failed to hash password");
-  let key = &hash.as_bytes()[32..64];
+  let key = &key.0;
 
   // Generate a random 96-bit nonce
   let nonce_rnd = rand::thread_rng().gen::<[u8; 12]>();
@@ -33,30 +69,57 @@ pub fn encrypt_message(
 
 pub fn decrypt_message(
   ciphertext: &[u8],
-  password: &str,
+  key: &Key,
   nonce: &GenericArray<u8, U12>,
-) -> String {
-  let hash = compute_password_hash(password).expect("Failed to hash password");
-  let key = &hash.as_bytes()[32..64];
+) -> Result<String> {
+  let key = &key.0;
 
   let cipher = Aes256Gcm::new(key.into());
 
   // Decrypt the message using the cipher and the nonce
   let decrypted_message = cipher
     .decrypt(nonce, ciphertext)
-    .expect("decryption failure!");
+    .map_err(|_| "Wrong password or corrupted message")?;
 
   // Convert the decrypted bytes to a String and return it
-  String::from_utf8(decrypted_message).expect("invalid UTF-8")
+  Ok(String::from_utf8(decrypted_message)?)
+}
+
+/// Encrypts `key` with `wrapping_key`. Wrapping keys are only ever used once,
+/// so the nonce can be fixed.
+pub fn wrap_key(key: &Key, wrapping_key: &Key) -> [u8; WRAPPED_KEY_LEN] {
+  let cipher = Aes256Gcm::new((&wrapping_key.0).into());
+  let wrapped = cipher
+    .encrypt(&Nonce::default(), key.0.as_slice())
+    .expect("encryption failure!");
+  wrapped.try_into().expect("wrapped key length")
+}
+
+/// Decrypts a key wrapped by `wrap_key`
+pub fn unwrap_key(
+  wrapped: &[u8; WRAPPED_KEY_LEN],
+  wrapping_key: &Key,
+) -> Result<Key> {
+  let cipher = Aes256Gcm::new((&wrapping_key.0).into());
+  let key = cipher
+    .decrypt(&Nonce::default(), wrapped.as_slice())
+    .map_err(|_| "Key was not wrapped for this identity")?;
+  Ok(Key(key.try_into().expect("unwrapped key length")))
 }
 
 fn compute_password_hash(password: &str) -> Result<String> {
   let fixed_salt = SaltString::from_b64("quVHYU6WoFQFvRPy0PLuhQ")
     .expect("Failed to parse salt");
+  let KdfParams {
+    memory_kib,
+    iterations,
+    parallelism,
+  } = KdfParams::LEGACY;
   let password_hash = Argon2::new(
     Algorithm::Argon2id,
     Version::V0x13,
-    Params::new(15000, 2, 1, None).expect("Failed to create params"),
+    Params::new(memory_kib, iterations, parallelism, None)
+      .expect("Failed to create params"),
   )
   .hash_password(password.as_bytes(), &fixed_salt)
   .expect("Failed to hash password")
@@ -72,13 +135,14 @@ mod tests {
   #[test]
   fn test_encrypt_decrypt() {
     let message = "This is a secret message!";
-    let password = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+    let password = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
 
     // Encrypt the message
-    let (ciphertext, nonce) = encrypt_message(message, password);
+    let (ciphertext, nonce) = encrypt_message(message, &password);
 
     // Decrypt the message
-    let decrypted_message = decrypt_message(&ciphertext, &password, &nonce);
+    let decrypted_message =
+      decrypt_message(&ciphertext, &password, &nonce).unwrap();
 
     // Assert that the decrypted message is equal to the original message
     assert_eq!(message, decrypted_message);
@@ -87,16 +151,14 @@ mod tests {
   #[test]
   fn test_decrypt_with_wrong_key() {
     let message = "This is a secret message!";
-    let password = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+    let password = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
 
     // Encrypt the message
-    let (ciphertext, nonce) = encrypt_message(message, password);
+    let (ciphertext, nonce) = encrypt_message(message, &password);
 
     // Attempt to decrypt the message with the wrong key
-    let wrong_password = "wrong password garbasish";
-    let result = std::panic::catch_unwind(|| {
-      decrypt_message(&ciphertext, &wrong_password, &nonce)
-    });
+    let wrong_password = Key::derive("wrong password garbasish");
+    let result = decrypt_message(&ciphertext, &wrong_password, &nonce);
 
     // Assert that the decryption failed
     assert!(result.is_err());
@@ -105,20 +167,28 @@ mod tests {
   #[test]
   fn test_decrypt_with_wrong_nonce() {
     let message = "This is a secret message!";
-    let password = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+    let password = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
 
     // Encrypt the message
-    let (ciphertext, mut nonce) = encrypt_message(message, password);
+    let (ciphertext, mut nonce) = encrypt_message(message, &password);
 
     // Tamper with the nonce
     nonce[0] ^= 0x01;
 
     // Attempt to decrypt the message with the wrong nonce
-    let result = std::panic::catch_unwind(|| {
-      decrypt_message(&ciphertext, &password, &nonce)
-    });
+    let result = decrypt_message(&ciphertext, &password, &nonce);
 
     // Assert that the decryption failed
     assert!(result.is_err());
   }
+
+  #[test]
+  fn test_wrap_unwrap_key() {
+    let key = Key::generate();
+    let wrapping_key = Key::generate();
+    let wrapped = wrap_key(&key, &wrapping_key);
+
+    assert_eq!(unwrap_key(&wrapped, &wrapping_key).unwrap(), key);
+    assert!(unwrap_key(&wrapped, &Key::generate()).is_err());
+  }
 }
@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use cachette::Result;
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+/// What an operation has to say about one file
+#[derive(Debug, Default)]
+pub struct Report {
+  /// The result of the operation, printed to stdout
+  pub output: Option<String>,
+  /// A status message, printed to stderr
+  pub status: Option<String>,
+}
+
+/// The files a command runs on
+#[derive(Debug, PartialEq, Eq)]
+pub struct Inputs {
+  pub files: Vec<PathBuf>,
+  /// Whether the command was given more than a single file, in which case
+  /// every result is labelled with its file
+  pub batch: bool,
+}
+
+/// The extensions of the files directories are searched for by commands
+/// that only work on PNG images
+pub const PNG_EXTENSIONS: &[&str] = &["png"];
+
+/// The extensions of every format messages can be hidden in
+pub const COVER_EXTENSIONS: &[&str] =
+  &["png", "bmp", "webp", "jpg", "jpeg", "wav", "pdf"];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+  path.extension().is_some_and(|extension| {
+    extensions
+      .iter()
+      .any(|wanted| extension.eq_ignore_ascii_case(wanted))
+  })
+}
+
+fn is_glob(path: &Path) -> bool {
+  path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Expands `inputs` into the files to process: directories become the
+/// files with one of `extensions` they contain, searched recursively if
+/// `recursive` is set, and glob patterns become the paths they match
+pub fn expand(
+  inputs: &[PathBuf],
+  recursive: bool,
+  extensions: &[&str],
+) -> Result<Inputs> {
+  if inputs.len() > 1 && inputs.iter().any(|input| input == Path::new("-")) {
+    return Err("- cannot be combined with other inputs".into());
+  }
+
+  let mut files = Vec::new();
+  let mut batch = inputs.len() > 1;
+  for input in inputs {
+    if input.is_dir() {
+      batch = true;
+      let depth = if recursive { usize::MAX } else { 1 };
+      for entry in WalkDir::new(input).max_depth(depth).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file()
+          && has_extension(entry.path(), extensions)
+        {
+          files.push(entry.into_path());
+        }
+      }
+    } else if !input.exists() && is_glob(input) {
+      batch = true;
+      let pattern = input.to_str().ok_or("Glob patterns must be UTF-8")?;
+      let matches =
+        glob::glob(pattern)?.collect::<std::result::Result<Vec<_>, _>>()?;
+      if matches.is_empty() {
+        return Err(format!("No files match {}", pattern).into());
+      }
+      files.extend(matches.into_iter().filter(|path| path.is_file()));
+    } else {
+      files.push(input.clone());
+    }
+  }
+
+  let mut seen = std::collections::HashSet::new();
+  files.retain(|file| seen.insert(file.clone()));
+  Ok(Inputs { files, batch })
+}
+
+/// Runs `op` on every file of `inputs`, in parallel for batches.
+///
+/// A single file behaves as if there were no batching at all. For batches,
+/// every file gets a line saying whether it succeeded, and an error is
+/// returned at the end if any of them failed.
+pub fn run<F>(inputs: &Inputs, op: F) -> Result<()>
+where
+  F: Fn(&Path) -> Result<Report> + Sync,
+{
+  if !inputs.batch {
+    for file in &inputs.files {
+      let report = op(file)?;
+      if let Some(status) = report.status {
+        eprintln!("{}", status);
+      }
+      if let Some(output) = report.output {
+        println!("{}", output);
+      }
+    }
+    return Ok(());
+  }
+
+  // Errors are turned into strings so that they can cross threads
+  let results = inputs
+    .files
+    .par_iter()
+    .map(|file| op(file).map_err(|error| error.to_string()))
+    .collect::<Vec<_>>();
+
+  let mut failed = 0;
+  for (file, result) in inputs.files.iter().zip(results) {
+    let file = file.display();
+    match result {
+      Ok(report) => {
+        if let Some(output) = report.output {
+          if output.contains('\n') {
+            println!("{}:", file);
+            for line in output.lines() {
+              println!("  {}", line);
+            }
+          } else {
+            println!("{}: {}", file, output);
+          }
+        }
+        let status = report.status.unwrap_or_else(|| "ok".to_string());
+        eprintln!("{}: {}", file, status);
+      }
+      Err(error) => {
+        failed += 1;
+        eprintln!("{}: failed: {}", file, error);
+      }
+    }
+  }
+
+  let total = inputs.files.len();
+  eprintln!("{} files processed, {} failed", total, failed);
+  if failed > 0 {
+    return Err(format!("{} of {} files failed", failed, total).into());
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  fn testing_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("nested")).unwrap();
+    for name in ["a.png", "b.PNG", "d.jpg", "notes.txt", "nested/c.png"] {
+      fs::write(dir.path().join(name), b"").unwrap();
+    }
+    dir
+  }
+
+  #[test]
+  fn test_expand_directory() {
+    let dir = testing_dir();
+    let root = dir.path().to_path_buf();
+
+    let inputs =
+      expand(std::slice::from_ref(&root), false, PNG_EXTENSIONS).unwrap();
+    assert_eq!(inputs.files, vec![root.join("a.png"), root.join("b.PNG")]);
+    assert!(inputs.batch);
+
+    let inputs =
+      expand(std::slice::from_ref(&root), true, PNG_EXTENSIONS).unwrap();
+    assert_eq!(inputs.files.len(), 3);
+    assert!(inputs.files.contains(&root.join("nested/c.png")));
+
+    let inputs =
+      expand(std::slice::from_ref(&root), false, COVER_EXTENSIONS).unwrap();
+    let expected = ["a.png", "b.PNG", "d.jpg"].map(|name| root.join(name));
+    assert_eq!(inputs.files, expected);
+  }
+
+  #[test]
+  fn test_expand_glob_and_files() {
+    let dir = testing_dir();
+    let root = dir.path();
+
+    let inputs = expand(
+      &[root.join("*.png"), root.join("a.png")],
+      false,
+      PNG_EXTENSIONS,
+    );
+    assert_eq!(inputs.unwrap().files, vec![root.join("a.png")]);
+
+    let single = expand(&[root.join("a.png")], false, PNG_EXTENSIONS).unwrap();
+    assert!(!single.batch);
+
+    assert!(expand(&[root.join("*.gif")], false, PNG_EXTENSIONS).is_err());
+    assert!(
+      expand(&["-".into(), root.join("a.png")], false, PNG_EXTENSIONS).is_err()
+    );
+  }
+}
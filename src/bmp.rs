@@ -0,0 +1,261 @@
+use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::png::{ColorType, FilterStrategy, ImageHeader, Pixels};
+use crate::Error;
+use crate::Result;
+
+/// The size of the BITMAPFILEHEADER that starts every BMP file
+const FILE_HEADER_LEN: usize = 14;
+
+/// The size of the OS/2 BITMAPCOREHEADER, whose dimensions are 16-bit
+const CORE_HEADER_LEN: u32 = 12;
+
+/// The compression value of uncompressed pixel data
+const BI_RGB: u32 = 0;
+
+/// An uncompressed 24-bit or 32-bit Windows bitmap. Its pixels are stored
+/// as BGR triplets, bottom row first unless the height is negative, with
+/// every row padded to four bytes. The file is kept as it was read, and
+/// only the colour bytes of the pixels are ever rewritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bmp {
+  bytes: Vec<u8>,
+  width: u32,
+  height: u32,
+  top_down: bool,
+  bytes_per_pixel: usize,
+  pixel_offset: usize,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+  let field = bytes
+    .get(offset..offset + 2)
+    .ok_or("BMP header is truncated")?;
+  Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+  let field = bytes
+    .get(offset..offset + 4)
+    .ok_or("BMP header is truncated")?;
+  Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+impl Bmp {
+  /// The width of the image in pixels
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  /// The height of the image in pixels
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// The number of bytes a row of pixels takes, padding included
+  fn stride(&self) -> usize {
+    (self.width as usize * self.bytes_per_pixel).div_ceil(4) * 4
+  }
+
+  /// The offset of the first byte of the pixel at (`x`, `y`), counting rows
+  /// from the top
+  fn pixel_position(&self, x: usize, y: usize) -> usize {
+    let row = if self.top_down {
+      y
+    } else {
+      self.height as usize - 1 - y
+    };
+    self.pixel_offset + row * self.stride() + x * self.bytes_per_pixel
+  }
+
+  fn image_header(&self) -> ImageHeader {
+    ImageHeader {
+      width: self.width,
+      height: self.height,
+      bit_depth: 8,
+      color_type: ColorType::Rgb,
+      interlaced: false,
+    }
+  }
+}
+
+/// Parses an uncompressed BMP file, failing on any other kind of bitmap
+impl TryFrom<Vec<u8>> for Bmp {
+  type Error = Error;
+
+  fn try_from(bytes: Vec<u8>) -> Result<Self> {
+    if !bytes.starts_with(b"BM") {
+      return Err("Invalid BMP header".into());
+    }
+    let pixel_offset = u32_at(&bytes, 10)? as usize;
+    let info_len = u32_at(&bytes, FILE_HEADER_LEN)?;
+    let info = FILE_HEADER_LEN + 4;
+    let (width, height, bit_count, compression) = if info_len == CORE_HEADER_LEN
+    {
+      let width = u16_at(&bytes, info)? as i32;
+      let height = u16_at(&bytes, info + 2)? as i32;
+      (width, height, u16_at(&bytes, info + 6)?, BI_RGB)
+    } else {
+      let width = u32_at(&bytes, info)? as i32;
+      let height = u32_at(&bytes, info + 4)? as i32;
+      let bit_count = u16_at(&bytes, info + 10)?;
+      (width, height, bit_count, u32_at(&bytes, info + 12)?)
+    };
+
+    if compression != BI_RGB || !matches!(bit_count, 24 | 32) {
+      return Err(
+        "Only uncompressed 24-bit and 32-bit BMP images are supported".into(),
+      );
+    }
+    if width <= 0 || height == 0 || height == i32::MIN {
+      return Err("BMP image has no pixels".into());
+    }
+
+    let bmp = Self {
+      width: width as u32,
+      height: height.unsigned_abs(),
+      top_down: height < 0,
+      bytes_per_pixel: bit_count as usize / 8,
+      pixel_offset,
+      bytes,
+    };
+    let end = bmp.stride() as u64 * bmp.height as u64 + pixel_offset as u64;
+    if end > bmp.bytes.len() as u64 {
+      return Err("BMP pixel data is truncated".into());
+    }
+    Ok(bmp)
+  }
+}
+
+impl CoverImage for Bmp {
+  fn format(&self) -> Format {
+    Format::Bmp
+  }
+
+  fn as_bytes(&self) -> Vec<u8> {
+    self.bytes.clone()
+  }
+
+  fn byte_len(&self) -> usize {
+    self.bytes.len()
+  }
+
+  fn message_chunk(&self, _chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    Err(no_chunks())
+  }
+
+  fn set_message_chunk(
+    &mut self,
+    _chunk_type: &ChunkType,
+    _data: Vec<u8>,
+  ) -> Result<()> {
+    Err(no_chunks())
+  }
+
+  /// The single frame of the image as 8-bit RGB. The unused fourth byte of
+  /// 32-bit pixels is left out.
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    let mut data =
+      Vec::with_capacity(self.width as usize * self.height as usize * 3);
+    for y in 0..self.height as usize {
+      for x in 0..self.width as usize {
+        let position = self.pixel_position(x, y);
+        let [b, g, r] = [0, 1, 2].map(|channel| self.bytes[position + channel]);
+        data.extend_from_slice(&[r, g, b]);
+      }
+    }
+    Ok(vec![Pixels::new(self.image_header(), data)?])
+  }
+
+  fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    _filter: FilterStrategy,
+  ) -> Result<()> {
+    let [pixels] = frames else {
+      return Err("A BMP image holds a single frame".into());
+    };
+    if *pixels.header() != self.image_header() {
+      return Err("Pixels do not match the BMP image".into());
+    }
+    for y in 0..self.height as usize {
+      for (x, rgb) in pixels.row(y).chunks(3).enumerate() {
+        let position = self.pixel_position(x, y);
+        self.bytes[position..position + 3]
+          .copy_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+      }
+    }
+    Ok(())
+  }
+}
+
+fn no_chunks() -> Error {
+  "BMP images have no chunks to hide a message in; use lsb mode".into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Decoder, Encoder, Mode};
+
+  /// A bitmap of `width` x `height` pixels with a 40-byte info header
+  fn bmp_bytes(width: i32, height: i32, bit_count: u16) -> Vec<u8> {
+    let stride = (width as usize * bit_count as usize / 8).div_ceil(4) * 4;
+    let pixels_len = stride * height.unsigned_abs() as usize;
+    let mut bytes = b"BM".to_vec();
+    bytes.extend_from_slice(&(54 + pixels_len as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&54u32.to_le_bytes());
+    bytes.extend_from_slice(&40u32.to_le_bytes());
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&bit_count.to_le_bytes());
+    bytes.extend_from_slice(&[0; 24]);
+    bytes.extend((0..pixels_len).map(|i| (i * 13 % 251) as u8));
+    bytes
+  }
+
+  #[test]
+  fn test_pixel_layout() {
+    // A bottom-up 3x2 image, whose rows are padded from 9 to 12 bytes
+    let bytes = bmp_bytes(3, 2, 24);
+    let bmp = Bmp::try_from(bytes.clone()).unwrap();
+    let pixels = &bmp.decode_frames().unwrap()[0];
+    assert_eq!(&pixels.row(0)[..3], [bytes[68], bytes[67], bytes[66]]);
+    assert_eq!(&pixels.row(1)[..3], [bytes[56], bytes[55], bytes[54]]);
+
+    let mut copy = bmp.clone();
+    copy
+      .encode_frames(std::slice::from_ref(pixels), FilterStrategy::default())
+      .unwrap();
+    assert_eq!(copy, bmp);
+    assert!(Bmp::try_from(bmp_bytes(3, 2, 8)).is_err());
+    assert!(Bmp::try_from(bytes[..60].to_vec()).is_err());
+  }
+
+  #[test]
+  fn test_lsb_round_trip() {
+    for (height, bit_count) in [(40, 24), (-40, 32)] {
+      let mut bmp = Bmp::try_from(bmp_bytes(40, height, bit_count)).unwrap();
+      let before = bmp.as_bytes();
+      Encoder::new("password")
+        .mode(Mode::Lsb)
+        .encode(&mut bmp, "Bitmap")
+        .unwrap();
+      let after = bmp.as_bytes();
+      assert_eq!(after.len(), before.len());
+      assert_eq!(after[..54], before[..54]);
+      if bit_count == 32 {
+        // The fourth byte of each pixel is never touched
+        let mut pixels = before[54..].chunks(4).zip(after[54..].chunks(4));
+        assert!(pixels.all(|(old, new)| old[3] == new[3]));
+      }
+
+      let bmp = crate::cover::from_bytes(after).unwrap();
+      let decoder = Decoder::new("password").mode(Mode::Lsb);
+      assert_eq!(decoder.decode(&*bmp).unwrap().message, "Bitmap");
+      assert!(Decoder::new("password").decode(&*bmp).is_err());
+    }
+  }
+}
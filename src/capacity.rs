@@ -0,0 +1,408 @@
+use std::fmt;
+
+use crate::cover::{self, CoverImage};
+use crate::deflate;
+use crate::ecc::EccLevel;
+use crate::envelope::{self, Envelope};
+use crate::ezstego;
+use crate::f5;
+use crate::jpeg::Jpeg;
+use crate::lsb;
+use crate::mode::Mode;
+use crate::png::{self, Palette, Pixels, Png};
+use crate::robust;
+use crate::session::Session;
+use crate::Result;
+
+/// The largest data length a single chunk may declare, per the PNG spec
+pub const MAX_CHUNK_LEN: usize = png::MAX_CHUNK_LEN as usize;
+
+/// Fraction of the carrier bits, at one bit per channel, that LSB embedding
+/// can change before simple statistical steganalysis starts to notice
+const RECOMMENDED_LSB_RATE_DIVISOR: usize = 4;
+
+/// Fraction of the file size a private chunk can take before it stands out
+const RECOMMENDED_CHUNK_RATIO_DIVISOR: usize = 10;
+
+/// How many bytes can be hidden in a PNG with a given embedding mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+  /// Where the message is hidden
+  pub mode: Mode,
+  /// How many low bits of each sample LSB mode uses
+  pub bits_per_channel: u8,
+  /// The error correction added around the envelope
+  pub ecc: EccLevel,
+  /// Bytes the carrier can hold, before any envelope overhead
+  pub raw: usize,
+  /// Bytes the carrier can hold while keeping detectability low
+  pub recommended_raw: usize,
+  /// Bytes taken by the key block of the envelope, with its length
+  pub key_block: usize,
+  /// Bytes taken by the envelope around the plaintext, key block included
+  pub overhead: usize,
+  /// Bytes taken by ECC parity and padding around a plaintext of `usable`
+  /// bytes
+  pub ecc_overhead: usize,
+  /// Largest plaintext, in bytes, that fits
+  pub usable: usize,
+  /// Largest plaintext, in bytes, that keeps detectability low
+  pub recommended: usize,
+}
+
+impl Capacity {
+  /// Computes the capacity of `cover` for `mode`, with payloads protected
+  /// at `ecc` level. `bits_per_channel` only applies to pixel modes.
+  pub fn of(
+    cover: &dyn CoverImage,
+    mode: Mode,
+    bits_per_channel: u8,
+    ecc: EccLevel,
+  ) -> Result<Self> {
+    match mode {
+      Mode::Chunk => Ok(Self::chunk(cover.byte_len() as u64, ecc)),
+      Mode::Lsb => Self::frames(&cover.decode_frames()?, bits_per_channel, ecc),
+      Mode::Palette => {
+        let png = cover::png(cover, mode)?;
+        Self::palette(&png.decode_frames()?, &ezstego::plan(png)?, ecc)
+      }
+      Mode::Deflate => Self::deflate(cover::png(cover, mode)?, ecc),
+      Mode::Robust => Self::robust(cover::png(cover, mode)?, ecc),
+      Mode::F5 => Ok(Self::f5(cover::jpeg(cover, mode)?, ecc)),
+    }
+  }
+
+  /// The capacity of chunk mode for a PNG file of `file_len` bytes, which
+  /// can be known without reading the file
+  pub fn chunk(file_len: u64, ecc: EccLevel) -> Self {
+    let recommended_raw = file_len / RECOMMENDED_CHUNK_RATIO_DIVISOR as u64;
+    let recommended_raw = recommended_raw.min(MAX_CHUNK_LEN as u64) as usize;
+    Self::from_raw(Mode::Chunk, 1, ecc, MAX_CHUNK_LEN, recommended_raw)
+  }
+
+  /// The capacity of LSB mode for the decoded `pixels` of a PNG
+  pub fn lsb(
+    pixels: &Pixels,
+    bits_per_channel: u8,
+    ecc: EccLevel,
+  ) -> Result<Self> {
+    Self::frames(std::slice::from_ref(pixels), bits_per_channel, ecc)
+  }
+
+  /// The capacity of LSB mode for every decoded frame of an APNG, as
+  /// returned by `Png::decode_frames`
+  pub fn frames(
+    frames: &[Pixels],
+    bits_per_channel: u8,
+    ecc: EccLevel,
+  ) -> Result<Self> {
+    let raw = lsb::capacity(frames, bits_per_channel)?;
+    let one_bit = lsb::carrier_count(frames) / 8;
+    let recommended_raw = one_bit / RECOMMENDED_LSB_RATE_DIVISOR;
+    Ok(Self::from_raw(
+      Mode::Lsb,
+      bits_per_channel,
+      ecc,
+      raw,
+      recommended_raw,
+    ))
+  }
+
+  /// The capacity of palette mode for every decoded frame of an indexed
+  /// image, embedding with `palette`
+  pub fn palette(
+    frames: &[Pixels],
+    palette: &Palette,
+    ecc: EccLevel,
+  ) -> Result<Self> {
+    let raw = ezstego::capacity(frames, palette)?;
+    let recommended_raw = raw / RECOMMENDED_LSB_RATE_DIVISOR;
+    Ok(Self::from_raw(Mode::Palette, 1, ecc, raw, recommended_raw))
+  }
+
+  /// The capacity of deflate mode, which hides the message in how the image
+  /// data of `png` is compressed
+  pub fn deflate(png: &Png, ecc: EccLevel) -> Result<Self> {
+    let raw = deflate::capacity(png)?;
+    Ok(Self::from_raw(Mode::Deflate, 1, ecc, raw, raw))
+  }
+
+  /// The capacity of robust mode, one bit for every visible pixel of `png`
+  pub fn robust(png: &Png, ecc: EccLevel) -> Result<Self> {
+    let raw = robust::capacity(png)?;
+    let recommended_raw = raw / RECOMMENDED_LSB_RATE_DIVISOR;
+    Ok(Self::from_raw(Mode::Robust, 1, ecc, raw, recommended_raw))
+  }
+
+  /// The capacity of F5 mode, about one bit for every non-zero AC
+  /// coefficient of `jpeg`
+  pub fn f5(jpeg: &Jpeg, ecc: EccLevel) -> Self {
+    let raw = f5::capacity(jpeg);
+    let recommended_raw = raw / RECOMMENDED_LSB_RATE_DIVISOR;
+    Self::from_raw(Mode::F5, 1, ecc, raw, recommended_raw)
+  }
+
+  /// The capacity for envelopes sealed by `session`, whose key block
+  /// takes room from the plaintext. The constructors above assume a key
+  /// shared out of band, which needs no key block.
+  pub fn for_session(self, session: &Session) -> Self {
+    self.with_key_block(session.key_block_len())
+  }
+
+  fn from_raw(
+    mode: Mode,
+    bits_per_channel: u8,
+    ecc: EccLevel,
+    raw: usize,
+    recommended_raw: usize,
+  ) -> Self {
+    Self {
+      mode,
+      bits_per_channel,
+      ecc,
+      raw,
+      recommended_raw,
+      key_block: 0,
+      overhead: 0,
+      ecc_overhead: 0,
+      usable: 0,
+      recommended: 0,
+    }
+    .with_key_block(0)
+  }
+
+  /// Works out the overhead and what fits around it, for envelopes with a
+  /// key block of `key_block_len` bytes
+  fn with_key_block(mut self, key_block_len: usize) -> Self {
+    self.key_block = Envelope::key_block_overhead(key_block_len);
+    self.overhead = envelope::OVERHEAD + self.key_block;
+    self.usable = self
+      .ecc
+      .max_data_len(self.raw)
+      .saturating_sub(self.overhead);
+    self.recommended = self
+      .ecc
+      .max_data_len(self.recommended_raw)
+      .saturating_sub(self.overhead);
+    let sealed_len = self.usable + self.overhead;
+    self.ecc_overhead = self.ecc.frame_len(sealed_len) - sealed_len;
+    self
+  }
+
+  /// Returns an error if a plaintext of `message_len` bytes does not fit
+  pub fn check(&self, message_len: usize) -> Result<()> {
+    self.check_sealed(message_len, message_len + self.overhead)
+  }
+
+  /// Like `check`, for an envelope of `sealed_len` bytes, which can be
+  /// larger than the plaintext and `overhead` when it is split into shares
+  pub fn check_sealed(
+    &self,
+    message_len: usize,
+    sealed_len: usize,
+  ) -> Result<()> {
+    let frame_len = self.ecc.frame_len(sealed_len);
+    if frame_len > self.raw {
+      let extra = sealed_len.saturating_sub(message_len + self.overhead);
+      return Err(
+        format!(
+          "Message is {} bytes ({} once sealed) but only {} bytes fit in \
+           this image with {} mode",
+          message_len,
+          frame_len,
+          self.usable.saturating_sub(extra),
+          self.mode
+        )
+        .into(),
+      );
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for Capacity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.mode {
+      Mode::Chunk => writeln!(f, "Mode: chunk")?,
+      Mode::Lsb => {
+        writeln!(f, "Mode: lsb ({} bits per channel)", self.bits_per_channel)?
+      }
+      Mode::Palette => writeln!(f, "Mode: palette")?,
+      Mode::Deflate => writeln!(f, "Mode: deflate")?,
+      Mode::Robust => writeln!(f, "Mode: robust")?,
+      Mode::F5 => writeln!(f, "Mode: f5")?,
+    }
+    writeln!(f, "Raw capacity: {} bytes", self.raw)?;
+    write!(
+      f,
+      "Envelope overhead: {} bytes (header {}",
+      self.overhead,
+      envelope::HEADER_LEN
+    )?;
+    if self.key_block > 0 {
+      write!(f, ", key block {}", self.key_block)?;
+    }
+    writeln!(
+      f,
+      ", nonce {}, tag {})",
+      envelope::NONCE_LEN,
+      envelope::TAG_LEN
+    )?;
+    if self.ecc != EccLevel::None {
+      writeln!(
+        f,
+        "ECC overhead: {} bytes ({} level, parity and padding)",
+        self.ecc_overhead, self.ecc
+      )?;
+    }
+    writeln!(f, "Usable capacity: {} bytes", self.usable)?;
+    write!(f, "Recommended maximum: {} bytes", self.recommended)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use crate::chunk_type::ChunkType;
+  use crate::png::{ColorType, FilterStrategy, ImageHeader, Pixels};
+  use crate::session::{Identity, KeyMaterial};
+
+  fn testing_png(width: u32, height: u32) -> Png {
+    let header = ImageHeader {
+      width,
+      height,
+      bit_depth: 8,
+      color_type: ColorType::Rgb,
+      interlaced: false,
+    };
+    let len = header.row_bytes(width as usize) * height as usize;
+    let pixels = Pixels::new(header, vec![128; len]).unwrap();
+
+    Png::from_chunks(vec![
+      header.as_chunk(),
+      Chunk::new(
+        ChunkType::try_from(*b"IDAT").unwrap(),
+        pixels.encode(FilterStrategy::default()),
+      ),
+      Chunk::new(ChunkType::try_from(*b"IEND").unwrap(), Vec::new()),
+    ])
+  }
+
+  /// What a password envelope adds around the plaintext
+  const PASSWORD_OVERHEAD: usize =
+    envelope::OVERHEAD + 2 + envelope::PASSWORD_KEY_BLOCK_LEN;
+
+  fn password_session() -> Session {
+    Session::password("correct horse battery staple")
+  }
+
+  #[test]
+  fn test_lsb_capacity() {
+    let png = testing_png(32, 32);
+    let capacity = Capacity::of(&png, Mode::Lsb, 2, EccLevel::None)
+      .unwrap()
+      .for_session(&password_session());
+    assert_eq!(capacity.raw, 32 * 32 * 3 * 2 / 8);
+    assert_eq!(capacity.overhead, PASSWORD_OVERHEAD);
+    assert_eq!(capacity.usable, capacity.raw - PASSWORD_OVERHEAD);
+    assert_eq!(
+      capacity.recommended,
+      32 * 32 * 3 / 8 / 4 - PASSWORD_OVERHEAD
+    );
+  }
+
+  #[test]
+  fn test_chunk_capacity() {
+    let png = testing_png(4, 4);
+    let capacity = Capacity::of(&png, Mode::Chunk, 1, EccLevel::None).unwrap();
+    assert_eq!(capacity.raw, MAX_CHUNK_LEN);
+    assert_eq!(capacity.recommended, 0);
+
+    let capacity =
+      Capacity::chunk(10_000, EccLevel::None).for_session(&password_session());
+    assert_eq!(capacity.recommended, 1_000 - PASSWORD_OVERHEAD);
+  }
+
+  #[test]
+  fn test_session_overhead() {
+    let capacity = Capacity::chunk(10_000, EccLevel::None);
+    assert_eq!(capacity.overhead, envelope::OVERHEAD);
+    assert_eq!(capacity.usable, MAX_CHUNK_LEN - envelope::OVERHEAD);
+
+    let recipients = vec![Identity::generate().recipient(); 3];
+    let session = Session::new(KeyMaterial::Recipients(recipients)).unwrap();
+    let capacity = capacity.for_session(&session);
+    let sealed_len = session.seal("").as_bytes().len();
+    assert_eq!(capacity.overhead, sealed_len);
+    assert_eq!(capacity.key_block, sealed_len - envelope::OVERHEAD);
+    assert!(capacity.to_string().contains(&format!(
+      "key block {}",
+      2 + envelope::recipients_key_block_len(3)
+    )));
+  }
+
+  #[test]
+  fn test_usable_fits() {
+    // 16x16 RGB holds 96 bytes at one bit per channel
+    let png = testing_png(16, 16);
+    let session = password_session();
+    let capacity = Capacity::of(&png, Mode::Lsb, 1, EccLevel::None)
+      .unwrap()
+      .for_session(&session);
+    assert_eq!(capacity.usable, 96 - PASSWORD_OVERHEAD);
+    let sealed = session.seal(&"x".repeat(capacity.usable)).as_bytes();
+    assert_eq!(sealed.len(), capacity.raw);
+    let sealed = session.seal(&"x".repeat(capacity.usable + 1)).as_bytes();
+    assert!(capacity
+      .check_sealed(capacity.usable + 1, sealed.len())
+      .is_err());
+  }
+
+  #[test]
+  fn test_ecc_capacity() {
+    let png = testing_png(64, 64);
+    let capacity = Capacity::of(&png, Mode::Lsb, 1, EccLevel::Low).unwrap();
+    let sealed_len = Envelope::sealed_len(capacity.usable);
+    assert!(EccLevel::Low.frame_len(sealed_len) <= capacity.raw);
+    assert!(EccLevel::Low.frame_len(sealed_len + 1) > capacity.raw);
+    assert!(capacity.ecc_overhead > EccLevel::Low.parity());
+  }
+
+  #[test]
+  fn test_check() {
+    let png = testing_png(32, 32);
+    for ecc in [EccLevel::None, EccLevel::High] {
+      let capacity = Capacity::of(&png, Mode::Lsb, 1, ecc)
+        .unwrap()
+        .for_session(&password_session());
+      assert!(capacity.check(capacity.usable).is_ok());
+      assert!(capacity.check(capacity.usable + 1).is_err());
+    }
+  }
+
+  #[test]
+  fn test_image_too_large() {
+    let header = ImageHeader {
+      width: 0x7fff_ffff,
+      height: 0x7fff_ffff,
+      bit_depth: 16,
+      color_type: ColorType::Rgba,
+      interlaced: false,
+    };
+    let png = Png::from_chunks(vec![
+      header.as_chunk(),
+      Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), vec![0; 16]),
+      Chunk::new(ChunkType::try_from(*b"IEND").unwrap(), Vec::new()),
+    ]);
+    for mode in [Mode::Lsb, Mode::Palette, Mode::Deflate, Mode::Robust] {
+      let error = Capacity::of(&png, mode, 1, EccLevel::None).unwrap_err();
+      assert!(
+        error.to_string().contains("too large"),
+        "{}: {}",
+        mode,
+        error
+      );
+    }
+  }
+}
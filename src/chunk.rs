@@ -4,6 +4,7 @@ use crate::chunk_type::ChunkType;
 
 pub const HDLC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
+/// A PNG chunk: its length, type, data and CRC
 #[derive(Debug, Clone)]
 pub struct Chunk {
   length: u32,
@@ -13,14 +14,10 @@ pub struct Chunk {
 }
 
 impl Chunk {
+  /// A chunk of `chunk_type` holding `data`, with its CRC computed
   pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
     let length = data.len() as u32;
-
-    // Avoid the allocation of an intermediate Vec<u8> when calculating the crc checksum
-    let mut buffer = Vec::with_capacity(chunk_type.bytes().len() + data.len());
-    buffer.extend_from_slice(&chunk_type.bytes());
-    buffer.extend_from_slice(&data);
-    let crc = HDLC.checksum(&buffer);
+    let crc = Self::compute_crc(&chunk_type, &data);
 
     Chunk {
       length,
@@ -30,6 +27,15 @@ impl Chunk {
     }
   }
 
+  /// Computes the CRC of a chunk type followed by its data, without
+  /// allocating an intermediate buffer
+  pub fn compute_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut digest = HDLC.digest();
+    digest.update(&chunk_type.bytes());
+    digest.update(data);
+    digest.finalize()
+  }
+
   /// The length of the data portion of this chunk
   pub fn length(&self) -> u32 {
     self.length
@@ -72,16 +78,39 @@ impl Chunk {
 
     bytes
   }
+
+  /// The number of bytes this chunk takes once serialised
+  pub fn byte_len(&self) -> usize {
+    12 + self.length as usize
+  }
+
+  /// Writes this chunk, as described by `as_bytes`, to `writer` without
+  /// copying its data into an intermediate buffer
+  pub fn write_to<W: std::io::Write>(
+    &self,
+    writer: &mut W,
+  ) -> std::io::Result<()> {
+    writer.write_all(&self.length.to_be_bytes())?;
+    writer.write_all(&self.chunk_type.bytes())?;
+    writer.write_all(&self.data)?;
+    writer.write_all(&self.crc.to_be_bytes())
+  }
 }
 
 impl TryFrom<&[u8]> for Chunk {
   type Error = &'static str;
 
   fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+    if bytes.len() < 12 {
+      return Err("Chunk is truncated");
+    }
     let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if bytes.len() - 12 < length as usize {
+      return Err("Chunk is truncated");
+    }
     let chunk_type =
       ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])?;
-    let data = bytes[8..(8 + length as usize)].to_vec();
+    let data = &bytes[8..(8 + length as usize)];
     let crc = u32::from_be_bytes([
       bytes[8 + length as usize],
       bytes[9 + length as usize],
@@ -89,20 +118,14 @@ impl TryFrom<&[u8]> for Chunk {
       bytes[11 + length as usize],
     ]);
 
-    let expected_crc = HDLC.checksum(
-      chunk_type
-        .bytes()
-        .iter()
-        .chain(data.iter())
-        .copied()
-        .collect::<Vec<u8>>()
-        .as_slice(),
-    );
+    // Check the CRC before copying the data out of `bytes`
+    let expected_crc = Self::compute_crc(&chunk_type, data);
 
     if crc != expected_crc {
       return Err("Invalid CRC");
     }
 
+    let data = data.to_vec();
     Ok(Chunk {
       length,
       chunk_type,
@@ -229,6 +252,22 @@ mod tests {
     assert!(chunk.is_err());
   }
 
+  #[test]
+  fn test_truncated_chunk_from_bytes() {
+    let bytes = testing_chunk().as_bytes();
+    let chunk = Chunk::try_from(&bytes[..bytes.len() - 1]);
+    assert!(chunk.is_err());
+  }
+
+  #[test]
+  fn test_write_to() {
+    let chunk = testing_chunk();
+    let mut bytes = Vec::new();
+    chunk.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, chunk.as_bytes());
+    assert_eq!(bytes.len(), chunk.byte_len());
+  }
+
   #[test]
   pub fn test_chunk_trait_impls() {
     let data_length: u32 = 42;
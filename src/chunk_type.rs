@@ -1,5 +1,6 @@
 use std::str::FromStr;
 
+/// The four-letter type of a PNG chunk, such as `IHDR` or `teXt`
 #[derive(Eq, Clone, PartialEq, Debug)]
 pub struct ChunkType {
   bytes: [u8; 4],
@@ -1,46 +1,463 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+
+use cachette::png::{ANIMATION_CHUNKS, RENDERING_CHUNKS};
+use cachette::strength::DEFAULT_MIN_SCORE;
+use cachette::{
+  EccLevel, KdfParams, KdfProfile, Mode, Result, Split, DEFAULT_CHUNK_TYPE,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "cahette")]
 #[command(bin_name = "cachette")]
-#[command(about = "A program to hide secret messages on PNG files", long_about = None)]
+#[command(
+  about = "A program to hide secret messages in image, audio and PDF files",
+  long_about = None
+)]
 pub struct Cli {
   #[command(subcommand)]
   pub command: Commands,
 }
 
+/// How passwords are stretched into keys
+#[derive(Args, Debug)]
+pub struct KdfArgs {
+  /// Argon2 cost preset: interactive, moderate or paranoid
+  #[arg(long, default_value_t = KdfProfile::Interactive)]
+  pub kdf_profile: KdfProfile,
+
+  /// Argon2 memory cost in KiB, overriding the profile
+  #[arg(long)]
+  pub kdf_memory: Option<u32>,
+
+  /// Argon2 passes over the memory, overriding the profile
+  #[arg(long)]
+  pub kdf_iterations: Option<u32>,
+
+  /// Argon2 lanes, overriding the profile
+  #[arg(long)]
+  pub kdf_parallelism: Option<u32>,
+}
+
+impl KdfArgs {
+  /// The profile's parameters with any explicit flags applied
+  pub fn params(&self) -> KdfParams {
+    let profile = self.kdf_profile.params();
+    KdfParams::new(
+      self.kdf_memory.unwrap_or(profile.memory_kib),
+      self.kdf_iterations.unwrap_or(profile.iterations),
+      self.kdf_parallelism.unwrap_or(profile.parallelism),
+    )
+  }
+}
+
+/// The positional arguments of encode, which end with the chunk type and,
+/// unless it is read from a file, the message
+#[derive(Debug, PartialEq, Eq)]
+pub struct EncodeArgs {
+  pub inputs: Vec<std::path::PathBuf>,
+  pub chunk_type: String,
+  pub message: Option<String>,
+}
+
+impl EncodeArgs {
+  /// Splits `args` into inputs, chunk type and message, which is left out
+  /// when `from_file` is set
+  pub fn parse(mut args: Vec<String>, from_file: bool) -> Result<Self> {
+    let message = if from_file { None } else { args.pop() };
+    let chunk_type = args.pop();
+    match chunk_type {
+      Some(chunk_type) if !args.is_empty() => Ok(Self {
+        inputs: args.into_iter().map(Into::into).collect(),
+        chunk_type,
+        message,
+      }),
+      _ if from_file => {
+        Err("Expected the files to encode and a chunk type".into())
+      }
+      _ => {
+        Err("Expected the files to encode, a chunk type and a message".into())
+      }
+    }
+  }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-  /// Encode a message in a PNG file
+  /// Encode a message in a PNG, BMP, WebP, JPEG, WAV or PDF file
+  #[command(override_usage = "cachette encode [OPTIONS] <INPUTS>... \
+                              <CHUNK_TYPE> [MESSAGE]")]
   Encode {
-    /// The PNG file to encode
-    input: std::path::PathBuf,
+    /// The images, directories or glob patterns to encode, or - for stdin,
+    /// then the chunk type to use and the message to encode, which is left
+    /// out with --file. The format is told from the file contents
+    #[arg(required = true, value_name = "ARGS")]
+    args: Vec<String>,
 
-    /// The chunk type to use
-    chunk_type: String,
+    /// Read the message from a file instead, or from stdin with -
+    #[arg(long)]
+    file: Option<std::path::PathBuf>,
+
+    /// Where to write the encoded image, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG, BMP, WebP, JPEG, WAV and PDF files in subdirectories
+    /// too
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Where to hide the message: chunk, lsb, palette, deflate, robust
+    /// or f5
+    #[arg(long, default_value_t = Mode::Chunk)]
+    mode: Mode,
+
+    /// How many low bits of each colour channel to use in lsb mode
+    #[arg(long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Reed-Solomon redundancy added to the payload: none, low, medium or high
+    #[arg(long, default_value_t = EccLevel::None)]
+    ecc: EccLevel,
+
+    /// Derive the key from this file instead of asking for a password
+    #[arg(long, conflicts_with = "recipient")]
+    key_file: Option<std::path::PathBuf>,
+
+    /// Encrypt to this recipient, as printed by keygen, instead of asking
+    /// for a password. Can be given several times
+    #[arg(long)]
+    recipient: Vec<String>,
 
-    /// The message to encode
-    message: String,
+    #[command(flatten)]
+    kdf: KdfArgs,
+
+    /// The lowest password strength score accepted, from 0 to 4
+    #[arg(long, default_value_t = DEFAULT_MIN_SCORE)]
+    min_score: u8,
+
+    /// Split the message into K-of-N shares, one in each of the N images
+    /// the inputs expand to, so that any K of them decode it
+    #[arg(long, value_name = "K-of-N", conflicts_with = "output")]
+    split: Option<Split>,
   },
-  /// Decode a message in a PNG file
+  /// Decode a message in a PNG, BMP, WebP, JPEG, WAV or PDF file, or
+  /// rebuild one from images holding enough of its shares
   Decode {
-    /// The PNG file to decode
-    input: std::path::PathBuf,
+    /// The images, directories or glob patterns to decode, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
 
     /// The chunk type to use
     chunk_type: String,
+
+    /// Where the message is hidden: chunk, lsb, palette, deflate, robust
+    /// or f5
+    #[arg(long, default_value_t = Mode::Chunk)]
+    mode: Mode,
+
+    /// How many low bits of each colour channel were used in lsb mode
+    #[arg(long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Look for PNG, BMP, WebP, JPEG, WAV and PDF files in subdirectories
+    /// too
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Derive the key from this file instead of asking for a password
+    #[arg(long, conflicts_with = "identity")]
+    key_file: Option<std::path::PathBuf>,
+
+    /// Decrypt with the identity in this file, as written by keygen
+    #[arg(long)]
+    identity: Option<std::path::PathBuf>,
+
+    /// Read past damaged chunks instead of failing on the first bad CRC
+    #[arg(long)]
+    lenient: bool,
   },
   /// Remove a message from a PNG file
   Remove {
-    /// The PNG file to remove the message from
-    input: std::path::PathBuf,
+    /// The PNG files, directories or glob patterns to remove the message
+    /// from, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// The chunk type to use
+    chunk_type: String,
+
+    /// Where to write the resulting PNG, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Remove every hidden message from PNG files: payload chunks, legacy
+  /// nonce chunks and LSB payloads, whose bits are randomised
+  Wipe {
+    /// The PNG files, directories or glob patterns to wipe, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// Also remove chunks of this type, with their legacy nonce chunk, even
+    /// if they hold nothing recognisable. Can be given several times
+    #[arg(long)]
+    chunk_type: Vec<String>,
+
+    /// Where to write the resulting PNG, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Keep only the chunks needed to display PNG files, dropping anything
+  /// after IEND and rewriting every CRC
+  Strip {
+    /// The PNG files, directories or glob patterns to strip, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// The comma separated ancillary chunk types to keep, or '' to keep
+    /// only critical chunks
+    #[arg(
+      long,
+      value_delimiter = ',',
+      default_values = RENDERING_CHUNKS.iter().chain(&ANIMATION_CHUNKS)
+    )]
+    keep: Vec<String>,
+
+    /// Join the IDAT chunks into one
+    #[arg(long)]
+    merge_idat: bool,
+
+    /// Where to write the resulting PNG, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Rewrite damaged PNG files with correct CRCs, dropping bytes that cannot
+  /// be read as chunks
+  Repair {
+    /// The PNG files, directories or glob patterns to repair, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// Where to write the repaired PNG, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Re-encrypt the message in image files with a new password, key file or
+  /// recipients, without it ever leaving memory
+  Rekey {
+    /// The images, directories or glob patterns to rekey, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
 
     /// The chunk type to use
     chunk_type: String,
+
+    /// Where to write the resulting PNG, or - for stdout. Defaults to the
+    /// input file, or stdout when reading from stdin
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    /// Look for PNG, BMP, WebP, JPEG, WAV and PDF files in subdirectories
+    /// too
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Where the message is hidden: chunk, lsb, palette, deflate, robust
+    /// or f5
+    #[arg(long, default_value_t = Mode::Chunk)]
+    mode: Mode,
+
+    /// How many low bits of each colour channel are used in lsb mode
+    #[arg(long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Derive the current key from this file instead of asking for the
+    /// current password
+    #[arg(long, conflicts_with = "identity")]
+    key_file: Option<std::path::PathBuf>,
+
+    /// Decrypt with the identity in this file, as written by keygen
+    #[arg(long)]
+    identity: Option<std::path::PathBuf>,
+
+    /// Derive the new key from this file instead of asking for a new
+    /// password
+    #[arg(long, conflicts_with = "recipient")]
+    new_key_file: Option<std::path::PathBuf>,
+
+    /// Encrypt to this recipient instead of asking for a new password. Can
+    /// be given several times
+    #[arg(long)]
+    recipient: Vec<String>,
+
+    #[command(flatten)]
+    kdf: KdfArgs,
+
+    /// The lowest new password strength score accepted, from 0 to 4
+    #[arg(long, default_value_t = DEFAULT_MIN_SCORE)]
+    min_score: u8,
+  },
+  /// List the chunks of PNG files with the length of their data
+  Inspect {
+    /// The PNG files, directories or glob patterns to inspect, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Look for hidden messages in PNG files, without needing the password
+  Scan {
+    /// The PNG files, directories or glob patterns to scan, or - for stdin
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// Look for PNG files in subdirectories too
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Generate an identity to decrypt messages with, and print the recipient
+  /// to encrypt them to
+  Keygen {
+    /// Where to write the identity, or - for stdout
+    output: std::path::PathBuf,
+  },
+  /// Generate a random passphrase strong enough to encode with
+  Genpass {
+    /// How many words the passphrase has
+    #[arg(long, default_value_t = 6)]
+    words: usize,
+
+    /// What goes between the words
+    #[arg(long, default_value = "-")]
+    separator: String,
+
+    /// The lowest strength score the passphrase must reach, from 0 to 4
+    #[arg(long, default_value_t = DEFAULT_MIN_SCORE)]
+    min_score: u8,
+  },
+  /// Suggest Argon2 parameters that take a given time on this machine
+  Calibrate {
+    /// How long unlocking a message should take, in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    target_ms: u64,
+
+    /// The most memory the suggestion may use, in MiB
+    #[arg(long, default_value_t = 1024)]
+    max_memory_mib: u32,
+  },
+  /// Check that the message hidden in a file is intact and opens with the
+  /// key, without printing it
+  ///
+  /// The file must be readable, every PNG chunk must match its CRC, and the
+  /// payload must be present in full, well formed and authenticated by the
+  /// key. The exit code tells which check failed: 3 for an unreadable file,
+  /// 4 for a bad CRC, 5 for a missing payload, 6 for a payload cut short or
+  /// a missing share, 7 for a malformed payload and 8 for one that does not
+  /// authenticate.
+  Verify {
+    /// The file to verify, or - for stdin, or every file holding a share of
+    /// a split message
+    #[arg(required = true)]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// The chunk type the message is stored in
+    #[arg(
+      long = "label",
+      value_name = "CHUNK_TYPE",
+      default_value = DEFAULT_CHUNK_TYPE
+    )]
+    chunk_type: String,
+
+    /// Where the message is hidden: chunk, lsb, palette, deflate, robust
+    /// or f5
+    #[arg(long, default_value_t = Mode::Chunk)]
+    mode: Mode,
+
+    /// How many low bits of each colour channel were used in lsb mode
+    #[arg(long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Derive the key from this file instead of asking for a password
+    #[arg(long, conflicts_with = "identity")]
+    key_file: Option<std::path::PathBuf>,
+
+    /// Decrypt with the identity in this file, as written by keygen
+    #[arg(long)]
+    identity: Option<std::path::PathBuf>,
   },
   /// Print a message from a PNG file
   Print {
-    /// The PNG file to print the message from
+    /// The PNG file to print the message from, or - for stdin
     input: std::path::PathBuf,
   },
+  /// Show how many bytes can be hidden in a PNG, BMP, WebP, JPEG, WAV or
+  /// PDF file
+  Capacity {
+    /// The image to inspect, or - for stdin
+    input: std::path::PathBuf,
+
+    /// The embedding mode to estimate: chunk, lsb, palette, deflate, robust
+    /// or f5
+    #[arg(long, default_value_t = Mode::Chunk)]
+    mode: Mode,
+
+    /// How many low bits of each colour channel to use in lsb mode
+    #[arg(long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Reed-Solomon redundancy to account for: none, low, medium or high
+    #[arg(long, default_value_t = EccLevel::None)]
+    ecc: EccLevel,
+  },
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(ToString::to_string).collect()
+  }
+
+  #[test]
+  fn test_encode_args() {
+    let parsed =
+      EncodeArgs::parse(args(&["a.png", "b.png", "teXt", "Hi"]), false)
+        .unwrap();
+    assert_eq!(
+      parsed.inputs,
+      ["a.png", "b.png"].map(std::path::PathBuf::from)
+    );
+    assert_eq!(parsed.chunk_type, "teXt");
+    assert_eq!(parsed.message.as_deref(), Some("Hi"));
+
+    let parsed = EncodeArgs::parse(args(&["a.png", "teXt"]), true).unwrap();
+    assert_eq!(parsed.inputs, ["a.png"].map(std::path::PathBuf::from));
+    assert_eq!(parsed.message, None);
+
+    assert!(EncodeArgs::parse(args(&["a.png", "teXt"]), false).is_err());
+    assert!(EncodeArgs::parse(args(&["teXt"]), true).is_err());
+  }
 }
@@ -0,0 +1,670 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::aes::Key;
+use crate::capacity::Capacity;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::cover::{self, CoverImage, Format};
+use crate::deflate;
+use crate::ecc::{self, EccLevel};
+use crate::envelope::Envelope;
+use crate::ezstego;
+use crate::f5;
+use crate::lsb;
+use crate::mode::Mode;
+use crate::png::{FilterStrategy, Png, PngReader, PngWriter};
+use crate::robust;
+use crate::scan;
+use crate::session::Session;
+use crate::shamir::{self, Share, Split};
+use crate::Result;
+
+/// The chunk type messages are stored in when none is given
+pub const DEFAULT_CHUNK_TYPE: &str = "teXt";
+
+fn default_chunk_type() -> ChunkType {
+  ChunkType::from_str(DEFAULT_CHUNK_TYPE).expect("valid default chunk type")
+}
+
+/// Hides messages in a `Png`, or any other `CoverImage`.
+///
+/// ```
+/// use cachette::{Decoder, Encoder, Mode, Png};
+///
+/// let mut png = Png::from_file(&"tests/cat.png".into())?;
+/// let password = "correct horse battery staple";
+///
+/// Encoder::new(password).mode(Mode::Lsb).encode(&mut png, "Hello")?;
+/// let decoded = Decoder::new(password).mode(Mode::Lsb).decode(&png)?;
+/// assert_eq!(decoded.message, "Hello");
+/// # Ok::<(), cachette::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Encoder {
+  session: Session,
+  chunk_type: ChunkType,
+  mode: Mode,
+  bits_per_channel: u8,
+  ecc: EccLevel,
+  filter: FilterStrategy,
+}
+
+impl Encoder {
+  /// An encoder that encrypts messages with a key stretched from
+  /// `password` with the default `KdfParams`, and stores them in a `teXt`
+  /// chunk without error correction
+  pub fn new(password: &str) -> Self {
+    Self::with_session(Session::password(password))
+  }
+
+  /// Like `new`, encrypting with `key` as is. The key is not stored, so the
+  /// decoder needs the same key.
+  pub fn with_key(key: Key) -> Self {
+    Self::with_session(key.into())
+  }
+
+  /// An encoder that seals messages with `session`, such as one encrypting
+  /// to recipients
+  pub fn with_session(session: Session) -> Self {
+    Self {
+      session,
+      chunk_type: default_chunk_type(),
+      mode: Mode::Chunk,
+      bits_per_channel: 1,
+      ecc: EccLevel::None,
+      filter: FilterStrategy::default(),
+    }
+  }
+
+  /// The chunk that holds the message in chunk mode
+  pub fn chunk_type(mut self, chunk_type: ChunkType) -> Self {
+    self.chunk_type = chunk_type;
+    self
+  }
+
+  /// Where the message is hidden
+  pub fn mode(mut self, mode: Mode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// How many low bits of each colour sample LSB mode uses
+  pub fn bits_per_channel(mut self, bits_per_channel: u8) -> Self {
+    self.bits_per_channel = bits_per_channel;
+    self
+  }
+
+  /// The Reed-Solomon redundancy added around the encrypted message
+  pub fn ecc(mut self, ecc: EccLevel) -> Self {
+    self.ecc = ecc;
+    self
+  }
+
+  /// How pixel modes filter the rows of the re-encoded image
+  pub fn filter(mut self, filter: FilterStrategy) -> Self {
+    self.filter = filter;
+    self
+  }
+
+  /// How many bytes of message `cover` can hold with these settings
+  pub fn capacity(&self, cover: &dyn CoverImage) -> Result<Capacity> {
+    Capacity::of(cover, self.mode, self.bits_per_channel, self.ecc)
+      .map(|capacity| capacity.for_session(&self.session))
+  }
+
+  /// Encrypts `message` and adds error correction, producing the bytes that
+  /// get hidden in the image
+  pub fn payload(&self, message: &str) -> Vec<u8> {
+    let envelope = self.session.seal(message).as_bytes();
+    ecc::protect(&envelope, self.ecc)
+  }
+
+  /// Like `payload`, returning an error if it does not fit in `capacity`
+  fn fitted_payload(
+    &self,
+    capacity: &Capacity,
+    message: &str,
+  ) -> Result<Vec<u8>> {
+    let envelope = self.session.seal(message).as_bytes();
+    self.check(*capacity, message.len(), &envelope)?;
+    Ok(ecc::protect(&envelope, self.ecc))
+  }
+
+  /// Hides `message` in `cover`, replacing any message stored there before
+  pub fn encode(
+    &self,
+    cover: &mut dyn CoverImage,
+    message: &str,
+  ) -> Result<()> {
+    let sealed = self.session.seal(message).as_bytes();
+    self.embed(cover, message.len(), &sealed)
+  }
+
+  /// Splits `message` across `covers`, one share in each, so that any
+  /// `threshold` of them are needed to decode it
+  pub fn encode_split<C: CoverImage>(
+    &self,
+    covers: &mut [C],
+    message: &str,
+    threshold: u8,
+  ) -> Result<()> {
+    let shares = u8::try_from(covers.len())
+      .map_err(|_| "Too many images to split into")?;
+    let split = Split::new(threshold, shares)?;
+    let sealed = self.session.seal(message).as_bytes();
+    for (cover, share) in covers.iter_mut().zip(shamir::split(&sealed, split)) {
+      self.embed(cover, message.len(), &share.as_bytes())?;
+    }
+    Ok(())
+  }
+
+  /// Returns an error if `sealed`, the envelope or share of a message of
+  /// `message_len` bytes, does not fit in `capacity`
+  fn check(
+    &self,
+    capacity: Capacity,
+    message_len: usize,
+    sealed: &[u8],
+  ) -> Result<()> {
+    let capacity = capacity.for_session(&self.session);
+    capacity.check_sealed(message_len, sealed.len())
+  }
+
+  /// Protects `sealed`, an envelope or share, and hides it in `cover`
+  fn embed(
+    &self,
+    cover: &mut dyn CoverImage,
+    message_len: usize,
+    sealed: &[u8],
+  ) -> Result<()> {
+    match self.mode {
+      Mode::Chunk => {
+        let capacity = Capacity::chunk(cover.byte_len() as u64, self.ecc);
+        self.check(capacity, message_len, sealed)?;
+        let payload = ecc::protect(sealed, self.ecc);
+        cover.set_message_chunk(&self.chunk_type, payload)
+      }
+      Mode::Lsb => {
+        let mut frames = cover.decode_frames()?;
+        let capacity =
+          Capacity::frames(&frames, self.bits_per_channel, self.ecc)?;
+        self.check(capacity, message_len, sealed)?;
+        let payload = ecc::protect(sealed, self.ecc);
+        lsb::embed(&mut frames, &payload, self.bits_per_channel)?;
+        cover.encode_frames(&frames, self.filter)
+      }
+      Mode::Palette => {
+        let png = cover::png_mut(cover, self.mode)?;
+        let mut frames = png.decode_frames()?;
+        let palette = ezstego::plan(png)?;
+        let capacity = Capacity::palette(&frames, &palette, self.ecc)?;
+        self.check(capacity, message_len, sealed)?;
+        let payload = ecc::protect(sealed, self.ecc);
+        ezstego::install(png, &mut frames, &palette)?;
+        ezstego::embed(&mut frames, &palette, &payload)?;
+        png.encode_frames(&frames, self.filter)
+      }
+      Mode::Deflate => {
+        let png = cover::png_mut(cover, self.mode)?;
+        let capacity = Capacity::deflate(png, self.ecc)?;
+        self.check(capacity, message_len, sealed)?;
+        deflate::embed(png, &ecc::protect(sealed, self.ecc))
+      }
+      Mode::Robust => {
+        let png = cover::png_mut(cover, self.mode)?;
+        let capacity = Capacity::robust(png, self.ecc)?;
+        self.check(capacity, message_len, sealed)?;
+        robust::embed(png, &ecc::protect(sealed, self.ecc), self.filter)
+      }
+      Mode::F5 => {
+        let jpeg = cover::jpeg_mut(cover, self.mode)?;
+        let capacity = Capacity::f5(jpeg, self.ecc);
+        self.check(capacity, message_len, sealed)?;
+        f5::embed(jpeg, &ecc::protect(sealed, self.ecc))
+      }
+    }
+  }
+
+  /// Like `encode`, reading the image from `reader` and writing the result
+  /// to `writer`, in whichever format it was read. Chunk mode passes every
+  /// other chunk of a PNG straight through, so the image is never held in
+  /// memory.
+  pub fn encode_stream<R: Read, W: Write>(
+    &self,
+    reader: R,
+    mut writer: W,
+    message: &str,
+  ) -> Result<()> {
+    let (format, reader) = cover::sniff(reader)?;
+    if format != Some(Format::Png) {
+      let mut cover = cover::from_reader(reader)?;
+      self.encode(&mut *cover, message)?;
+      writer.write_all(&cover.as_bytes())?;
+      return Ok(());
+    }
+    if self.mode != Mode::Chunk {
+      let mut png = Png::from_reader(reader)?;
+      self.encode(&mut png, message)?;
+      return png.write_to(writer);
+    }
+
+    let mut reader = PngReader::new(reader)?;
+    // The file size only affects the recommended maximum, not what fits
+    let payload =
+      self.fitted_payload(&Capacity::chunk(0, self.ecc), message)?;
+    let message_type = self.chunk_type.to_string();
+    let nonce_type = Png::legacy_nonce_chunk_type(&message_type);
+
+    let mut writer = PngWriter::new(writer)?;
+    while let Some(header) = reader.next_header()? {
+      let chunk_type = header.chunk_type.to_string();
+      if chunk_type == message_type || chunk_type == nonce_type {
+        reader.skip_data(header)?;
+      } else {
+        writer.copy_chunk(&mut reader, header)?;
+      }
+    }
+    writer.write_chunk(&Chunk::new(self.chunk_type.clone(), payload))?;
+    writer.finish()?;
+    Ok(())
+  }
+}
+
+/// The key is left out so that it never ends up in logs
+impl fmt::Debug for Encoder {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Encoder")
+      .field("chunk_type", &self.chunk_type)
+      .field("mode", &self.mode)
+      .field("bits_per_channel", &self.bits_per_channel)
+      .field("ecc", &self.ecc)
+      .field("filter", &self.filter)
+      .finish_non_exhaustive()
+  }
+}
+
+/// A message recovered by a `Decoder`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+  /// The decrypted message
+  pub message: String,
+  /// How many bytes error correction repaired, or `None` if the payload was
+  /// not protected
+  pub corrected: Option<usize>,
+  /// The level the payload was protected at
+  pub ecc: EccLevel,
+}
+
+/// Recovers messages hidden by an `Encoder`, and messages encoded before
+/// envelopes existed
+#[derive(Clone)]
+pub struct Decoder {
+  session: Session,
+  chunk_type: ChunkType,
+  mode: Mode,
+  bits_per_channel: u8,
+}
+
+impl Decoder {
+  /// A decoder that decrypts messages with keys stretched from `password`,
+  /// looking for them in a `teXt` chunk
+  pub fn new(password: &str) -> Self {
+    Self::with_session(Session::password(password))
+  }
+
+  /// Like `new`, decrypting with `key` as is
+  pub fn with_key(key: Key) -> Self {
+    Self::with_session(key.into())
+  }
+
+  /// A decoder that opens messages with `session`, such as one holding an
+  /// identity
+  pub fn with_session(session: Session) -> Self {
+    Self {
+      session,
+      chunk_type: default_chunk_type(),
+      mode: Mode::Chunk,
+      bits_per_channel: 1,
+    }
+  }
+
+  /// The chunk that holds the message in chunk mode
+  pub fn chunk_type(mut self, chunk_type: ChunkType) -> Self {
+    self.chunk_type = chunk_type;
+    self
+  }
+
+  /// Where the message is hidden
+  pub fn mode(mut self, mode: Mode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// How many low bits of each colour sample LSB mode used
+  pub fn bits_per_channel(mut self, bits_per_channel: u8) -> Self {
+    self.bits_per_channel = bits_per_channel;
+    self
+  }
+
+  /// Finds and decrypts the message hidden in `cover`
+  pub fn decode(&self, cover: &dyn CoverImage) -> Result<Decoded> {
+    match self.mode {
+      Mode::Chunk => {
+        let data = cover
+          .message_chunk(&self.chunk_type)?
+          .ok_or("Message chunk not found")?;
+        // Only PNG files hold messages encoded before envelopes existed
+        let Some(png) = cover.as_png() else {
+          return self.open(data);
+        };
+        if scan::payload_len(data).is_some() {
+          return self.open(data);
+        }
+        let chunk_type = self.chunk_type.to_string();
+        let key = self
+          .session
+          .shared_key()
+          .ok_or("Messages encoded before envelopes need a password")?;
+        Ok(Decoded {
+          message: png.decode_message(&chunk_type, &key)?,
+          corrected: None,
+          ecc: EccLevel::None,
+        })
+      }
+      _ => self.open(&self.read_payload(cover)?),
+    }
+  }
+
+  /// Like `decode`, reading the image from `reader`. Chunk mode only keeps
+  /// the chunks of a PNG that can hold the message in memory.
+  pub fn decode_stream<R: Read>(&self, reader: R) -> Result<Decoded> {
+    let (format, reader) = cover::sniff(reader)?;
+    if format != Some(Format::Png) {
+      return self.decode(&*cover::from_reader(reader)?);
+    }
+    if self.mode != Mode::Chunk {
+      return self.decode(&Png::from_reader(reader)?);
+    }
+
+    let message_type = self.chunk_type.to_string();
+    let nonce_type = Png::legacy_nonce_chunk_type(&message_type);
+    let mut reader = PngReader::new(reader)?;
+    let mut chunks = Vec::new();
+    while let Some(header) = reader.next_header()? {
+      let chunk_type = header.chunk_type.to_string();
+      if chunk_type == message_type || chunk_type == nonce_type {
+        chunks.push(reader.read_data(header)?);
+      } else {
+        reader.skip_data(header)?;
+      }
+    }
+    self.decode(&Png::from_chunks(chunks))
+  }
+
+  /// Corrects `payload` if it is ECC protected, then decrypts its envelope
+  pub fn open(&self, payload: &[u8]) -> Result<Decoded> {
+    let (sealed, corrected, ecc) = unprotect(payload)?;
+    let message = if Share::is_share(&sealed) {
+      let share = Share::try_from(sealed.as_ref())?;
+      self
+        .combine(std::slice::from_ref(&share))
+        .map_err(|error| {
+          format!(
+            "This image holds share {} of a {} split. {}",
+            share.index, share.split, error
+          )
+        })?
+    } else {
+      self.session.open(&Envelope::try_from(sealed.as_ref())?)?
+    };
+    Ok(Decoded {
+      message,
+      corrected,
+      ecc,
+    })
+  }
+
+  /// Reads the share hidden in `cover`, or `None` if it holds a whole
+  /// message
+  pub fn share(&self, cover: &dyn CoverImage) -> Result<Option<Share>> {
+    let payload = self.read_payload(cover)?;
+    if scan::payload_len(&payload).is_none() {
+      return Ok(None);
+    }
+    let (sealed, _, _) = unprotect(&payload)?;
+    if !Share::is_share(&sealed) {
+      return Ok(None);
+    }
+    Ok(Some(Share::try_from(sealed.as_ref())?))
+  }
+
+  /// Rebuilds and decrypts a message split across `covers` by
+  /// `Encoder::encode_split`. The error says how many shares are missing.
+  pub fn decode_split<'a, C: CoverImage + 'a>(
+    &self,
+    covers: impl IntoIterator<Item = &'a C>,
+  ) -> Result<String> {
+    let shares = covers
+      .into_iter()
+      .map(|cover| {
+        self
+          .share(cover)?
+          .ok_or_else(|| "Image holds no share".into())
+      })
+      .collect::<Result<Vec<_>>>()?;
+    self.combine(&shares)
+  }
+
+  /// Rebuilds and decrypts a message from its shares
+  pub fn combine(&self, shares: &[Share]) -> Result<String> {
+    let sealed = shamir::combine(shares)?;
+    self.session.open(&Envelope::try_from(sealed.as_ref())?)
+  }
+
+  /// The session messages are opened with
+  pub(crate) fn session(&self) -> &Session {
+    &self.session
+  }
+
+  /// The payload hidden in `cover` where the mode of this decoder looks, or
+  /// `None` if there is none
+  pub(crate) fn find_payload(
+    &self,
+    cover: &dyn CoverImage,
+  ) -> Result<Option<Vec<u8>>> {
+    let payload = match self.mode {
+      Mode::Chunk => cover.message_chunk(&self.chunk_type)?.map(<[u8]>::to_vec),
+      Mode::Lsb => {
+        let frames = cover.decode_frames()?;
+        read_hidden(|len| lsb::extract(&frames, self.bits_per_channel, len))
+      }
+      Mode::Palette => {
+        let png = cover::png(cover, self.mode)?;
+        let frames = png.decode_frames()?;
+        let palette = png.palette()?;
+        read_hidden(|len| ezstego::extract(&frames, &palette, len))
+      }
+      Mode::Deflate => {
+        let png = cover::png(cover, self.mode)?;
+        read_hidden(|len| deflate::extract(png, len))
+      }
+      Mode::Robust => {
+        let png = cover::png(cover, self.mode)?;
+        read_hidden(|len| robust::extract(png, len))
+      }
+      Mode::F5 => {
+        let jpeg = cover::jpeg(cover, self.mode)?;
+        read_hidden(|len| f5::extract(jpeg, len))
+      }
+    };
+    Ok(payload)
+  }
+
+  /// Like `find_payload`, failing if there is no payload
+  fn read_payload(&self, cover: &dyn CoverImage) -> Result<Vec<u8>> {
+    self.find_payload(cover)?.ok_or_else(|| {
+      let error = match self.mode {
+        Mode::Chunk => "Message chunk not found",
+        Mode::Lsb | Mode::Robust => "No message found in the pixels",
+        Mode::Palette => "No message found in the palette",
+        Mode::Deflate => "No message found in the deflate stream",
+        Mode::F5 => "No message found in the DCT coefficients",
+      };
+      error.into()
+    })
+  }
+}
+
+/// Reads a payload hidden by a pixel or coefficient mode with `read`, which
+/// returns the first `len` bytes hidden: the header first, for the length,
+/// then the whole payload. Returns `None` if no payload starts there, and
+/// the header alone if the payload runs past the end of the image, so that
+/// opening it fails as truncated.
+fn read_hidden(read: impl Fn(usize) -> Result<Vec<u8>>) -> Option<Vec<u8>> {
+  let header = read(scan::PEEK_LEN).ok()?;
+  let (_, len) = scan::payload_len(&header)?;
+  Some(read(len).unwrap_or(header))
+}
+
+/// Corrects `payload` if it is ECC protected, returning the envelope or
+/// share inside, how many bytes were repaired and the protection level
+pub(crate) fn unprotect(
+  payload: &[u8],
+) -> Result<(Vec<u8>, Option<usize>, EccLevel)> {
+  if !ecc::is_protected(payload) {
+    return Ok((payload.to_vec(), None, EccLevel::None));
+  }
+  let (sealed, corrected) = ecc::recover(payload)?;
+  Ok((sealed, Some(corrected), ecc::level(payload)?))
+}
+
+/// The key is left out so that it never ends up in logs
+impl fmt::Debug for Decoder {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Decoder")
+      .field("chunk_type", &self.chunk_type)
+      .field("mode", &self.mode)
+      .field("bits_per_channel", &self.bits_per_channel)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::png::{ColorType, ImageHeader, Pixels};
+
+  const PASSWORD: &str = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+
+  fn testing_png() -> Png {
+    let header = ImageHeader {
+      width: 32,
+      height: 32,
+      bit_depth: 8,
+      color_type: ColorType::Rgb,
+      interlaced: false,
+    };
+    let len = header.row_bytes(32) * 32;
+    let data = (0..len).map(|i| (i * 7) as u8).collect();
+    let pixels = Pixels::new(header, data).unwrap();
+
+    Png::from_chunks(vec![
+      header.as_chunk(),
+      Chunk::new(
+        ChunkType::try_from(*b"IDAT").unwrap(),
+        pixels.encode(FilterStrategy::default()),
+      ),
+      Chunk::new(ChunkType::try_from(*b"IEND").unwrap(), Vec::new()),
+    ])
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    let encoders = [
+      Encoder::new(PASSWORD).chunk_type(chunk_type.clone()),
+      Encoder::new(PASSWORD).mode(Mode::Lsb).bits_per_channel(2),
+      Encoder::new(PASSWORD).mode(Mode::Lsb).ecc(EccLevel::Low),
+    ];
+    let decoders = [
+      Decoder::new(PASSWORD).chunk_type(chunk_type),
+      Decoder::new(PASSWORD).mode(Mode::Lsb).bits_per_channel(2),
+      Decoder::new(PASSWORD).mode(Mode::Lsb),
+    ];
+
+    for (encoder, decoder) in encoders.iter().zip(&decoders) {
+      let mut png = testing_png();
+      encoder.encode(&mut png, "Hidden").unwrap();
+      let decoded = decoder.decode(&png).unwrap();
+      assert_eq!(decoded.message, "Hidden");
+      let expected_corrected = (encoder.ecc != EccLevel::None).then_some(0);
+      assert_eq!(decoded.corrected, expected_corrected);
+      assert_eq!(decoded.ecc, encoder.ecc);
+    }
+  }
+
+  #[test]
+  fn test_stream_round_trip() {
+    let key = Key::derive(PASSWORD);
+    let png = testing_png().as_bytes();
+    for mode in [Mode::Chunk, Mode::Lsb] {
+      let mut encoded = Vec::new();
+      Encoder::with_key(key.clone())
+        .mode(mode)
+        .encode_stream(png.as_slice(), &mut encoded, "Streamed")
+        .unwrap();
+      let decoded = Decoder::with_key(key.clone())
+        .mode(mode)
+        .decode_stream(encoded.as_slice())
+        .unwrap();
+      assert_eq!(decoded.message, "Streamed");
+    }
+  }
+
+  #[test]
+  fn test_split_round_trip() {
+    let mut pngs = vec![testing_png(), testing_png(), testing_png()];
+    let encoder = Encoder::new(PASSWORD).mode(Mode::Lsb).ecc(EccLevel::Low);
+    encoder.encode_split(&mut pngs, "Shared", 2).unwrap();
+
+    let decoder = Decoder::new(PASSWORD).mode(Mode::Lsb);
+    assert_eq!(
+      decoder.decode_split([&pngs[2], &pngs[0]]).unwrap(),
+      "Shared"
+    );
+    let error = decoder.decode_split([&pngs[1]]).unwrap_err().to_string();
+    assert!(error.contains("1 more is needed"), "{}", error);
+    let error = decoder.decode(&pngs[0]).unwrap_err().to_string();
+    assert!(error.contains("share 1 of a 2-of-3 split"), "{}", error);
+    assert!(error.contains("1 more is needed"), "{}", error);
+  }
+
+  #[test]
+  fn test_message_too_long() {
+    let mut png = testing_png();
+    let encoder = Encoder::new(PASSWORD).mode(Mode::Lsb);
+    let capacity = encoder.capacity(&png).unwrap();
+    let message = "x".repeat(capacity.usable + 1);
+    assert!(encoder.encode(&mut png, &message).is_err());
+    let message = "x".repeat(capacity.usable);
+    encoder.encode(&mut png, &message).unwrap();
+  }
+
+  #[test]
+  fn test_wrong_password() {
+    let mut png = testing_png();
+    Encoder::new(PASSWORD).encode(&mut png, "Hidden").unwrap();
+    assert!(Decoder::new("not the password").decode(&png).is_err());
+  }
+
+  #[test]
+  fn test_key_is_not_debug_printed() {
+    let encoder = format!("{:?}", Encoder::new(PASSWORD));
+    let decoder = format!("{:?}", Decoder::new(PASSWORD));
+    assert!(!encoder.contains("Key"));
+    assert!(!decoder.contains("Key"));
+    assert_eq!(format!("{:?}", Key::derive(PASSWORD)), "Key(..)");
+  }
+}
@@ -0,0 +1,240 @@
+//! The image, audio and document formats messages can be hidden in, and
+//! reading a file of any of them with its format told from its contents.
+
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+
+use crate::bmp::Bmp;
+use crate::chunk_type::ChunkType;
+use crate::jpeg::Jpeg;
+use crate::mode::Mode;
+use crate::pdf::Pdf;
+use crate::png::{FilterStrategy, Pixels, Png};
+use crate::wav::Wav;
+use crate::webp::WebP;
+use crate::Result;
+
+/// The number of leading bytes `Format::detect` needs
+pub const MAGIC_LEN: usize = 12;
+
+/// A container format messages can be hidden in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// PNG images, including APNG animations
+  Png,
+  /// Uncompressed Windows bitmaps
+  Bmp,
+  /// WebP, of which only lossless images carry pixel modes
+  WebP,
+  /// Baseline JPEG, which only carries messages in its DCT coefficients
+  Jpeg,
+  /// PCM audio in a WAV file
+  Wav,
+  /// PDF documents, which only carry messages in chunk mode
+  Pdf,
+}
+
+impl Format {
+  /// Recognises a format from the first `MAGIC_LEN` bytes of a file
+  pub fn detect(header: &[u8]) -> Option<Self> {
+    if header.starts_with(&Png::STANDARD_HEADER) {
+      Some(Format::Png)
+    } else if header.starts_with(b"BM") {
+      Some(Format::Bmp)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP")
+    {
+      Some(Format::WebP)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE")
+    {
+      Some(Format::Wav)
+    } else if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+      Some(Format::Jpeg)
+    } else if header.starts_with(b"%PDF-") {
+      Some(Format::Pdf)
+    } else {
+      None
+    }
+  }
+}
+
+impl FromStr for Format {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "png" => Ok(Format::Png),
+      "bmp" => Ok(Format::Bmp),
+      "webp" => Ok(Format::WebP),
+      "jpeg" => Ok(Format::Jpeg),
+      "wav" => Ok(Format::Wav),
+      "pdf" => Ok(Format::Pdf),
+      _ => Err("Format must be one of: png, bmp, webp, jpeg, wav, pdf"),
+    }
+  }
+}
+
+impl fmt::Display for Format {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Format::Png => write!(f, "png"),
+      Format::Bmp => write!(f, "bmp"),
+      Format::WebP => write!(f, "webp"),
+      Format::Jpeg => write!(f, "jpeg"),
+      Format::Wav => write!(f, "wav"),
+      Format::Pdf => write!(f, "pdf"),
+    }
+  }
+}
+
+/// An image, audio file or document a message can be hidden in. Each
+/// format parses its own container, says where payloads go and serialises
+/// itself back. Audio samples are exposed as the pixels of a single frame.
+pub trait CoverImage {
+  /// The container format of this image
+  fn format(&self) -> Format;
+
+  /// The whole file, serialised
+  fn as_bytes(&self) -> Vec<u8>;
+
+  /// The number of bytes this image takes once serialised
+  fn byte_len(&self) -> usize {
+    self.as_bytes().len()
+  }
+
+  /// The data of the chunk of type `chunk_type`, or `None` if there is no
+  /// such chunk. Formats without chunks return an error.
+  fn message_chunk(&self, chunk_type: &ChunkType) -> Result<Option<&[u8]>>;
+
+  /// Stores `data` in a chunk of type `chunk_type`, replacing any message
+  /// stored there before
+  fn set_message_chunk(
+    &mut self,
+    chunk_type: &ChunkType,
+    data: Vec<u8>,
+  ) -> Result<()>;
+
+  /// The decoded pixels of every frame
+  fn decode_frames(&self) -> Result<Vec<Pixels>>;
+
+  /// Replaces the image data with `frames`, as returned by `decode_frames`.
+  /// `filter` only applies to formats that filter their rows.
+  fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    filter: FilterStrategy,
+  ) -> Result<()>;
+
+  /// This image as a `Png`, for the modes only PNG supports
+  fn as_png(&self) -> Option<&Png> {
+    None
+  }
+
+  /// Like `as_png`, for modes that rewrite the image
+  fn as_png_mut(&mut self) -> Option<&mut Png> {
+    None
+  }
+
+  /// This image as a `Jpeg`, for F5 mode
+  fn as_jpeg(&self) -> Option<&Jpeg> {
+    None
+  }
+
+  /// Like `as_jpeg`, to embed a message
+  fn as_jpeg_mut(&mut self) -> Option<&mut Jpeg> {
+    None
+  }
+}
+
+/// The `Png` behind `cover`, or an error saying `mode` needs one
+pub(crate) fn png(cover: &dyn CoverImage, mode: Mode) -> Result<&Png> {
+  let format = cover.format();
+  cover.as_png().ok_or_else(|| unsupported(mode, format))
+}
+
+/// Like `png`, for modes that rewrite the image
+pub(crate) fn png_mut(
+  cover: &mut dyn CoverImage,
+  mode: Mode,
+) -> Result<&mut Png> {
+  let format = cover.format();
+  cover.as_png_mut().ok_or_else(|| unsupported(mode, format))
+}
+
+/// The `Jpeg` behind `cover`, or an error saying `mode` needs one
+pub(crate) fn jpeg(cover: &dyn CoverImage, mode: Mode) -> Result<&Jpeg> {
+  let format = cover.format();
+  cover.as_jpeg().ok_or_else(|| unsupported(mode, format))
+}
+
+/// Like `jpeg`, to embed a message
+pub(crate) fn jpeg_mut(
+  cover: &mut dyn CoverImage,
+  mode: Mode,
+) -> Result<&mut Jpeg> {
+  let format = cover.format();
+  cover.as_jpeg_mut().ok_or_else(|| unsupported(mode, format))
+}
+
+fn unsupported(mode: Mode, format: Format) -> crate::Error {
+  format!("Files in {} format do not support {} mode", format, mode).into()
+}
+
+/// Parses `bytes` as whichever format its magic bytes announce
+pub fn from_bytes(bytes: Vec<u8>) -> Result<Box<dyn CoverImage>> {
+  match Format::detect(&bytes) {
+    Some(Format::Png) => Ok(Box::new(Png::try_from(bytes.as_slice())?)),
+    Some(Format::Bmp) => Ok(Box::new(Bmp::try_from(bytes)?)),
+    Some(Format::WebP) => Ok(Box::new(WebP::try_from(bytes.as_slice())?)),
+    Some(Format::Jpeg) => Ok(Box::new(Jpeg::try_from(bytes.as_slice())?)),
+    Some(Format::Wav) => Ok(Box::new(Wav::try_from(bytes.as_slice())?)),
+    Some(Format::Pdf) => Ok(Box::new(Pdf::try_from(bytes.as_slice())?)),
+    None => Err(
+      "Unrecognised file format; expected PNG, BMP, WebP, JPEG, WAV or PDF"
+        .into(),
+    ),
+  }
+}
+
+/// Reads a whole image from `reader`, in whichever format it is
+pub fn from_reader<R: Read>(mut reader: R) -> Result<Box<dyn CoverImage>> {
+  let mut bytes = Vec::new();
+  reader.read_to_end(&mut bytes)?;
+  from_bytes(bytes)
+}
+
+/// Reads the magic bytes of `reader` to tell its format, returning a reader
+/// that still starts at the beginning of the file
+pub fn sniff<R: Read>(mut reader: R) -> Result<(Option<Format>, impl Read)> {
+  let mut header = Vec::with_capacity(MAGIC_LEN);
+  reader
+    .by_ref()
+    .take(MAGIC_LEN as u64)
+    .read_to_end(&mut header)?;
+  let format = Format::detect(&header);
+  Ok((format, Cursor::new(header).chain(reader)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_detect() {
+    let png = std::fs::read("tests/cat.png").unwrap();
+    assert_eq!(Format::detect(&png[..MAGIC_LEN]), Some(Format::Png));
+    assert_eq!(Format::detect(b"BM\x46\0\0\0"), Some(Format::Bmp));
+    assert_eq!(Format::detect(b"RIFF\0\0\0\0WEBP"), Some(Format::WebP));
+    assert_eq!(Format::detect(b"RIFF\0\0\0\0WAVE"), Some(Format::Wav));
+    assert_eq!(Format::detect(b"RIFF\0\0\0\0AVI "), None);
+    assert_eq!(Format::detect(b"\xFF\xD8\xFF\xE0"), Some(Format::Jpeg));
+    assert_eq!(Format::detect(b"%PDF-1.7\n%"), Some(Format::Pdf));
+    assert!(from_bytes(b"GIF89a".to_vec()).is_err());
+
+    let (format, mut reader) = sniff(png.as_slice()).unwrap();
+    assert_eq!(format, Some(Format::Png));
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, png);
+  }
+}
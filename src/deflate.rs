@@ -0,0 +1,531 @@
+use std::iter::Peekable;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::png::Png;
+use crate::Result;
+
+/// How far back a match may reach
+const WINDOW_LEN: usize = 32 * 1024;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// How many earlier positions with the same hash are tried for a match
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+/// Marks the end of a hash chain
+const NO_POSITION: u32 = u32::MAX;
+
+const END_OF_BLOCK: u16 = 256;
+
+/// The shortest length of each length code, from symbol 257 on
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67,
+  83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5,
+  5, 5, 0,
+];
+
+/// The shortest distance of each distance code
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513,
+  769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11,
+  11, 12, 12, 13, 13,
+];
+
+/// The order code length code lengths are stored in by dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+  16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// How many whole bytes the image data of `png` can carry. The count
+/// depends on the bits being stored, so it is measured around random bits,
+/// which is what an encrypted payload looks like, less a safety margin.
+pub fn capacity(png: &Png) -> Result<usize> {
+  let filtered = png.inflate_idat()?;
+  let mut rng = StdRng::seed_from_u64(0);
+  let mut noise = std::iter::repeat_with(|| rng.gen_range(0..=1)).peekable();
+  let (_, carried) = compress(&filtered, &mut noise);
+  Ok((carried - carried / 16) / 8)
+}
+
+/// Compresses the image data of `png` again, choosing the length of every
+/// match so that its parity spells out `payload`, most significant bit
+/// first. The filtered scanlines, and so the pixels, stay as they are.
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<()> {
+  let filtered = png.inflate_idat()?;
+  let mut bits = payload
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+    .peekable();
+  let (idat, carried) = compress(&filtered, &mut bits);
+  if carried < payload.len() * 8 {
+    return Err("Payload does not fit in the deflate stream".into());
+  }
+  png.set_idat_data(&idat)
+}
+
+/// Reads `len` bytes from the parity of the match lengths of the image
+/// data of `png`, in the order `embed` writes them
+pub fn extract(png: &Png, len: usize) -> Result<Vec<u8>> {
+  let lengths = match_lengths(&png.idat_data())?;
+  if len * 8 > lengths.len() {
+    return Err("Payload is larger than the deflate stream capacity".into());
+  }
+  Ok(
+    lengths
+      .chunks(8)
+      .take(len)
+      .map(|bits| {
+        bits
+          .iter()
+          .fold(0, |byte, length| byte << 1 | (length & 1) as u8)
+      })
+      .collect(),
+  )
+}
+
+/// Finds earlier occurrences of the data at a position with hash chains
+struct Matcher<'a> {
+  data: &'a [u8],
+  head: Vec<u32>,
+  prev: Vec<u32>,
+}
+
+impl<'a> Matcher<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Self {
+      data,
+      head: vec![NO_POSITION; 1 << HASH_BITS],
+      prev: vec![NO_POSITION; data.len()],
+    }
+  }
+
+  fn hash(&self, position: usize) -> usize {
+    let bytes = &self.data[position..position + MIN_MATCH];
+    let hash =
+      (bytes[0] as usize) << 10 ^ (bytes[1] as usize) << 5 ^ bytes[2] as usize;
+    hash & ((1 << HASH_BITS) - 1)
+  }
+
+  /// Makes `position` available to later matches
+  fn insert(&mut self, position: usize) {
+    if position + MIN_MATCH <= self.data.len() {
+      let hash = self.hash(position);
+      self.prev[position] = self.head[hash];
+      self.head[hash] = position as u32;
+    }
+  }
+
+  /// The length and distance of the longest match at `position`, or a
+  /// length of 0 if there is none
+  fn longest(&self, position: usize) -> (usize, usize) {
+    if position + MIN_MATCH > self.data.len() {
+      return (0, 0);
+    }
+    let max_len = MAX_MATCH.min(self.data.len() - position);
+    let (mut best_len, mut best_distance) = (0, 0);
+    let mut candidate = self.head[self.hash(position)];
+    for _ in 0..MAX_CHAIN {
+      if candidate == NO_POSITION || position - candidate as usize > WINDOW_LEN
+      {
+        break;
+      }
+      let start = candidate as usize;
+      let len = self.data[start..start + max_len]
+        .iter()
+        .zip(&self.data[position..position + max_len])
+        .take_while(|(a, b)| a == b)
+        .count();
+      if len > best_len {
+        (best_len, best_distance) = (len, position - start);
+        if len == max_len {
+          break;
+        }
+      }
+      candidate = self.prev[start];
+    }
+    if best_len < MIN_MATCH {
+      return (0, 0);
+    }
+    (best_len, best_distance)
+  }
+}
+
+/// Writes bits least significant first, as deflate streams store them
+#[derive(Default)]
+struct BitWriter {
+  bytes: Vec<u8>,
+  buffer: u32,
+  count: u32,
+}
+
+impl BitWriter {
+  fn write(&mut self, value: u32, len: u32) {
+    self.buffer |= value << self.count;
+    self.count += len;
+    while self.count >= 8 {
+      self.bytes.push(self.buffer as u8);
+      self.buffer >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  /// Writes a Huffman code, which deflate stores most significant bit first
+  fn write_code(&mut self, code: u32, len: u32) {
+    self.write(code.reverse_bits() >> (32 - len), len);
+  }
+
+  /// Writes the code of `symbol` in the fixed literal/length alphabet
+  fn write_symbol(&mut self, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+      0..=143 => self.write_code(0x30 + symbol, 8),
+      144..=255 => self.write_code(0x190 + symbol - 144, 9),
+      256..=279 => self.write_code(symbol - 256, 7),
+      _ => self.write_code(0xC0 + symbol - 280, 8),
+    }
+  }
+
+  fn write_match(&mut self, len: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len);
+    let code = code.expect("match length is at least 3");
+    self.write_symbol(257 + code as u16);
+    let extra = (len - LENGTH_BASE[code] as usize) as u32;
+    self.write(extra, LENGTH_EXTRA[code] as u32);
+
+    let code = DISTANCE_BASE
+      .iter()
+      .rposition(|&base| base as usize <= distance);
+    let code = code.expect("match distance is at least 1");
+    self.write_code(code as u32, 5);
+    let extra = (distance - DISTANCE_BASE[code] as usize) as u32;
+    self.write(extra, DISTANCE_EXTRA[code] as u32);
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.bytes.push(self.buffer as u8);
+    }
+    self.bytes
+  }
+}
+
+/// Compresses `data` into a zlib stream made of a single block with fixed
+/// Huffman codes. Each match stores the next bit of `bits` in the parity of
+/// its length: a longest match of the wrong parity is cut one byte short,
+/// or written as a literal when that would leave it too short. Returns the
+/// stream and how many bits it carries.
+fn compress<I: Iterator<Item = u8>>(
+  data: &[u8],
+  bits: &mut Peekable<I>,
+) -> (Vec<u8>, usize) {
+  let mut writer = BitWriter::default();
+  // Final block with fixed codes
+  writer.write(1, 1);
+  writer.write(1, 2);
+
+  let mut matcher = Matcher::new(data);
+  let mut carried = 0;
+  let mut position = 0;
+  while position < data.len() {
+    let (longest, distance) = matcher.longest(position);
+    let len = match bits.peek() {
+      Some(&bit) if longest % 2 != bit as usize => longest.saturating_sub(1),
+      _ => longest,
+    };
+    if len >= MIN_MATCH {
+      if bits.next().is_some() {
+        carried += 1;
+      }
+      writer.write_match(len, distance);
+      for _ in 0..len {
+        matcher.insert(position);
+        position += 1;
+      }
+    } else {
+      writer.write_symbol(data[position] as u16);
+      matcher.insert(position);
+      position += 1;
+    }
+  }
+  writer.write_symbol(END_OF_BLOCK);
+
+  let mut stream = vec![0x78, 0x9C];
+  stream.extend(writer.finish());
+  stream.extend_from_slice(&adler32(data).to_be_bytes());
+  (stream, carried)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  const MOD: u32 = 65521;
+  let (mut a, mut b) = (1u32, 0u32);
+  for chunk in data.chunks(5552) {
+    for &byte in chunk {
+      a += byte as u32;
+      b += a;
+    }
+    a %= MOD;
+    b %= MOD;
+  }
+  b << 16 | a
+}
+
+/// Reads bits least significant first
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl BitReader<'_> {
+  fn read(&mut self, len: u32) -> Result<u32> {
+    let mut value = 0;
+    for i in 0..len {
+      let byte = self
+        .data
+        .get(self.position / 8)
+        .ok_or("Deflate stream is truncated")?;
+      value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+      self.position += 1;
+    }
+    Ok(value)
+  }
+
+  fn align(&mut self) {
+    self.position = self.position.div_ceil(8) * 8;
+  }
+}
+
+/// A canonical Huffman code, decoded one bit at a time
+struct Huffman {
+  /// How many codes have each length
+  counts: [u16; 16],
+  /// The symbols, ordered by code
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Self {
+    let mut counts = [0; 16];
+    for &len in lengths {
+      counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut symbols = (0..lengths.len() as u16)
+      .filter(|&symbol| lengths[symbol as usize] != 0)
+      .collect::<Vec<_>>();
+    symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+    Self { counts, symbols }
+  }
+
+  fn fixed() -> (Self, Self) {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Self::new(&lengths), Self::new(&[5; 30]))
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+    let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+    for &count in &self.counts[1..] {
+      code |= reader.read(1)? as i32;
+      let count = count as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("Invalid Huffman code in the deflate stream".into())
+  }
+}
+
+/// Reads the code lengths of a dynamic block and builds its codes
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+  let literal_count = reader.read(5)? as usize + 257;
+  let distance_count = reader.read(5)? as usize + 1;
+  let code_length_count = reader.read(4)? as usize + 4;
+  let mut code_lengths = [0; 19];
+  for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+    code_lengths[symbol] = reader.read(3)? as u8;
+  }
+  let code_lengths = Huffman::new(&code_lengths);
+
+  let mut lengths = Vec::with_capacity(literal_count + distance_count);
+  while lengths.len() < literal_count + distance_count {
+    let (len, repeat) = match code_lengths.decode(reader)? {
+      symbol @ 0..=15 => (symbol as u8, 1),
+      16 => {
+        let previous = *lengths.last().ok_or("Invalid code lengths")?;
+        (previous, 3 + reader.read(2)?)
+      }
+      17 => (0, 3 + reader.read(3)?),
+      _ => (0, 11 + reader.read(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(len, repeat as usize));
+  }
+  if lengths.len() != literal_count + distance_count {
+    return Err("Invalid code lengths".into());
+  }
+  Ok((
+    Huffman::new(&lengths[..literal_count]),
+    Huffman::new(&lengths[literal_count..]),
+  ))
+}
+
+/// The length of every match in the zlib stream `zlib`, in order. Only the
+/// structure of the stream is read; nothing is inflated.
+fn match_lengths(zlib: &[u8]) -> Result<Vec<u16>> {
+  if zlib.len() < 2 || zlib[0] & 0x0F != 8 || zlib[1] & 0x20 != 0 {
+    return Err("Unsupported zlib stream".into());
+  }
+  let mut reader = BitReader {
+    data: &zlib[2..],
+    position: 0,
+  };
+  let mut lengths = Vec::new();
+  loop {
+    let is_final = reader.read(1)? == 1;
+    let (literals, distances) = match reader.read(2)? {
+      0 => {
+        reader.align();
+        let len = reader.read(16)? as usize;
+        reader.read(16)?;
+        reader.position += len * 8;
+        if is_final {
+          return Ok(lengths);
+        }
+        continue;
+      }
+      1 => Huffman::fixed(),
+      2 => dynamic_codes(&mut reader)?,
+      _ => return Err("Invalid deflate block type".into()),
+    };
+
+    loop {
+      let symbol = literals.decode(&mut reader)?;
+      if symbol < END_OF_BLOCK {
+        continue;
+      }
+      if symbol == END_OF_BLOCK {
+        break;
+      }
+      let code = (symbol - 257) as usize;
+      if code >= LENGTH_BASE.len() {
+        return Err("Invalid match length in the deflate stream".into());
+      }
+      let extra = reader.read(LENGTH_EXTRA[code] as u32)?;
+      lengths.push(LENGTH_BASE[code] + extra as u16);
+
+      let code = distances.decode(&mut reader)? as usize;
+      if code >= DISTANCE_BASE.len() {
+        return Err("Invalid match distance in the deflate stream".into());
+      }
+      reader.read(DISTANCE_EXTRA[code] as u32)?;
+    }
+    if is_final {
+      return Ok(lengths);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use crate::chunk_type::ChunkType;
+  use crate::png::{ColorType, FilterStrategy, ImageHeader, Pixels};
+  use crate::{scan, wipe, Decoder, Encoder, Mode};
+  use miniz_oxide::deflate::compress_to_vec_zlib;
+  use miniz_oxide::inflate::decompress_to_vec_zlib;
+  use std::str::FromStr;
+
+  fn testing_data() -> Vec<u8> {
+    (0..20_000u32)
+      .map(|i| ((i / 3 % 251) ^ (i / 1000)) as u8)
+      .collect()
+  }
+
+  #[test]
+  fn test_compress_round_trip() {
+    let data = testing_data();
+    let payload = b"parity"
+      .iter()
+      .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    let (stream, carried) = compress(&data, &mut payload.clone().peekable());
+    assert_eq!(carried, 48);
+    assert_eq!(decompress_to_vec_zlib(&stream).unwrap(), data);
+    assert_eq!(adler32(&data).to_be_bytes(), stream[stream.len() - 4..]);
+
+    let lengths = match_lengths(&stream).unwrap();
+    let bits = lengths.iter().take(48).map(|len| (len & 1) as u8);
+    assert!(bits.eq(payload));
+  }
+
+  #[test]
+  fn test_match_lengths_of_other_encoders() {
+    // miniz writes dynamic blocks, and stored ones at level 0
+    let data = testing_data();
+    for level in [0, 1, 9] {
+      let stream = compress_to_vec_zlib(&data, level);
+      let lengths = match_lengths(&stream).unwrap();
+      assert_eq!(lengths.is_empty(), level == 0, "level {}", level);
+    }
+    assert!(match_lengths(&[0x78, 0x9C, 0xFF]).is_err());
+  }
+
+  #[test]
+  fn test_deflate_mode() {
+    let header = ImageHeader {
+      width: 64,
+      height: 64,
+      bit_depth: 8,
+      color_type: ColorType::Rgb,
+      interlaced: false,
+    };
+    // Few distinct values in no particular order make many short matches
+    let mut rng = StdRng::seed_from_u64(1);
+    let data = (0..64 * 64 * 3).map(|_| rng.gen_range(0..4) * 60).collect();
+    let pixels = Pixels::new(header, data).unwrap();
+    let mut png = Png::from_chunks(vec![
+      header.as_chunk(),
+      Chunk::new(
+        ChunkType::from_str("IDAT").unwrap(),
+        pixels.encode(FilterStrategy::default()),
+      ),
+      Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+    ]);
+    let types = |png: &Png| {
+      let types = png
+        .chunks()
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string());
+      types.collect::<Vec<_>>()
+    };
+    let before = types(&png);
+
+    let encoder = Encoder::new("password").mode(Mode::Deflate);
+    assert!(encoder.capacity(&png).unwrap().usable > 100);
+    encoder.encode(&mut png, "Compressed").unwrap();
+    assert_eq!(png.decode_pixels().unwrap(), pixels);
+    assert_eq!(types(&png), before);
+    let decoder = Decoder::new("password").mode(Mode::Deflate);
+    assert_eq!(decoder.decode(&png).unwrap().message, "Compressed");
+    assert_eq!(scan::scan(&png)[0].location, scan::Location::Deflate);
+
+    let wiped = wipe::wipe(&mut png, &[]).unwrap();
+    assert!(wiped.deflate);
+    assert_eq!(png.decode_pixels().unwrap(), pixels);
+    assert!(decoder.decode(&png).is_err());
+  }
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::gf256::{self, poly};
+use crate::Result;
+
+/// Magic bytes that start every ECC frame
+pub const FRAME_MAGIC: [u8; 4] = *b"CHTR";
+
+/// Magic bytes, parity symbols per block and protected data length
+const HEADER_DATA_LEN: usize = 9;
+
+/// Parity symbols protecting the frame header, correcting up to 4 errors
+const HEADER_PARITY: usize = 8;
+
+/// The frame header along with its own parity symbols
+pub const FRAME_HEADER_LEN: usize = HEADER_DATA_LEN + HEADER_PARITY;
+
+/// The largest Reed-Solomon codeword over GF(2^8)
+const MAX_BLOCK_LEN: usize = 255;
+
+/// How much Reed-Solomon redundancy protects a payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EccLevel {
+  /// No error correction
+  #[default]
+  None,
+  /// Corrects up to 8 damaged bytes per 255-byte block
+  Low,
+  /// Corrects up to 16 damaged bytes per 255-byte block
+  Medium,
+  /// Corrects up to 32 damaged bytes per 255-byte block
+  High,
+}
+
+impl EccLevel {
+  /// The number of parity symbols added to each block
+  pub fn parity(&self) -> usize {
+    match self {
+      EccLevel::None => 0,
+      EccLevel::Low => 16,
+      EccLevel::Medium => 32,
+      EccLevel::High => 64,
+    }
+  }
+
+  /// The number of bytes `data_len` bytes take once protected at this level
+  pub fn frame_len(&self, data_len: usize) -> usize {
+    if *self == EccLevel::None {
+      return data_len;
+    }
+    let (blocks, block_data_len) = layout(data_len, self.parity());
+    FRAME_HEADER_LEN + blocks * (block_data_len + self.parity())
+  }
+
+  /// The largest number of bytes that fit in `available` bytes once
+  /// protected at this level
+  pub fn max_data_len(&self, available: usize) -> usize {
+    let (mut low, mut high) = (0, available);
+    while low < high {
+      let mid = low + (high - low).div_ceil(2);
+      if self.frame_len(mid) <= available {
+        low = mid;
+      } else {
+        high = mid - 1;
+      }
+    }
+    low
+  }
+}
+
+impl FromStr for EccLevel {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(EccLevel::None),
+      "low" => Ok(EccLevel::Low),
+      "medium" => Ok(EccLevel::Medium),
+      "high" => Ok(EccLevel::High),
+      _ => Err("ECC level must be one of: none, low, medium, high"),
+    }
+  }
+}
+
+impl fmt::Display for EccLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      EccLevel::None => "none",
+      EccLevel::Low => "low",
+      EccLevel::Medium => "medium",
+      EccLevel::High => "high",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Splits `data_len` bytes into the fewest blocks that fit in a codeword,
+/// all of the same length. Returns the number of blocks and the data length
+/// of each, the last block being padded with zeros.
+fn layout(data_len: usize, parity: usize) -> (usize, usize) {
+  let max_data = MAX_BLOCK_LEN - parity;
+  let blocks = data_len.div_ceil(max_data).max(1);
+  (blocks, data_len.div_ceil(blocks))
+}
+
+/// Wraps `data` in an ECC frame:
+///
+/// | magic (4) | parity (1) | data length (4) | header parity (8) | blocks |
+///
+/// Codewords are interleaved byte by byte, so that a burst of damaged bytes
+/// is spread over every block instead of exhausting a single one.
+pub fn protect(data: &[u8], level: EccLevel) -> Vec<u8> {
+  let parity = level.parity();
+  if parity == 0 {
+    return data.to_vec();
+  }
+
+  let mut header = Vec::with_capacity(HEADER_DATA_LEN);
+  header.extend_from_slice(&FRAME_MAGIC);
+  header.push(parity as u8);
+  header.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+  let (blocks, block_data_len) = layout(data.len(), parity);
+  let codewords = (0..blocks)
+    .map(|i| {
+      let mut block = vec![0u8; block_data_len];
+      let start = (i * block_data_len).min(data.len());
+      let end = ((i + 1) * block_data_len).min(data.len());
+      block[..end - start].copy_from_slice(&data[start..end]);
+      encode_block(&block, parity)
+    })
+    .collect::<Vec<_>>();
+
+  let codeword_len = block_data_len + parity;
+  let mut frame = encode_block(&header, HEADER_PARITY);
+  frame.reserve(blocks * codeword_len);
+  for i in 0..codeword_len {
+    frame.extend(codewords.iter().map(|codeword| codeword[i]));
+  }
+  frame
+}
+
+/// Decodes the frame header at the start of `bytes`. Returns the parity
+/// symbols per block, the protected data length and the number of
+/// corrected errors.
+fn decode_header(bytes: &[u8]) -> Result<(usize, usize, usize)> {
+  if bytes.len() < FRAME_HEADER_LEN {
+    return Err("No ECC frame found".into());
+  }
+  let (header, corrected) =
+    decode_block(&bytes[..FRAME_HEADER_LEN], HEADER_PARITY)
+      .map_err(|_| "No ECC frame found")?;
+  if header[..4] != FRAME_MAGIC {
+    return Err("No ECC frame found".into());
+  }
+
+  let parity = header[4] as usize;
+  if parity == 0 || parity >= MAX_BLOCK_LEN - 1 {
+    return Err("Invalid ECC parity".into());
+  }
+  let data_len =
+    u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+  Ok((parity, data_len as usize, corrected))
+}
+
+/// Reads the level an ECC frame was protected at from its header
+pub fn level(header: &[u8]) -> Result<EccLevel> {
+  let (parity, _, _) = decode_header(header)?;
+  [EccLevel::Low, EccLevel::Medium, EccLevel::High]
+    .into_iter()
+    .find(|level| level.parity() == parity)
+    .ok_or_else(|| "Unknown ECC level".into())
+}
+
+/// Returns true if `bytes` starts with an ECC frame, possibly damaged
+pub fn is_protected(bytes: &[u8]) -> bool {
+  decode_header(bytes).is_ok()
+}
+
+/// Reads the total length of an ECC frame from its header
+pub fn frame_len(header: &[u8]) -> Result<usize> {
+  let (parity, data_len, _) = decode_header(header)?;
+  let (blocks, block_data_len) = layout(data_len, parity);
+  Ok(FRAME_HEADER_LEN + blocks * (block_data_len + parity))
+}
+
+/// Corrects and unwraps the ECC frame at the start of `bytes`. Returns the
+/// protected data and the number of bytes that had to be corrected.
+pub fn recover(bytes: &[u8]) -> Result<(Vec<u8>, usize)> {
+  let (parity, data_len, mut corrected) = decode_header(bytes)?;
+  let (blocks, block_data_len) = layout(data_len, parity);
+  let codeword_len = block_data_len + parity;
+  let body = &bytes[FRAME_HEADER_LEN..];
+  if body.len() < blocks * codeword_len {
+    return Err("ECC frame is truncated".into());
+  }
+
+  let mut data = Vec::with_capacity(blocks * block_data_len);
+  for i in 0..blocks {
+    let codeword = (0..codeword_len)
+      .map(|j| body[j * blocks + i])
+      .collect::<Vec<_>>();
+    let (block, block_corrected) = decode_block(&codeword, parity)
+      .map_err(|_| format!("Too many errors to correct in block {}", i))?;
+    corrected += block_corrected;
+    data.extend_from_slice(&block);
+  }
+  data.truncate(data_len);
+
+  Ok((data, corrected))
+}
+
+/// Appends `parity` Reed-Solomon symbols to `data`
+fn encode_block(data: &[u8], parity: usize) -> Vec<u8> {
+  let generator = (0..parity as i32)
+    .fold(vec![1], |g, i| poly::mul_poly(&g, &[1, gf256::exp(i)]));
+
+  let mut padded = data.to_vec();
+  padded.resize(data.len() + parity, 0);
+  let (_, remainder) = poly::div(&padded, &generator);
+
+  let mut codeword = data.to_vec();
+  codeword.extend_from_slice(&remainder);
+  codeword
+}
+
+/// Corrects up to `parity / 2` errors in `codeword` and returns its data
+/// along with the number of corrected errors
+fn decode_block(
+  codeword: &[u8],
+  parity: usize,
+) -> std::result::Result<(Vec<u8>, usize), &'static str> {
+  let data_len = codeword.len() - parity;
+  let syndromes = syndromes(codeword, parity);
+  if syndromes.iter().all(|&s| s == 0) {
+    return Ok((codeword[..data_len].to_vec(), 0));
+  }
+
+  let locator = error_locator(&syndromes, parity)?;
+  let reversed = locator.iter().rev().copied().collect::<Vec<_>>();
+  let positions = (0..codeword.len())
+    .filter(|&i| poly::eval(&reversed, gf256::exp(i as i32)) == 0)
+    .map(|i| codeword.len() - 1 - i)
+    .collect::<Vec<_>>();
+  if positions.len() != locator.len() - 1 {
+    return Err("Could not locate errors");
+  }
+
+  let corrected = correct_errors(codeword, &syndromes, &positions)?;
+  if syndromes_of(&corrected, parity).iter().any(|&s| s != 0) {
+    return Err("Could not correct errors");
+  }
+  Ok((corrected[..data_len].to_vec(), positions.len()))
+}
+
+/// The syndromes of `codeword`, preceded by a zero coefficient
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+  let mut syndromes = vec![0u8];
+  syndromes.extend(syndromes_of(codeword, parity));
+  syndromes
+}
+
+fn syndromes_of(codeword: &[u8], parity: usize) -> Vec<u8> {
+  (0..parity as i32)
+    .map(|i| poly::eval(codeword, gf256::exp(i)))
+    .collect()
+}
+
+/// Finds the error locator polynomial with the Berlekamp-Massey algorithm
+fn error_locator(
+  syndromes: &[u8],
+  parity: usize,
+) -> std::result::Result<Vec<u8>, &'static str> {
+  let mut locator = vec![1u8];
+  let mut old_locator = vec![1u8];
+  let shift = syndromes.len() - parity;
+
+  for i in 0..parity {
+    let k = i + shift;
+    let mut delta = syndromes[k];
+    for j in 1..locator.len() {
+      delta ^= gf256::mul(locator[locator.len() - 1 - j], syndromes[k - j]);
+    }
+    old_locator.push(0);
+
+    if delta != 0 {
+      if old_locator.len() > locator.len() {
+        let new_locator = poly::scale(&old_locator, delta);
+        old_locator = poly::scale(&locator, gf256::inverse(delta));
+        locator = new_locator;
+      }
+      locator = poly::add(&locator, &poly::scale(&old_locator, delta));
+    }
+  }
+
+  let first = locator
+    .iter()
+    .position(|&c| c != 0)
+    .unwrap_or(locator.len());
+  let locator = locator[first..].to_vec();
+  if locator.is_empty() || (locator.len() - 1) * 2 > parity {
+    return Err("Too many errors to correct");
+  }
+  Ok(locator)
+}
+
+/// Computes the error magnitudes with the Forney algorithm and applies them
+fn correct_errors(
+  codeword: &[u8],
+  syndromes: &[u8],
+  positions: &[usize],
+) -> std::result::Result<Vec<u8>, &'static str> {
+  let coefficients = positions
+    .iter()
+    .map(|&p| codeword.len() - 1 - p)
+    .collect::<Vec<_>>();
+
+  let locator = coefficients.iter().fold(vec![1u8], |l, &c| {
+    poly::mul_poly(&l, &poly::add(&[1], &[gf256::exp(c as i32), 0]))
+  });
+
+  let reversed_syndromes = syndromes.iter().rev().copied().collect::<Vec<_>>();
+  let mut divisor = vec![1u8];
+  divisor.resize(locator.len() + 1, 0);
+  let (_, evaluator) =
+    poly::div(&poly::mul_poly(&reversed_syndromes, &locator), &divisor);
+
+  let roots = coefficients
+    .iter()
+    .map(|&c| gf256::exp(c as i32))
+    .collect::<Vec<_>>();
+
+  let mut corrected = codeword.to_vec();
+  for (i, &root) in roots.iter().enumerate() {
+    let root_inverse = gf256::inverse(root);
+    let derivative = roots
+      .iter()
+      .enumerate()
+      .filter(|(j, _)| *j != i)
+      .fold(1u8, |acc, (_, &other)| {
+        gf256::mul(acc, 1 ^ gf256::mul(root_inverse, other))
+      });
+    if derivative == 0 {
+      return Err("Could not compute error magnitude");
+    }
+
+    let y = gf256::mul(root, poly::eval(&evaluator, root_inverse));
+    corrected[positions[i]] ^= gf256::div(y, derivative);
+  }
+  Ok(corrected)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+
+  fn testing_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 37 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn test_block_round_trip() {
+    let data = testing_data(100);
+    let codeword = encode_block(&data, 16);
+    assert_eq!(codeword.len(), 116);
+    assert_eq!(decode_block(&codeword, 16).unwrap(), (data, 0));
+  }
+
+  #[test]
+  fn test_block_corrects_errors() {
+    let data = testing_data(200);
+    let mut codeword = encode_block(&data, 32);
+    for i in 0..16 {
+      codeword[i * 13] ^= 0x5A;
+    }
+    assert_eq!(decode_block(&codeword, 32).unwrap(), (data, 16));
+  }
+
+  #[test]
+  fn test_block_rejects_too_many_errors() {
+    let data = testing_data(200);
+    let mut codeword = encode_block(&data, 16);
+    for i in 0..12 {
+      codeword[i * 7] ^= 0xFF;
+    }
+    let decoded = decode_block(&codeword, 16);
+    assert!(decoded.map(|(d, _)| d != data).unwrap_or(true));
+  }
+
+  #[test]
+  fn test_frame_round_trip() {
+    for level in [EccLevel::Low, EccLevel::Medium, EccLevel::High] {
+      for len in [1, 100, 239, 240, 1000] {
+        let data = testing_data(len);
+        let frame = protect(&data, level);
+        assert_eq!(frame.len(), level.frame_len(len));
+        assert_eq!(frame_len(&frame).unwrap(), frame.len());
+        assert_eq!(super::level(&frame).unwrap(), level);
+        assert_eq!(recover(&frame).unwrap(), (data, 0));
+      }
+    }
+  }
+
+  #[test]
+  fn test_frame_corrects_random_damage() {
+    let data = testing_data(2000);
+    let mut frame = protect(&data, EccLevel::Medium);
+    let mut rng = rand::thread_rng();
+
+    // A burst in the body and a few errors in the header
+    let start = rng.gen_range(FRAME_HEADER_LEN..frame.len() - 64);
+    for byte in &mut frame[start..start + 64] {
+      *byte ^= 0xA5;
+    }
+    frame[0] ^= 1;
+    frame[6] ^= 0x80;
+
+    let (recovered, corrected) = recover(&frame).unwrap();
+    assert_eq!(recovered, data);
+    assert_eq!(corrected, 66);
+  }
+
+  #[test]
+  fn test_max_data_len() {
+    for level in [EccLevel::None, EccLevel::Low, EccLevel::High] {
+      for available in [0, 17, 100, 5000] {
+        let max = level.max_data_len(available);
+        assert!(level.frame_len(max) <= available || max == 0);
+        assert!(level.frame_len(max + 1) > available);
+      }
+    }
+  }
+
+  #[test]
+  fn test_unprotected_data_is_not_a_frame() {
+    assert!(!is_protected(b"CHTE\x01\x00\x00\x00\x10 plus some padding"));
+    assert!(recover(&testing_data(40)).is_err());
+  }
+}
@@ -0,0 +1,383 @@
+use aes_gcm::aead::generic_array::GenericArray;
+
+use crate::aes::{decrypt_message, encrypt_message, Key};
+use crate::kdf::{KdfParams, SALT_LEN};
+use crate::Result;
+
+/// Magic bytes that start every serialised envelope
+pub const MAGIC: [u8; 4] = *b"CHTE";
+
+/// The version of the envelope layout for keys shared out of band, such as
+/// passwords and key files
+pub const VERSION: u8 = 1;
+
+/// The version of the envelope layout that starts with a key block
+pub const KEY_BLOCK_VERSION: u8 = 2;
+
+/// Magic bytes, version and ciphertext length
+pub const HEADER_LEN: usize = 9;
+
+/// Bytes needed to read the total length of an envelope of any version
+pub const PEEK_LEN: usize = HEADER_LEN + 2;
+
+/// Length of an X25519 public key
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of a message key once wrapped with AES-GCM
+pub const WRAPPED_KEY_LEN: usize = 32 + TAG_LEN;
+
+/// Length of a serialised `Stanza`
+pub const STANZA_LEN: usize = PUBLIC_KEY_LEN + WRAPPED_KEY_LEN;
+
+/// The most recipients a key block can hold
+pub const MAX_RECIPIENTS: usize = u8::MAX as usize;
+
+/// Key block kind for a key wrapped for recipients
+const RECIPIENTS_KIND: u8 = 1;
+
+/// Key block kind for a key stretched from a password
+const PASSWORD_KIND: u8 = 2;
+
+/// Length of a password key block: kind, three parameters and the salt
+pub const PASSWORD_KEY_BLOCK_LEN: usize = 1 + 3 * 4 + SALT_LEN;
+
+/// Length of the AES-GCM nonce
+pub const NONCE_LEN: usize = 12;
+
+/// Length of the AES-GCM authentication tag appended to the ciphertext
+pub const TAG_LEN: usize = 16;
+
+/// Number of bytes an envelope adds on top of the plaintext
+pub const OVERHEAD: usize = HEADER_LEN + NONCE_LEN + TAG_LEN;
+
+/// Length of a key block wrapping the message key for `count` recipients:
+/// kind, count and a stanza each
+pub const fn recipients_key_block_len(count: usize) -> usize {
+  2 + count * STANZA_LEN
+}
+
+/// The message key wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stanza {
+  /// The ephemeral public key the wrapping key was agreed with
+  pub ephemeral: [u8; PUBLIC_KEY_LEN],
+  /// The message key, encrypted with the wrapping key
+  pub wrapped_key: [u8; WRAPPED_KEY_LEN],
+}
+
+/// How the key of an envelope is found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyBlock {
+  /// The key is shared out of band, and nothing about it is stored
+  Shared,
+  /// A random message key, wrapped for each recipient
+  Recipients(Vec<Stanza>),
+  /// A key stretched from a password with these parameters and salt
+  Password {
+    /// The Argon2 parameters the key was stretched with
+    params: KdfParams,
+    /// The random salt the key was stretched with
+    salt: [u8; SALT_LEN],
+  },
+}
+
+impl KeyBlock {
+  fn as_bytes(&self) -> Vec<u8> {
+    match self {
+      KeyBlock::Shared => Vec::new(),
+      KeyBlock::Recipients(stanzas) => {
+        let mut bytes = vec![RECIPIENTS_KIND, stanzas.len() as u8];
+        for stanza in stanzas {
+          bytes.extend_from_slice(&stanza.ephemeral);
+          bytes.extend_from_slice(&stanza.wrapped_key);
+        }
+        bytes
+      }
+      KeyBlock::Password { params, salt } => {
+        let mut bytes = Vec::with_capacity(PASSWORD_KEY_BLOCK_LEN);
+        bytes.push(PASSWORD_KIND);
+        bytes.extend_from_slice(&params.memory_kib.to_be_bytes());
+        bytes.extend_from_slice(&params.iterations.to_be_bytes());
+        bytes.extend_from_slice(&params.parallelism.to_be_bytes());
+        bytes.extend_from_slice(salt);
+        bytes
+      }
+    }
+  }
+
+  fn parse(bytes: &[u8]) -> Result<Self> {
+    match bytes {
+      [RECIPIENTS_KIND, count, stanzas @ ..]
+        if stanzas.len() == *count as usize * STANZA_LEN =>
+      {
+        let stanzas = stanzas
+          .chunks_exact(STANZA_LEN)
+          .map(|stanza| {
+            let (ephemeral, wrapped_key) = stanza.split_at(PUBLIC_KEY_LEN);
+            Stanza {
+              ephemeral: ephemeral.try_into().unwrap(),
+              wrapped_key: wrapped_key.try_into().unwrap(),
+            }
+          })
+          .collect();
+        Ok(KeyBlock::Recipients(stanzas))
+      }
+      [PASSWORD_KIND, rest @ ..] if bytes.len() == PASSWORD_KEY_BLOCK_LEN => {
+        let word = |i: usize| {
+          u32::from_be_bytes(rest[i * 4..i * 4 + 4].try_into().unwrap())
+        };
+        let params = KdfParams::new(word(0), word(1), word(2));
+        params.validate()?;
+        Ok(KeyBlock::Password {
+          params,
+          salt: rest[12..].try_into().unwrap(),
+        })
+      }
+      _ => Err("Invalid envelope key block".into()),
+    }
+  }
+}
+
+/// An encrypted message together with everything needed to decrypt it,
+/// except the key. Every embedding mode stores one of these byte layouts,
+/// the second one when the key block is not `Shared`:
+///
+/// | magic (4) | version (1) | ciphertext length (4) | nonce (12) | ciphertext |
+///
+/// | magic (4) | version (1) | ciphertext length (4) | key block length (2) |
+/// key block | nonce (12) | ciphertext |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+  key_block: KeyBlock,
+  nonce: [u8; NONCE_LEN],
+  ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+  /// Encrypts `message` with a shared `key`
+  pub fn seal(message: &str, key: &Key) -> Self {
+    Self::seal_with(message, key, KeyBlock::Shared)
+  }
+
+  /// Encrypts `message` with `key`, storing `key_block` to find it again
+  pub fn seal_with(message: &str, key: &Key, key_block: KeyBlock) -> Self {
+    let (ciphertext, nonce) = encrypt_message(message, key);
+    Self {
+      key_block,
+      nonce: nonce.into(),
+      ciphertext,
+    }
+  }
+
+  /// How the key of this envelope is found
+  pub fn key_block(&self) -> &KeyBlock {
+    &self.key_block
+  }
+
+  /// Decrypts this envelope with `key`
+  pub fn open(&self, key: &Key) -> Result<String> {
+    let nonce = GenericArray::from_slice(&self.nonce);
+    decrypt_message(&self.ciphertext, key, nonce)
+  }
+
+  /// The size of an envelope holding a plaintext of `message_len` bytes
+  /// with a shared key
+  pub fn sealed_len(message_len: usize) -> usize {
+    message_len + OVERHEAD
+  }
+
+  /// Bytes a key block of `key_block_len` bytes adds to an envelope, with
+  /// its length. Envelopes without a key block keep the version 1 layout.
+  pub fn key_block_overhead(key_block_len: usize) -> usize {
+    match key_block_len {
+      0 => 0,
+      len => 2 + len,
+    }
+  }
+
+  /// Returns true if `bytes` starts with the envelope magic bytes
+  pub fn is_envelope(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+  }
+
+  /// Reads the total length of a serialised envelope from its header
+  pub fn total_len(header: &[u8]) -> Result<usize> {
+    Ok(Self::key_block_range(header)?.end + NONCE_LEN + ciphertext_len(header))
+  }
+
+  /// Where the key block lies in a serialised envelope starting with
+  /// `header`, which is empty for version 1
+  fn key_block_range(header: &[u8]) -> Result<std::ops::Range<usize>> {
+    if header.len() < HEADER_LEN || !Self::is_envelope(header) {
+      return Err("No envelope found".into());
+    }
+    match header[4] {
+      VERSION => Ok(HEADER_LEN..HEADER_LEN),
+      KEY_BLOCK_VERSION => {
+        if header.len() < PEEK_LEN {
+          return Err("No envelope found".into());
+        }
+        let length = u16::from_be_bytes([header[9], header[10]]) as usize;
+        Ok(PEEK_LEN..PEEK_LEN + length)
+      }
+      _ => Err("Unsupported envelope version".into()),
+    }
+  }
+
+  /// Serialises this envelope
+  pub fn as_bytes(&self) -> Vec<u8> {
+    let key_block = self.key_block.as_bytes();
+    let mut bytes = Vec::with_capacity(
+      OVERHEAD + 2 + key_block.len() + self.ciphertext.len(),
+    );
+    bytes.extend_from_slice(&MAGIC);
+    if self.key_block == KeyBlock::Shared {
+      bytes.push(VERSION);
+      bytes.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+    } else {
+      bytes.push(KEY_BLOCK_VERSION);
+      bytes.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+      bytes.extend_from_slice(&(key_block.len() as u16).to_be_bytes());
+      bytes.extend_from_slice(&key_block);
+    }
+    bytes.extend_from_slice(&self.nonce);
+    bytes.extend_from_slice(&self.ciphertext);
+    bytes
+  }
+}
+
+/// The ciphertext length stored in a checked envelope header
+fn ciphertext_len(header: &[u8]) -> usize {
+  u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize
+}
+
+/// Parses a serialised envelope. Bytes past the end of the envelope are
+/// ignored, since pixel modes read more bits than the envelope occupies.
+impl TryFrom<&[u8]> for Envelope {
+  type Error = crate::Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    let total_len = Self::total_len(bytes)?;
+    if bytes.len() < total_len {
+      return Err("Envelope is truncated".into());
+    }
+
+    let key_block = Self::key_block_range(bytes)?;
+    let nonce_start = key_block.end;
+    let key_block = if key_block.is_empty() {
+      KeyBlock::Shared
+    } else {
+      KeyBlock::parse(&bytes[key_block])?
+    };
+
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&bytes[nonce_start..nonce_start + NONCE_LEN]);
+    let ciphertext = bytes[nonce_start + NONCE_LEN..total_len].to_vec();
+
+    Ok(Self {
+      key_block,
+      nonce,
+      ciphertext,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_key() -> Key {
+    Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ")
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let envelope = Envelope::seal("This is a secret message!", &testing_key());
+    let bytes = envelope.as_bytes();
+    assert_eq!(bytes.len(), Envelope::sealed_len(25));
+
+    let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+    assert_eq!(parsed, envelope);
+    assert_eq!(
+      parsed.open(&testing_key()).unwrap(),
+      "This is a secret message!"
+    );
+  }
+
+  #[test]
+  fn test_trailing_bytes_are_ignored() {
+    let envelope = Envelope::seal("message", &testing_key());
+    let mut bytes = envelope.as_bytes();
+    bytes.extend_from_slice(&[0xAA; 32]);
+
+    let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+    assert_eq!(parsed, envelope);
+  }
+
+  #[test]
+  fn test_invalid_envelopes() {
+    let bytes = Envelope::seal("message", &testing_key()).as_bytes();
+
+    assert!(Envelope::try_from(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Envelope::try_from(&bytes[1..]).is_err());
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[4] = KEY_BLOCK_VERSION + 1;
+    assert!(Envelope::try_from(wrong_version.as_ref()).is_err());
+  }
+
+  #[test]
+  fn test_key_block_round_trip() {
+    let stanzas = (0..3)
+      .map(|i| Stanza {
+        ephemeral: [i; PUBLIC_KEY_LEN],
+        wrapped_key: [i + 1; WRAPPED_KEY_LEN],
+      })
+      .collect();
+    let key_block = KeyBlock::Recipients(stanzas);
+    let envelope =
+      Envelope::seal_with("message", &testing_key(), key_block.clone());
+    let bytes = envelope.as_bytes();
+    assert_eq!(bytes[4], KEY_BLOCK_VERSION);
+    assert_eq!(
+      Envelope::total_len(&bytes[..PEEK_LEN]).unwrap(),
+      bytes.len()
+    );
+
+    let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+    assert_eq!(parsed.key_block(), &key_block);
+    assert_eq!(parsed.open(&testing_key()).unwrap(), "message");
+
+    // A key block that disagrees with its stanza count is rejected
+    let mut bytes = bytes;
+    bytes[PEEK_LEN + 1] = 2;
+    assert!(Envelope::try_from(bytes.as_ref()).is_err());
+  }
+
+  #[test]
+  fn test_password_key_block() {
+    let key_block = KeyBlock::Password {
+      params: KdfParams::LEGACY,
+      salt: [9; SALT_LEN],
+    };
+    let envelope =
+      Envelope::seal_with("message", &testing_key(), key_block.clone());
+    let bytes = envelope.as_bytes();
+    assert_eq!(
+      Envelope::total_len(&bytes[..PEEK_LEN]).unwrap(),
+      bytes.len()
+    );
+    let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+    assert_eq!(parsed.key_block(), &key_block);
+    assert_eq!(parsed.open(&testing_key()).unwrap(), "message");
+
+    // Parameters too costly to derive a key with are rejected, whether it
+    // is the memory or the passes
+    let mut memory = bytes.clone();
+    memory[PEEK_LEN + 1] = 0xFF;
+    assert!(Envelope::try_from(memory.as_ref()).is_err());
+    let mut iterations = bytes;
+    iterations[PEEK_LEN + 8] = 0xFF;
+    assert!(Envelope::try_from(iterations.as_ref()).is_err());
+  }
+}
@@ -0,0 +1,307 @@
+use crate::png::{
+  ColorType, ImageHeader, Palette, Pixels, Png, MAX_PALETTE_LEN,
+};
+use crate::Result;
+
+/// The largest difference in any channel between two colours of a full
+/// palette that get paired, small enough that swapping them goes unseen
+const MAX_PAIR_DISTANCE: u8 = 8;
+
+/// Checks that `pixels` hold palette indices
+fn validate(pixels: &Pixels) -> Result<()> {
+  if pixels.header().color_type != ColorType::Indexed {
+    return Err("Palette mode only supports indexed-colour images".into());
+  }
+  Ok(())
+}
+
+/// For each palette index, the bit it stands for and the index that stands
+/// for the other bit, or `None` if no colour is close enough to pair with.
+/// Entries are sorted by luminance and paired with their closest neighbour,
+/// like EzStego does, so that the pairing only depends on the palette.
+fn pairs(palette: &Palette) -> Vec<Option<(u8, u8)>> {
+  let entries = palette.entries();
+  let mut order = (0..entries.len()).collect::<Vec<_>>();
+  order.sort_by_key(|&index| {
+    let [r, g, b, a] = entries[index];
+    let luma = 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+    (luma, [r, g, b, a], index)
+  });
+
+  let mut pairs = vec![None; entries.len()];
+  let mut sorted = order.iter().peekable();
+  while let Some(&first) = sorted.next() {
+    let Some(&&second) = sorted.peek() else {
+      break;
+    };
+    let distance = entries[first]
+      .iter()
+      .zip(&entries[second])
+      .map(|(a, b)| a.abs_diff(*b))
+      .max()
+      .unwrap_or(0);
+    if distance <= MAX_PAIR_DISTANCE {
+      pairs[first] = Some((0, second as u8));
+      pairs[second] = Some((1, first as u8));
+      sorted.next();
+    }
+  }
+  pairs
+}
+
+/// The palette to embed in `png` with. A palette with room to spare is
+/// doubled, so that every colour is paired with an exact copy of itself and
+/// the image looks the same whatever is embedded. A full palette is used as
+/// is, pairing only colours that look alike.
+pub fn plan(png: &Png) -> Result<Palette> {
+  let palette = png.palette()?;
+  if is_doubled(&palette) || palette.len() * 2 > MAX_PALETTE_LEN {
+    return Ok(palette);
+  }
+  Palette::new(palette.entries().repeat(2))
+}
+
+/// Returns true if every entry of `palette` is paired with a copy of itself,
+/// as `plan` leaves it
+fn is_doubled(palette: &Palette) -> bool {
+  let entries = palette.entries();
+  pairs(palette).iter().zip(entries).all(|(pair, entry)| {
+    pair.is_some_and(|(_, other)| entries[other as usize] == *entry)
+  })
+}
+
+/// Writes `palette`, as returned by `plan`, to `png`. When it has more
+/// entries than the bit depth of the image can index, the image and its
+/// `frames` move to a larger bit depth.
+pub fn install(
+  png: &mut Png,
+  frames: &mut [Pixels],
+  palette: &Palette,
+) -> Result<()> {
+  let mut header = png.image_header()?;
+  let bit_depth = [1, 2, 4, 8]
+    .into_iter()
+    .find(|&depth| palette.len() <= 1 << depth)
+    .unwrap_or(8)
+    .max(header.bit_depth);
+  if bit_depth != header.bit_depth {
+    header.bit_depth = bit_depth;
+    png.set_image_header(&header)?;
+    for pixels in frames.iter_mut() {
+      let frame_header = ImageHeader {
+        bit_depth,
+        ..*pixels.header()
+      };
+      let len = frame_header.row_bytes(frame_header.width as usize)
+        * frame_header.height as usize;
+      let mut widened = Pixels::new(frame_header, vec![0; len])?;
+      for index in 0..pixels.sample_count() {
+        widened.set_sample(index, pixels.sample(index));
+      }
+      *pixels = widened;
+    }
+  }
+  png.set_palette(palette)
+}
+
+/// The number of whole bytes `frames` can carry with `palette`, one bit for
+/// every pixel whose colour is paired
+pub fn capacity(frames: &[Pixels], palette: &Palette) -> Result<usize> {
+  let pairs = pairs(palette);
+  let mut carriers = 0;
+  for pixels in frames {
+    validate(pixels)?;
+    carriers += (0..pixels.sample_count())
+      .filter(|&index| is_carrier(&pairs, pixels, index))
+      .count();
+  }
+  Ok(carriers / 8)
+}
+
+fn is_carrier(
+  pairs: &[Option<(u8, u8)>],
+  pixels: &Pixels,
+  index: usize,
+) -> bool {
+  matches!(pairs.get(pixels.sample(index) as usize), Some(Some(_)))
+}
+
+/// Writes `payload` into the pixels of `frames` whose colour is paired in
+/// `palette`, most significant bit first, swapping a colour for its pair
+/// where the bit it stands for is wrong
+pub fn embed(
+  frames: &mut [Pixels],
+  palette: &Palette,
+  payload: &[u8],
+) -> Result<()> {
+  if payload.len() > capacity(frames, palette)? {
+    return Err("Payload does not fit in the palette".into());
+  }
+
+  let pairs = pairs(palette);
+  let mut bits = payload
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+  for pixels in frames {
+    for index in 0..pixels.sample_count() {
+      let Some(Some((bit, other))) = pairs.get(pixels.sample(index) as usize)
+      else {
+        continue;
+      };
+      let Some(wanted) = bits.next() else {
+        return Ok(());
+      };
+      if *bit != wanted {
+        pixels.set_sample(index, *other as u16);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Reads `len` bytes from the paired pixels of `frames`, in the order
+/// `embed` writes them
+pub fn extract(
+  frames: &[Pixels],
+  palette: &Palette,
+  len: usize,
+) -> Result<Vec<u8>> {
+  if len > capacity(frames, palette)? {
+    return Err("Payload is larger than the palette capacity".into());
+  }
+
+  let pairs = pairs(palette);
+  let mut bits = frames.iter().flat_map(|pixels| {
+    (0..pixels.sample_count())
+      .filter_map(|index| pairs.get(pixels.sample(index) as usize).copied())
+      .flatten()
+      .map(|(bit, _)| bit)
+  });
+  Ok(
+    (0..len)
+      .map(|_| bits.by_ref().take(8).fold(0, |byte, bit| (byte << 1) | bit))
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use crate::chunk_type::ChunkType;
+  use crate::png::FilterStrategy;
+  use crate::{scan, wipe, Mode};
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+  }
+
+  /// A 32x32 image drawn with the given `colors` in turn
+  fn indexed_png(bit_depth: u8, colors: &[[u8; 3]]) -> Png {
+    let header = ImageHeader {
+      width: 32,
+      height: 32,
+      bit_depth,
+      color_type: ColorType::Indexed,
+      interlaced: false,
+    };
+    let len = header.row_bytes(32) * 32;
+    let mut pixels = Pixels::new(header, vec![0; len]).unwrap();
+    for index in 0..pixels.sample_count() {
+      pixels.set_sample(index, (index * 7 % colors.len()) as u16);
+    }
+    Png::from_chunks(vec![
+      header.as_chunk(),
+      chunk("PLTE", colors.concat()),
+      chunk("IDAT", pixels.encode(FilterStrategy::default())),
+      chunk("IEND", Vec::new()),
+    ])
+  }
+
+  /// What `png` looks like: the colour of every pixel
+  fn colors(png: &Png) -> Vec<[u8; 4]> {
+    let palette = png.palette().unwrap();
+    let pixels = png.decode_pixels().unwrap();
+    (0..pixels.sample_count())
+      .map(|index| palette.entries()[pixels.sample(index) as usize])
+      .collect()
+  }
+
+  #[test]
+  fn test_doubled_palette_looks_the_same() {
+    // Two colours at 1 bit per pixel need a larger bit depth to double
+    let mut png = indexed_png(1, &[[0, 0, 0], [255, 255, 255]]);
+    let before = colors(&png);
+    let palette = plan(&png).unwrap();
+    assert_eq!(palette.len(), 4);
+
+    let mut frames = png.decode_frames().unwrap();
+    install(&mut png, &mut frames, &palette).unwrap();
+    assert_eq!(png.image_header().unwrap().bit_depth, 2);
+    assert_eq!(capacity(&frames, &palette).unwrap(), 32 * 32 / 8);
+
+    let payload = (0..128).map(|i| (i * 37) as u8).collect::<Vec<_>>();
+    embed(&mut frames, &palette, &payload).unwrap();
+    png
+      .encode_frames(&frames, FilterStrategy::default())
+      .unwrap();
+    assert_eq!(colors(&png), before);
+    let frames = png.decode_frames().unwrap();
+    let palette = png.palette().unwrap();
+    assert_eq!(extract(&frames, &palette, 128).unwrap(), payload);
+    assert_eq!(plan(&png).unwrap(), palette);
+  }
+
+  #[test]
+  fn test_full_palette_pairs_close_colours() {
+    // 256 greys two apart pair up; a full palette with distant colours
+    // cannot carry anything
+    let greys = (0..=255).map(|i| [i, i, i]).collect::<Vec<_>>();
+    let png = indexed_png(8, &greys);
+    let palette = plan(&png).unwrap();
+    assert_eq!(palette.len(), 256);
+    let mut frames = png.decode_frames().unwrap();
+    assert_eq!(capacity(&frames, &palette).unwrap(), 32 * 32 / 8);
+
+    let before = frames.clone();
+    embed(&mut frames, &palette, b"greys").unwrap();
+    for (index, sample) in (0..before[0].sample_count())
+      .map(|index| (index, before[0].sample(index)))
+    {
+      assert!(frames[0].sample(index).abs_diff(sample) <= 1);
+    }
+    assert_eq!(extract(&frames, &palette, 5).unwrap(), b"greys");
+
+    let spread = (0..=255u8)
+      .map(|i| [i, i.wrapping_mul(97), i.wrapping_mul(31)])
+      .collect::<Vec<_>>();
+    let png = indexed_png(8, &spread);
+    let palette = plan(&png).unwrap();
+    let frames = png.decode_frames().unwrap();
+    assert!(capacity(&frames, &palette).unwrap() < 32 * 32 / 8);
+  }
+
+  #[test]
+  fn test_palette_mode() {
+    let mut png = indexed_png(4, &[[10, 20, 30], [200, 100, 0], [0, 0, 255]]);
+    let before = colors(&png);
+    let encoder = crate::Encoder::new("password").mode(Mode::Palette);
+    assert_eq!(encoder.capacity(&png).unwrap().raw, 32 * 32 / 8);
+    encoder.encode(&mut png, "Indexed").unwrap();
+    assert_eq!(colors(&png), before);
+
+    let decoder = crate::Decoder::new("password").mode(Mode::Palette);
+    assert_eq!(decoder.decode(&png).unwrap().message, "Indexed");
+    let findings = scan::scan(&png);
+    assert_eq!(findings[0].location, scan::Location::Palette);
+
+    let wiped = wipe::wipe(&mut png, &[]).unwrap();
+    assert!(wiped.palette);
+    assert_eq!(colors(&png), before);
+    assert!(decoder.decode(&png).is_err());
+
+    let mut rgb = Png::from_file(&"tests/cat.png".into()).unwrap();
+    assert!(encoder.encode(&mut rgb, "Indexed").is_err());
+  }
+}
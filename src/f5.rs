@@ -0,0 +1,269 @@
+use crate::jpeg::{Block, Jpeg};
+use crate::Result;
+
+/// The number of bits before the payload that give the `k` of the matrix
+/// code it was embedded with, themselves embedded with `k = 1`
+const HEADER_BITS: usize = 4;
+
+/// The largest `k` tried, which hides 7 bits in every 127 coefficients
+const MAX_K: usize = 7;
+
+/// Seeds the order coefficients are visited in. It is fixed, so reading a
+/// message needs nothing but the image; the payload is encrypted anyway.
+const ORDER_SEED: u64 = 0x6361_6368_6574_7465;
+
+/// The bit a non-zero coefficient carries: its lowest bit, inverted for
+/// negative coefficients, so that shrinking either towards zero flips it
+fn carried_bit(coefficient: i16) -> usize {
+  (coefficient & 1) as usize ^ (coefficient < 0) as usize
+}
+
+fn coefficient(blocks: &[Block], position: usize) -> i16 {
+  blocks[position / 64][position % 64]
+}
+
+/// The position of every AC coefficient, as `block * 64 + index`, shuffled
+/// so that the changes spread over the whole image rather than its top
+fn positions(block_count: usize) -> Vec<usize> {
+  let mut positions = (0..block_count)
+    .flat_map(|block| (1..64).map(move |index| block * 64 + index))
+    .collect::<Vec<_>>();
+  // SplitMix64, which is plenty for a shuffle that hides nothing
+  let mut state = ORDER_SEED;
+  let mut next = || {
+    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  };
+  for i in (1..positions.len()).rev() {
+    positions.swap(i, (next() % (i as u64 + 1)) as usize);
+  }
+  positions
+}
+
+/// The number of non-zero AC coefficients and how many of them are ±1
+fn statistics(blocks: &[Block]) -> (usize, usize) {
+  let ac = blocks.iter().flat_map(|block| &block[1..]);
+  ac.fold((0, 0), |(non_zero, ones), &c| {
+    (non_zero + (c != 0) as usize, ones + (c.abs() == 1) as usize)
+  })
+}
+
+/// The number of bits `k = 1` is expected to hide: one in every non-zero
+/// coefficient, less the ±1 coefficients that shrink to zero when changed,
+/// which is half of them
+fn expected_bits(blocks: &[Block]) -> usize {
+  let (non_zero, ones) = statistics(blocks);
+  (non_zero - ones / 2).saturating_sub(HEADER_BITS)
+}
+
+/// The number of whole bytes F5 is expected to hide in `jpeg`. Embedding
+/// may still fall short near the limit, as shrinkage depends on the data.
+pub fn capacity(jpeg: &Jpeg) -> usize {
+  expected_bits(jpeg.blocks()) / 8
+}
+
+/// Hides the `k` bits of `value` in the next `2^k - 1` non-zero
+/// coefficients from `cursor`, changing at most one of them. If the change
+/// shrinks a coefficient to zero it carries nothing, so the group is taken
+/// again without it. Returns false when the coefficients run out.
+fn embed_group(
+  blocks: &mut [Block],
+  order: &[usize],
+  cursor: &mut usize,
+  k: usize,
+  value: usize,
+) -> bool {
+  let n = (1 << k) - 1;
+  let mut group = Vec::with_capacity(n);
+  loop {
+    let start = *cursor;
+    group.clear();
+    while group.len() < n {
+      let Some(&position) = order.get(*cursor) else {
+        return false;
+      };
+      *cursor += 1;
+      if coefficient(blocks, position) != 0 {
+        group.push(position);
+      }
+    }
+    let change = hash(blocks, &group) ^ value;
+    if change == 0 {
+      return true;
+    }
+    let position = group[change - 1];
+    let c = &mut blocks[position / 64][position % 64];
+    *c -= c.signum();
+    if *c != 0 {
+      return true;
+    }
+    *cursor = start;
+  }
+}
+
+/// The XOR of the 1-based indices in `group` of the coefficients that carry
+/// a one, which is what a group of the matrix code stores
+fn hash(blocks: &[Block], group: &[usize]) -> usize {
+  group
+    .iter()
+    .enumerate()
+    .filter(|(_, &position)| carried_bit(coefficient(blocks, position)) == 1)
+    .fold(0, |hash, (index, _)| hash ^ (index + 1))
+}
+
+/// Embeds `payload` with the matrix code `k` into `blocks`, returning
+/// false if it does not fit
+fn embed_with(
+  blocks: &mut [Block],
+  order: &[usize],
+  k: usize,
+  payload: &[u8],
+) -> bool {
+  let mut cursor = 0;
+  let mut header = (0..HEADER_BITS).rev().map(|i| (k >> i) & 1);
+  if !header.all(|bit| embed_group(blocks, order, &mut cursor, 1, bit)) {
+    return false;
+  }
+  let bits = payload
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| (*byte as usize >> i) & 1))
+    .collect::<Vec<_>>();
+  bits.chunks(k).all(|chunk| {
+    let value = chunk.iter().fold(0, |value, bit| (value << 1) | bit);
+    let value = value << (k - chunk.len());
+    embed_group(blocks, order, &mut cursor, k, value)
+  })
+}
+
+/// Hides `payload` in the non-zero AC coefficients of `jpeg` with F5: a
+/// bit is changed by moving a coefficient one step towards zero, and matrix
+/// embedding hides `k` bits in every `2^k - 1` coefficients by changing at
+/// most one. The largest `k` the payload is expected to fit with is tried
+/// first, as it changes the fewest coefficients.
+pub fn embed(jpeg: &mut Jpeg, payload: &[u8]) -> Result<()> {
+  let order = positions(jpeg.blocks().len());
+  let available = expected_bits(jpeg.blocks());
+  let needed = payload.len() * 8;
+  let best_k = (1..=MAX_K)
+    .rev()
+    .find(|&k| available * k / ((1 << k) - 1) >= needed)
+    .unwrap_or(1);
+  for k in (1..=best_k).rev() {
+    let mut blocks = jpeg.blocks().to_vec();
+    if embed_with(&mut blocks, &order, k, payload) {
+      jpeg.blocks_mut().copy_from_slice(&blocks);
+      return Ok(());
+    }
+  }
+  Err("Payload does not fit in the image".into())
+}
+
+/// Reads `len` bytes hidden by `embed` from the coefficients of `jpeg`
+pub fn extract(jpeg: &Jpeg, len: usize) -> Result<Vec<u8>> {
+  let blocks = jpeg.blocks();
+  let carriers = positions(blocks.len())
+    .into_iter()
+    .filter(|&position| coefficient(blocks, position) != 0)
+    .collect::<Vec<_>>();
+  let Some((header, carriers)) = carriers.split_at_checked(HEADER_BITS) else {
+    return Err("No message found in the DCT coefficients".into());
+  };
+  let k = header.iter().fold(0, |k, &position| {
+    (k << 1) | carried_bit(coefficient(blocks, position))
+  });
+  if !(1..=MAX_K).contains(&k) {
+    return Err("No message found in the DCT coefficients".into());
+  }
+
+  // Every group of n carriers holds k bits, so a length read from a
+  // damaged header is refused before anything is allocated for it
+  let n = (1 << k) - 1;
+  let available = carriers.len() / n * k;
+  let wanted = len.checked_mul(8).filter(|&bits| bits <= available);
+  let Some(wanted) = wanted else {
+    return Err("Payload is larger than the image capacity".into());
+  };
+  let mut bits = Vec::with_capacity(wanted + k);
+  for group in carriers.chunks_exact(n) {
+    if bits.len() >= wanted {
+      break;
+    }
+    let hash = hash(blocks, group);
+    bits.extend((0..k).rev().map(|i| (hash >> i) & 1));
+  }
+  Ok(
+    bits[..wanted]
+      .chunks(8)
+      .map(|bits| bits.iter().fold(0, |byte, &bit| (byte << 1) | bit as u8))
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cat() -> Jpeg {
+    Jpeg::try_from(std::fs::read("tests/cat.jpg").unwrap().as_slice()).unwrap()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for len in [1, 16, capacity(&cat()) / 2] {
+      let mut jpeg = cat();
+      let payload = (0..len).map(|i| (i * 37 % 256) as u8).collect::<Vec<_>>();
+      embed(&mut jpeg, &payload).unwrap();
+      assert_eq!(extract(&jpeg, len).unwrap(), payload);
+
+      // Only AC coefficients move, and only towards zero
+      let original = cat();
+      for (before, after) in original.blocks().iter().zip(jpeg.blocks()) {
+        assert_eq!(before[0], after[0]);
+        for (b, a) in before.iter().zip(after) {
+          assert!(a.abs() <= b.abs() && b.abs() - a.abs() <= 1);
+          assert!(*a == 0 || a.signum() == b.signum());
+        }
+      }
+      let bytes = crate::CoverImage::as_bytes(&jpeg);
+      let copy = Jpeg::try_from(bytes.as_slice()).unwrap();
+      assert_eq!(extract(&copy, len).unwrap(), payload);
+    }
+  }
+
+  #[test]
+  fn test_matrix_embedding_changes_less() {
+    let payload = [0x5A; 16];
+    let changes = |jpeg: &Jpeg| {
+      let original = cat();
+      let pairs = original.blocks().iter().zip(jpeg.blocks());
+      pairs
+        .flat_map(|(before, after)| before.iter().zip(after))
+        .filter(|(b, a)| b != a)
+        .count()
+    };
+    let mut plain = cat();
+    let order = positions(plain.blocks().len());
+    assert!(embed_with(plain.blocks_mut(), &order, 1, &payload));
+    let mut matrix = cat();
+    embed(&mut matrix, &payload).unwrap();
+    assert!(changes(&matrix) < changes(&plain));
+    assert_eq!(extract(&matrix, 16).unwrap(), payload);
+  }
+
+  #[test]
+  fn test_too_long() {
+    let mut jpeg = cat();
+    let payload = vec![0xFF; capacity(&jpeg) * 2];
+    assert!(embed(&mut jpeg, &payload).is_err());
+    assert_eq!(jpeg, cat());
+
+    // A length read from a damaged header fails instead of being allocated
+    embed(&mut jpeg, b"short").unwrap();
+    for len in [capacity(&jpeg) * 2, 5_713_711_966, usize::MAX] {
+      assert!(extract(&jpeg, len).is_err());
+    }
+  }
+}
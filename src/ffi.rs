@@ -0,0 +1,449 @@
+//! C bindings over byte buffers. `include/cachette.h` is generated from this
+//! module by cbindgen; regenerate it with
+//! `CACHETTE_BLESS=1 cargo test --test ffi` after changing anything here.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, UnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+
+use crate::{ChunkType, Decoder, EccLevel, Encoder, Error, Mode, Png};
+
+/// Hide the message in a chunk of its own
+pub const CACHETTE_MODE_CHUNK: u32 = 0;
+/// Hide the message in the low bits of the pixels
+pub const CACHETTE_MODE_LSB: u32 = 1;
+/// Hide the message in the paired colours of the palette of an
+/// indexed-colour image
+pub const CACHETTE_MODE_PALETTE: u32 = 2;
+/// Hide the message in how the image data is compressed
+pub const CACHETTE_MODE_DEFLATE: u32 = 3;
+/// Hide the message in the colour parity of the pixels, so that it survives
+/// lossless re-encoding
+pub const CACHETTE_MODE_ROBUST: u32 = 4;
+/// Hide the message in the DCT coefficients of a JPEG image
+pub const CACHETTE_MODE_F5: u32 = 5;
+
+pub const CACHETTE_ECC_NONE: u32 = 0;
+pub const CACHETTE_ECC_LOW: u32 = 1;
+pub const CACHETTE_ECC_MEDIUM: u32 = 2;
+pub const CACHETTE_ECC_HIGH: u32 = 3;
+
+/// The outcome of every `cachette_*` call. Anything other than
+/// `CACHETTE_STATUS_OK` comes with a description from `cachette_last_error`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachetteStatus {
+  Ok = 0,
+  /// A required pointer was null
+  NullPointer = 1,
+  /// A string was not valid UTF-8
+  InvalidUtf8 = 2,
+  /// The input is not a valid PNG
+  InvalidPng = 3,
+  /// The chunk type is not four valid ASCII letters
+  InvalidChunkType = 4,
+  /// The options are out of range or do not suit the image
+  InvalidOptions = 5,
+  /// The message does not fit in the image
+  MessageTooLong = 6,
+  /// No message was found, or it could not be decrypted with the password
+  DecodeFailed = 7,
+  /// cachette panicked; this is a bug. Only builds that unwind on panic,
+  /// such as the `ffi` profile, return it: the others abort the process
+  Panic = 8,
+}
+
+/// Where and how messages are hidden
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CachetteOptions {
+  /// One of the `CACHETTE_MODE_*` constants
+  pub mode: u32,
+  /// How many low bits of each colour sample LSB mode uses
+  pub bits_per_channel: u8,
+  /// One of the `CACHETTE_ECC_*` constants
+  pub ecc: u32,
+}
+
+/// Bytes allocated by cachette. `data` is followed by a NUL byte that `len`
+/// does not count, so text can be used as a C string. Release it with
+/// `cachette_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CachetteBuffer {
+  pub data: *mut u8,
+  pub len: usize,
+}
+
+impl CachetteBuffer {
+  fn empty() -> Self {
+    Self {
+      data: ptr::null_mut(),
+      len: 0,
+    }
+  }
+
+  fn from_vec(mut bytes: Vec<u8>) -> Self {
+    let len = bytes.len();
+    bytes.push(0);
+    let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+    Self { data, len }
+  }
+}
+
+type Failure = (CachetteStatus, Error);
+
+thread_local! {
+  static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: &str) {
+  let message =
+    CString::new(message.replace('\0', " ")).expect("NUL bytes were replaced");
+  LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Runs `body`, recording its error for `cachette_last_error` and turning
+/// panics into `CACHETTE_STATUS_PANIC` so they never unwind into C. Under
+/// `panic = "abort"`, as in the release profile, there is nothing to catch.
+fn guard(
+  body: impl FnOnce() -> Result<(), Failure> + UnwindSafe,
+) -> CachetteStatus {
+  match panic::catch_unwind(body) {
+    Ok(Ok(())) => {
+      set_last_error("");
+      CachetteStatus::Ok
+    }
+    Ok(Err((status, error))) => {
+      set_last_error(&error.to_string());
+      status
+    }
+    Err(_) => {
+      set_last_error("cachette panicked");
+      CachetteStatus::Panic
+    }
+  }
+}
+
+fn fail<E: Into<Error>>(status: CachetteStatus) -> impl FnOnce(E) -> Failure {
+  move |error| (status, error.into())
+}
+
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, or be null
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+  if data.is_null() {
+    return Err((CachetteStatus::NullPointer, "PNG buffer is null".into()));
+  }
+  Ok(std::slice::from_raw_parts(data, len))
+}
+
+/// # Safety
+///
+/// `string` must be a NUL-terminated string, or null
+unsafe fn text<'a>(
+  string: *const c_char,
+  name: &str,
+) -> Result<&'a str, Failure> {
+  if string.is_null() {
+    return Err((
+      CachetteStatus::NullPointer,
+      format!("{} is null", name).into(),
+    ));
+  }
+  CStr::from_ptr(string)
+    .to_str()
+    .map_err(fail(CachetteStatus::InvalidUtf8))
+}
+
+fn parse_png(png: &[u8]) -> Result<Png, Failure> {
+  Png::try_from(png).map_err(fail(CachetteStatus::InvalidPng))
+}
+
+fn parse_chunk_type(chunk_type: &str) -> Result<ChunkType, Failure> {
+  ChunkType::from_str(chunk_type)
+    .map_err(fail(CachetteStatus::InvalidChunkType))
+}
+
+/// # Safety
+///
+/// `options` must point to valid `CachetteOptions`, or be null
+unsafe fn parse_options(
+  options: *const CachetteOptions,
+) -> Result<(Mode, u8, EccLevel), Failure> {
+  let options = match options.as_ref() {
+    Some(options) => *options,
+    None => cachette_default_options(),
+  };
+  let mode = match options.mode {
+    CACHETTE_MODE_CHUNK => Mode::Chunk,
+    CACHETTE_MODE_LSB => Mode::Lsb,
+    CACHETTE_MODE_PALETTE => Mode::Palette,
+    CACHETTE_MODE_DEFLATE => Mode::Deflate,
+    CACHETTE_MODE_ROBUST => Mode::Robust,
+    CACHETTE_MODE_F5 => Mode::F5,
+    _ => return Err((CachetteStatus::InvalidOptions, "Unknown mode".into())),
+  };
+  let ecc = match options.ecc {
+    CACHETTE_ECC_NONE => EccLevel::None,
+    CACHETTE_ECC_LOW => EccLevel::Low,
+    CACHETTE_ECC_MEDIUM => EccLevel::Medium,
+    CACHETTE_ECC_HIGH => EccLevel::High,
+    _ => {
+      return Err((CachetteStatus::InvalidOptions, "Unknown ECC level".into()))
+    }
+  };
+  Ok((mode, options.bits_per_channel, ecc))
+}
+
+/// # Safety
+///
+/// `out` must point to a writable `CachetteBuffer`, or be null
+unsafe fn write_output(
+  out: *mut CachetteBuffer,
+  bytes: Vec<u8>,
+) -> Result<(), Failure> {
+  let out = out
+    .as_mut()
+    .ok_or((CachetteStatus::NullPointer, "Output buffer is null".into()))?;
+  *out = CachetteBuffer::from_vec(bytes);
+  Ok(())
+}
+
+/// Chunk mode, one bit per channel and no error correction
+#[no_mangle]
+pub extern "C" fn cachette_default_options() -> CachetteOptions {
+  CachetteOptions {
+    mode: CACHETTE_MODE_CHUNK,
+    bits_per_channel: 1,
+    ecc: CACHETTE_ECC_NONE,
+  }
+}
+
+/// Hides `message` in the PNG held by `png`, writing the resulting PNG to
+/// `out`. `options` may be null to use `cachette_default_options`.
+///
+/// # Safety
+///
+/// `png` must point to `png_len` readable bytes, the strings must be
+/// NUL-terminated, `options` must be valid or null and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn cachette_encode_buffer(
+  png: *const u8,
+  png_len: usize,
+  chunk_type: *const c_char,
+  message: *const c_char,
+  password: *const c_char,
+  options: *const CachetteOptions,
+  out: *mut CachetteBuffer,
+) -> CachetteStatus {
+  guard(move || {
+    let mut png = parse_png(bytes(png, png_len)?)?;
+    let chunk_type = parse_chunk_type(text(chunk_type, "Chunk type")?)?;
+    let message = text(message, "Message")?;
+    let password = text(password, "Password")?;
+    let (mode, bits_per_channel, ecc) = parse_options(options)?;
+
+    let encoder = Encoder::new(password)
+      .chunk_type(chunk_type)
+      .mode(mode)
+      .bits_per_channel(bits_per_channel)
+      .ecc(ecc);
+    encoder
+      .capacity(&png)
+      .map_err(fail(CachetteStatus::InvalidOptions))?
+      .check(message.len())
+      .map_err(fail(CachetteStatus::MessageTooLong))?;
+    encoder
+      .encode(&mut png, message)
+      .map_err(fail(CachetteStatus::InvalidPng))?;
+    write_output(out, png.as_bytes())
+  })
+}
+
+/// Recovers the message hidden in the PNG held by `png`, writing it to `out`
+/// as a NUL-terminated string. `options` may be null to use
+/// `cachette_default_options`; only `mode` and `bits_per_channel` are used.
+///
+/// # Safety
+///
+/// `png` must point to `png_len` readable bytes, the strings must be
+/// NUL-terminated, `options` must be valid or null and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn cachette_decode_buffer(
+  png: *const u8,
+  png_len: usize,
+  chunk_type: *const c_char,
+  password: *const c_char,
+  options: *const CachetteOptions,
+  out: *mut CachetteBuffer,
+) -> CachetteStatus {
+  guard(move || {
+    let png = parse_png(bytes(png, png_len)?)?;
+    let chunk_type = parse_chunk_type(text(chunk_type, "Chunk type")?)?;
+    let password = text(password, "Password")?;
+    let (mode, bits_per_channel, _) = parse_options(options)?;
+
+    let decoded = Decoder::new(password)
+      .chunk_type(chunk_type)
+      .mode(mode)
+      .bits_per_channel(bits_per_channel)
+      .decode(&png)
+      .map_err(fail(CachetteStatus::DecodeFailed))?;
+    write_output(out, decoded.message.into_bytes())
+  })
+}
+
+/// Lists the chunks of the PNG held by `png` in `out`, one
+/// `<type> <length>` line per chunk
+///
+/// # Safety
+///
+/// `png` must point to `png_len` readable bytes and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn cachette_inspect(
+  png: *const u8,
+  png_len: usize,
+  out: *mut CachetteBuffer,
+) -> CachetteStatus {
+  guard(move || {
+    let png = parse_png(bytes(png, png_len)?)?;
+    let report = png
+      .chunks()
+      .iter()
+      .map(|chunk| format!("{} {}\n", chunk.chunk_type(), chunk.length()))
+      .collect::<String>();
+    write_output(out, report.into_bytes())
+  })
+}
+
+/// Releases a buffer returned by cachette and resets it to empty. Freeing an
+/// empty buffer does nothing.
+///
+/// # Safety
+///
+/// `buffer` must be null or hold a buffer returned by cachette that has not
+/// been modified.
+#[no_mangle]
+pub unsafe extern "C" fn cachette_free(buffer: *mut CachetteBuffer) {
+  let Some(buffer) = buffer.as_mut() else {
+    return;
+  };
+  if !buffer.data.is_null() {
+    let slice = ptr::slice_from_raw_parts_mut(buffer.data, buffer.len + 1);
+    drop(Box::from_raw(slice));
+  }
+  *buffer = CachetteBuffer::empty();
+}
+
+/// Describes why the last call on this thread failed, or returns an empty
+/// string if it succeeded. The string stays valid until the next call.
+#[no_mangle]
+pub extern "C" fn cachette_last_error() -> *const c_char {
+  LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PASSWORD: &CStr = c"bXR3be-ujj!hP@NDaTUnnBuBQ";
+  const CHUNK_TYPE: &CStr = c"ruSt";
+
+  fn last_error() -> String {
+    let error = unsafe { CStr::from_ptr(cachette_last_error()) };
+    error.to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let png = std::fs::read("tests/cat.png").unwrap();
+    let mut encoded = CachetteBuffer::empty();
+    let mut decoded = CachetteBuffer::empty();
+    unsafe {
+      let status = cachette_encode_buffer(
+        png.as_ptr(),
+        png.len(),
+        CHUNK_TYPE.as_ptr(),
+        c"Hello from C".as_ptr(),
+        PASSWORD.as_ptr(),
+        ptr::null(),
+        &mut encoded,
+      );
+      assert_eq!(status, CachetteStatus::Ok);
+
+      let status = cachette_decode_buffer(
+        encoded.data,
+        encoded.len,
+        CHUNK_TYPE.as_ptr(),
+        PASSWORD.as_ptr(),
+        ptr::null(),
+        &mut decoded,
+      );
+      assert_eq!(status, CachetteStatus::Ok);
+      assert_eq!(
+        CStr::from_ptr(decoded.data as *const c_char),
+        c"Hello from C"
+      );
+
+      cachette_free(&mut encoded);
+      cachette_free(&mut decoded);
+    }
+    assert!(encoded.data.is_null());
+    assert_eq!(last_error(), "");
+  }
+
+  #[test]
+  fn test_errors() {
+    let png = std::fs::read("tests/cat.png").unwrap();
+    let mut out = CachetteBuffer::empty();
+    unsafe {
+      let status = cachette_inspect(png.as_ptr(), 10, &mut out);
+      assert_eq!(status, CachetteStatus::InvalidPng);
+      assert!(!last_error().is_empty());
+
+      let status = cachette_decode_buffer(
+        png.as_ptr(),
+        png.len(),
+        c"ru1t".as_ptr(),
+        PASSWORD.as_ptr(),
+        ptr::null(),
+        &mut out,
+      );
+      assert_eq!(status, CachetteStatus::InvalidChunkType);
+
+      let status = cachette_decode_buffer(
+        png.as_ptr(),
+        png.len(),
+        CHUNK_TYPE.as_ptr(),
+        PASSWORD.as_ptr(),
+        ptr::null(),
+        &mut out,
+      );
+      assert_eq!(status, CachetteStatus::DecodeFailed);
+
+      let options = CachetteOptions {
+        mode: CACHETTE_MODE_LSB,
+        bits_per_channel: 9,
+        ecc: CACHETTE_ECC_NONE,
+      };
+      let status = cachette_encode_buffer(
+        png.as_ptr(),
+        png.len(),
+        CHUNK_TYPE.as_ptr(),
+        c"message".as_ptr(),
+        PASSWORD.as_ptr(),
+        &options,
+        &mut out,
+      );
+      assert_eq!(status, CachetteStatus::InvalidOptions);
+
+      let status = cachette_inspect(ptr::null(), 0, &mut out);
+      assert_eq!(status, CachetteStatus::NullPointer);
+    }
+    assert!(out.data.is_null());
+  }
+}
@@ -0,0 +1,139 @@
+/// The primitive polynomial x^8 + x^4 + x^3 + x^2 + 1 used to build GF(2^8)
+const PRIMITIVE: u16 = 0x11d;
+
+/// Powers of the generator 2, repeated once so that products of two
+/// logarithms can index the table without a modulo
+const EXP: [u8; 512] = build_tables().0;
+
+/// Discrete logarithms base 2; `LOG[0]` is unused
+const LOG: [u8; 256] = build_tables().1;
+
+const fn build_tables() -> ([u8; 512], [u8; 256]) {
+  let mut exp = [0u8; 512];
+  let mut log = [0u8; 256];
+  let mut x: u16 = 1;
+  let mut i = 0;
+  while i < 255 {
+    exp[i] = x as u8;
+    log[x as usize] = i as u8;
+    x <<= 1;
+    if x & 0x100 != 0 {
+      x ^= PRIMITIVE;
+    }
+    i += 1;
+  }
+  while i < 512 {
+    exp[i] = exp[i - 255];
+    i += 1;
+  }
+  (exp, log)
+}
+
+/// Returns 2 raised to `power`, which may be negative
+pub fn exp(power: i32) -> u8 {
+  EXP[power.rem_euclid(255) as usize]
+}
+
+/// Multiplies two field elements
+pub fn mul(a: u8, b: u8) -> u8 {
+  if a == 0 || b == 0 {
+    return 0;
+  }
+  EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+/// Divides `a` by the non-zero element `b`
+pub fn div(a: u8, b: u8) -> u8 {
+  assert!(b != 0, "division by zero in GF(256)");
+  if a == 0 {
+    return 0;
+  }
+  EXP[LOG[a as usize] as usize + 255 - LOG[b as usize] as usize]
+}
+
+/// Returns the multiplicative inverse of the non-zero element `a`
+pub fn inverse(a: u8) -> u8 {
+  div(1, a)
+}
+
+/// Polynomials are stored with the highest degree coefficient first
+pub mod poly {
+  use super::mul;
+
+  /// Multiplies every coefficient of `p` by `x`
+  pub fn scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+  }
+
+  /// Adds (and, in GF(2^8), subtracts) two polynomials
+  pub fn add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0u8; len];
+    for (i, &c) in p.iter().enumerate() {
+      r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+      r[i + len - q.len()] ^= c;
+    }
+    r
+  }
+
+  /// Multiplies two polynomials
+  pub fn mul_poly(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len() + q.len() - 1];
+    for (j, &b) in q.iter().enumerate() {
+      for (i, &a) in p.iter().enumerate() {
+        r[i + j] ^= mul(a, b);
+      }
+    }
+    r
+  }
+
+  /// Divides `dividend` by the monic polynomial `divisor` and returns the
+  /// quotient and the remainder
+  pub fn div(dividend: &[u8], divisor: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut out = dividend.to_vec();
+    for i in 0..dividend.len().saturating_sub(divisor.len() - 1) {
+      let coef = out[i];
+      if coef != 0 {
+        for (j, &d) in divisor.iter().enumerate().skip(1) {
+          out[i + j] ^= mul(d, coef);
+        }
+      }
+    }
+    let separator = out.len() - (divisor.len() - 1);
+    let remainder = out.split_off(separator);
+    (out, remainder)
+  }
+
+  /// Evaluates `p` at `x` using Horner's method
+  pub fn eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |acc, &c| mul(acc, x) ^ c)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_field_identities() {
+    for a in 1..=255u8 {
+      assert_eq!(mul(a, inverse(a)), 1);
+      assert_eq!(div(mul(a, 7), 7), a);
+    }
+    assert_eq!(mul(0, 9), 0);
+    assert_eq!(exp(-1), inverse(2));
+  }
+
+  #[test]
+  fn test_poly_div() {
+    // (x + 3)(x + 5) + 1 divided by (x + 3)
+    let product = poly::mul_poly(&[1, 3], &[1, 5]);
+    let dividend = poly::add(&product, &[1]);
+    let (quotient, remainder) = poly::div(&dividend, &[1, 3]);
+    assert_eq!(quotient, vec![1, 5]);
+    assert_eq!(remainder, vec![1]);
+    assert_eq!(poly::eval(&quotient, 5), 0);
+  }
+}
@@ -0,0 +1,875 @@
+use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::png::{FilterStrategy, Pixels};
+use crate::Error;
+use crate::Result;
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DRI: u8 = 0xDD;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+const TEM: u8 = 0x01;
+
+/// The quantised DCT coefficients of an 8x8 block, in zigzag order. The
+/// first is the DC coefficient, the other 63 the AC coefficients.
+pub type Block = [i16; 64];
+
+/// A marker segment: its marker byte and the data after its length
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+  marker: u8,
+  data: Vec<u8>,
+}
+
+impl Segment {
+  fn write_to(&self, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&[0xFF, self.marker]);
+    bytes.extend_from_slice(&(self.data.len() as u16 + 2).to_be_bytes());
+    bytes.extend_from_slice(&self.data);
+  }
+}
+
+/// A Huffman table, as stored in a DHT segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HuffmanTable {
+  /// How many codes there are of each length, from 1 to 16 bits
+  counts: [u8; 16],
+  /// The symbols, in the order of their codes
+  symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+  /// The code and length in bits of every symbol the table holds
+  fn codes(&self) -> Vec<Option<(u16, u8)>> {
+    let mut codes = vec![None; 256];
+    let mut symbols = self.symbols.iter();
+    let mut code = 0u32;
+    for (len, &count) in (1..=16).zip(&self.counts) {
+      for symbol in symbols.by_ref().take(count as usize) {
+        codes[*symbol as usize] = Some((code as u16, len));
+        code += 1;
+      }
+      code <<= 1;
+    }
+    codes
+  }
+
+  /// The table that codes symbols seen with `frequencies` in the fewest
+  /// bits, no code longer than 16 bits nor made of ones only, following
+  /// annex K.2 of the JPEG spec
+  fn optimal(frequencies: &[u32; 256]) -> Self {
+    // Symbol 256 is reserved so that no real symbol gets the all-ones code
+    let mut freq = frequencies.map(u64::from).to_vec();
+    freq.push(1);
+    let mut code_size = [0usize; 257];
+    let mut others = [None::<usize>; 257];
+    loop {
+      // The two least frequent symbols, ties going to the highest one
+      let least = |skip: Option<usize>| {
+        (0..257)
+          .filter(|&i| freq[i] > 0 && Some(i) != skip)
+          .min_by_key(|&i| (freq[i], std::cmp::Reverse(i)))
+      };
+      let (Some(mut v1), Some(mut v2)) = (least(None), least(least(None)))
+      else {
+        break;
+      };
+      freq[v1] += freq[v2];
+      freq[v2] = 0;
+      code_size[v1] += 1;
+      while let Some(next) = others[v1] {
+        v1 = next;
+        code_size[v1] += 1;
+      }
+      others[v1] = Some(v2);
+      code_size[v2] += 1;
+      while let Some(next) = others[v2] {
+        v2 = next;
+        code_size[v2] += 1;
+      }
+    }
+
+    let mut bits = vec![0usize; code_size.iter().max().unwrap_or(&0) + 1];
+    bits.resize(bits.len().max(17), 0);
+    for &size in code_size.iter().filter(|&&size| size > 0) {
+      bits[size] += 1;
+    }
+    // Codes longer than 16 bits are shortened by pairing them off
+    for i in (17..bits.len()).rev() {
+      while bits[i] > 0 {
+        let mut j = i - 2;
+        while bits[j] == 0 {
+          j -= 1;
+        }
+        bits[i] -= 2;
+        bits[i - 1] += 1;
+        bits[j + 1] += 2;
+        bits[j] -= 1;
+      }
+    }
+    let longest = (1..=16).rev().find(|&i| bits[i] > 0).unwrap_or(1);
+    bits[longest] = bits[longest].saturating_sub(1);
+
+    let mut symbols =
+      (0..256).filter(|&i| code_size[i] > 0).collect::<Vec<_>>();
+    symbols.sort_by_key(|&i| code_size[i]);
+    let mut counts = [0; 16];
+    for (count, bits) in counts.iter_mut().zip(&bits[1..=16]) {
+      *count = *bits as u8;
+    }
+    Self {
+      counts,
+      symbols: symbols.into_iter().map(|i| i as u8).collect(),
+    }
+  }
+}
+
+/// Decodes symbols with a Huffman table, per annex F.2.2.3 of the spec
+struct HuffmanDecoder<'a> {
+  symbols: &'a [u8],
+  /// The largest code of each length, or -1 if there is none
+  max_code: [i32; 17],
+  /// What to add to a code of each length to find its symbol's index
+  offset: [i32; 17],
+}
+
+impl<'a> HuffmanDecoder<'a> {
+  fn new(table: &'a HuffmanTable) -> Self {
+    let mut max_code = [-1; 17];
+    let mut offset = [0; 17];
+    let (mut code, mut index) = (0, 0);
+    for len in 1..=16 {
+      let count = table.counts[len - 1] as i32;
+      if count > 0 {
+        offset[len] = index - code;
+        code += count;
+        index += count;
+        max_code[len] = code - 1;
+      }
+      code <<= 1;
+    }
+    Self {
+      symbols: &table.symbols,
+      max_code,
+      offset,
+    }
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> Result<u8> {
+    let mut code = 0;
+    for len in 1..=16 {
+      code = (code << 1) | reader.bit()? as i32;
+      if code <= self.max_code[len] {
+        let index = (code + self.offset[len]) as usize;
+        return Ok(*self.symbols.get(index).ok_or(INVALID_CODE)?);
+      }
+    }
+    Err(INVALID_CODE.into())
+  }
+}
+
+const INVALID_CODE: &str = "Invalid Huffman code in the JPEG scan";
+
+/// Reads bits from entropy-coded data whose stuffed bytes were removed
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl BitReader<'_> {
+  fn bit(&mut self) -> Result<u16> {
+    let byte = self
+      .data
+      .get(self.position / 8)
+      .ok_or("JPEG scan is truncated")?;
+    let bit = (byte >> (7 - self.position % 8)) & 1;
+    self.position += 1;
+    Ok(bit as u16)
+  }
+
+  fn bits(&mut self, len: u8) -> Result<u16> {
+    (0..len).try_fold(0, |value, _| Ok((value << 1) | self.bit()?))
+  }
+
+  /// Reads a coefficient of `category` bits, whose values below half the
+  /// range stand for negative numbers
+  fn coefficient(&mut self, category: u8) -> Result<i32> {
+    let value = self.bits(category)? as i32;
+    match category {
+      0 => Ok(0),
+      _ if value < 1 << (category - 1) => Ok(value - (1 << category) + 1),
+      _ => Ok(value),
+    }
+  }
+}
+
+/// Writes entropy-coded data, stuffing a zero byte after every 0xFF
+#[derive(Default)]
+struct BitWriter {
+  bytes: Vec<u8>,
+  byte: u8,
+  len: u8,
+}
+
+impl BitWriter {
+  fn write(&mut self, value: u16, len: u8) {
+    for i in (0..len).rev() {
+      self.byte = (self.byte << 1) | ((value >> i) & 1) as u8;
+      self.len += 1;
+      if self.len == 8 {
+        self.bytes.push(self.byte);
+        if self.byte == 0xFF {
+          self.bytes.push(0);
+        }
+        (self.byte, self.len) = (0, 0);
+      }
+    }
+  }
+
+  /// Pads the last byte with ones, as the spec asks
+  fn flush(&mut self) {
+    while self.len > 0 {
+      self.write(1, 1);
+    }
+  }
+}
+
+/// The number of bits a coefficient of `value` takes
+fn category(value: i32) -> u8 {
+  (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// The bits of `value` in its category, negative numbers stored as
+/// `value - 1`
+fn magnitude_bits(value: i32) -> u16 {
+  match value {
+    0.. => value as u16,
+    _ => (value - 1) as u16 & ((1 << category(value)) - 1),
+  }
+}
+
+/// Calls `emit` with the table class (0 for DC, 1 for AC), symbol and extra
+/// bits of every code `block` is written with, given the DC coefficient of
+/// the block before it
+fn block_symbols(
+  block: &Block,
+  previous_dc: i32,
+  mut emit: impl FnMut(usize, u8, u16, u8),
+) {
+  let diff = block[0] as i32 - previous_dc;
+  emit(0, category(diff), magnitude_bits(diff), category(diff));
+  let mut run = 0;
+  for &coefficient in &block[1..] {
+    let value = coefficient as i32;
+    if value == 0 {
+      run += 1;
+      continue;
+    }
+    while run > 15 {
+      emit(1, 0xF0, 0, 0);
+      run -= 16;
+    }
+    let category = category(value);
+    emit(1, (run << 4) | category, magnitude_bits(value), category);
+    run = 0;
+  }
+  if run > 0 {
+    emit(1, 0x00, 0, 0);
+  }
+}
+
+/// A component of the frame, with the tables the scan codes it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScanComponent {
+  dc_table: usize,
+  ac_table: usize,
+}
+
+type Tables = [[Option<HuffmanTable>; 4]; 2];
+
+/// A baseline JPEG image, kept as the quantised DCT coefficients of its
+/// single scan. Changing a coefficient and writing the image out again
+/// codes the scan anew without ever quantising it again, so no other
+/// coefficient changes. The original Huffman tables are kept whenever they
+/// can code the new coefficients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jpeg {
+  /// The marker segments between SOI and SOS, in file order
+  segments: Vec<Segment>,
+  tables: Tables,
+  width: u16,
+  height: u16,
+  scan: Vec<ScanComponent>,
+  /// The data of the SOS segment, written back as it was
+  scan_header: Vec<u8>,
+  /// How many MCUs there are between restart markers, or 0 for none
+  restart_interval: usize,
+  /// The index in `scan` of each block of a minimum coded unit
+  mcu: Vec<usize>,
+  mcu_count: usize,
+  blocks: Vec<Block>,
+  /// Everything from the EOI marker on
+  tail: Vec<u8>,
+}
+
+impl Jpeg {
+  /// The width of the image in pixels
+  pub fn width(&self) -> u16 {
+    self.width
+  }
+
+  /// The height of the image in pixels
+  pub fn height(&self) -> u16 {
+    self.height
+  }
+
+  /// Every block of the scan, in the order it codes them
+  pub fn blocks(&self) -> &[Block] {
+    &self.blocks
+  }
+
+  /// Like `blocks`, to change coefficients
+  pub fn blocks_mut(&mut self) -> &mut [Block] {
+    &mut self.blocks
+  }
+
+  /// Whether the block at `index` starts a restart interval
+  fn starts_interval(&self, index: usize) -> bool {
+    let mcu = index / self.mcu.len();
+    self.restart_interval > 0
+      && mcu.is_multiple_of(self.restart_interval)
+      && index.is_multiple_of(self.mcu.len())
+  }
+
+  /// Calls `f` with the scan component and DC coefficient of the block
+  /// before every block, which is zero at the start of each restart
+  /// interval
+  fn for_each_block(&self, mut f: impl FnMut(usize, usize, i32)) {
+    let mut previous_dc = vec![0; self.scan.len()];
+    for (index, block) in self.blocks.iter().enumerate() {
+      if self.starts_interval(index) {
+        previous_dc.fill(0);
+      }
+      let component = self.mcu[index % self.mcu.len()];
+      f(index, component, previous_dc[component]);
+      previous_dc[component] = block[0] as i32;
+    }
+  }
+
+  /// How often each symbol of each table codes the blocks
+  fn frequencies(&self) -> Vec<[[u32; 256]; 4]> {
+    let mut frequencies = vec![[[0; 256]; 4]; 2];
+    self.for_each_block(|index, component, previous_dc| {
+      let tables = &self.scan[component];
+      block_symbols(&self.blocks[index], previous_dc, |class, symbol, _, _| {
+        let table = [tables.dc_table, tables.ac_table][class];
+        frequencies[class][table][symbol as usize] += 1;
+      });
+    });
+    frequencies
+  }
+
+  /// The original tables if they can code every symbol the blocks need,
+  /// and otherwise the optimal tables for the blocks
+  fn tables_for_blocks(&self) -> (Tables, bool) {
+    let frequencies = self.frequencies();
+    let covered = (0..2).all(|class| {
+      (0..4).all(|table| {
+        let used = frequencies[class][table].iter().any(|&f| f > 0);
+        let codes = self.tables[class][table].as_ref().map(|t| t.codes());
+        !used
+          || codes.is_some_and(|codes| {
+            (0..256).all(|symbol| {
+              frequencies[class][table][symbol] == 0 || codes[symbol].is_some()
+            })
+          })
+      })
+    });
+    if covered {
+      return (self.tables.clone(), false);
+    }
+    let optimal = |class: usize, table: usize| {
+      let used = frequencies[class][table].iter().any(|&f| f > 0);
+      used.then(|| HuffmanTable::optimal(&frequencies[class][table]))
+    };
+    let tables = [0, 1].map(|class| [0, 1, 2, 3].map(|t| optimal(class, t)));
+    (tables, true)
+  }
+
+  /// Codes the blocks with `tables`, which must hold every symbol they use
+  fn encode_scan(&self, tables: &Tables) -> Vec<u8> {
+    let codes = tables
+      .each_ref()
+      .map(|class| class.each_ref().map(|t| t.as_ref().map(|t| t.codes())));
+    let mut writer = BitWriter::default();
+    let mut restarts = 0;
+    self.for_each_block(|index, component, previous_dc| {
+      if index > 0 && self.starts_interval(index) {
+        writer.flush();
+        writer.bytes.extend_from_slice(&[0xFF, RST0 + restarts % 8]);
+        restarts += 1;
+      }
+      let scan = &self.scan[component];
+      block_symbols(
+        &self.blocks[index],
+        previous_dc,
+        |class, symbol, bits, len| {
+          let table = [scan.dc_table, scan.ac_table][class];
+          let (code, code_len) = codes[class][table]
+            .as_ref()
+            .and_then(|codes| codes[symbol as usize])
+            .expect("tables hold every symbol of the blocks");
+          writer.write(code, code_len);
+          writer.write(bits, len);
+        },
+      );
+    });
+    writer.flush();
+    writer.bytes
+  }
+
+  /// Decodes the entropy-coded `data` of the scan into blocks
+  fn decode_scan(&self, data: &[u8]) -> Result<Vec<Block>> {
+    let decoders = self.tables.each_ref().map(|class| {
+      class
+        .each_ref()
+        .map(|t| t.as_ref().map(HuffmanDecoder::new))
+    });
+    let decoder = |class: usize, table: usize| {
+      decoders[class][table]
+        .as_ref()
+        .ok_or("JPEG scan uses a missing Huffman table")
+    };
+    let interval_len = match self.restart_interval {
+      0 => self.mcu_count,
+      len => len,
+    };
+    let intervals = restart_intervals(data);
+
+    let mut blocks = Vec::new();
+    for first_mcu in (0..self.mcu_count).step_by(interval_len.max(1)) {
+      let data = intervals
+        .get(first_mcu / interval_len)
+        .ok_or("JPEG scan is truncated")?;
+      let mut reader = BitReader { data, position: 0 };
+      let mut previous_dc = vec![0; self.scan.len()];
+      let last_mcu = (first_mcu + interval_len).min(self.mcu_count);
+      for _ in first_mcu..last_mcu {
+        for &component in &self.mcu {
+          let scan = &self.scan[component];
+          let mut block = [0; 64];
+          let category = decoder(0, scan.dc_table)?.decode(&mut reader)?;
+          if category > 11 {
+            return Err(INVALID_COEFFICIENT.into());
+          }
+          previous_dc[component] += reader.coefficient(category)?;
+          block[0] = i16::try_from(previous_dc[component])
+            .map_err(|_| INVALID_COEFFICIENT)?;
+
+          let ac = decoder(1, scan.ac_table)?;
+          let mut index = 1;
+          while index < 64 {
+            let symbol = ac.decode(&mut reader)?;
+            let (run, category) = ((symbol >> 4) as usize, symbol & 15);
+            if category == 0 && run != 15 {
+              break;
+            }
+            index += run;
+            if category == 0 {
+              index += 1;
+              continue;
+            }
+            if index > 63 || category > 10 {
+              return Err(INVALID_COEFFICIENT.into());
+            }
+            block[index] = reader.coefficient(category)? as i16;
+            index += 1;
+          }
+          blocks.push(block);
+        }
+      }
+    }
+    Ok(blocks)
+  }
+}
+
+const INVALID_COEFFICIENT: &str = "Invalid coefficient in the JPEG scan";
+
+/// Splits entropy-coded data at its restart markers, removing the zero
+/// bytes stuffed after every 0xFF
+fn restart_intervals(data: &[u8]) -> Vec<Vec<u8>> {
+  let mut intervals = vec![Vec::new()];
+  let mut bytes = data.iter().copied().peekable();
+  while let Some(byte) = bytes.next() {
+    if byte != 0xFF {
+      intervals.last_mut().expect("never empty").push(byte);
+      continue;
+    }
+    match bytes.peek() {
+      Some(0) => {
+        bytes.next();
+        intervals.last_mut().expect("never empty").push(0xFF);
+      }
+      Some(RST0..=RST7) => {
+        bytes.next();
+        intervals.push(Vec::new());
+      }
+      // Any other 0xFF is a fill byte
+      _ => {}
+    }
+  }
+  intervals
+}
+
+/// Reads a big-endian u16 at `offset` of a segment
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+  let field = data
+    .get(offset..offset + 2)
+    .ok_or("JPEG segment is truncated")?;
+  Ok(u16::from_be_bytes([field[0], field[1]]))
+}
+
+/// Parses the Huffman tables of a DHT segment into `tables`
+fn parse_tables(data: &[u8], tables: &mut Tables) -> Result<()> {
+  let mut rest = data;
+  while let Some((&class_and_id, after)) = rest.split_first() {
+    let (class, id) =
+      ((class_and_id >> 4) as usize, (class_and_id & 15) as usize);
+    if class > 1 || id > 3 {
+      return Err("Invalid Huffman table in the JPEG image".into());
+    }
+    let counts: [u8; 16] = after
+      .get(..16)
+      .ok_or("JPEG segment is truncated")?
+      .try_into()?;
+    let len = counts.iter().map(|&count| count as usize).sum::<usize>();
+    let symbols = after.get(16..16 + len).ok_or("JPEG segment is truncated")?;
+    tables[class][id] = Some(HuffmanTable {
+      counts,
+      symbols: symbols.to_vec(),
+    });
+    rest = &after[16 + len..];
+  }
+  Ok(())
+}
+
+/// A component of the frame header
+struct FrameComponent {
+  id: u8,
+  horizontal: usize,
+  vertical: usize,
+}
+
+/// Parses a baseline JPEG file, failing on progressive, lossless and
+/// arithmetic-coded images and on images with more than one scan
+impl TryFrom<&[u8]> for Jpeg {
+  type Error = Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    if !bytes.starts_with(&[0xFF, SOI]) {
+      return Err("Invalid JPEG header".into());
+    }
+    let mut segments = Vec::new();
+    let mut tables = Tables::default();
+    let mut frame = None;
+    let mut restart_interval = 0;
+    let mut position = 2;
+    let scan_header = loop {
+      if bytes.get(position) != Some(&0xFF) {
+        return Err("Invalid JPEG marker".into());
+      }
+      // Markers may be preceded by any number of 0xFF fill bytes
+      while bytes.get(position) == Some(&0xFF) {
+        position += 1;
+      }
+      let marker = *bytes.get(position).ok_or("JPEG file is truncated")?;
+      position += 1;
+      match marker {
+        EOI => return Err("JPEG image has no scan".into()),
+        TEM | RST0..=RST7 => continue,
+        _ => {}
+      }
+      let len = u16_at(bytes, position)? as usize;
+      let data = bytes
+        .get(position + 2..position + len.max(2))
+        .ok_or("JPEG file is truncated")?
+        .to_vec();
+      position += len.max(2);
+      match marker {
+        0xC0 | 0xC1 => frame = Some(data.clone()),
+        0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+          return Err("Only baseline JPEG images are supported".into())
+        }
+        DHT => parse_tables(&data, &mut tables)?,
+        DRI => restart_interval = u16_at(&data, 0)? as usize,
+        SOS => break data,
+        _ => {}
+      }
+      segments.push(Segment { marker, data });
+    };
+
+    let frame = frame.ok_or("JPEG image has no frame header")?;
+    if frame.first() != Some(&8) {
+      return Err("Only 8-bit JPEG images are supported".into());
+    }
+    let height = u16_at(&frame, 1)?;
+    let width = u16_at(&frame, 3)?;
+    if width == 0 || height == 0 {
+      return Err(
+        "JPEG images that give their height after the scan are not supported"
+          .into(),
+      );
+    }
+    let components = frame
+      .get(6..)
+      .unwrap_or_default()
+      .chunks_exact(3)
+      .take(*frame.get(5).unwrap_or(&0) as usize)
+      .map(|component| FrameComponent {
+        id: component[0],
+        horizontal: (component[1] >> 4).max(1) as usize,
+        vertical: (component[1] & 15).max(1) as usize,
+      })
+      .collect::<Vec<_>>();
+
+    let scan_len = *scan_header.first().unwrap_or(&0) as usize;
+    let selectors = scan_header
+      .get(1..1 + 2 * scan_len)
+      .ok_or("JPEG segment is truncated")?;
+    if scan_header.get(1 + 2 * scan_len..) != Some(&[0, 63, 0]) {
+      return Err("Only baseline JPEG images are supported".into());
+    }
+    if scan_len != components.len() || scan_len == 0 {
+      return Err("JPEG images with several scans are not supported".into());
+    }
+    let mut scan = Vec::new();
+    let mut scan_components = Vec::new();
+    for selector in selectors.chunks_exact(2) {
+      let component = components
+        .iter()
+        .find(|component| component.id == selector[0])
+        .ok_or("JPEG scan refers to an unknown component")?;
+      scan.push(ScanComponent {
+        dc_table: (selector[1] >> 4) as usize & 3,
+        ac_table: (selector[1] & 15) as usize & 3,
+      });
+      scan_components.push(component);
+    }
+
+    // A single component is coded block by block, several in minimum coded
+    // units holding each component's blocks in turn
+    let max_horizontal = components.iter().map(|c| c.horizontal).max();
+    let max_vertical = components.iter().map(|c| c.vertical).max();
+    let (max_horizontal, max_vertical) =
+      (max_horizontal.unwrap_or(1), max_vertical.unwrap_or(1));
+    let (width_px, height_px) = (width as usize, height as usize);
+    let (mcu, mcu_count) = if let [component] = scan_components[..] {
+      let columns = (width_px * component.horizontal).div_ceil(max_horizontal);
+      let rows = (height_px * component.vertical).div_ceil(max_vertical);
+      (vec![0], columns.div_ceil(8) * rows.div_ceil(8))
+    } else {
+      let mcu = scan_components
+        .iter()
+        .enumerate()
+        .flat_map(|(index, component)| {
+          std::iter::repeat_n(index, component.horizontal * component.vertical)
+        })
+        .collect();
+      let columns = width_px.div_ceil(8 * max_horizontal);
+      let rows = height_px.div_ceil(8 * max_vertical);
+      (mcu, columns * rows)
+    };
+
+    // The entropy-coded data runs up to the first marker that is neither a
+    // stuffed byte nor a restart marker
+    let data_start = position;
+    let data_len = bytes[data_start..]
+      .windows(2)
+      .position(|pair| {
+        pair[0] == 0xFF && !matches!(pair[1], 0 | RST0..=RST7 | 0xFF)
+      })
+      .ok_or("JPEG scan is truncated")?;
+    let tail = bytes[data_start + data_len..].to_vec();
+    if !tail.starts_with(&[0xFF, EOI]) {
+      return Err("JPEG images with several scans are not supported".into());
+    }
+    // Every block takes at least two bits, a DC and an AC code, so a frame
+    // claiming more blocks than the scan can hold is refused before decoding
+    if mcu_count.saturating_mul(mcu.len()) > data_len.saturating_mul(4) {
+      return Err("JPEG scan is truncated".into());
+    }
+
+    let mut jpeg = Self {
+      segments,
+      tables,
+      width,
+      height,
+      scan,
+      scan_header,
+      restart_interval,
+      mcu,
+      mcu_count,
+      blocks: Vec::new(),
+      tail,
+    };
+    jpeg.blocks =
+      jpeg.decode_scan(&bytes[data_start..data_start + data_len])?;
+    Ok(jpeg)
+  }
+}
+
+impl CoverImage for Jpeg {
+  fn format(&self) -> Format {
+    Format::Jpeg
+  }
+
+  /// The image with its scan coded again. If the coefficients need codes
+  /// the original Huffman tables lack, optimal tables replace them.
+  fn as_bytes(&self) -> Vec<u8> {
+    let (tables, replaced) = self.tables_for_blocks();
+    let mut bytes = vec![0xFF, SOI];
+    for segment in &self.segments {
+      if !(replaced && segment.marker == DHT) {
+        segment.write_to(&mut bytes);
+      }
+    }
+    if replaced {
+      let mut data = Vec::new();
+      for (class, class_tables) in tables.iter().enumerate() {
+        for (id, table) in class_tables.iter().enumerate() {
+          if let Some(table) = table {
+            data.push(((class as u8) << 4) | id as u8);
+            data.extend_from_slice(&table.counts);
+            data.extend_from_slice(&table.symbols);
+          }
+        }
+      }
+      Segment { marker: DHT, data }.write_to(&mut bytes);
+    }
+    Segment {
+      marker: SOS,
+      data: self.scan_header.clone(),
+    }
+    .write_to(&mut bytes);
+    bytes.extend(self.encode_scan(&tables));
+    bytes.extend_from_slice(&self.tail);
+    bytes
+  }
+
+  fn message_chunk(&self, _chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    Err(use_f5())
+  }
+
+  fn set_message_chunk(
+    &mut self,
+    _chunk_type: &ChunkType,
+    _data: Vec<u8>,
+  ) -> Result<()> {
+    Err(use_f5())
+  }
+
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    Err(use_f5())
+  }
+
+  fn encode_frames(
+    &mut self,
+    _frames: &[Pixels],
+    _filter: FilterStrategy,
+  ) -> Result<()> {
+    Err(use_f5())
+  }
+
+  fn as_jpeg(&self) -> Option<&Jpeg> {
+    Some(self)
+  }
+
+  fn as_jpeg_mut(&mut self) -> Option<&mut Jpeg> {
+    Some(self)
+  }
+}
+
+fn use_f5() -> Error {
+  "JPEG images only hide messages in their DCT coefficients; use f5 mode".into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cat() -> Vec<u8> {
+    std::fs::read("tests/cat.jpg").unwrap()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let bytes = cat();
+    let jpeg = Jpeg::try_from(bytes.as_slice()).unwrap();
+    assert_eq!((jpeg.width(), jpeg.height()), (160, 240));
+    // 4:2:0 sampling: four luma blocks and two chroma blocks per 16x16 MCU
+    assert_eq!(jpeg.mcu, [0, 0, 0, 0, 1, 2]);
+    assert_eq!(jpeg.blocks().len(), 10 * 15 * 6);
+    assert_eq!(jpeg.as_bytes(), bytes);
+  }
+
+  #[test]
+  fn test_optimal_tables() {
+    // Without tables, the image is written with the optimal ones, which
+    // hold no symbol the blocks do not need
+    let mut jpeg = Jpeg::try_from(cat().as_slice()).unwrap();
+    jpeg.tables = Tables::default();
+    let bytes = jpeg.as_bytes();
+    assert!(bytes.len() < cat().len());
+    let mut optimised = Jpeg::try_from(bytes.as_slice()).unwrap();
+    assert_eq!(optimised.blocks(), jpeg.blocks());
+    assert_eq!(optimised.as_bytes(), bytes);
+
+    // A coefficient needing a code those tables lack replaces them again
+    optimised.blocks_mut()[7][5] = 1000;
+    let bytes = optimised.as_bytes();
+    let copy = Jpeg::try_from(bytes.as_slice()).unwrap();
+    assert_ne!(copy.tables, optimised.tables);
+    assert_eq!(copy.blocks(), optimised.blocks());
+
+    // Fibonacci frequencies would give codes up to 30 bits long
+    let mut frequencies = [0; 256];
+    (frequencies[0], frequencies[1]) = (1, 1);
+    for i in 2..30 {
+      frequencies[i] = frequencies[i - 1] + frequencies[i - 2];
+    }
+    let table = HuffmanTable::optimal(&frequencies);
+    assert_eq!(table.symbols.len(), 30);
+    let codes = table.codes().into_iter().flatten().collect::<Vec<_>>();
+    assert_eq!(codes.len(), 30);
+    assert!(codes.iter().all(|&(_, len)| len >= codes[29].1));
+    assert!(codes
+      .iter()
+      .all(|&(code, len)| len <= 16 && code as u32 != (1 << len) - 1));
+  }
+
+  #[test]
+  fn test_rejects_unsupported() {
+    let mut progressive = cat();
+    let sof = progressive
+      .windows(2)
+      .position(|pair| pair == [0xFF, 0xC0])
+      .unwrap();
+    progressive[sof + 1] = 0xC2;
+    assert!(Jpeg::try_from(progressive.as_slice()).is_err());
+    let bytes = cat();
+    assert!(Jpeg::try_from(&bytes[..bytes.len() / 2]).is_err());
+
+    // A frame of 65535x65535 pixels cannot fit in the scan of a small file
+    let mut huge = cat();
+    let sof = huge
+      .windows(2)
+      .position(|pair| pair == [0xFF, 0xC0])
+      .unwrap();
+    huge[sof + 5..sof + 9].fill(0xFF);
+    let error = Jpeg::try_from(huge.as_slice()).unwrap_err();
+    assert_eq!(error.to_string(), "JPEG scan is truncated");
+  }
+}
@@ -0,0 +1,235 @@
+//! Stretching passwords into keys with Argon2id, and picking parameters
+//! that suit the machine.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+
+use crate::aes::Key;
+use crate::Result;
+
+/// Length of the random salt stored with password envelopes
+pub const SALT_LEN: usize = 16;
+
+/// Memory cost, in KiB, above which stored parameters are refused, so that
+/// a crafted payload cannot make decoding exhaust memory. This leaves a
+/// little room above the paranoid profile.
+pub const MAX_MEMORY_KIB: u32 = 1280 * 1024;
+
+/// Time cost above which stored parameters are refused, a little above the
+/// paranoid profile
+pub const MAX_ITERATIONS: u32 = 6;
+
+/// Lanes above which stored parameters are refused
+pub const MAX_PARALLELISM: u32 = 16;
+
+/// The smallest memory cost `calibrate` suggests
+const MIN_CALIBRATED_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KdfParams {
+  /// Memory cost in KiB
+  pub memory_kib: u32,
+  /// Number of passes over the memory
+  pub iterations: u32,
+  /// Number of lanes
+  pub parallelism: u32,
+}
+
+impl KdfParams {
+  /// The parameters used before they were stored in payloads
+  pub const LEGACY: Self = Self::new(15000, 2, 1);
+
+  /// Parameters costing `memory_kib` KiB of memory, `iterations` passes
+  /// over it and `parallelism` lanes
+  pub const fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+    Self {
+      memory_kib,
+      iterations,
+      parallelism,
+    }
+  }
+
+  /// Returns an error if Argon2 refuses these parameters, or if they are
+  /// too costly to accept from a payload
+  pub fn validate(&self) -> Result<()> {
+    if self.memory_kib > MAX_MEMORY_KIB
+      || self.iterations > MAX_ITERATIONS
+      || self.parallelism > MAX_PARALLELISM
+    {
+      return Err("KDF parameters are too costly".into());
+    }
+    self.argon2()?;
+    Ok(())
+  }
+
+  fn argon2(&self) -> Result<Argon2<'static>> {
+    let params =
+      Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+        .map_err(|error| format!("Invalid KDF parameters: {}", error))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+  }
+
+  /// Stretches `password` into a key
+  pub fn derive(&self, password: &str, salt: &[u8; SALT_LEN]) -> Result<Key> {
+    self.validate()?;
+    let mut key = [0u8; 32];
+    self
+      .argon2()?
+      .hash_password_into(password.as_bytes(), salt, &mut key)
+      .map_err(|error| format!("Failed to derive key: {}", error))?;
+    Ok(Key::from_bytes(key))
+  }
+}
+
+impl Default for KdfParams {
+  fn default() -> Self {
+    KdfProfile::default().params()
+  }
+}
+
+impl fmt::Display for KdfParams {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} KiB, {} iterations, {} lanes",
+      self.memory_kib, self.iterations, self.parallelism
+    )
+  }
+}
+
+/// Named sets of `KdfParams`, from quickest to slowest to unlock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KdfProfile {
+  /// 19 MiB and 2 passes, for passwords typed often
+  #[default]
+  Interactive,
+  /// 64 MiB and 3 passes
+  Moderate,
+  /// 1 GiB and 4 passes, for messages that are rarely unlocked
+  Paranoid,
+}
+
+impl KdfProfile {
+  /// The Argon2 parameters of this profile
+  pub fn params(&self) -> KdfParams {
+    match self {
+      KdfProfile::Interactive => KdfParams::new(19 * 1024, 2, 1),
+      KdfProfile::Moderate => KdfParams::new(64 * 1024, 3, 1),
+      KdfProfile::Paranoid => KdfParams::new(1024 * 1024, 4, 1),
+    }
+  }
+}
+
+impl FromStr for KdfProfile {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "interactive" => Ok(KdfProfile::Interactive),
+      "moderate" => Ok(KdfProfile::Moderate),
+      "paranoid" => Ok(KdfProfile::Paranoid),
+      _ => Err("KDF profile must be one of: interactive, moderate, paranoid"),
+    }
+  }
+}
+
+impl fmt::Display for KdfProfile {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      KdfProfile::Interactive => "interactive",
+      KdfProfile::Moderate => "moderate",
+      KdfProfile::Paranoid => "paranoid",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// How long deriving a key with `params` takes on this machine
+pub fn measure(params: &KdfParams) -> Result<Duration> {
+  let start = Instant::now();
+  params.derive("calibration", &[0; SALT_LEN])?;
+  Ok(start.elapsed())
+}
+
+/// Suggests parameters that take about `target` to derive a key on this
+/// machine, using at most `max_memory_kib` of memory. Memory is raised
+/// first, since it is what makes guessing costly on dedicated hardware;
+/// passes are added once it reaches the limit. Suggestions never cost more
+/// than the paranoid profile.
+pub fn calibrate(target: Duration, max_memory_kib: u32) -> Result<KdfParams> {
+  let ceiling = KdfProfile::Paranoid.params();
+  let max_memory_kib = max_memory_kib.clamp(8, ceiling.memory_kib);
+  let probe = KdfProfile::Interactive.params();
+  let probe = KdfParams {
+    memory_kib: probe.memory_kib.min(max_memory_kib),
+    ..probe
+  };
+  let elapsed = measure(&probe)?.as_secs_f64().max(1e-6);
+
+  // Argon2 takes time roughly proportional to memory times passes
+  let cost =
+    probe.memory_kib as f64 * probe.iterations as f64 * target.as_secs_f64()
+      / elapsed;
+  let iterations = (cost / max_memory_kib as f64)
+    .ceil()
+    .clamp(1.0, ceiling.iterations as f64) as u32;
+  let memory_kib = (cost / iterations as f64) as u32;
+  let min_memory_kib = MIN_CALIBRATED_MEMORY_KIB.min(max_memory_kib);
+  Ok(KdfParams::new(
+    memory_kib.clamp(min_memory_kib, max_memory_kib),
+    iterations,
+    1,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_derive() {
+    let params = KdfParams::new(64, 1, 1);
+    let salt = [1; SALT_LEN];
+    let key = params.derive("password", &salt).unwrap();
+    assert_eq!(params.derive("password", &salt).unwrap(), key);
+    assert_ne!(params.derive("password", &[2; SALT_LEN]).unwrap(), key);
+    assert_ne!(
+      KdfParams::new(64, 2, 1).derive("password", &salt).unwrap(),
+      key
+    );
+  }
+
+  #[test]
+  fn test_validate() {
+    assert!(KdfParams::LEGACY.validate().is_ok());
+    assert!(KdfProfile::Paranoid.params().validate().is_ok());
+    assert!(KdfParams::new(MAX_MEMORY_KIB + 1, 1, 1).validate().is_err());
+    assert!(KdfParams::new(64, MAX_ITERATIONS + 1, 1)
+      .validate()
+      .is_err());
+    assert!(KdfParams::new(4 * 1024 * 1024, 64, 1).validate().is_err());
+    assert!(KdfParams::new(64, 0, 1).validate().is_err());
+    assert!(KdfParams::new(64, 1, MAX_PARALLELISM + 1)
+      .validate()
+      .is_err());
+  }
+
+  #[test]
+  fn test_calibrate() {
+    let params = calibrate(Duration::from_millis(1), 1024).unwrap();
+    assert!(params.validate().is_ok());
+    assert!(params.memory_kib <= 1024);
+    assert!(params.iterations >= 1);
+
+    // However long the target, suggestions stay within what decoding accepts
+    let params = calibrate(Duration::from_secs(3600), u32::MAX).unwrap();
+    let paranoid = KdfProfile::Paranoid.params();
+    assert!(params.memory_kib <= paranoid.memory_kib);
+    assert!(params.iterations <= paranoid.iterations);
+    assert!(params.validate().is_ok());
+  }
+}
@@ -0,0 +1,87 @@
+//! Hide encrypted messages in PNG, BMP, WebP and JPEG images, in WAV audio
+//! and in PDF documents.
+//!
+//! Messages are sealed in an AES-256-GCM `Envelope`, optionally wrapped in
+//! Reed-Solomon error correction, then stored either in a chunk of their own
+//! or in the low bits of the pixels. `Encoder` and `Decoder` cover the whole
+//! round trip:
+//!
+//! ```
+//! use cachette::{ChunkType, Decoder, Encoder, Png};
+//! use std::str::FromStr;
+//!
+//! let mut png = Png::from_file(&"tests/cat.png".into())?;
+//! let password = "correct horse battery staple";
+//! let chunk_type = ChunkType::from_str("ruSt")?;
+//!
+//! Encoder::new(password)
+//!   .chunk_type(chunk_type.clone())
+//!   .encode(&mut png, "Meet at noon")?;
+//! let decoded = Decoder::new(password).chunk_type(chunk_type).decode(&png)?;
+//! assert_eq!(decoded.message, "Meet at noon");
+//! # Ok::<(), cachette::Error>(())
+//! ```
+//!
+//! The crate follows semantic versioning: the items exported here only
+//! change in incompatible ways on a major version bump (a minor bump while
+//! the version is below 1.0).
+
+#![warn(missing_docs)]
+
+mod aes;
+mod bmp;
+mod capacity;
+mod chunk;
+mod chunk_type;
+mod codec;
+pub mod cover;
+mod deflate;
+mod ecc;
+mod envelope;
+mod ezstego;
+mod f5;
+mod ffi;
+mod gf256;
+mod jpeg;
+pub mod kdf;
+mod lsb;
+mod mode;
+mod pdf;
+pub mod png;
+mod riff;
+mod robust;
+mod scan;
+mod session;
+mod shamir;
+pub mod strength;
+pub mod verify;
+mod wav;
+mod webp;
+mod wipe;
+
+pub use aes::Key;
+pub use bmp::Bmp;
+pub use capacity::Capacity;
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use codec::{Decoded, Decoder, Encoder, DEFAULT_CHUNK_TYPE};
+pub use cover::{CoverImage, Format};
+pub use ecc::EccLevel;
+pub use envelope::{Envelope, KeyBlock, Stanza};
+pub use jpeg::Jpeg;
+pub use kdf::{KdfParams, KdfProfile};
+pub use mode::Mode;
+pub use pdf::Pdf;
+pub use png::Png;
+pub use scan::{scan, Finding, Location, PayloadKind};
+pub use session::{Identity, KeyMaterial, Recipient, Session};
+pub use shamir::{Share, Split};
+pub use wav::Wav;
+pub use webp::WebP;
+pub use wipe::{wipe, Wiped};
+
+/// The error type of every fallible operation in this crate
+pub type Error = Box<dyn std::error::Error>;
+
+/// A `Result` whose error is `Error`
+pub type Result<T> = std::result::Result<T, Error>;
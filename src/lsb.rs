@@ -0,0 +1,229 @@
+use crate::png::Pixels;
+use crate::Result;
+
+/// Checks that `bits_per_channel` low bits can be used in every colour sample
+/// of `pixels`
+pub fn validate(pixels: &Pixels, bits_per_channel: u8) -> Result<()> {
+  let header = pixels.header();
+  if header.color_type == crate::png::ColorType::Indexed {
+    return Err(
+      "LSB mode does not support indexed-colour images; use palette mode"
+        .into(),
+    );
+  }
+  let max_bits = header.bit_depth.min(8);
+  if bits_per_channel == 0 || bits_per_channel > max_bits {
+    return Err(
+      format!("Bits per channel must be between 1 and {}", max_bits).into(),
+    );
+  }
+  Ok(())
+}
+
+/// The indices of the samples that carry payload bits: every colour sample,
+/// alpha samples are left untouched
+fn carriers(pixels: &Pixels) -> impl Iterator<Item = usize> {
+  let color_type = pixels.header().color_type;
+  let channels = color_type.channels();
+  let has_alpha = color_type.has_alpha();
+  (0..pixels.sample_count())
+    .filter(move |index| !has_alpha || index % channels != channels - 1)
+}
+
+/// The number of samples of a frame that can carry payload bits
+fn frame_carrier_count(pixels: &Pixels) -> usize {
+  let color_type = pixels.header().color_type;
+  let channels = color_type.channels();
+  let color_channels = channels - color_type.has_alpha() as usize;
+  pixels.sample_count() / channels * color_channels
+}
+
+/// The number of whole bytes that fit in the low bits of a frame
+fn frame_capacity(pixels: &Pixels, bits_per_channel: u8) -> usize {
+  frame_carrier_count(pixels) * bits_per_channel as usize / 8
+}
+
+/// The number of samples of all `frames` that can carry payload bits
+pub fn carrier_count(frames: &[Pixels]) -> usize {
+  frames.iter().map(frame_carrier_count).sum()
+}
+
+/// The number of whole bytes that fit in the low bits of `frames`. Every
+/// frame holds a whole number of bytes.
+pub fn capacity(frames: &[Pixels], bits_per_channel: u8) -> Result<usize> {
+  for pixels in frames {
+    validate(pixels, bits_per_channel)?;
+  }
+  Ok(
+    frames
+      .iter()
+      .map(|pixels| frame_capacity(pixels, bits_per_channel))
+      .sum(),
+  )
+}
+
+/// Writes `payload` into the `bits_per_channel` low bits of the colour samples
+/// of `frames`, filling each frame in turn, most significant bit first
+pub fn embed(
+  frames: &mut [Pixels],
+  payload: &[u8],
+  bits_per_channel: u8,
+) -> Result<()> {
+  if payload.len() > capacity(frames, bits_per_channel)? {
+    return Err("Payload does not fit in the image".into());
+  }
+
+  let mut rest = payload;
+  for pixels in frames {
+    let len = frame_capacity(pixels, bits_per_channel).min(rest.len());
+    let (part, remaining) = rest.split_at(len);
+    embed_frame(pixels, part, bits_per_channel);
+    rest = remaining;
+  }
+  Ok(())
+}
+
+/// Reads `len` bytes from the `bits_per_channel` low bits of the colour
+/// samples of `frames`, in the order `embed` writes them
+pub fn extract(
+  frames: &[Pixels],
+  bits_per_channel: u8,
+  len: usize,
+) -> Result<Vec<u8>> {
+  if len > capacity(frames, bits_per_channel)? {
+    return Err("Payload is larger than the image capacity".into());
+  }
+
+  let mut bytes = Vec::with_capacity(len);
+  for pixels in frames {
+    let part = frame_capacity(pixels, bits_per_channel).min(len - bytes.len());
+    bytes.extend(extract_frame(pixels, bits_per_channel, part));
+  }
+  Ok(bytes)
+}
+
+/// Writes `payload`, which must fit, into the low bits of a single frame
+fn embed_frame(pixels: &mut Pixels, payload: &[u8], bits_per_channel: u8) {
+  let mut bits = payload
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+  let indices = carriers(pixels).collect::<Vec<_>>();
+  for index in indices {
+    let mut value = 0u16;
+    let mut taken = 0;
+    for bit in bits.by_ref().take(bits_per_channel as usize) {
+      value = (value << 1) | bit as u16;
+      taken += 1;
+    }
+    if taken == 0 {
+      break;
+    }
+
+    // A partially filled sample keeps its remaining low bits
+    let shift = bits_per_channel as usize - taken;
+    let mask = ((1u16 << taken) - 1) << shift;
+    let sample = pixels.sample(index);
+    pixels.set_sample(index, (sample & !mask) | (value << shift));
+  }
+}
+
+/// Reads `len` bytes, which must fit, from the low bits of a single frame
+fn extract_frame(pixels: &Pixels, bits_per_channel: u8, len: usize) -> Vec<u8> {
+  let samples_needed = (len * 8).div_ceil(bits_per_channel as usize);
+  let mut bytes = Vec::with_capacity(len);
+  let mut byte = 0u8;
+  let mut filled = 0;
+  for index in carriers(pixels).take(samples_needed) {
+    let sample = pixels.sample(index);
+    for i in (0..bits_per_channel).rev() {
+      byte = (byte << 1) | ((sample >> i) & 1) as u8;
+      filled += 1;
+      if filled == 8 {
+        bytes.push(byte);
+        byte = 0;
+        filled = 0;
+      }
+    }
+  }
+  bytes.truncate(len);
+  bytes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::png::{ColorType, ImageHeader};
+  use std::slice::{from_mut, from_ref};
+
+  fn testing_pixels(color_type: ColorType, bit_depth: u8) -> Pixels {
+    let header = ImageHeader {
+      width: 7,
+      height: 5,
+      bit_depth,
+      color_type,
+      interlaced: false,
+    };
+    let len = header.row_bytes(7) * 5;
+    let data = (0..len).map(|i| (i * 31) as u8).collect();
+    Pixels::new(header, data).unwrap()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let payload = b"hidden".to_vec();
+    for bits_per_channel in 1..=3 {
+      let mut pixels = testing_pixels(ColorType::Rgb, 8);
+      embed(from_mut(&mut pixels), &payload, bits_per_channel).unwrap();
+      let extracted =
+        extract(from_ref(&pixels), bits_per_channel, payload.len());
+      assert_eq!(extracted.unwrap(), payload);
+    }
+  }
+
+  #[test]
+  fn test_frames() {
+    let mut frames = [
+      testing_pixels(ColorType::Rgb, 8),
+      testing_pixels(ColorType::Rgb, 8),
+    ];
+    let frame_capacity = capacity(&frames[..1], 1).unwrap();
+    assert_eq!(capacity(&frames, 1).unwrap(), 2 * frame_capacity);
+
+    let payload = (0..frame_capacity + 5).map(|i| i as u8).collect::<Vec<_>>();
+    embed(&mut frames, &payload, 1).unwrap();
+    assert_eq!(extract(&frames, 1, payload.len()).unwrap(), payload);
+    let first = extract(&frames[..1], 1, frame_capacity).unwrap();
+    assert_eq!(first, payload[..frame_capacity]);
+    assert!(embed(&mut frames, &vec![0; 2 * frame_capacity + 1], 1).is_err());
+  }
+
+  #[test]
+  fn test_alpha_is_untouched() {
+    let original = testing_pixels(ColorType::Rgba, 16);
+    let mut pixels = original.clone();
+    embed(from_mut(&mut pixels), &[0xFF; 20], 2).unwrap();
+
+    for index in (3..pixels.sample_count()).step_by(4) {
+      assert_eq!(pixels.sample(index), original.sample(index));
+    }
+    assert_eq!(extract(from_ref(&pixels), 2, 20).unwrap(), vec![0xFF; 20]);
+  }
+
+  #[test]
+  fn test_capacity() {
+    let pixels = testing_pixels(ColorType::Rgba, 8);
+    assert_eq!(carrier_count(from_ref(&pixels)), 7 * 5 * 3);
+    assert_eq!(capacity(from_ref(&pixels), 1).unwrap(), 13);
+    assert_eq!(capacity(from_ref(&pixels), 8).unwrap(), 105);
+    assert!(capacity(from_ref(&pixels), 9).is_err());
+
+    let mut pixels = testing_pixels(ColorType::Grayscale, 8);
+    assert!(embed(from_mut(&mut pixels), &[0; 5], 1).is_err());
+  }
+
+  #[test]
+  fn test_indexed_is_rejected() {
+    let pixels = testing_pixels(ColorType::Indexed, 8);
+    assert!(capacity(from_ref(&pixels), 1).is_err());
+  }
+}
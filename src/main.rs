@@ -1,28 +1,254 @@
-#![allow(dead_code)]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use batch::{Inputs, COVER_EXTENSIONS, PNG_EXTENSIONS};
+use cachette::png::StripOptions;
+use cachette::{ChunkType, Decoder, Encoder, Result};
 use clap::Parser;
-use cli::{Cli, Commands};
-use png_ops::{decode, encode, print_chunks, remove};
+use cli::{Cli, Commands, EncodeArgs};
+use png_ops::{
+  calibrate, decode, decode_split, encode, encode_split, genpass, inspect,
+  keygen, open_session, print_capacity, print_chunks, read_message, rekey,
+  remove, repair, scan, strip, verify, wipe, PasswordPrompt,
+};
 
-mod aes;
-mod chunk;
-mod chunk_type;
+mod batch;
 mod cli;
-mod png;
 mod png_ops;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+/// Batches are always rewritten in place
+fn check_output(inputs: &Inputs, output: &Option<PathBuf>) -> Result<()> {
+  if inputs.batch && output.is_some() {
+    return Err("--output can only be used with a single input file".into());
+  }
+  Ok(())
+}
 
 fn main() -> Result<()> {
   let args = Cli::parse();
   match args.command {
     Commands::Encode {
-      input,
-      message,
+      args,
+      output,
+      file,
+      recursive,
+      mode,
+      bits_per_channel,
+      ecc,
+      key_file,
+      recipient,
+      kdf,
+      min_score,
+      split,
+    } => {
+      let EncodeArgs {
+        inputs,
+        chunk_type,
+        message,
+      } = EncodeArgs::parse(args, file.is_some())?;
+      let message = read_message(message, file, &inputs)?;
+      let inputs = batch::expand(&inputs, recursive, COVER_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      let chunk_type = ChunkType::from_str(&chunk_type)?;
+
+      // The key is derived once for the whole batch
+      let session = open_session(
+        key_file,
+        &recipient,
+        None,
+        &PasswordPrompt::new(min_score),
+      )?
+      .kdf(kdf.params())?;
+      let encoder = Encoder::with_session(session)
+        .chunk_type(chunk_type)
+        .mode(mode)
+        .bits_per_channel(bits_per_channel)
+        .ecc(ecc);
+      if let Some(split) = split {
+        return encode_split(&inputs.files, &message, &encoder, split);
+      }
+      batch::run(&inputs, |file| {
+        encode(file, output.as_deref().unwrap_or(file), &message, &encoder)
+      })
+    }
+    Commands::Decode {
+      inputs,
+      chunk_type,
+      mode,
+      bits_per_channel,
+      recursive,
+      key_file,
+      identity,
+      lenient,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, COVER_EXTENSIONS)?;
+      let chunk_type = ChunkType::from_str(&chunk_type)?;
+
+      // The key is derived once for the whole batch
+      let session =
+        open_session(key_file, &[], identity, &PasswordPrompt::existing())?;
+      let decoder = Decoder::with_session(session)
+        .chunk_type(chunk_type)
+        .mode(mode)
+        .bits_per_channel(bits_per_channel);
+      if inputs.files.len() > 1 {
+        if let Some(message) = decode_split(&inputs.files, &decoder)? {
+          println!("{}", message);
+          return Ok(());
+        }
+      }
+      batch::run(&inputs, |file| decode(file, &decoder, lenient))
+    }
+    Commands::Remove {
+      inputs,
+      chunk_type,
+      output,
+      recursive,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, PNG_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      let chunk_type = ChunkType::from_str(&chunk_type)?;
+      batch::run(&inputs, |file| {
+        remove(file, output.as_deref().unwrap_or(file), &chunk_type)
+      })
+    }
+    Commands::Wipe {
+      inputs,
+      chunk_type,
+      output,
+      recursive,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, PNG_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      let chunk_types = chunk_type
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+      batch::run(&inputs, |file| {
+        wipe(file, output.as_deref().unwrap_or(file), &chunk_types)
+      })
+    }
+    Commands::Strip {
+      inputs,
+      keep,
+      merge_idat,
+      output,
+      recursive,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, PNG_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      let keep = keep
+        .iter()
+        .filter(|chunk_type| !chunk_type.is_empty())
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+      let options = StripOptions { keep, merge_idat };
+      batch::run(&inputs, |file| {
+        strip(file, output.as_deref().unwrap_or(file), &options)
+      })
+    }
+    Commands::Repair {
+      inputs,
+      output,
+      recursive,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, PNG_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      batch::run(&inputs, |file| {
+        repair(file, output.as_deref().unwrap_or(file))
+      })
+    }
+    Commands::Rekey {
+      inputs,
       chunk_type,
-    } => encode(input, &message, &chunk_type),
-    Commands::Decode { input, chunk_type } => decode(input, &chunk_type),
-    Commands::Remove { input, chunk_type } => remove(input, &chunk_type),
+      output,
+      recursive,
+      mode,
+      bits_per_channel,
+      key_file,
+      identity,
+      new_key_file,
+      recipient,
+      kdf,
+      min_score,
+    } => {
+      let inputs = batch::expand(&inputs, recursive, COVER_EXTENSIONS)?;
+      check_output(&inputs, &output)?;
+      let chunk_type = ChunkType::from_str(&chunk_type)?;
+
+      let current = PasswordPrompt {
+        prompt: "Current password: ",
+        ..PasswordPrompt::existing()
+      };
+      let decoder =
+        Decoder::with_session(open_session(key_file, &[], identity, &current)?)
+          .chunk_type(chunk_type.clone())
+          .mode(mode)
+          .bits_per_channel(bits_per_channel);
+      let new = PasswordPrompt {
+        prompt: "New password: ",
+        env_var: "TEST_NEW_PASSWORD",
+        min_score: Some(min_score),
+      };
+      let session = open_session(new_key_file, &recipient, None, &new)?
+        .kdf(kdf.params())?;
+      let encoder = Encoder::with_session(session)
+        .chunk_type(chunk_type)
+        .mode(mode)
+        .bits_per_channel(bits_per_channel);
+      batch::run(&inputs, |file| {
+        rekey(file, output.as_deref().unwrap_or(file), &decoder, &encoder)
+      })
+    }
+    Commands::Inspect { inputs, recursive } => {
+      batch::run(&batch::expand(&inputs, recursive, PNG_EXTENSIONS)?, inspect)
+    }
+    Commands::Scan { inputs, recursive } => {
+      batch::run(&batch::expand(&inputs, recursive, PNG_EXTENSIONS)?, scan)
+    }
+    Commands::Keygen { output } => keygen(output),
+    Commands::Genpass {
+      words,
+      separator,
+      min_score,
+    } => genpass(words, &separator, min_score),
+    Commands::Calibrate {
+      target_ms,
+      max_memory_mib,
+    } => calibrate(target_ms, max_memory_mib),
+    Commands::Verify {
+      inputs,
+      chunk_type,
+      mode,
+      bits_per_channel,
+      key_file,
+      identity,
+    } => {
+      let chunk_type = ChunkType::from_str(&chunk_type)?;
+      let session =
+        open_session(key_file, &[], identity, &PasswordPrompt::existing())?;
+      let decoder = Decoder::with_session(session)
+        .chunk_type(chunk_type)
+        .mode(mode)
+        .bits_per_channel(bits_per_channel);
+      match verify(&inputs, &decoder) {
+        Ok(verified) => {
+          println!("{}", verified);
+          Ok(())
+        }
+        // Monitoring tells failures apart by exit code
+        Err(failure) => {
+          eprintln!("Error: {}", failure);
+          std::process::exit(failure.check.exit_code().into())
+        }
+      }
+    }
     Commands::Print { input } => print_chunks(input),
+    Commands::Capacity {
+      input,
+      mode,
+      bits_per_channel,
+      ecc,
+    } => print_capacity(input, mode, bits_per_channel, ecc),
   }
 }
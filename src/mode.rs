@@ -0,0 +1,50 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Where an envelope is hidden inside an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+  /// An ancillary chunk of a user-chosen type
+  Chunk,
+  /// The least significant bits of the colour samples
+  Lsb,
+  /// The parity of paired palette colours, for indexed-colour images
+  Palette,
+  /// The lengths of the matches in the compressed image data, leaving both
+  /// the chunks and the pixels as they were
+  Deflate,
+  /// The parity of the colour of every visible pixel, which survives
+  /// lossless re-encoding by optimisers
+  Robust,
+  /// The non-zero AC coefficients of a JPEG, with F5 matrix embedding
+  F5,
+}
+
+impl FromStr for Mode {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "chunk" => Ok(Mode::Chunk),
+      "lsb" => Ok(Mode::Lsb),
+      "palette" => Ok(Mode::Palette),
+      "deflate" => Ok(Mode::Deflate),
+      "robust" => Ok(Mode::Robust),
+      "f5" => Ok(Mode::F5),
+      _ => Err("Mode must be one of: chunk, lsb, palette, deflate, robust, f5"),
+    }
+  }
+}
+
+impl fmt::Display for Mode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Mode::Chunk => write!(f, "chunk"),
+      Mode::Lsb => write!(f, "lsb"),
+      Mode::Palette => write!(f, "palette"),
+      Mode::Deflate => write!(f, "deflate"),
+      Mode::Robust => write!(f, "robust"),
+      Mode::F5 => write!(f, "f5"),
+    }
+  }
+}
@@ -0,0 +1,634 @@
+use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::png::{FilterStrategy, Pixels};
+use crate::Error;
+use crate::Result;
+
+/// A message stored in an incremental update of its own: a stream object
+/// nothing refers to, whose `/Type` is the chunk type
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Message {
+  fourcc: [u8; 4],
+  data: Vec<u8>,
+}
+
+/// The entries of the last trailer of the original document that an
+/// incremental update carries over, kept as they were written
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Trailer {
+  /// The offset of the last cross-reference section
+  xref_offset: usize,
+  /// One more than the highest object number in use
+  size: u32,
+  root: Vec<u8>,
+  info: Option<Vec<u8>>,
+  id: Option<Vec<u8>>,
+}
+
+/// A PDF document. Its bytes are never rewritten: each message is appended
+/// as an incremental update holding one unreferenced stream object and a
+/// cross-reference section for it, which readers load and ignore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdf {
+  /// The document without the updates holding messages
+  base: Vec<u8>,
+  trailer: Trailer,
+  messages: Vec<Message>,
+}
+
+impl Pdf {
+  /// Appends to `bytes` an incremental update holding `message` as object
+  /// `number`, chained to the cross-reference section at `prev`. Returns
+  /// the offset of the new cross-reference section.
+  fn write_update(
+    &self,
+    bytes: &mut Vec<u8>,
+    message: &Message,
+    number: u32,
+    prev: usize,
+  ) -> usize {
+    let offset = bytes.len();
+    bytes.extend_from_slice(
+      format!(
+        "{} 0 obj\n<< /Type /{} /Length {} >>\nstream\n",
+        number,
+        String::from_utf8_lossy(&message.fourcc),
+        message.data.len()
+      )
+      .as_bytes(),
+    );
+    bytes.extend_from_slice(&message.data);
+    bytes.extend_from_slice(b"\nendstream\nendobj\n");
+
+    let xref = bytes.len();
+    let trailer = &self.trailer;
+    bytes.extend_from_slice(
+      format!(
+        "xref\n{} 1\n{:010} 00000 n\r\ntrailer\n<< /Size {} /Root ",
+        number,
+        offset,
+        number + 1
+      )
+      .as_bytes(),
+    );
+    bytes.extend_from_slice(&trailer.root);
+    if let Some(info) = &trailer.info {
+      bytes.extend_from_slice(b" /Info ");
+      bytes.extend_from_slice(info);
+    }
+    if let Some(id) = &trailer.id {
+      bytes.extend_from_slice(b" /ID ");
+      bytes.extend_from_slice(id);
+    }
+    bytes.extend_from_slice(
+      format!(" /Prev {} >>\nstartxref\n{}\n%%EOF\n", prev, xref).as_bytes(),
+    );
+    xref
+  }
+}
+
+/// Parses a PDF document, setting aside the updates that hold messages so
+/// that storing a message again replaces them
+impl TryFrom<&[u8]> for Pdf {
+  type Error = Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    if !bytes.starts_with(b"%PDF-") {
+      return Err("Invalid PDF header".into());
+    }
+    let mut base = bytes;
+    let mut messages = Vec::new();
+    while let Some((offset, message)) = trailing_message(base) {
+      messages.insert(0, message);
+      base = &base[..offset];
+    }
+    let trailer = parse_trailer(base)?;
+    Ok(Self {
+      base: base.to_vec(),
+      trailer,
+      messages,
+    })
+  }
+}
+
+/// The offset the last `startxref` of `bytes` points to
+fn last_startxref(bytes: &[u8]) -> Option<usize> {
+  let keyword = b"startxref";
+  let start = bytes.windows(keyword.len()).rposition(|w| w == keyword)?;
+  let mut lexer = Lexer::new(bytes, start + keyword.len());
+  lexer.skip_space();
+  parse_number(lexer.regular())
+}
+
+fn parse_number<T: std::str::FromStr>(bytes: &[u8]) -> Option<T> {
+  if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+    return None;
+  }
+  std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// The message in the last incremental update of `bytes` and the offset
+/// the update starts at, if the update is exactly one `Pdf` writes
+fn trailing_message(bytes: &[u8]) -> Option<(usize, Message)> {
+  let xref = last_startxref(bytes)?;
+  let mut lexer = Lexer::new(bytes, xref);
+  if !lexer.eat(b"xref\n") {
+    return None;
+  }
+  let number = parse_number::<u32>(lexer.regular())?;
+  if !lexer.eat(b" 1\n") {
+    return None;
+  }
+  let offset = parse_number::<usize>(lexer.regular())?;
+  if !lexer.eat(b" 00000 n\r\ntrailer\n") {
+    return None;
+  }
+
+  let object = bytes.get(offset..xref)?;
+  let header = format!("{} 0 obj\n<< /Type /", number);
+  let object = object.strip_prefix(header.as_bytes())?;
+  let fourcc = object.get(..4)?.try_into().ok()?;
+  let object = object[4..].strip_prefix(b" /Length ")?;
+  let mut lexer = Lexer::new(object, 0);
+  let len = parse_number::<usize>(lexer.regular())?;
+  if !lexer.eat(b" >>\nstream\n") {
+    return None;
+  }
+  let data = object.get(lexer.position..lexer.position + len)?;
+  let rest = &object[lexer.position + len..];
+  if rest != b"\nendstream\nendobj\n" {
+    return None;
+  }
+  let message = Message {
+    fourcc,
+    data: data.to_vec(),
+  };
+  Some((offset, message))
+}
+
+/// Reads the trailer the last `startxref` of `bytes` points to, either
+/// after a classic cross-reference table or as the dictionary of a
+/// cross-reference stream
+fn parse_trailer(bytes: &[u8]) -> Result<Trailer> {
+  let xref_offset =
+    last_startxref(bytes).ok_or("PDF document has no startxref")?;
+  let mut lexer = Lexer::new(bytes, xref_offset);
+  if lexer.eat(b"xref") {
+    let keyword = b"trailer";
+    let start = find(bytes, lexer.position, keyword)
+      .ok_or("PDF cross-reference table has no trailer")?;
+    lexer.position = start + keyword.len();
+  } else {
+    // A cross-reference stream: "N G obj" and the stream dictionary
+    for _ in 0..2 {
+      lexer.skip_space();
+      parse_number::<u32>(lexer.regular())
+        .ok_or("PDF startxref does not point to a cross-reference section")?;
+    }
+    lexer.skip_space();
+    if !lexer.eat(b"obj") {
+      return Err(
+        "PDF startxref does not point to a cross-reference section".into(),
+      );
+    }
+  }
+
+  let mut trailer = Trailer {
+    xref_offset,
+    size: 0,
+    root: Vec::new(),
+    info: None,
+    id: None,
+  };
+  for (key, value) in lexer.dictionary()? {
+    match key {
+      b"Size" => {
+        trailer.size =
+          parse_number(value).ok_or("PDF trailer has an invalid /Size")?;
+      }
+      b"Root" => trailer.root = value.to_vec(),
+      b"Info" => trailer.info = Some(value.to_vec()),
+      b"ID" => trailer.id = Some(value.to_vec()),
+      b"Encrypt" => {
+        return Err("Encrypted PDF documents are not supported".into())
+      }
+      _ => {}
+    }
+  }
+  if trailer.root.is_empty() {
+    return Err("PDF trailer has no /Root".into());
+  }
+  if trailer.size == 0 {
+    return Err("PDF trailer has no /Size".into());
+  }
+  Ok(trailer)
+}
+
+/// The offset of the first `needle` in `bytes` at or after `from`
+fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+  let rest = bytes.get(from..)?;
+  let found = rest.windows(needle.len()).position(|w| w == needle)?;
+  Some(from + found)
+}
+
+/// The data of the stream whose dictionary holds the name `name` at
+/// `offset`, if that dictionary gives it as its `/Type` with a direct
+/// `/Length`
+fn stream_of_type<'a>(
+  bytes: &'a [u8],
+  offset: usize,
+  name: &str,
+) -> Option<&'a [u8]> {
+  let start = bytes[..offset].windows(2).rposition(|w| w == b"<<")?;
+  let mut lexer = Lexer::new(bytes, start);
+  let entries = lexer.dictionary().ok()?;
+  let value = |key: &[u8]| {
+    let entry = entries.iter().find(|(k, _)| *k == key);
+    entry.map(|(_, value)| *value)
+  };
+  if value(b"Type")? != name.as_bytes() {
+    return None;
+  }
+  let len = parse_number::<usize>(value(b"Length")?)?;
+  lexer.skip_space();
+  if !lexer.eat(b"stream") {
+    return None;
+  }
+  lexer.eat(b"\r");
+  lexer.eat(b"\n");
+  bytes.get(lexer.position..lexer.position + len)
+}
+
+/// Whether `byte` is neither white-space nor a delimiter, and so part of a
+/// name, number or keyword
+fn is_regular(byte: u8) -> bool {
+  !matches!(
+    byte,
+    b'\0'
+      | b'\t'
+      | b'\n'
+      | b'\x0C'
+      | b'\r'
+      | b' '
+      | b'('
+      | b')'
+      | b'<'
+      | b'>'
+      | b'['
+      | b']'
+      | b'{'
+      | b'}'
+      | b'/'
+      | b'%'
+  )
+}
+
+/// Just enough of a PDF tokenizer to read trailer and stream dictionaries,
+/// whose values are skipped over and returned as written
+struct Lexer<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Lexer<'a> {
+  fn new(bytes: &'a [u8], position: usize) -> Self {
+    Self { bytes, position }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.bytes.get(self.position).copied()
+  }
+
+  /// Skips white-space and comments
+  fn skip_space(&mut self) {
+    while let Some(byte) = self.peek() {
+      match byte {
+        b'\0' | b'\t' | b'\n' | b'\x0C' | b'\r' | b' ' => self.position += 1,
+        b'%' => {
+          while !matches!(self.peek(), None | Some(b'\n' | b'\r')) {
+            self.position += 1;
+          }
+        }
+        _ => break,
+      }
+    }
+  }
+
+  /// Consumes `token` if the input continues with it
+  fn eat(&mut self, token: &[u8]) -> bool {
+    let rest = self.bytes.get(self.position..).unwrap_or_default();
+    let matches = rest.starts_with(token);
+    if matches {
+      self.position += token.len();
+    }
+    matches
+  }
+
+  /// Consumes a run of regular characters
+  fn regular(&mut self) -> &'a [u8] {
+    let start = self.position;
+    while self.peek().is_some_and(is_regular) {
+      self.position += 1;
+    }
+    &self.bytes[start..self.position]
+  }
+
+  /// Reads a dictionary, returning each key with its value as written
+  fn dictionary(&mut self) -> Result<Vec<(&'a [u8], &'a [u8])>> {
+    self.skip_space();
+    if !self.eat(b"<<") {
+      return Err("Expected a PDF dictionary".into());
+    }
+    let mut entries = Vec::new();
+    loop {
+      self.skip_space();
+      if self.eat(b">>") {
+        return Ok(entries);
+      }
+      if !self.eat(b"/") {
+        return Err("Invalid PDF dictionary".into());
+      }
+      let key = self.regular();
+      self.skip_space();
+      let start = self.position;
+      self.value()?;
+      entries.push((key, &self.bytes[start..self.position]));
+    }
+  }
+
+  /// Skips one object, which may be an indirect reference
+  fn value(&mut self) -> Result<()> {
+    self.skip_space();
+    match self.peek() {
+      Some(b'<') if self.bytes.get(self.position + 1) == Some(&b'<') => {
+        self.dictionary()?;
+      }
+      Some(b'<') => {
+        let rest = &self.bytes[self.position..];
+        let end = rest
+          .iter()
+          .position(|&byte| byte == b'>')
+          .ok_or("PDF string is truncated")?;
+        self.position += end + 1;
+      }
+      Some(b'(') => self.literal_string()?,
+      Some(b'[') => {
+        self.position += 1;
+        loop {
+          self.skip_space();
+          if self.eat(b"]") {
+            break;
+          }
+          if self.peek().is_none() {
+            return Err("PDF array is truncated".into());
+          }
+          self.value()?;
+        }
+      }
+      Some(b'/') => {
+        self.position += 1;
+        self.regular();
+      }
+      _ => {
+        let token = self.regular();
+        if token.is_empty() {
+          return Err("Invalid PDF object".into());
+        }
+        // "N G R" is a single value
+        let number = self.position;
+        self.skip_space();
+        let generation = self.regular();
+        self.skip_space();
+        let is_reference = parse_number::<u32>(token).is_some()
+          && parse_number::<u16>(generation).is_some()
+          && self.eat(b"R")
+          && !self.peek().is_some_and(is_regular);
+        if !is_reference {
+          self.position = number;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Skips a string in parentheses, which may nest
+  fn literal_string(&mut self) -> Result<()> {
+    let mut depth = 0;
+    loop {
+      let byte = self.peek().ok_or("PDF string is truncated")?;
+      self.position += 1;
+      match byte {
+        b'\\' => self.position += 1,
+        b'(' => depth += 1,
+        b')' => depth -= 1,
+        _ => {}
+      }
+      if depth == 0 {
+        return Ok(());
+      }
+    }
+  }
+}
+
+impl CoverImage for Pdf {
+  fn format(&self) -> Format {
+    Format::Pdf
+  }
+
+  fn as_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.base.clone();
+    if !bytes.ends_with(b"\n") && !bytes.ends_with(b"\r") {
+      bytes.push(b'\n');
+    }
+    let mut prev = self.trailer.xref_offset;
+    for (number, message) in (self.trailer.size..).zip(&self.messages) {
+      prev = self.write_update(&mut bytes, message, number, prev);
+    }
+    bytes
+  }
+
+  /// Messages in updates written by `Pdf` come first, then any other
+  /// stream whose `/Type` is the chunk type, in case the document was saved
+  /// again since and its updates merged
+  fn message_chunk(&self, chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    let fourcc = chunk_type.bytes();
+    if let Some(message) = self.messages.iter().find(|m| m.fourcc == fourcc) {
+      return Ok(Some(&message.data));
+    }
+    let name = format!("/{}", chunk_type);
+    let mut from = 0;
+    while let Some(found) = find(&self.base, from, name.as_bytes()) {
+      from = found + name.len();
+      if let Some(data) = stream_of_type(&self.base, found, &name) {
+        return Ok(Some(data));
+      }
+    }
+    Ok(None)
+  }
+
+  fn set_message_chunk(
+    &mut self,
+    chunk_type: &ChunkType,
+    data: Vec<u8>,
+  ) -> Result<()> {
+    let fourcc = chunk_type.bytes();
+    self.messages.retain(|message| message.fourcc != fourcc);
+    self.messages.push(Message { fourcc, data });
+    Ok(())
+  }
+
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    Err("PDF documents only hide messages in chunk mode".into())
+  }
+
+  fn encode_frames(
+    &mut self,
+    _frames: &[Pixels],
+    _filter: FilterStrategy,
+  ) -> Result<()> {
+    Err("PDF documents only hide messages in chunk mode".into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Decoder, Encoder, Mode};
+  use std::str::FromStr;
+
+  /// A PDF document of `objects`, numbered from 1, with a classic
+  /// cross-reference table and `entries` in its trailer besides /Size
+  fn pdf_bytes(objects: &[&str], entries: &str) -> Vec<u8> {
+    let mut bytes = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+      offsets.push(bytes.len());
+      let object = format!("{} 0 obj\n{}\nendobj\n", i + 1, object);
+      bytes.extend_from_slice(object.as_bytes());
+    }
+    let xref = bytes.len();
+    let size = objects.len() + 1;
+    let mut table = format!("xref\n0 {}\n0000000000 65535 f\r\n", size);
+    for offset in offsets {
+      table.push_str(&format!("{:010} 00000 n\r\n", offset));
+    }
+    table.push_str(&format!(
+      "trailer\n<< /Size {} {} >>\nstartxref\n{}\n%%EOF\n",
+      size, entries, xref
+    ));
+    bytes.extend_from_slice(table.as_bytes());
+    bytes
+  }
+
+  fn document() -> Vec<u8> {
+    pdf_bytes(
+      &[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>",
+        "<< /Title (Minutes \\(draft\\)) >>",
+      ],
+      "/Root 1 0 R /Info 4 0 R % the document information\n\
+       /ID [<0123456789ABCDEF> <0123456789ABCDEF>]",
+    )
+  }
+
+  #[test]
+  fn test_trailer() {
+    let pdf = Pdf::try_from(document().as_slice()).unwrap();
+    assert_eq!(pdf.trailer.size, 5);
+    assert_eq!(pdf.trailer.root, b"1 0 R");
+    assert_eq!(pdf.trailer.info.as_deref(), Some(&b"4 0 R"[..]));
+    let id = b"[<0123456789ABCDEF> <0123456789ABCDEF>]";
+    assert_eq!(pdf.trailer.id.as_deref(), Some(&id[..]));
+    assert!(pdf.messages.is_empty());
+    assert_eq!(pdf.as_bytes(), document());
+
+    // A cross-reference stream holds the trailer in its dictionary
+    let mut bytes =
+      b"%PDF-1.5\n1 0 obj\n<< /Type /Catalog >>\nendobj\n".to_vec();
+    let xref = bytes.len();
+    bytes.extend_from_slice(
+      b"2 0 obj\n<< /Type /XRef /Size 3 /W [1 2 1] /Root 1 0 R \
+        /Length 0 >>\nstream\n\nendstream\nendobj\n",
+    );
+    let end = format!("startxref\n{}\n%%EOF\n", xref);
+    bytes.extend_from_slice(end.as_bytes());
+    let pdf = Pdf::try_from(bytes.as_slice()).unwrap();
+    assert_eq!((pdf.trailer.size, pdf.trailer.xref_offset), (3, xref));
+    assert_eq!(pdf.trailer.root, b"1 0 R");
+  }
+
+  #[test]
+  fn test_rejects_invalid() {
+    assert!(Pdf::try_from(&b"%!PS-Adobe-3.0\n"[..]).is_err());
+    let no_root = pdf_bytes(&["<< /Type /Catalog >>"], "");
+    assert!(Pdf::try_from(no_root.as_slice()).is_err());
+    let encrypted = pdf_bytes(
+      &["<< /Type /Catalog >>", "<< /Filter /Standard >>"],
+      "/Root 1 0 R /Encrypt 2 0 R",
+    );
+    let error = Pdf::try_from(encrypted.as_slice()).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Encrypted PDF documents are not supported"
+    );
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    let encoder = Encoder::new("password").chunk_type(chunk_type.clone());
+    let decoder = Decoder::new("password").chunk_type(chunk_type);
+    let mut pdf = Pdf::try_from(document().as_slice()).unwrap();
+    encoder.encode(&mut pdf, "Minutes of the meeting").unwrap();
+    let bytes = pdf.as_bytes();
+    assert!(bytes.starts_with(&document()));
+
+    let mut pdf = Pdf::try_from(bytes.as_slice()).unwrap();
+    assert_eq!(pdf.base, document());
+    let decoded = decoder.decode(&pdf).unwrap();
+    assert_eq!(decoded.message, "Minutes of the meeting");
+
+    // Encoding again replaces the update rather than adding another
+    encoder.encode(&mut pdf, "Amended minutes").unwrap();
+    let again = pdf.as_bytes();
+    let marker = b"<< /Type /ruSt";
+    let count = again.windows(marker.len()).filter(|w| w == marker).count();
+    assert_eq!(count, 1);
+    let pdf = crate::cover::from_bytes(again).unwrap();
+    assert_eq!(decoder.decode(&*pdf).unwrap().message, "Amended minutes");
+    assert!(Decoder::new("password")
+      .mode(Mode::Lsb)
+      .decode(&*pdf)
+      .is_err());
+  }
+
+  #[test]
+  fn test_chained_updates() {
+    let mut pdf = Pdf::try_from(document().as_slice()).unwrap();
+    for (chunk_type, message) in [("ruSt", "First"), ("teSt", "Second")] {
+      let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+      Encoder::new("password")
+        .chunk_type(chunk_type)
+        .encode(&mut pdf, message)
+        .unwrap();
+    }
+    let bytes = pdf.as_bytes();
+    let pdf = Pdf::try_from(bytes.as_slice()).unwrap();
+    assert_eq!(pdf.messages.len(), 2);
+    assert_eq!(pdf.as_bytes(), bytes);
+
+    // Each update takes the next object number and points back to the last
+    let find = |from, needle| find(&bytes, from, needle).unwrap();
+    let prev = |from: usize| {
+      let mut lexer = Lexer::new(&bytes, find(from, b"/Prev ") + 6);
+      parse_number::<usize>(lexer.regular()).unwrap()
+    };
+    let first = find(0, b"5 0 obj\n<< /Type /ruSt");
+    let second = find(0, b"6 0 obj\n<< /Type /teSt");
+    assert_eq!(prev(first), last_startxref(&document()).unwrap());
+    assert_eq!(prev(second), find(first, b"xref\n5 1\n"));
+    assert_eq!(last_startxref(&bytes), Some(find(second, b"xref\n6 1\n")));
+  }
+}
@@ -1,21 +1,42 @@
+//! PNG images: their chunks, pixels and animation frames, streaming them
+//! chunk by chunk, and stripping or repairing them.
+
 use base64::{engine::general_purpose, Engine as _};
 use std::fmt;
 use std::path::PathBuf;
 
 use aes_gcm::aead::generic_array::GenericArray;
 
-use crate::aes::decrypt_message;
+use crate::aes::{decrypt_message, Key};
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::envelope::Envelope;
 use crate::Error;
 use crate::Result;
 
+mod apng;
+mod palette;
+mod pixels;
+mod repair;
+mod stream;
+mod strip;
+
+pub use apng::{Animation, Frame, FrameControl, ANIMATION_CHUNKS};
+pub use palette::{Palette, MAX_PALETTE_LEN};
+pub use pixels::{ColorType, FilterStrategy, FilterType, ImageHeader, Pixels};
+pub use repair::Issue;
+pub use stream::{ChunkHeader, PngReader, PngWriter, MAX_CHUNK_LEN};
+pub use strip::{StripOptions, Stripped, RENDERING_CHUNKS};
+
+/// A PNG image, as the list of its chunks
 #[derive(Debug)]
 pub struct Png {
   chunks: Vec<Chunk>,
 }
 
 impl Png {
+  /// The signature every PNG file starts with
   pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
   /// Creates a `Png` from a list of chunks using the correct header
@@ -26,13 +47,15 @@ impl Png {
   /// Creates a `Png` from a file path
   pub fn from_file(path: &PathBuf) -> Result<Self> {
     use std::fs::File;
-    use std::io::Read;
+    use std::io::BufReader;
 
-    let mut file = File::open(path)?;
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes)?;
+    Self::from_reader(BufReader::new(File::open(path)?))
+  }
 
-    Self::try_from(bytes.as_ref())
+  /// Reads a whole `Png` from `reader`
+  pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self> {
+    let chunks = PngReader::new(reader)?.collect::<Result<Vec<_>>>()?;
+    Ok(Self { chunks })
   }
 
   /// Appends a chunk to the end of this `Png` file's `Chunk` list.
@@ -50,6 +73,11 @@ impl Png {
     Some(self.chunks.remove(index))
   }
 
+  /// Keeps only the chunks for which `keep` returns true
+  pub fn retain_chunks(&mut self, keep: impl FnMut(&Chunk) -> bool) {
+    self.chunks.retain(keep);
+  }
+
   /// The header of this PNG.
   pub fn header(&self) -> &[u8] {
     &Self::STANDARD_HEADER
@@ -69,20 +97,116 @@ impl Png {
       .find(|chunk| chunk.chunk_type().to_string() == chunk_type)
   }
 
+  /// Parses the IHDR chunk of this `Png`
+  pub fn image_header(&self) -> Result<ImageHeader> {
+    let ihdr = self.chunk_by_type("IHDR").ok_or("IHDR chunk not found")?;
+    ImageHeader::try_from(ihdr)
+  }
+
+  /// Replaces the IHDR chunk of this `Png` with `header`. The image data is
+  /// left as is, so it must be re-encoded to match.
+  pub fn set_image_header(&mut self, header: &ImageHeader) -> Result<()> {
+    let ihdr = self
+      .chunks
+      .iter_mut()
+      .find(|chunk| chunk.chunk_type().to_string() == "IHDR")
+      .ok_or("IHDR chunk not found")?;
+    *ihdr = header.as_chunk();
+    Ok(())
+  }
+
+  /// Returns the contents of every IDAT chunk concatenated in order
+  pub fn idat_data(&self) -> Vec<u8> {
+    self
+      .chunks
+      .iter()
+      .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+      .flat_map(|chunk| chunk.data().iter().copied())
+      .collect()
+  }
+
+  /// Inflates and unfilters the image data of this `Png`
+  pub fn decode_pixels(&self) -> Result<Pixels> {
+    Pixels::decode(self.image_header()?, &self.idat_data())
+  }
+
+  /// Inflates the image data of this `Png` into its filtered scanlines,
+  /// each starting with its filter type byte
+  pub fn inflate_idat(&self) -> Result<Vec<u8>> {
+    pixels::inflate(&self.image_header()?, &self.idat_data())
+  }
+
+  /// Replaces the image data of this `Png` with the zlib stream `idat`,
+  /// spread evenly over as many IDAT chunks as it had before
+  pub fn set_idat_data(&mut self, idat: &[u8]) -> Result<()> {
+    let indices = self
+      .chunks
+      .iter()
+      .enumerate()
+      .filter(|(_, chunk)| chunk.chunk_type().to_string() == "IDAT")
+      .map(|(index, _)| index)
+      .collect::<Vec<_>>();
+    if indices.is_empty() {
+      return Err("IDAT chunk not found".into());
+    }
+
+    let part_len = idat.len().div_ceil(indices.len()).max(1);
+    let mut parts = idat.chunks(part_len);
+    for index in indices {
+      let part = parts.next().unwrap_or_default();
+      self.chunks[index] =
+        Chunk::new(ChunkType::try_from(*b"IDAT")?, part.to_vec());
+    }
+    Ok(())
+  }
+
+  /// Replaces the IDAT chunks of this `Png` with a single IDAT chunk holding
+  /// `pixels`, filtered according to `strategy`
+  pub fn encode_pixels(
+    &mut self,
+    pixels: &Pixels,
+    strategy: FilterStrategy,
+  ) -> Result<()> {
+    if *pixels.header() != self.image_header()? {
+      return Err("Pixels do not match the IHDR of this PNG".into());
+    }
+    let index = self
+      .chunks
+      .iter()
+      .position(|chunk| chunk.chunk_type().to_string() == "IDAT")
+      .ok_or("IDAT chunk not found")?;
+    self
+      .chunks
+      .retain(|chunk| chunk.chunk_type().to_string() != "IDAT");
+
+    let chunk_type = ChunkType::try_from(*b"IDAT")?;
+    let idat = Chunk::new(chunk_type, pixels.encode(strategy));
+    self.chunks.insert(index, idat);
+    Ok(())
+  }
+
   /// Returns this `Png` as a byte sequence.
   /// These bytes will contain the header followed by the bytes of all of the chunks
   pub fn as_bytes(&self) -> Vec<u8> {
-    let mut bytes = Vec::new();
+    let mut bytes = Vec::with_capacity(self.byte_len());
 
     bytes.extend_from_slice(&Self::STANDARD_HEADER);
 
     for chunk in &self.chunks {
-      bytes.extend_from_slice(chunk.as_bytes().as_ref());
+      chunk
+        .write_to(&mut bytes)
+        .expect("writing to a Vec cannot fail");
     }
 
     bytes
   }
 
+  /// The number of bytes this `Png` takes once serialised
+  pub fn byte_len(&self) -> usize {
+    Self::STANDARD_HEADER.len()
+      + self.chunks.iter().map(Chunk::byte_len).sum::<usize>()
+  }
+
   /// Encode a message into a PNG file and saves the result
   pub fn encode_message(
     &mut self,
@@ -98,17 +222,25 @@ impl Png {
     Ok(())
   }
 
-  pub fn decode_message(
-    &self,
-    chunk_type: &str,
-    password: &str,
-  ) -> Result<String> {
+  /// The type of the chunk that held the nonce of messages encoded before
+  /// envelopes existed: `chunk_type` with its first letter replaced by `n`
+  pub fn legacy_nonce_chunk_type(chunk_type: &str) -> String {
+    format!("n{}", &chunk_type[1..])
+  }
+
+  /// Decrypts the message stored in the chunk of type `chunk_type`
+  pub fn decode_message(&self, chunk_type: &str, key: &Key) -> Result<String> {
     let message_chunk = self
       .chunk_by_type(chunk_type)
       .ok_or("Message chunk not found")?;
-    let nonce_chunk_type = format!("n{}", &chunk_type.to_string()[1..]);
+    if Envelope::is_envelope(message_chunk.data()) {
+      return Envelope::try_from(message_chunk.data())?.open(key);
+    }
+
+    // Messages encoded before envelopes existed keep their nonce in a
+    // separate chunk
     let nonce_chunk = self
-      .chunk_by_type(&nonce_chunk_type)
+      .chunk_by_type(&Self::legacy_nonce_chunk_type(chunk_type))
       .ok_or("Nonce chunk not found")?;
 
     let base64_nonce = String::from_utf8(nonce_chunk.data().to_vec())?;
@@ -118,19 +250,25 @@ impl Png {
 
     let nonce = GenericArray::from_slice(&decoded_nonce);
 
-    let ciphertext = &message_chunk.data()[..];
-    let decrypted_message = decrypt_message(ciphertext, password, &nonce);
-
-    Ok(decrypted_message)
+    let ciphertext = message_chunk.data();
+    decrypt_message(ciphertext, key, nonce)
   }
 
   /// Save this `Png` to a file
   pub fn save(&self, path: PathBuf) -> Result<()> {
     use std::fs::File;
-    use std::io::Write;
+    use std::io::BufWriter;
 
-    let mut file = File::create(path)?;
-    file.write_all(self.as_bytes().as_ref())?;
+    self.write_to(BufWriter::new(File::create(path)?))
+  }
+
+  /// Writes this `Png` to `writer`
+  pub fn write_to<W: std::io::Write>(&self, writer: W) -> Result<()> {
+    let mut writer = PngWriter::new(writer)?;
+    for chunk in &self.chunks {
+      writer.write_chunk(chunk)?;
+    }
+    writer.finish()?;
     Ok(())
   }
 
@@ -148,9 +286,7 @@ impl TryFrom<&[u8]> for Png {
   type Error = Error;
 
   fn try_from(bytes: &[u8]) -> Result<Self> {
-    let header = &bytes[0..8];
-
-    if header != Self::STANDARD_HEADER {
+    if !bytes.starts_with(&Self::STANDARD_HEADER) {
       return Err("Invalid PNG header".into());
     }
 
@@ -161,7 +297,7 @@ impl TryFrom<&[u8]> for Png {
     while index < bytes.len() {
       let chunk = Chunk::try_from(&bytes[index..])?;
 
-      index += chunk.as_bytes().len();
+      index += chunk.byte_len();
 
       chunks.push(chunk);
     }
@@ -182,21 +318,70 @@ impl fmt::Display for Png {
   }
 }
 
+impl CoverImage for Png {
+  fn format(&self) -> Format {
+    Format::Png
+  }
+
+  fn as_bytes(&self) -> Vec<u8> {
+    Png::as_bytes(self)
+  }
+
+  fn byte_len(&self) -> usize {
+    Png::byte_len(self)
+  }
+
+  fn message_chunk(&self, chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    let chunk = self.chunk_by_type(&chunk_type.to_string());
+    Ok(chunk.map(Chunk::data))
+  }
+
+  /// Also drops the nonce chunk of a message encoded before envelopes
+  /// existed
+  fn set_message_chunk(
+    &mut self,
+    chunk_type: &ChunkType,
+    data: Vec<u8>,
+  ) -> Result<()> {
+    self.remove_chunk(&Self::legacy_nonce_chunk_type(&chunk_type.to_string()));
+    self.encode_message(data, chunk_type.clone())
+  }
+
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    Png::decode_frames(self)
+  }
+
+  fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    filter: FilterStrategy,
+  ) -> Result<()> {
+    Png::encode_frames(self, frames, filter)
+  }
+
+  fn as_png(&self) -> Option<&Png> {
+    Some(self)
+  }
+
+  fn as_png_mut(&mut self) -> Option<&mut Png> {
+    Some(self)
+  }
+}
+
 #[cfg(test)]
 mod tests {
+  use super::pixels::FilterType;
   use super::*;
   use crate::chunk::Chunk;
   use crate::chunk_type::ChunkType;
   use std::convert::TryFrom;
 
   fn testing_chunks() -> Vec<Chunk> {
-    let mut chunks = Vec::new();
-
-    chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-    chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-    chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-    chunks
+    vec![
+      chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+      chunk_from_strings("miDl", "I am another chunk").unwrap(),
+      chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+    ]
   }
 
   fn testing_png() -> Png {
@@ -326,6 +511,28 @@ mod tests {
     assert_eq!(actual, expected);
   }
 
+  #[test]
+  fn test_decode_pixels() {
+    let png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let pixels = png.decode_pixels().unwrap();
+    assert_eq!((pixels.width(), pixels.height()), (50, 50));
+    assert_eq!(pixels.header().color_type, ColorType::Rgba);
+    assert_eq!(pixels.sample_count(), 50 * 50 * 4);
+  }
+
+  #[test]
+  fn test_encode_pixels() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let mut pixels = png.decode_pixels().unwrap();
+    pixels.set_sample(0, pixels.sample(0) ^ 1);
+
+    let strategy = FilterStrategy::Fixed(FilterType::Paeth);
+    png.encode_pixels(&pixels, strategy).unwrap();
+
+    assert_eq!(png.chunks().len(), 7);
+    assert_eq!(png.decode_pixels().unwrap(), pixels);
+  }
+
   #[test]
   fn test_png_trait_impls() {
     let chunk_bytes: Vec<u8> = testing_chunks()
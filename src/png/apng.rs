@@ -0,0 +1,473 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{FilterStrategy, ImageHeader, Pixels, Png};
+use crate::Result;
+
+/// The chunks that make up an animation, kept by `strip` by default
+pub const ANIMATION_CHUNKS: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// Length of the data of an fcTL chunk
+const FRAME_CONTROL_LEN: usize = 26;
+
+/// How a frame is placed on the canvas, read from its fcTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+  /// Position of the chunk among the fcTL and fdAT chunks
+  pub sequence: u32,
+  /// Width of the frame in pixels
+  pub width: u32,
+  /// Height of the frame in pixels
+  pub height: u32,
+  /// Where the frame starts from the left of the canvas
+  pub x_offset: u32,
+  /// Where the frame starts from the top of the canvas
+  pub y_offset: u32,
+  /// Numerator of the frame delay, in seconds
+  pub delay_num: u16,
+  /// Denominator of the frame delay, 0 meaning 100
+  pub delay_den: u16,
+  /// What happens to the frame's region before the next frame
+  pub dispose_op: u8,
+  /// Whether the frame replaces or is blended over the canvas
+  pub blend_op: u8,
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+  type Error = crate::Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self> {
+    let data = chunk.data();
+    if data.len() != FRAME_CONTROL_LEN {
+      return Err("fcTL chunk has the wrong length".into());
+    }
+    let u32_at = |i: usize| {
+      u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+    };
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    Ok(Self {
+      sequence: u32_at(0),
+      width: u32_at(4),
+      height: u32_at(8),
+      x_offset: u32_at(12),
+      y_offset: u32_at(16),
+      delay_num: u16_at(20),
+      delay_den: u16_at(22),
+      dispose_op: data[24],
+      blend_op: data[25],
+    })
+  }
+}
+
+/// Where the image data of a frame lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FrameData {
+  /// The frame is the default image, stored in IDAT chunks
+  Default,
+  /// The indices of the fdAT chunks holding the frame
+  Chunks(Vec<usize>),
+}
+
+/// A frame of an animation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  /// Where the frame goes and for how long
+  pub control: FrameControl,
+  data: FrameData,
+}
+
+impl Frame {
+  /// Returns true if this frame is the default image
+  pub fn is_default_image(&self) -> bool {
+    self.data == FrameData::Default
+  }
+}
+
+/// The animation of an APNG, read from its acTL, fcTL and fdAT chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+  /// How many times the animation plays, or 0 to loop forever
+  pub num_plays: u32,
+  /// The frames, in display order
+  pub frames: Vec<Frame>,
+}
+
+impl Animation {
+  /// The frames stored in fdAT chunks, in file order
+  fn fdat_frames(&self) -> impl Iterator<Item = &[usize]> {
+    self.frames.iter().filter_map(|frame| match &frame.data {
+      FrameData::Default => None,
+      FrameData::Chunks(indices) => Some(indices.as_slice()),
+    })
+  }
+}
+
+fn is(chunk: &Chunk, chunk_type: &str) -> bool {
+  chunk.chunk_type().to_string() == chunk_type
+}
+
+/// The sequence number every fcTL and fdAT chunk starts with
+fn sequence(chunk: &Chunk) -> Result<u32> {
+  let data = chunk.data();
+  if data.len() < 4 {
+    return Err(format!("{} chunk is too short", chunk.chunk_type()).into());
+  }
+  Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+impl Png {
+  /// Parses and checks the animation of this `Png`, or returns `None` if
+  /// it is a still image. The frame count must match acTL, sequence numbers
+  /// must count up from 0 across fcTL and fdAT chunks, and every frame must
+  /// have image data and fit on the canvas.
+  pub fn animation(&self) -> Result<Option<Animation>> {
+    let chunks = self.chunks();
+    let Some(actl_index) = chunks.iter().position(|chunk| is(chunk, "acTL"))
+    else {
+      if chunks
+        .iter()
+        .any(|chunk| is(chunk, "fcTL") || is(chunk, "fdAT"))
+      {
+        return Err("Frame chunks found without an acTL chunk".into());
+      }
+      return Ok(None);
+    };
+
+    let actl = chunks[actl_index].data();
+    if actl.len() != 8 {
+      return Err("acTL chunk has the wrong length".into());
+    }
+    let num_frames = u32::from_be_bytes([actl[0], actl[1], actl[2], actl[3]]);
+    let num_plays = u32::from_be_bytes([actl[4], actl[5], actl[6], actl[7]]);
+    let first_idat = chunks
+      .iter()
+      .position(|chunk| is(chunk, "IDAT"))
+      .ok_or("IDAT chunk not found")?;
+    if actl_index > first_idat {
+      return Err("acTL chunk must come before the image data".into());
+    }
+
+    let header = self.image_header()?;
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut next_sequence = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+      if is(chunk, "fcTL") || is(chunk, "fdAT") {
+        let sequence = sequence(chunk)?;
+        if sequence != next_sequence {
+          return Err(
+            format!(
+              "{} chunk has sequence number {} instead of {}",
+              chunk.chunk_type(),
+              sequence,
+              next_sequence
+            )
+            .into(),
+          );
+        }
+        next_sequence += 1;
+      }
+
+      if is(chunk, "fcTL") {
+        if let Some(frame) = frames.last() {
+          if frame.data == FrameData::Chunks(Vec::new()) {
+            return Err("Frame has no image data".into());
+          }
+        }
+        let control = FrameControl::try_from(chunk)?;
+        if control.width == 0
+          || control.height == 0
+          || control.x_offset as u64 + control.width as u64
+            > header.width as u64
+          || control.y_offset as u64 + control.height as u64
+            > header.height as u64
+        {
+          return Err("Frame does not fit on the canvas".into());
+        }
+        let data = if index < first_idat {
+          if (control.width, control.height) != (header.width, header.height)
+            || control.x_offset != 0
+            || control.y_offset != 0
+          {
+            return Err("The default image frame must fill the canvas".into());
+          }
+          FrameData::Default
+        } else {
+          FrameData::Chunks(Vec::new())
+        };
+        frames.push(Frame { control, data });
+      } else if is(chunk, "fdAT") {
+        match frames.last_mut().map(|frame| &mut frame.data) {
+          Some(FrameData::Chunks(indices)) => indices.push(index),
+          _ => return Err("fdAT chunk does not follow an fcTL chunk".into()),
+        }
+      }
+    }
+
+    if frames.last().map(|frame| &frame.data)
+      == Some(&FrameData::Chunks(Vec::new()))
+    {
+      return Err("Frame has no image data".into());
+    }
+    if frames.len() as u64 != num_frames as u64 || num_frames == 0 {
+      return Err(
+        format!(
+          "acTL declares {} frames but {} were found",
+          num_frames,
+          frames.len()
+        )
+        .into(),
+      );
+    }
+    Ok(Some(Animation { num_plays, frames }))
+  }
+
+  /// Inflates and unfilters the default image followed by every frame
+  /// stored in fdAT chunks. A still image has a single frame.
+  pub fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    let mut frames = vec![self.decode_pixels()?];
+    let Some(animation) = self.animation()? else {
+      return Ok(frames);
+    };
+
+    let header = self.image_header()?;
+    for (frame, indices) in animation
+      .frames
+      .iter()
+      .filter(|frame| !frame.is_default_image())
+      .zip(animation.fdat_frames())
+    {
+      let data = indices
+        .iter()
+        .flat_map(|&index| self.chunks()[index].data()[4..].iter().copied())
+        .collect::<Vec<_>>();
+      let header = ImageHeader {
+        width: frame.control.width,
+        height: frame.control.height,
+        ..header
+      };
+      frames.push(Pixels::decode(header, &data)?);
+    }
+    Ok(frames)
+  }
+
+  /// Replaces the image data of the default image and of every fdAT frame
+  /// with `frames`, in the order `decode_frames` returns them. Each frame
+  /// ends up in a single chunk, and the sequence numbers of the animation
+  /// are renumbered to stay consecutive.
+  pub fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    strategy: FilterStrategy,
+  ) -> Result<()> {
+    let (default, rest) = frames.split_first().ok_or("No frames to encode")?;
+    let animation = self.animation()?;
+    let fdat_frames = animation
+      .as_ref()
+      .map(|animation| animation.fdat_frames().collect::<Vec<_>>())
+      .unwrap_or_default();
+    if rest.len() != fdat_frames.len() {
+      return Err(
+        format!(
+          "Expected {} frames but got {}",
+          fdat_frames.len() + 1,
+          frames.len()
+        )
+        .into(),
+      );
+    }
+    // Sizes are checked before anything changes
+    if *default.header() != self.image_header()? {
+      return Err("Pixels do not match the IHDR of this PNG".into());
+    }
+    if let Some(animation) = &animation {
+      let frames = animation.frames.iter().filter(|f| !f.is_default_image());
+      for (frame, pixels) in frames.zip(rest) {
+        let (width, height) = (frame.control.width, frame.control.height);
+        if (pixels.width(), pixels.height()) != (width, height) {
+          return Err("Frame size does not match its fcTL chunk".into());
+        }
+      }
+    }
+
+    // Every frame is replaced by one fdAT at the place of its first one
+    let mut replaced = Vec::with_capacity(self.chunks.len());
+    let fdat_type = ChunkType::try_from(*b"fdAT")?;
+    for (index, chunk) in self.chunks.drain(..).enumerate() {
+      if !is(&chunk, "fdAT") {
+        replaced.push(chunk);
+        continue;
+      }
+      let frame = fdat_frames.iter().position(|indices| indices[0] == index);
+      if let Some(frame) = frame {
+        let mut data = vec![0; 4];
+        data.extend(rest[frame].encode(strategy));
+        replaced.push(Chunk::new(fdat_type.clone(), data));
+      }
+    }
+    self.chunks = replaced;
+    self.renumber_frames();
+    self.encode_pixels(default, strategy)
+  }
+
+  /// Rewrites the sequence numbers of the fcTL and fdAT chunks to count up
+  /// from 0 in file order
+  fn renumber_frames(&mut self) {
+    let mut sequence = 0u32;
+    for chunk in &mut self.chunks {
+      if is(chunk, "fcTL") || is(chunk, "fdAT") {
+        let mut data = chunk.data().to_vec();
+        data[..4].copy_from_slice(&sequence.to_be_bytes());
+        *chunk = Chunk::new(chunk.chunk_type().clone(), data);
+        sequence += 1;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::png::{ColorType, StripOptions};
+  use crate::{Decoder, Encoder, Mode};
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+  }
+
+  fn frame_control(sequence: u32, width: u32, height: u32) -> Chunk {
+    let mut data = Vec::new();
+    for value in [sequence, width, height, 0, 0] {
+      data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&[0, 1, 0, 10, 0, 0]);
+    chunk("fcTL", data)
+  }
+
+  fn frame_pixels(width: u32, height: u32, seed: usize) -> Pixels {
+    let header = ImageHeader {
+      width,
+      height,
+      bit_depth: 8,
+      color_type: ColorType::Rgb,
+      interlaced: false,
+    };
+    let len = header.row_bytes(width as usize) * height as usize;
+    let data = (0..len).map(|i| (i * 7 + seed) as u8).collect();
+    Pixels::new(header, data).unwrap()
+  }
+
+  /// A 16x16 animation of three frames, the first being the default image.
+  /// The second frame is split over two fdAT chunks.
+  pub(crate) fn testing_apng() -> Png {
+    let default = frame_pixels(16, 16, 0);
+    let second = frame_pixels(8, 8, 1).encode(FilterStrategy::default());
+    let third = frame_pixels(16, 16, 2).encode(FilterStrategy::default());
+    let (second_a, second_b) = second.split_at(second.len() / 2);
+    let fdat = |sequence: u32, data: &[u8]| {
+      let mut bytes = sequence.to_be_bytes().to_vec();
+      bytes.extend_from_slice(data);
+      chunk("fdAT", bytes)
+    };
+
+    Png::from_chunks(vec![
+      default.header().as_chunk(),
+      chunk("acTL", [3u32.to_be_bytes(), 0u32.to_be_bytes()].concat()),
+      frame_control(0, 16, 16),
+      chunk("IDAT", default.encode(FilterStrategy::default())),
+      frame_control(1, 8, 8),
+      fdat(2, second_a),
+      fdat(3, second_b),
+      frame_control(4, 16, 16),
+      fdat(5, &third),
+      chunk("IEND", Vec::new()),
+    ])
+  }
+
+  #[test]
+  fn test_parse_animation() {
+    let png = testing_apng();
+    let animation = png.animation().unwrap().unwrap();
+    assert_eq!(animation.num_plays, 0);
+    assert_eq!(animation.frames.len(), 3);
+    assert!(animation.frames[0].is_default_image());
+    assert_eq!(animation.frames[1].control.width, 8);
+
+    let frames = png.decode_frames().unwrap();
+    assert_eq!(
+      frames,
+      [
+        frame_pixels(16, 16, 0),
+        frame_pixels(8, 8, 1),
+        frame_pixels(16, 16, 2)
+      ]
+    );
+
+    let still = Png::from_file(&"tests/cat.png".into()).unwrap();
+    assert!(still.animation().unwrap().is_none());
+    assert_eq!(still.decode_frames().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_invalid_animations() {
+    let mut png = testing_apng();
+    png.chunks[5] = chunk("fdAT", [9u32.to_be_bytes(), [0; 4]].concat());
+    let error = png.animation().unwrap_err().to_string();
+    assert_eq!(error, "fdAT chunk has sequence number 9 instead of 2");
+
+    let mut png = testing_apng();
+    png.chunks[1] = chunk("acTL", [2u32.to_be_bytes(), [0; 4]].concat());
+    assert!(png.animation().is_err());
+
+    let mut png = testing_apng();
+    png.chunks[4] = frame_control(1, 17, 8);
+    assert!(png.animation().is_err());
+
+    let mut png = testing_apng();
+    png.chunks.remove(1);
+    assert!(png.animation().is_err());
+  }
+
+  #[test]
+  fn test_encode_frames_renumbers() {
+    let mut png = testing_apng();
+    let mut frames = png.decode_frames().unwrap();
+    frames[1].set_sample(0, 0xAB);
+    png
+      .encode_frames(&frames, FilterStrategy::default())
+      .unwrap();
+
+    // The split frame now has a single fdAT, and numbering has no gap
+    let animation = png.animation().unwrap().unwrap();
+    assert_eq!(animation.frames[2].control.sequence, 3);
+    assert_eq!(png.decode_frames().unwrap(), frames);
+    let types = png
+      .chunks()
+      .iter()
+      .map(|chunk| chunk.chunk_type().to_string());
+    assert_eq!(
+      types.collect::<Vec<_>>(),
+      ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"]
+    );
+    assert!(png
+      .encode_frames(&frames[..2], FilterStrategy::default())
+      .is_err());
+  }
+
+  #[test]
+  fn test_lsb_across_frames() {
+    let password = "password";
+    let message = "A message too long for the default image alone. ".repeat(2);
+    let mut png = testing_apng();
+    let encoder = Encoder::new(password).mode(Mode::Lsb);
+    encoder.encode(&mut png, &message).unwrap();
+
+    let frames = png.decode_frames().unwrap();
+    assert_ne!(frames[1], frame_pixels(8, 8, 1));
+    assert_eq!(png.animation().unwrap().unwrap().frames.len(), 3);
+    let decoder = Decoder::new(password).mode(Mode::Lsb);
+    assert_eq!(decoder.decode(&png).unwrap().message, message);
+
+    png.strip(&StripOptions::default()).unwrap();
+    assert_eq!(decoder.decode(&png).unwrap().message, message);
+  }
+}
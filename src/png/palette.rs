@@ -0,0 +1,156 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{ColorType, Png};
+use crate::Result;
+
+/// The most entries a palette can hold
+pub const MAX_PALETTE_LEN: usize = 256;
+
+/// The colours of an indexed-colour image, read from its PLTE chunk, with
+/// the alpha of each entry read from its tRNS chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+  entries: Vec<[u8; 4]>,
+}
+
+impl Palette {
+  /// Creates a palette of RGBA `entries`, of which there must be between 1
+  /// and 256
+  pub fn new(entries: Vec<[u8; 4]>) -> Result<Self> {
+    if entries.is_empty() || entries.len() > MAX_PALETTE_LEN {
+      return Err("A palette holds between 1 and 256 entries".into());
+    }
+    Ok(Self { entries })
+  }
+
+  /// The RGBA colour of every entry, in index order
+  pub fn entries(&self) -> &[[u8; 4]] {
+    &self.entries
+  }
+
+  /// The number of entries
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  /// Always false, as a palette has at least one entry
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+}
+
+fn is(chunk: &Chunk, chunk_type: &str) -> bool {
+  chunk.chunk_type().to_string() == chunk_type
+}
+
+impl Png {
+  /// Reads the palette of this `Png`, with alpha from tRNS when present
+  pub fn palette(&self) -> Result<Palette> {
+    let plte = self.chunk_by_type("PLTE").ok_or("PLTE chunk not found")?;
+    if plte.data().len() % 3 != 0 {
+      return Err("PLTE chunk length is not a multiple of 3".into());
+    }
+    let alpha = match self.chunk_by_type("tRNS") {
+      Some(trns) if self.image_header()?.color_type == ColorType::Indexed => {
+        trns.data()
+      }
+      _ => &[],
+    };
+    let entries = plte
+      .data()
+      .chunks(3)
+      .enumerate()
+      .map(|(index, rgb)| {
+        [
+          rgb[0],
+          rgb[1],
+          rgb[2],
+          alpha.get(index).copied().unwrap_or(255),
+        ]
+      })
+      .collect();
+    Palette::new(entries)
+  }
+
+  /// Replaces the PLTE chunk with `palette`, writing the alpha of its
+  /// entries to a tRNS chunk when any is translucent. A histogram that no
+  /// longer matches the palette is dropped.
+  pub fn set_palette(&mut self, palette: &Palette) -> Result<()> {
+    let index = self
+      .chunks
+      .iter()
+      .position(|chunk| is(chunk, "PLTE"))
+      .ok_or("PLTE chunk not found")?;
+    let rgb = palette.entries.iter().flat_map(|entry| &entry[..3]);
+    let plte =
+      Chunk::new(ChunkType::try_from(*b"PLTE")?, rgb.copied().collect());
+    self.chunks[index] = plte;
+
+    // tRNS may leave out the trailing opaque entries
+    let mut alpha = palette
+      .entries
+      .iter()
+      .map(|entry| entry[3])
+      .collect::<Vec<_>>();
+    while alpha.last() == Some(&255) {
+      alpha.pop();
+    }
+    let is_stale = |chunk: &Chunk| {
+      is(chunk, "tRNS")
+        || is(chunk, "hIST") && chunk.data().len() != palette.len() * 2
+    };
+    self.retain_chunks(|chunk| !is_stale(chunk));
+    if !alpha.is_empty() {
+      let trns = Chunk::new(ChunkType::try_from(*b"tRNS")?, alpha);
+      self.chunks.insert(index + 1, trns);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::png::ImageHeader;
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+  }
+
+  #[test]
+  fn test_palette_round_trip() {
+    let header = ImageHeader {
+      width: 1,
+      height: 1,
+      bit_depth: 8,
+      color_type: ColorType::Indexed,
+      interlaced: false,
+    };
+    let mut png = Png::from_chunks(vec![
+      header.as_chunk(),
+      chunk("PLTE", vec![1, 2, 3, 4, 5, 6]),
+      chunk("tRNS", vec![7]),
+      chunk("hIST", vec![0, 1, 0, 0]),
+      chunk("IEND", Vec::new()),
+    ]);
+    let palette = png.palette().unwrap();
+    assert_eq!(palette.entries(), [[1, 2, 3, 7], [4, 5, 6, 255]]);
+
+    let entries = [palette.entries(), &[[8, 9, 10, 255]]].concat();
+    png
+      .set_palette(&Palette::new(entries.clone()).unwrap())
+      .unwrap();
+    assert_eq!(png.palette().unwrap().entries(), entries);
+    let types = png
+      .chunks()
+      .iter()
+      .map(|chunk| chunk.chunk_type().to_string());
+    assert_eq!(types.collect::<Vec<_>>(), ["IHDR", "PLTE", "tRNS", "IEND"]);
+
+    let opaque = Palette::new(vec![[0, 0, 0, 255]]).unwrap();
+    png.set_palette(&opaque).unwrap();
+    assert!(png.chunk_by_type("tRNS").is_none());
+    assert!(Palette::new(Vec::new()).is_err());
+  }
+}
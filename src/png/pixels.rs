@@ -0,0 +1,797 @@
+use std::fmt;
+use std::str::FromStr;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::Error;
+use crate::Result;
+
+/// Starting column, starting row, column step and row step of each of the
+/// seven Adam7 passes.
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+  (0, 0, 8, 8),
+  (4, 0, 8, 8),
+  (0, 4, 4, 8),
+  (2, 0, 4, 4),
+  (0, 2, 2, 4),
+  (1, 0, 2, 2),
+  (0, 1, 1, 2),
+];
+
+/// The compression level used when deflating filtered scanlines.
+const DEFLATE_LEVEL: u8 = 9;
+
+/// The colour types described in the IHDR section of the PNG spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+  /// One grey sample per pixel
+  Grayscale,
+  /// Red, green and blue samples
+  Rgb,
+  /// An index into the palette
+  Indexed,
+  /// A grey sample and an alpha sample
+  GrayscaleAlpha,
+  /// Red, green, blue and alpha samples
+  Rgba,
+}
+
+impl ColorType {
+  /// The number of samples (channels) that make up a single pixel
+  pub fn channels(&self) -> usize {
+    match self {
+      ColorType::Grayscale | ColorType::Indexed => 1,
+      ColorType::GrayscaleAlpha => 2,
+      ColorType::Rgb => 3,
+      ColorType::Rgba => 4,
+    }
+  }
+
+  /// Returns true if the last channel of a pixel is an alpha channel
+  pub fn has_alpha(&self) -> bool {
+    matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+  }
+
+  /// The value stored in the IHDR chunk for this colour type
+  pub fn to_u8(self) -> u8 {
+    match self {
+      ColorType::Grayscale => 0,
+      ColorType::Rgb => 2,
+      ColorType::Indexed => 3,
+      ColorType::GrayscaleAlpha => 4,
+      ColorType::Rgba => 6,
+    }
+  }
+
+  /// Returns true if `bit_depth` is allowed for this colour type
+  fn allows_bit_depth(&self, bit_depth: u8) -> bool {
+    match self {
+      ColorType::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+      ColorType::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+      _ => matches!(bit_depth, 8 | 16),
+    }
+  }
+}
+
+impl TryFrom<u8> for ColorType {
+  type Error = &'static str;
+
+  fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+    match value {
+      0 => Ok(ColorType::Grayscale),
+      2 => Ok(ColorType::Rgb),
+      3 => Ok(ColorType::Indexed),
+      4 => Ok(ColorType::GrayscaleAlpha),
+      6 => Ok(ColorType::Rgba),
+      _ => Err("Invalid colour type"),
+    }
+  }
+}
+
+/// The image properties stored in the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+  /// Width of the image in pixels
+  pub width: u32,
+  /// Height of the image in pixels
+  pub height: u32,
+  /// Bits per sample, or per palette index
+  pub bit_depth: u8,
+  /// Which samples make up a pixel
+  pub color_type: ColorType,
+  /// Whether the image data is stored in Adam7 passes
+  pub interlaced: bool,
+}
+
+impl ImageHeader {
+  /// The number of bits used by a single pixel
+  pub fn bits_per_pixel(&self) -> usize {
+    self.bit_depth as usize * self.color_type.channels()
+  }
+
+  /// The number of bytes a pixel occupies, rounded up to 1 as the filter
+  /// algorithms of the PNG spec require
+  pub fn filter_stride(&self) -> usize {
+    self.bits_per_pixel().div_ceil(8)
+  }
+
+  /// The number of bytes of an unfiltered scanline `width` pixels wide.
+  /// Saturates rather than overflowing; `data_len` checks that a whole
+  /// image can be addressed.
+  pub fn row_bytes(&self, width: usize) -> usize {
+    width.saturating_mul(self.bits_per_pixel()).div_ceil(8)
+  }
+
+  /// Like `row_bytes`, returning `None` if the scanline cannot be addressed
+  fn checked_row_bytes(&self, width: usize) -> Option<usize> {
+    Some(width.checked_mul(self.bits_per_pixel())?.div_ceil(8))
+  }
+
+  /// The number of bytes of the unfiltered scanlines of the whole image, or
+  /// an error if it is too large to address
+  pub fn data_len(&self) -> Result<usize> {
+    self
+      .checked_row_bytes(self.width as usize)
+      .and_then(|row_bytes| row_bytes.checked_mul(self.height as usize))
+      .ok_or_else(|| "Image is too large".into())
+  }
+
+  /// The number of samples stored in a single scanline of the full image
+  pub fn samples_per_row(&self) -> usize {
+    self.width as usize * self.color_type.channels()
+  }
+
+  /// Serialises this header into an IHDR chunk
+  pub fn as_chunk(&self) -> Chunk {
+    let mut data = Vec::with_capacity(13);
+    data.extend_from_slice(&self.width.to_be_bytes());
+    data.extend_from_slice(&self.height.to_be_bytes());
+    data.push(self.bit_depth);
+    data.push(self.color_type.to_u8());
+    data.extend_from_slice(&[0, 0, self.interlaced as u8]);
+
+    let chunk_type = ChunkType::try_from(*b"IHDR").expect("valid chunk type");
+    Chunk::new(chunk_type, data)
+  }
+}
+
+impl TryFrom<&Chunk> for ImageHeader {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self> {
+    if chunk.chunk_type().to_string() != "IHDR" {
+      return Err("Expected an IHDR chunk".into());
+    }
+    let data = chunk.data();
+    if data.len() != 13 {
+      return Err("Invalid IHDR length".into());
+    }
+
+    let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let bit_depth = data[8];
+    let color_type = ColorType::try_from(data[9])?;
+
+    if width == 0 || height == 0 {
+      return Err("Image dimensions must be non-zero".into());
+    }
+    if !color_type.allows_bit_depth(bit_depth) {
+      return Err("Invalid bit depth for colour type".into());
+    }
+    if data[10] != 0 || data[11] != 0 {
+      return Err("Unsupported compression or filter method".into());
+    }
+    let interlaced = match data[12] {
+      0 => false,
+      1 => true,
+      _ => return Err("Invalid interlace method".into()),
+    };
+
+    Ok(Self {
+      width,
+      height,
+      bit_depth,
+      color_type,
+      interlaced,
+    })
+  }
+}
+
+/// The five filter types defined by the PNG spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+  /// Bytes are stored as they are
+  None,
+  /// Each byte is predicted from the pixel to its left
+  Sub,
+  /// Each byte is predicted from the pixel above
+  Up,
+  /// Each byte is predicted from the mean of the left and upper pixels
+  Average,
+  /// Each byte is predicted from whichever of the left, upper and upper
+  /// left pixels is closest to their Paeth estimate
+  Paeth,
+}
+
+impl FilterType {
+  const ALL: [FilterType; 5] = [
+    FilterType::None,
+    FilterType::Sub,
+    FilterType::Up,
+    FilterType::Average,
+    FilterType::Paeth,
+  ];
+
+  /// The filter type byte that prefixes a filtered scanline
+  pub fn to_u8(self) -> u8 {
+    self as u8
+  }
+}
+
+impl TryFrom<u8> for FilterType {
+  type Error = &'static str;
+
+  fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+    FilterType::ALL
+      .get(value as usize)
+      .copied()
+      .ok_or("Invalid filter type")
+  }
+}
+
+/// The heuristic used to pick the filter type of each scanline when encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterStrategy {
+  /// Uses the same filter type for every scanline
+  Fixed(FilterType),
+  /// Picks, for each scanline, the filter type that produces the minimum sum
+  /// of absolute differences. Indexed and sub-byte images fall back to
+  /// `FilterType::None`, as recommended by the PNG spec.
+  #[default]
+  MinSum,
+}
+
+impl FromStr for FilterStrategy {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(FilterStrategy::Fixed(FilterType::None)),
+      "sub" => Ok(FilterStrategy::Fixed(FilterType::Sub)),
+      "up" => Ok(FilterStrategy::Fixed(FilterType::Up)),
+      "average" => Ok(FilterStrategy::Fixed(FilterType::Average)),
+      "paeth" => Ok(FilterStrategy::Fixed(FilterType::Paeth)),
+      "minsum" => Ok(FilterStrategy::MinSum),
+      _ => Err("Unknown filter strategy"),
+    }
+  }
+}
+
+impl fmt::Display for FilterStrategy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      FilterStrategy::Fixed(FilterType::None) => "none",
+      FilterStrategy::Fixed(FilterType::Sub) => "sub",
+      FilterStrategy::Fixed(FilterType::Up) => "up",
+      FilterStrategy::Fixed(FilterType::Average) => "average",
+      FilterStrategy::Fixed(FilterType::Paeth) => "paeth",
+      FilterStrategy::MinSum => "minsum",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// The decoded image data of a PNG: unfiltered, de-interlaced scanlines
+/// packed as described by the image header, without filter type bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pixels {
+  header: ImageHeader,
+  data: Vec<u8>,
+}
+
+impl Pixels {
+  /// Creates a `Pixels` buffer from raw scanlines laid out as described by
+  /// `header`
+  pub fn new(header: ImageHeader, data: Vec<u8>) -> Result<Self> {
+    if data.len() != header.data_len()? {
+      return Err("Pixel data does not match the image dimensions".into());
+    }
+    Ok(Self { header, data })
+  }
+
+  /// The header describing the layout of these pixels
+  pub fn header(&self) -> &ImageHeader {
+    &self.header
+  }
+
+  /// The width of the image in pixels
+  pub fn width(&self) -> u32 {
+    self.header.width
+  }
+
+  /// The height of the image in pixels
+  pub fn height(&self) -> u32 {
+    self.header.height
+  }
+
+  /// The raw, unfiltered scanlines of the image
+  pub fn data(&self) -> &[u8] {
+    &self.data
+  }
+
+  /// The raw, unfiltered scanline at row `y`
+  pub fn row(&self, y: usize) -> &[u8] {
+    let row_bytes = self.header.row_bytes(self.header.width as usize);
+    &self.data[y * row_bytes..(y + 1) * row_bytes]
+  }
+
+  /// The total number of samples (pixels times channels) in the image
+  pub fn sample_count(&self) -> usize {
+    self.header.samples_per_row() * self.header.height as usize
+  }
+
+  /// Returns the sample at `index`, counting samples left to right and top to
+  /// bottom. 16-bit samples are returned in native order.
+  pub fn sample(&self, index: usize) -> u16 {
+    let (offset, shift) = self.sample_position(index);
+    match self.header.bit_depth {
+      16 => u16::from_be_bytes([self.data[offset], self.data[offset + 1]]),
+      8 => self.data[offset] as u16,
+      depth => {
+        let mask = (1u8 << depth) - 1;
+        ((self.data[offset] >> shift) & mask) as u16
+      }
+    }
+  }
+
+  /// Overwrites the sample at `index`. Bits above the image's bit depth are
+  /// ignored.
+  pub fn set_sample(&mut self, index: usize, value: u16) {
+    let (offset, shift) = self.sample_position(index);
+    match self.header.bit_depth {
+      16 => {
+        let [high, low] = value.to_be_bytes();
+        self.data[offset] = high;
+        self.data[offset + 1] = low;
+      }
+      8 => self.data[offset] = value as u8,
+      depth => {
+        let mask = ((1u8 << depth) - 1) << shift;
+        let value = ((value as u8) << shift) & mask;
+        self.data[offset] = (self.data[offset] & !mask) | value;
+      }
+    }
+  }
+
+  /// Returns the byte offset and, for sub-byte depths, the bit shift of the
+  /// sample at `index`
+  fn sample_position(&self, index: usize) -> (usize, u32) {
+    let samples_per_row = self.header.samples_per_row();
+    let row_bytes = self.header.row_bytes(self.header.width as usize);
+    let (y, x) = (index / samples_per_row, index % samples_per_row);
+    let depth = self.header.bit_depth as usize;
+    let bit = x * depth;
+    let shift = (8 - depth % 8 - bit % 8) % 8;
+    (y * row_bytes + bit / 8, shift as u32)
+  }
+
+  /// Decodes the concatenated IDAT stream of an image described by `header`
+  pub fn decode(header: ImageHeader, idat: &[u8]) -> Result<Self> {
+    let passes = passes(&header);
+    let filtered = inflate(&header, idat)?;
+
+    if !header.interlaced {
+      let (w, h) = passes[0];
+      let data = unfilter(&header, &filtered, w, h)?;
+      return Self::new(header, data);
+    }
+
+    let row_bytes = header.row_bytes(header.width as usize);
+    let mut data = vec![0u8; header.data_len()?];
+    let mut offset = 0;
+    for (&(x0, y0, dx, dy), &(w, h)) in ADAM7_PASSES.iter().zip(passes.iter()) {
+      if w == 0 || h == 0 {
+        continue;
+      }
+      let len = (header.row_bytes(w) + 1) * h;
+      let pass = unfilter(&header, &filtered[offset..offset + len], w, h)?;
+      offset += len;
+
+      let pass_row_bytes = header.row_bytes(w);
+      for py in 0..h {
+        let src = &pass[py * pass_row_bytes..(py + 1) * pass_row_bytes];
+        let y = y0 + py * dy;
+        let dst = &mut data[y * row_bytes..(y + 1) * row_bytes];
+        for px in 0..w {
+          copy_pixel(&header, src, px, dst, x0 + px * dx);
+        }
+      }
+    }
+
+    Self::new(header, data)
+  }
+
+  /// Filters and deflates these pixels into an IDAT stream, interlacing them
+  /// if the header asks for it
+  pub fn encode(&self, strategy: FilterStrategy) -> Vec<u8> {
+    let header = &self.header;
+    let mut filtered = Vec::new();
+
+    if !header.interlaced {
+      let (w, h) = (header.width as usize, header.height as usize);
+      filter(header, &self.data, w, h, strategy, &mut filtered);
+      return compress_to_vec_zlib(&filtered, DEFLATE_LEVEL);
+    }
+
+    let row_bytes = header.row_bytes(header.width as usize);
+    for (&(x0, y0, dx, dy), &(w, h)) in
+      ADAM7_PASSES.iter().zip(passes(header).iter())
+    {
+      if w == 0 || h == 0 {
+        continue;
+      }
+      let pass_row_bytes = header.row_bytes(w);
+      let mut pass = vec![0u8; pass_row_bytes * h];
+      for py in 0..h {
+        let y = y0 + py * dy;
+        let src = &self.data[y * row_bytes..(y + 1) * row_bytes];
+        let dst = &mut pass[py * pass_row_bytes..(py + 1) * pass_row_bytes];
+        for px in 0..w {
+          copy_pixel(header, src, x0 + px * dx, dst, px);
+        }
+      }
+      filter(header, &pass, w, h, strategy, &mut filtered);
+    }
+
+    compress_to_vec_zlib(&filtered, DEFLATE_LEVEL)
+  }
+}
+
+/// Inflates the concatenated IDAT stream of an image described by `header`
+/// into its filtered scanlines, refusing to inflate more than the image holds
+pub(super) fn inflate(header: &ImageHeader, idat: &[u8]) -> Result<Vec<u8>> {
+  let expected = passes(header)
+    .iter()
+    .filter(|(w, h)| *w > 0 && *h > 0)
+    .try_fold(0usize, |total, &(w, h)| {
+      let len = header
+        .checked_row_bytes(w)?
+        .checked_add(1)?
+        .checked_mul(h)?;
+      total.checked_add(len)
+    })
+    .ok_or("Image is too large")?;
+
+  let filtered = decompress_to_vec_zlib_with_limit(idat, expected)
+    .map_err(|_| "Failed to inflate the IDAT stream")?;
+  if filtered.len() != expected {
+    return Err("IDAT stream does not match the image dimensions".into());
+  }
+  Ok(filtered)
+}
+
+/// The width and height of every reduced image stored in the IDAT stream.
+/// Non-interlaced images have a single pass covering the whole image.
+fn passes(header: &ImageHeader) -> Vec<(usize, usize)> {
+  let (width, height) = (header.width as usize, header.height as usize);
+  if !header.interlaced {
+    return vec![(width, height)];
+  }
+
+  ADAM7_PASSES
+    .iter()
+    .map(|&(x0, y0, dx, dy)| {
+      let w = if width > x0 {
+        (width - x0).div_ceil(dx)
+      } else {
+        0
+      };
+      let h = if height > y0 {
+        (height - y0).div_ceil(dy)
+      } else {
+        0
+      };
+      (w, h)
+    })
+    .collect()
+}
+
+/// Copies the pixel at column `src_x` of `src` to column `dst_x` of `dst`
+fn copy_pixel(
+  header: &ImageHeader,
+  src: &[u8],
+  src_x: usize,
+  dst: &mut [u8],
+  dst_x: usize,
+) {
+  let bits = header.bits_per_pixel();
+  if bits >= 8 {
+    let bytes = bits / 8;
+    dst[dst_x * bytes..(dst_x + 1) * bytes]
+      .copy_from_slice(&src[src_x * bytes..(src_x + 1) * bytes]);
+    return;
+  }
+
+  let mask = (1u8 << bits) - 1;
+  let src_shift = 8 - bits - (src_x * bits) % 8;
+  let dst_shift = 8 - bits - (dst_x * bits) % 8;
+  let value = (src[src_x * bits / 8] >> src_shift) & mask;
+  let byte = &mut dst[dst_x * bits / 8];
+  *byte = (*byte & !(mask << dst_shift)) | (value << dst_shift);
+}
+
+/// Reverses the per-scanline filters of a reduced image `width` x `height`
+fn unfilter(
+  header: &ImageHeader,
+  filtered: &[u8],
+  width: usize,
+  height: usize,
+) -> Result<Vec<u8>> {
+  let row_bytes = header.row_bytes(width);
+  let stride = header.filter_stride();
+  let mut data = vec![0u8; row_bytes * height];
+  let zeros = vec![0u8; row_bytes];
+
+  for y in 0..height {
+    let line = &filtered[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+    let filter_type = FilterType::try_from(line[0])?;
+    let (previous, current) = data.split_at_mut(y * row_bytes);
+    let previous = if y == 0 {
+      &zeros[..]
+    } else {
+      &previous[(y - 1) * row_bytes..]
+    };
+    let current = &mut current[..row_bytes];
+
+    for x in 0..row_bytes {
+      let a = if x >= stride { current[x - stride] } else { 0 };
+      let b = previous[x];
+      let c = if x >= stride { previous[x - stride] } else { 0 };
+      current[x] = line[x + 1].wrapping_add(predict(filter_type, a, b, c));
+    }
+  }
+
+  Ok(data)
+}
+
+/// Filters the scanlines of a reduced image `width` x `height` and appends
+/// them, each prefixed by its filter type, to `out`
+fn filter(
+  header: &ImageHeader,
+  data: &[u8],
+  width: usize,
+  height: usize,
+  strategy: FilterStrategy,
+  out: &mut Vec<u8>,
+) {
+  let row_bytes = header.row_bytes(width);
+  let zeros = vec![0u8; row_bytes];
+  let mut candidate = vec![0u8; row_bytes];
+  let mut best = vec![0u8; row_bytes];
+
+  let candidates: &[FilterType] = match strategy {
+    FilterStrategy::Fixed(filter_type) => &[filter_type],
+    FilterStrategy::MinSum
+      if header.color_type == ColorType::Indexed || header.bit_depth < 8 =>
+    {
+      &[FilterType::None]
+    }
+    FilterStrategy::MinSum => &FilterType::ALL,
+  };
+
+  for y in 0..height {
+    let current = &data[y * row_bytes..(y + 1) * row_bytes];
+    let previous = if y == 0 {
+      &zeros[..]
+    } else {
+      &data[(y - 1) * row_bytes..y * row_bytes]
+    };
+
+    let mut best_type = candidates[0];
+    let mut best_sum = u64::MAX;
+    for &filter_type in candidates {
+      filter_row(header, filter_type, current, previous, &mut candidate);
+      let sum = candidate
+        .iter()
+        .map(|&byte| (byte as i8).unsigned_abs() as u64)
+        .sum::<u64>();
+      if sum < best_sum {
+        best_sum = sum;
+        best_type = filter_type;
+        std::mem::swap(&mut best, &mut candidate);
+      }
+    }
+
+    out.push(best_type.to_u8());
+    out.extend_from_slice(&best);
+  }
+}
+
+/// Applies `filter_type` to `current` and writes the result into `out`
+fn filter_row(
+  header: &ImageHeader,
+  filter_type: FilterType,
+  current: &[u8],
+  previous: &[u8],
+  out: &mut [u8],
+) {
+  let stride = header.filter_stride();
+  for x in 0..current.len() {
+    let a = if x >= stride { current[x - stride] } else { 0 };
+    let b = previous[x];
+    let c = if x >= stride { previous[x - stride] } else { 0 };
+    out[x] = current[x].wrapping_sub(predict(filter_type, a, b, c));
+  }
+}
+
+/// The predictor of `filter_type` given the left (`a`), up (`b`) and
+/// upper-left (`c`) bytes
+fn predict(filter_type: FilterType, a: u8, b: u8, c: u8) -> u8 {
+  match filter_type {
+    FilterType::None => 0,
+    FilterType::Sub => a,
+    FilterType::Up => b,
+    FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+    FilterType::Paeth => {
+      let p = a as i16 + b as i16 - c as i16;
+      let pa = (p - a as i16).abs();
+      let pb = (p - b as i16).abs();
+      let pc = (p - c as i16).abs();
+      if pa <= pb && pa <= pc {
+        a
+      } else if pb <= pc {
+        b
+      } else {
+        c
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlaced: bool,
+  ) -> ImageHeader {
+    ImageHeader {
+      width,
+      height,
+      bit_depth,
+      color_type,
+      interlaced,
+    }
+  }
+
+  fn gradient(header: ImageHeader) -> Pixels {
+    let len = header.row_bytes(header.width as usize) * header.height as usize;
+    let mut pixels = Pixels::new(header, vec![0; len]).unwrap();
+    for index in 0..pixels.sample_count() {
+      let value = (index * 7 + index / 3) as u16;
+      pixels.set_sample(index, value.wrapping_mul(251));
+    }
+    pixels
+  }
+
+  #[test]
+  fn test_round_trip_every_format() {
+    let formats = [
+      (ColorType::Grayscale, 1),
+      (ColorType::Grayscale, 2),
+      (ColorType::Grayscale, 4),
+      (ColorType::Grayscale, 8),
+      (ColorType::Grayscale, 16),
+      (ColorType::Indexed, 1),
+      (ColorType::Indexed, 4),
+      (ColorType::Indexed, 8),
+      (ColorType::Rgb, 8),
+      (ColorType::Rgb, 16),
+      (ColorType::GrayscaleAlpha, 8),
+      (ColorType::GrayscaleAlpha, 16),
+      (ColorType::Rgba, 8),
+      (ColorType::Rgba, 16),
+    ];
+
+    for (color_type, bit_depth) in formats {
+      for interlaced in [false, true] {
+        let pixels =
+          gradient(header(13, 11, bit_depth, color_type, interlaced));
+        let idat = pixels.encode(FilterStrategy::MinSum);
+        let decoded = Pixels::decode(*pixels.header(), &idat).unwrap();
+        assert_eq!(
+          decoded, pixels,
+          "{:?} {} {}",
+          color_type, bit_depth, interlaced
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_round_trip_every_filter() {
+    for strategy in ["none", "sub", "up", "average", "paeth", "minsum"] {
+      let strategy = FilterStrategy::from_str(strategy).unwrap();
+      let pixels = gradient(header(9, 5, 8, ColorType::Rgba, false));
+      let idat = pixels.encode(strategy);
+      let decoded = Pixels::decode(*pixels.header(), &idat).unwrap();
+      assert_eq!(decoded, pixels);
+    }
+  }
+
+  #[test]
+  fn test_interlaced_tiny_image() {
+    // Images smaller than 8x8 leave some Adam7 passes empty
+    for (width, height) in [(1, 1), (2, 3), (5, 1)] {
+      let pixels = gradient(header(width, height, 8, ColorType::Rgb, true));
+      let idat = pixels.encode(FilterStrategy::default());
+      let decoded = Pixels::decode(*pixels.header(), &idat).unwrap();
+      assert_eq!(decoded, pixels);
+    }
+  }
+
+  #[test]
+  fn test_header_chunk_round_trip() {
+    let header = header(640, 480, 16, ColorType::Rgba, true);
+    let chunk = header.as_chunk();
+    assert_eq!(chunk.length(), 13);
+    assert_eq!(ImageHeader::try_from(&chunk).unwrap(), header);
+  }
+
+  #[test]
+  fn test_decode_known_scanlines() {
+    let header = header(2, 2, 8, ColorType::Grayscale, false);
+    #[rustfmt::skip]
+    let filtered = [
+      1, 10, 5,  // Sub: 10, 15
+      2, 1, 1,   // Up: 11, 16
+    ];
+    let idat = compress_to_vec_zlib(&filtered, 6);
+    let pixels = Pixels::decode(header, &idat).unwrap();
+    assert_eq!(pixels.data(), &[10, 15, 11, 16]);
+  }
+
+  #[test]
+  fn test_image_too_large() {
+    let huge = header(0x7fff_ffff, 0x7fff_ffff, 16, ColorType::Rgba, false);
+    assert!(huge.data_len().is_err());
+    assert!(Pixels::new(huge, Vec::new()).is_err());
+    let idat = compress_to_vec_zlib(&[0; 16], 6);
+    let error = Pixels::decode(huge, &idat).unwrap_err();
+    assert_eq!(error.to_string(), "Image is too large");
+    assert_eq!(
+      header(3, 2, 4, ColorType::Rgb, false).data_len().unwrap(),
+      10
+    );
+  }
+
+  #[test]
+  fn test_decode_truncated_stream() {
+    let pixels = gradient(header(4, 4, 8, ColorType::Rgb, false));
+    let idat = pixels.encode(FilterStrategy::default());
+    let result = Pixels::decode(*pixels.header(), &idat[..idat.len() / 2]);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn test_samples() {
+    let mut pixels = gradient(header(3, 2, 2, ColorType::Grayscale, false));
+    assert_eq!(pixels.sample_count(), 6);
+    pixels.set_sample(4, 0b11);
+    assert_eq!(pixels.sample(4), 0b11);
+    pixels.set_sample(4, 0b01);
+    assert_eq!(pixels.sample(4), 0b01);
+
+    let mut pixels = gradient(header(2, 2, 16, ColorType::Rgb, false));
+    pixels.set_sample(7, 0xABCD);
+    assert_eq!(pixels.sample(7), 0xABCD);
+    assert_eq!(&pixels.row(1)[2..4], &[0xAB, 0xCD]);
+  }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use std::io::Read;
+
+use crate::chunk::{Chunk, HDLC};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, MAX_CHUNK_LEN};
+use crate::Result;
+
+/// Length, type and CRC around the data of every chunk
+const CHUNK_OVERHEAD: usize = 12;
+
+/// A problem found while reading a PNG leniently
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+  /// The file does not start with the PNG signature
+  BadSignature,
+  /// The CRC of a chunk does not match its contents. The chunk is kept,
+  /// since what follows it still looks like a chunk.
+  BadCrc {
+    /// Where the chunk starts in the file
+    offset: usize,
+    /// The type of the chunk
+    chunk_type: ChunkType,
+    /// The CRC stored after the chunk
+    stored: u32,
+    /// The CRC of the chunk's type and data
+    computed: u32,
+  },
+  /// Bytes that could not be read as chunks, skipped up to the next chunk
+  /// whose CRC checks out or to the end of the file
+  Skipped {
+    /// Where the skipped bytes start in the file
+    offset: usize,
+    /// How many bytes were skipped
+    len: usize,
+  },
+}
+
+impl fmt::Display for Issue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Issue::BadSignature => write!(f, "damaged PNG signature"),
+      Issue::BadCrc {
+        offset,
+        chunk_type,
+        stored,
+        computed,
+      } => write!(
+        f,
+        "{} chunk at offset {} has CRC {:08x} instead of {:08x}",
+        chunk_type, offset, stored, computed
+      ),
+      Issue::Skipped { offset, len } => {
+        write!(f, "{} unreadable bytes at offset {}", len, offset)
+      }
+    }
+  }
+}
+
+/// A chunk read from the start of some bytes
+enum Parsed {
+  Valid(Chunk),
+  BadCrc(Chunk, u32),
+  Invalid,
+}
+
+impl Parsed {
+  fn read(bytes: &[u8]) -> Self {
+    let Some(len) = chunk_len(bytes) else {
+      return Parsed::Invalid;
+    };
+    let Ok(chunk_type) =
+      ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])
+    else {
+      return Parsed::Invalid;
+    };
+    let stored = stored_crc(&bytes[..len]);
+    let chunk = Chunk::new(chunk_type, bytes[8..len - 4].to_vec());
+    if chunk.crc() == stored {
+      Parsed::Valid(chunk)
+    } else {
+      Parsed::BadCrc(chunk, stored)
+    }
+  }
+}
+
+/// The length of the chunk `bytes` starts with, if it fits in `bytes`
+fn chunk_len(bytes: &[u8]) -> Option<usize> {
+  let header = bytes.get(..8)?;
+  let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+  if length > MAX_CHUNK_LEN || !header[4..].iter().all(u8::is_ascii_alphabetic)
+  {
+    return None;
+  }
+  let len = length as usize + CHUNK_OVERHEAD;
+  (len <= bytes.len()).then_some(len)
+}
+
+/// The CRC stored at the end of `chunk`, the bytes of a whole chunk
+fn stored_crc(chunk: &[u8]) -> u32 {
+  let crc = &chunk[chunk.len() - 4..];
+  u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]])
+}
+
+/// Returns true if `bytes` starts with a chunk whose CRC checks out. Unlike
+/// `Parsed::read`, nothing is copied, so every offset of a damaged region
+/// can be tried cheaply.
+fn starts_valid_chunk(bytes: &[u8]) -> bool {
+  chunk_len(bytes).is_some_and(|len| {
+    HDLC.checksum(&bytes[4..len - 4]) == stored_crc(&bytes[..len])
+  })
+}
+
+impl Png {
+  /// Reads a whole `Png` from `reader` without giving up on damage. Chunks
+  /// whose CRC is wrong are kept as long as a chunk follows them, and
+  /// unreadable bytes are skipped up to the next chunk with a valid CRC.
+  /// Every problem is returned along with the chunks that could be read.
+  pub fn from_reader_lenient<R: Read>(
+    mut reader: R,
+  ) -> Result<(Self, Vec<Issue>)> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let mut issues = Vec::new();
+    if !bytes.starts_with(&Self::STANDARD_HEADER) {
+      issues.push(Issue::BadSignature);
+    }
+    let mut chunks = Vec::new();
+    let mut offset = Self::STANDARD_HEADER.len().min(bytes.len());
+    while offset < bytes.len() {
+      let rest = &bytes[offset..];
+      match Parsed::read(rest) {
+        Parsed::Valid(chunk) => {
+          offset += chunk.byte_len();
+          chunks.push(chunk);
+          continue;
+        }
+        Parsed::BadCrc(chunk, stored) => {
+          // A damaged length would leave us in the middle of nowhere, so
+          // the chunk is only trusted if another one follows it
+          let next = &rest[chunk.byte_len()..];
+          if next.is_empty() || chunk_len(next).is_some() {
+            issues.push(Issue::BadCrc {
+              offset,
+              chunk_type: chunk.chunk_type().clone(),
+              stored,
+              computed: chunk.crc(),
+            });
+            offset += chunk.byte_len();
+            chunks.push(chunk);
+            continue;
+          }
+        }
+        Parsed::Invalid => {}
+      }
+
+      let len = (1..rest.len())
+        .find(|&skip| starts_valid_chunk(&rest[skip..]))
+        .unwrap_or(rest.len());
+      issues.push(Issue::Skipped { offset, len });
+      offset += len;
+    }
+
+    if chunks.is_empty() {
+      return Err("No PNG chunk could be read".into());
+    }
+    Ok((Self::from_chunks(chunks), issues))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_bytes() -> Vec<u8> {
+    Png::from_file(&"tests/cat.png".into()).unwrap().as_bytes()
+  }
+
+  /// The offset of the first chunk of type `chunk_type` in `bytes`
+  fn offset_of(bytes: &[u8], chunk_type: &[u8; 4]) -> usize {
+    bytes
+      .windows(4)
+      .position(|window| window == chunk_type)
+      .unwrap()
+      - 4
+  }
+
+  #[test]
+  fn test_intact_file() {
+    let bytes = testing_bytes();
+    let (png, issues) = Png::from_reader_lenient(bytes.as_slice()).unwrap();
+    assert!(issues.is_empty());
+    assert_eq!(png.as_bytes(), bytes);
+  }
+
+  #[test]
+  fn test_bad_crc_is_kept() {
+    let mut bytes = testing_bytes();
+    let offset = offset_of(&bytes, b"zTXt");
+    bytes[offset + 10] ^= 0xFF;
+    assert!(Png::from_reader(bytes.as_slice()).is_err());
+
+    let (png, issues) = Png::from_reader_lenient(bytes.as_slice()).unwrap();
+    assert_eq!(png.chunks().len(), 7);
+    assert!(matches!(
+      issues.as_slice(),
+      [Issue::BadCrc { offset: found, .. }] if *found == offset
+    ));
+  }
+
+  #[test]
+  fn test_resync_after_damage() {
+    let mut bytes = testing_bytes();
+    let offset = offset_of(&bytes, b"zTXt");
+    // A damaged length makes the chunk run into the next ones
+    bytes[offset] = 0x7F;
+    bytes.splice(offset + 20..offset + 20, [0xAA; 5]);
+    bytes.extend_from_slice(b"garbage");
+
+    let (png, issues) = Png::from_reader_lenient(bytes.as_slice()).unwrap();
+    let types = png
+      .chunks()
+      .iter()
+      .map(|chunk| chunk.chunk_type().to_string());
+    assert_eq!(
+      types.collect::<Vec<_>>(),
+      ["IHDR", "pHYs", "IDAT", "IEND", "teSt", "neSt"]
+    );
+    assert_eq!(
+      issues,
+      [
+        Issue::Skipped {
+          offset,
+          len: 45 + 12 + 5
+        },
+        Issue::Skipped {
+          offset: bytes.len() - 7,
+          len: 7
+        },
+      ]
+    );
+
+    bytes[0] = 0;
+    let (_, issues) = Png::from_reader_lenient(bytes.as_slice()).unwrap();
+    assert_eq!(issues[0], Issue::BadSignature);
+  }
+
+  #[test]
+  fn test_resync_through_chunk_lookalikes() {
+    let mut bytes = testing_bytes();
+    let offset = offset_of(&bytes, b"IEND");
+    // Every 8 bytes look like the header of a chunk that fits, but none
+    // has a valid CRC
+    let garbage = [0, 0, 1, 0, b'a', b'b', b'c', b'd'].repeat(1 << 16);
+    bytes.splice(offset..offset, garbage.iter().copied());
+
+    let (png, issues) = Png::from_reader_lenient(bytes.as_slice()).unwrap();
+    assert_eq!(png.chunks().len(), 7);
+    assert_eq!(
+      issues,
+      [Issue::Skipped {
+        offset,
+        len: garbage.len()
+      }]
+    );
+    assert!(!starts_valid_chunk(&garbage));
+    assert!(starts_valid_chunk(&bytes[offset + garbage.len()..]));
+  }
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Read, Write};
+
+use crate::chunk::{Chunk, HDLC};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// The largest data length a chunk may declare, per the PNG spec
+pub const MAX_CHUNK_LEN: u32 = (1 << 31) - 1;
+
+/// Chunk data is copied in pieces of this size so that large chunks never
+/// have to fit in memory
+const COPY_BUFFER_LEN: usize = 64 * 1024;
+
+/// The length and type of a chunk, read before its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkHeader {
+  /// The length of the chunk's data
+  pub length: u32,
+  /// The type of the chunk
+  pub chunk_type: ChunkType,
+}
+
+/// Reads a PNG one chunk at a time.
+///
+/// Every call to `next_header` must be followed by exactly one call to
+/// `read_data`, `copy_chunk` or `skip_data` for that header before the next
+/// chunk can be read.
+pub struct PngReader<R: Read> {
+  reader: R,
+}
+
+impl<R: Read> PngReader<R> {
+  /// Reads and checks the PNG signature
+  pub fn new(mut reader: R) -> Result<Self> {
+    let mut signature = [0u8; 8];
+    read_exact(&mut reader, &mut signature)?;
+    if signature != Png::STANDARD_HEADER {
+      return Err("Invalid PNG header".into());
+    }
+    Ok(Self { reader })
+  }
+
+  /// Reads the length and type of the next chunk. Returns `None` once the
+  /// input ends cleanly between two chunks.
+  pub fn next_header(&mut self) -> Result<Option<ChunkHeader>> {
+    let mut bytes = [0u8; 8];
+    let mut filled = 0;
+    while filled < bytes.len() {
+      match self.reader.read(&mut bytes[filled..]) {
+        Ok(0) => break,
+        Ok(n) => filled += n,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(e.into()),
+      }
+    }
+    match filled {
+      0 => return Ok(None),
+      8 => {}
+      _ => return Err("PNG is truncated".into()),
+    }
+
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if length > MAX_CHUNK_LEN {
+      return Err("Chunk length exceeds the PNG limit".into());
+    }
+    let chunk_type =
+      ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])?;
+    Ok(Some(ChunkHeader { length, chunk_type }))
+  }
+
+  /// Reads the data and CRC of the chunk described by `header`
+  pub fn read_data(&mut self, header: ChunkHeader) -> Result<Chunk> {
+    let mut data = Vec::new();
+    (&mut self.reader)
+      .take(header.length as u64)
+      .read_to_end(&mut data)?;
+    if data.len() != header.length as usize {
+      return Err("PNG is truncated".into());
+    }
+    let crc = self.read_crc()?;
+    if crc != Chunk::compute_crc(&header.chunk_type, &data) {
+      return Err("Invalid CRC".into());
+    }
+    Ok(Chunk::new(header.chunk_type, data))
+  }
+
+  /// Copies the chunk described by `header`, header and CRC included, to
+  /// `writer` without holding its data in memory. The CRC is still checked.
+  pub fn copy_chunk<W: Write>(
+    &mut self,
+    header: ChunkHeader,
+    writer: &mut W,
+  ) -> Result<()> {
+    writer.write_all(&header.length.to_be_bytes())?;
+    writer.write_all(&header.chunk_type.bytes())?;
+
+    let mut digest = HDLC.digest();
+    digest.update(&header.chunk_type.bytes());
+    let mut buffer = vec![0u8; COPY_BUFFER_LEN.min(header.length as usize)];
+    let mut remaining = header.length as usize;
+    while remaining > 0 {
+      let piece = &mut buffer[..COPY_BUFFER_LEN.min(remaining)];
+      read_exact(&mut self.reader, piece)?;
+      digest.update(piece);
+      writer.write_all(piece)?;
+      remaining -= piece.len();
+    }
+
+    let crc = self.read_crc()?;
+    if crc != digest.finalize() {
+      return Err("Invalid CRC".into());
+    }
+    writer.write_all(&crc.to_be_bytes())?;
+    Ok(())
+  }
+
+  /// Checks and discards the data of the chunk described by `header`
+  pub fn skip_data(&mut self, header: ChunkHeader) -> Result<()> {
+    self.copy_chunk(header, &mut io::sink())
+  }
+
+  /// Reads the next whole chunk, or `None` at the end of the input
+  pub fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+    match self.next_header()? {
+      Some(header) => self.read_data(header).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  /// Returns the underlying reader, positioned after the last chunk read
+  pub fn into_inner(self) -> R {
+    self.reader
+  }
+
+  fn read_crc(&mut self) -> Result<u32> {
+    let mut crc = [0u8; 4];
+    read_exact(&mut self.reader, &mut crc)?;
+    Ok(u32::from_be_bytes(crc))
+  }
+}
+
+impl<R: Read> Iterator for PngReader<R> {
+  type Item = Result<Chunk>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_chunk().transpose()
+  }
+}
+
+/// Writes a PNG one chunk at a time
+pub struct PngWriter<W: Write> {
+  writer: W,
+}
+
+impl<W: Write> PngWriter<W> {
+  /// Writes the PNG signature
+  pub fn new(mut writer: W) -> Result<Self> {
+    writer.write_all(&Png::STANDARD_HEADER)?;
+    Ok(Self { writer })
+  }
+
+  /// Writes a whole chunk
+  pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+    chunk.write_to(&mut self.writer)?;
+    Ok(())
+  }
+
+  /// Copies the chunk described by `header` from `reader` unchanged
+  pub fn copy_chunk<R: Read>(
+    &mut self,
+    reader: &mut PngReader<R>,
+    header: ChunkHeader,
+  ) -> Result<()> {
+    reader.copy_chunk(header, &mut self.writer)
+  }
+
+  /// Flushes the output and returns the underlying writer
+  pub fn finish(mut self) -> Result<W> {
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+}
+
+/// Like `Read::read_exact`, but reports a short read as a truncated PNG
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<()> {
+  reader.read_exact(buffer).map_err(|e| {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+      "PNG is truncated".into()
+    } else {
+      e.into()
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::str::FromStr;
+
+  fn testing_chunks() -> Vec<Chunk> {
+    ["FrSt", "miDl", "LASt"]
+      .iter()
+      .enumerate()
+      .map(|(i, chunk_type)| {
+        let data = vec![i as u8; 100_000 * i];
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+      })
+      .collect()
+  }
+
+  fn testing_bytes() -> Vec<u8> {
+    Png::from_chunks(testing_chunks()).as_bytes()
+  }
+
+  #[test]
+  fn test_read_chunks() {
+    let bytes = testing_bytes();
+    let reader = PngReader::new(bytes.as_slice()).unwrap();
+    let chunks = reader.collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(chunks.len(), 3);
+    for (read, expected) in chunks.iter().zip(testing_chunks()) {
+      assert_eq!(read.as_bytes(), expected.as_bytes());
+    }
+  }
+
+  #[test]
+  fn test_copy_and_replace() {
+    let bytes = testing_bytes();
+    let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+    let mut writer = PngWriter::new(Vec::new()).unwrap();
+    let replacement = Chunk::new(ChunkType::from_str("miDl").unwrap(), vec![7]);
+    while let Some(header) = reader.next_header().unwrap() {
+      if header.chunk_type.to_string() == "miDl" {
+        reader.skip_data(header).unwrap();
+        writer.write_chunk(&replacement).unwrap();
+      } else {
+        writer.copy_chunk(&mut reader, header).unwrap();
+      }
+    }
+
+    let mut chunks = testing_chunks();
+    chunks[1] = replacement;
+    let expected = Png::from_chunks(chunks).as_bytes();
+    assert_eq!(writer.finish().unwrap(), expected);
+  }
+
+  #[test]
+  fn test_invalid_input() {
+    let bytes = testing_bytes();
+    assert!(PngReader::new(&bytes[1..]).is_err());
+
+    let reader = PngReader::new(&bytes[..bytes.len() - 1]).unwrap();
+    assert!(reader.collect::<Result<Vec<_>>>().is_err());
+
+    let mut corrupted = bytes.clone();
+    let last = corrupted.len() - 5;
+    corrupted[last] ^= 1;
+    let reader = PngReader::new(corrupted.as_slice()).unwrap();
+    assert!(reader.collect::<Result<Vec<_>>>().is_err());
+
+    let mut too_long = bytes;
+    too_long[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+    let mut reader = PngReader::new(too_long.as_slice()).unwrap();
+    assert!(reader.next_header().is_err());
+  }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngReader, ANIMATION_CHUNKS};
+use crate::Result;
+
+/// The chunks every decoder needs; any other critical chunk is dropped
+const CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// Ancillary chunks that change how an image looks, kept by default
+pub const RENDERING_CHUNKS: [&str; 6] =
+  ["tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "sBIT"];
+
+/// Which chunks `Png::strip` keeps and how it tidies them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripOptions {
+  /// The ancillary chunk types kept next to the critical chunks
+  pub keep: Vec<ChunkType>,
+  /// Whether the IDAT chunks are joined into one
+  pub merge_idat: bool,
+}
+
+impl Default for StripOptions {
+  /// Keeps the rendering and animation chunks and leaves IDAT chunks as
+  /// they are
+  fn default() -> Self {
+    Self {
+      keep: RENDERING_CHUNKS
+        .iter()
+        .chain(&ANIMATION_CHUNKS)
+        .map(|chunk_type| chunk_type.parse().expect("valid chunk type"))
+        .collect(),
+      merge_idat: false,
+    }
+  }
+}
+
+/// What `Png::strip` changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stripped {
+  /// The types of the chunks that were dropped, in file order
+  pub removed: Vec<ChunkType>,
+  /// How many IDAT chunks were joined into one, or 0 if none were
+  pub merged_idat: usize,
+}
+
+impl fmt::Display for Stripped {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.removed.is_empty() {
+      write!(f, "No chunk removed")?;
+    } else {
+      let removed = self.removed.iter().map(ToString::to_string);
+      write!(f, "Removed {}", removed.collect::<Vec<_>>().join(", "))?;
+    }
+    if self.merged_idat > 0 {
+      write!(f, "; merged {} IDAT chunks", self.merged_idat)?;
+    }
+    Ok(())
+  }
+}
+
+impl Png {
+  /// Reads a `Png` up to its IEND chunk, skipping whatever follows, even if
+  /// it is not made of chunks. Returns how many bytes were skipped too.
+  pub fn from_reader_to_iend<R: Read>(reader: R) -> Result<(Self, u64)> {
+    let mut reader = PngReader::new(reader)?;
+    let mut chunks = Vec::new();
+    while let Some(chunk) = reader.next_chunk()? {
+      let is_end = chunk.chunk_type().to_string() == "IEND";
+      chunks.push(chunk);
+      if is_end {
+        let trailing = io::copy(&mut reader.into_inner(), &mut io::sink())?;
+        return Ok((Self::from_chunks(chunks), trailing));
+      }
+    }
+    Ok((Self::from_chunks(chunks), 0))
+  }
+
+  /// Drops every chunk after IEND, every ancillary chunk not in
+  /// `options.keep` and any critical chunk other than IHDR, PLTE, IDAT and
+  /// IEND. The image data is never decoded, so it is kept bit for bit.
+  /// Every chunk is written with a freshly computed CRC.
+  pub fn strip(&mut self, options: &StripOptions) -> Result<Stripped> {
+    self.image_header()?;
+    let mut stripped = Stripped::default();
+
+    let end = self
+      .chunks
+      .iter()
+      .position(|chunk| chunk.chunk_type().to_string() == "IEND")
+      .unwrap_or(self.chunks.len());
+    let has_end = end < self.chunks.len();
+    let mut chunks = Vec::with_capacity(end + 1);
+    for (index, chunk) in self.chunks.drain(..).enumerate() {
+      let chunk_type = chunk.chunk_type();
+      let kept = index <= end
+        && if chunk_type.is_critical() {
+          CRITICAL.contains(&chunk_type.to_string().as_str())
+        } else {
+          options.keep.contains(chunk_type)
+        };
+      if kept {
+        chunks.push(Chunk::new(chunk_type.clone(), chunk.data().to_vec()));
+      } else {
+        stripped.removed.push(chunk_type.clone());
+      }
+    }
+    if !has_end {
+      chunks.push(Chunk::new(ChunkType::try_from(*b"IEND")?, Vec::new()));
+    }
+
+    if options.merge_idat {
+      let is_idat = |chunk: &Chunk| chunk.chunk_type().to_string() == "IDAT";
+      let idat_count = chunks.iter().filter(|chunk| is_idat(chunk)).count();
+      if idat_count > 1 {
+        let first = chunks.iter().position(is_idat).expect("IDAT exists");
+        let data = chunks
+          .iter()
+          .filter(|chunk| is_idat(chunk))
+          .flat_map(|chunk| chunk.data().iter().copied())
+          .collect();
+        chunks.retain(|chunk| !is_idat(chunk));
+        let idat = Chunk::new(ChunkType::try_from(*b"IDAT")?, data);
+        chunks.insert(first, idat);
+        stripped.merged_idat = idat_count;
+      }
+    }
+
+    self.chunks = chunks;
+    Ok(stripped)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Decoder, Encoder, Key, Mode};
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+  }
+
+  #[test]
+  fn test_strip() {
+    let mut png = Png::from_file(&"tests/cat.png".into()).unwrap();
+    let pixels = png.decode_pixels().unwrap();
+    let idat = png.chunk_by_type("IDAT").unwrap().data().to_vec();
+    let (first, second) = idat.split_at(idat.len() / 2);
+    png.retain_chunks(|chunk| chunk.chunk_type().to_string() != "IDAT");
+    png.chunks.insert(1, chunk("gAMA", &[0, 0, 177, 143]));
+    png.chunks.insert(2, chunk("IDAT", first));
+    png.chunks.insert(3, chunk("IDAT", second));
+
+    let options = StripOptions {
+      merge_idat: true,
+      ..StripOptions::default()
+    };
+    let stripped = png.strip(&options).unwrap();
+    let types = png
+      .chunks()
+      .iter()
+      .map(|chunk| chunk.chunk_type().to_string());
+    assert_eq!(types.collect::<Vec<_>>(), ["IHDR", "gAMA", "IDAT", "IEND"]);
+    let removed = stripped.removed.iter().map(ToString::to_string);
+    assert_eq!(
+      removed.collect::<Vec<_>>(),
+      ["pHYs", "zTXt", "teSt", "neSt"]
+    );
+    assert_eq!(stripped.merged_idat, 2);
+    assert_eq!(png.decode_pixels().unwrap(), pixels);
+  }
+
+  #[test]
+  fn test_read_to_iend() {
+    let mut bytes = Png::from_file(&"tests/cat.png".into()).unwrap().as_bytes();
+    bytes.extend_from_slice(b"trailing garbage");
+    assert!(Png::from_reader(bytes.as_slice()).is_err());
+    let (png, trailing) = Png::from_reader_to_iend(bytes.as_slice()).unwrap();
+    // The message chunks of the fixture follow IEND too
+    assert_eq!(trailing, 80 + 16);
+    let last = png.chunks().last().unwrap();
+    assert_eq!(last.chunk_type().to_string(), "IEND");
+  }
+
+  #[test]
+  fn test_hiding_modes_against_strip() {
+    let key = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
+    for (mode, survives) in [(Mode::Chunk, false), (Mode::Lsb, true)] {
+      let mut png = Png::from_file(&"tests/cat.png".into()).unwrap();
+      Encoder::with_key(key.clone())
+        .mode(mode)
+        .encode(&mut png, "Hidden")
+        .unwrap();
+      png.strip(&StripOptions::default()).unwrap();
+      let decoded = Decoder::with_key(key.clone()).mode(mode).decode(&png);
+      assert_eq!(decoded.is_ok(), survives, "{}", mode);
+    }
+  }
+}
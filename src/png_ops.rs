@@ -1,14 +1,49 @@
-use crate::aes::encrypt_message;
-use crate::chunk_type::ChunkType;
-use crate::png::Png;
-use crate::Result;
-use base64::{engine::general_purpose, Engine as _};
-use std::str::FromStr;
+use crate::batch::Report;
+use cachette::png::{PngReader, PngWriter, StripOptions};
+use cachette::verify::{self, Check, Failure, Verified};
+use cachette::{cover, strength};
+use cachette::{
+  Capacity, ChunkType, Decoded, Decoder, EccLevel, Encoder, Format, Identity,
+  KeyMaterial, Mode, Png, Recipient, Result, Session, Split,
+};
 
 use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
-fn get_password() -> Result<String> {
-  let password = match env::var("TEST_PASSWORD") {
+/// How a command asks for a password
+pub struct PasswordPrompt {
+  /// Shown when asking on the terminal
+  pub prompt: &'static str,
+  /// Read instead of asking when set, so that tests need no terminal
+  pub env_var: &'static str,
+  /// The lowest strength score accepted, for passwords that protect new
+  /// messages. Passwords of existing messages are taken as they are.
+  pub min_score: Option<u8>,
+}
+
+impl PasswordPrompt {
+  /// The password of existing messages
+  pub fn existing() -> Self {
+    Self {
+      prompt: "Your password: ",
+      env_var: "TEST_PASSWORD",
+      min_score: None,
+    }
+  }
+
+  /// A password for new messages
+  pub fn new(min_score: u8) -> Self {
+    Self {
+      min_score: Some(min_score),
+      ..Self::existing()
+    }
+  }
+}
+
+pub fn get_password(prompt: &PasswordPrompt) -> Result<String> {
+  let password = match env::var(prompt.env_var) {
     Ok(val) => {
       if val.is_empty() {
         None
@@ -19,63 +54,500 @@ fn get_password() -> Result<String> {
     Err(_) => None,
   };
 
-  let password = password.unwrap_or_else(|| {
-    rpassword::prompt_password("Your password: ")
-      .expect("Failed to read password")
-  });
+  Ok(password.unwrap_or_else(|| {
+    rpassword::prompt_password(prompt.prompt).expect("Failed to read password")
+  }))
+}
 
-  if password.len() < 18 {
-    Err("Password must be at least 18 characters long".into())
+/// Derives the session for the key options of a command, asking for a
+/// password with `prompt` when none of them is given
+pub fn open_session(
+  key_file: Option<PathBuf>,
+  recipients: &[String],
+  identity: Option<PathBuf>,
+  prompt: &PasswordPrompt,
+) -> Result<Session> {
+  let material = if let Some(key_file) = key_file {
+    KeyMaterial::KeyFile(std::fs::read(key_file)?)
+  } else if !recipients.is_empty() {
+    let recipients = recipients
+      .iter()
+      .map(|recipient| recipient.parse())
+      .collect::<Result<Vec<Recipient>>>()?;
+    KeyMaterial::Recipients(recipients)
+  } else if let Some(identity) = identity {
+    let contents = std::fs::read_to_string(identity)?;
+    KeyMaterial::Identity(Identity::from_file_contents(&contents)?)
   } else {
-    Ok(password)
+    let password = get_password(prompt)?;
+    if let Some(min_score) = prompt.min_score {
+      strength::estimate(&password).check(min_score)?;
+    }
+    KeyMaterial::Password(password)
+  };
+  Session::new(material)
+}
+
+/// The path that stands for stdin as an input, or stdout as an output
+const STDIO_PATH: &str = "-";
+
+/// How many passphrases `genpass` draws before deciding that the requested
+/// number of words cannot reach the score
+const GENPASS_ATTEMPTS: usize = 100;
+
+fn is_stdio(path: &Path) -> bool {
+  path == Path::new(STDIO_PATH)
+}
+
+/// Opens the file at `path`, or stdin for `-`
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+  if is_stdio(path) {
+    Ok(Box::new(io::stdin().lock()))
+  } else {
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+  }
+}
+
+/// Lets `write` produce the file at `path`, or stdout for `-`. Files are
+/// written to a temporary file next to `path` and moved into place once
+/// complete, so the original is left untouched if anything fails.
+fn write_output(
+  path: &Path,
+  write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+  if is_stdio(path) {
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    write(&mut stdout)?;
+    stdout.flush()?;
+    return Ok(());
+  }
+
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  let mut output = tempfile::NamedTempFile::new_in(dir)?;
+  let mut writer = BufWriter::new(output.as_file_mut());
+  write(&mut writer)?;
+  writer.flush()?;
+  drop(writer);
+
+  output.persist(path)?;
+  Ok(())
+}
+
+/// Returns the message to encode: `message` itself, or the contents of
+/// `file`, where `-` is stdin
+pub fn read_message(
+  message: Option<String>,
+  file: Option<PathBuf>,
+  inputs: &[PathBuf],
+) -> Result<String> {
+  match (message, file) {
+    (Some(message), _) => Ok(message),
+    (None, Some(file)) if is_stdio(&file) => {
+      if inputs.iter().any(|input| is_stdio(input)) {
+        return Err(
+          "The image and the message cannot both be read from stdin".into(),
+        );
+      }
+      let mut message = String::new();
+      io::stdin().read_to_string(&mut message)?;
+      Ok(message)
+    }
+    (None, Some(file)) => Ok(std::fs::read_to_string(file)?),
+    (None, None) => Err("No message to encode".into()),
   }
 }
 
+/// Encodes a message in an image file, writing the result to `output`
 pub fn encode(
-  input: std::path::PathBuf,
+  input: &Path,
+  output: &Path,
   message: &str,
-  chunk_type: &str,
+  encoder: &Encoder,
+) -> Result<Report> {
+  let reader = open_input(input)?;
+  write_output(output, |writer| {
+    encoder.encode_stream(reader, writer, message)
+  })?;
+  Ok(Report::default())
+}
+
+/// Splits a message across PNG files, one share in each, rewriting them in
+/// place. Every file is encoded before any is written.
+pub fn encode_split(
+  files: &[PathBuf],
+  message: &str,
+  encoder: &Encoder,
+  split: Split,
 ) -> Result<()> {
-  let password = get_password()?;
-  let (encrypted_message, nonce) = encrypt_message(message, &password);
-  let mut png = Png::from_file(&input)?;
-  let chunk_type = ChunkType::from_str(chunk_type)?;
-  let nonce_chunk_type_str = format!("n{}", &chunk_type.to_string()[1..]);
-  let nonce_chunk_type = ChunkType::from_str(&nonce_chunk_type_str)?;
-
-  let base64_nonce = general_purpose::STANDARD_NO_PAD.encode(&nonce);
-  png.encode_message(encrypted_message, chunk_type)?;
-  png.encode_message(base64_nonce.into_bytes(), nonce_chunk_type)?;
-
-  png.save(input)?;
+  if files.len() != split.shares as usize {
+    return Err(
+      format!(
+        "A {} split needs {} images but {} were given",
+        split,
+        split.shares,
+        files.len()
+      )
+      .into(),
+    );
+  }
+  if files.iter().any(|file| is_stdio(file)) {
+    return Err("Split images cannot be read from stdin".into());
+  }
+
+  let mut pngs = files
+    .iter()
+    .map(|file| Png::from_reader(open_input(file)?))
+    .collect::<Result<Vec<_>>>()?;
+  encoder.encode_split(&mut pngs, message, split.threshold)?;
+  for (file, png) in files.iter().zip(&pngs) {
+    write_output(file, |writer| png.write_to(writer))?;
+  }
+  eprintln!(
+    "Message split into {} shares, any {} of which decode it",
+    split.shares, split.threshold
+  );
   Ok(())
 }
 
-/// Decodes a message from a PNG file
-pub fn decode(input: std::path::PathBuf, chunk_type: &str) -> Result<()> {
-  let password = get_password()?;
-  let png = Png::from_file(&input)?;
-  let chunk_type = ChunkType::from_str(chunk_type)?;
-  let message = png.decode_message(&chunk_type.to_string(), &password)?;
-  println!("{}", message);
+/// The chunk mode capacity of the file at `input`, from the size alone of a
+/// PNG, which is streamed rather than read into memory
+fn chunk_capacity(input: &Path, ecc: EccLevel) -> Result<Capacity> {
+  let (format, mut reader) = cover::sniff(open_input(input)?)?;
+  if format != Some(Format::Png) {
+    let cover = cover::from_reader(reader)?;
+    return Capacity::of(&*cover, Mode::Chunk, 1, ecc);
+  }
+  PngReader::new(&mut reader)?;
+  let file_len = if is_stdio(input) {
+    Png::STANDARD_HEADER.len() as u64 + io::copy(&mut reader, &mut io::sink())?
+  } else {
+    std::fs::metadata(input)?.len()
+  };
+  Ok(Capacity::chunk(file_len, ecc))
+}
+
+/// Streams the chunks of `reader` to `output`, keeping the chunks whose type
+/// passes `keep`. Only one chunk is held in memory at a time.
+fn rewrite_chunks<R: Read>(
+  mut reader: PngReader<R>,
+  output: &Path,
+  mut keep: impl FnMut(&ChunkType) -> bool,
+) -> Result<()> {
+  write_output(output, |output| {
+    let mut writer = PngWriter::new(output)?;
+    while let Some(header) = reader.next_header()? {
+      if keep(&header.chunk_type) {
+        writer.copy_chunk(&mut reader, header)?;
+      } else {
+        reader.skip_data(header)?;
+      }
+    }
+    writer.finish()?;
+    Ok(())
+  })
+}
+
+/// Decodes a message from an image file
+pub fn decode(
+  input: &Path,
+  decoder: &Decoder,
+  lenient: bool,
+) -> Result<Report> {
+  let mut notes = Vec::new();
+  let decoded = if lenient {
+    let (png, issues) = Png::from_reader_lenient(open_input(input)?)?;
+    notes.extend(issues.iter().map(|issue| format!("Read past {}", issue)));
+    decoder.decode(&png)?
+  } else {
+    decoder.decode_stream(open_input(input)?)?
+  };
+  let Decoded {
+    message, corrected, ..
+  } = decoded;
+  notes.extend(
+    corrected.map(|corrected| format!("Corrected {} errors", corrected)),
+  );
+  Ok(Report {
+    output: Some(message),
+    status: (!notes.is_empty()).then(|| notes.join("; ")),
+  })
+}
+
+/// Rebuilds a message from the shares hidden in image files, or returns
+/// `None` if the first file holds no share, leaving the files to be
+/// decoded one by one
+pub fn decode_split(
+  files: &[PathBuf],
+  decoder: &Decoder,
+) -> Result<Option<String>> {
+  let read_share =
+    |file: &PathBuf| decoder.share(&*cover::from_reader(open_input(file)?)?);
+  let Some((first, rest)) = files.split_first() else {
+    return Ok(None);
+  };
+  let Ok(Some(first)) = read_share(first) else {
+    return Ok(None);
+  };
+
+  let mut shares = vec![first];
+  for file in rest {
+    let share = read_share(file)
+      .and_then(|share| share.ok_or_else(|| "no share found".into()))
+      .map_err(|error| format!("{}: {}", file.display(), error))?;
+    shares.push(share);
+  }
+  decoder.combine(&shares).map(Some)
+}
+
+/// Re-encrypts the message in an image file, opening it with `decoder` and
+/// sealing it with `encoder` at the ECC level it had. The message is only
+/// ever held in memory.
+pub fn rekey(
+  input: &Path,
+  output: &Path,
+  decoder: &Decoder,
+  encoder: &Encoder,
+) -> Result<Report> {
+  let mut image = cover::from_reader(open_input(input)?)?;
+  let Decoded { message, ecc, .. } = decoder.decode(&*image)?;
+  encoder.clone().ecc(ecc).encode(&mut *image, &message)?;
+  write_output(output, |writer| Ok(writer.write_all(&image.as_bytes())?))?;
+  Ok(Report {
+    output: None,
+    status: Some("Message rekeyed".to_string()),
+  })
+}
+
+/// Removes a chunk from a PNG file, writing the result to `output`
+pub fn remove(
+  input: &Path,
+  output: &Path,
+  chunk_type: &ChunkType,
+) -> Result<Report> {
+  let reader = PngReader::new(open_input(input)?)?;
+  let mut removed = false;
+  rewrite_chunks(reader, output, |other| {
+    // Like `Png::remove_chunk`, only the first matching chunk is removed
+    if !removed && other == chunk_type {
+      removed = true;
+      return false;
+    }
+    true
+  })?;
+  // stdout may be carrying the image, so status goes to stderr
+  let status = if removed {
+    "Chunk removed"
+  } else {
+    "Chunk not found"
+  };
+  Ok(Report {
+    output: None,
+    status: Some(status.to_string()),
+  })
+}
+
+/// Removes every cachette payload from a PNG file, writing the result to
+/// `output` once `scan` finds nothing left
+pub fn wipe(
+  input: &Path,
+  output: &Path,
+  chunk_types: &[ChunkType],
+) -> Result<Report> {
+  let mut png = Png::from_reader(open_input(input)?)?;
+  let wiped = cachette::wipe(&mut png, chunk_types)?;
+  write_output(output, |writer| png.write_to(writer))?;
+  Ok(Report {
+    output: None,
+    status: Some(format!("{}; no payload left", wiped)),
+  })
+}
+
+/// Strips a PNG file down to the chunks `options` keeps, writing the result
+/// to `output`
+pub fn strip(
+  input: &Path,
+  output: &Path,
+  options: &StripOptions,
+) -> Result<Report> {
+  let (mut png, trailing) = Png::from_reader_to_iend(open_input(input)?)?;
+  let stripped = png.strip(options)?;
+  write_output(output, |writer| png.write_to(writer))?;
+  let mut status = stripped.to_string();
+  if trailing > 0 {
+    status.push_str(&format!("; dropped {} bytes after IEND", trailing));
+  }
+  Ok(Report {
+    output: None,
+    status: Some(status),
+  })
+}
+
+/// Rewrites a damaged PNG file with every chunk that could be read and
+/// freshly computed CRCs, writing the result to `output`
+pub fn repair(input: &Path, output: &Path) -> Result<Report> {
+  let (png, issues) = Png::from_reader_lenient(open_input(input)?)?;
+  write_output(output, |writer| png.write_to(writer))?;
+  let status = if issues.is_empty() {
+    "No damage found".to_string()
+  } else {
+    let issues = issues.iter().map(ToString::to_string).collect::<Vec<_>>();
+    format!("Repaired {}", issues.join("; "))
+  };
+  Ok(Report {
+    output: None,
+    status: Some(status),
+  })
+}
+
+/// Checks the message hidden in `inputs` without revealing it. A single
+/// file holds a whole message; several files hold every share of one.
+pub fn verify(
+  inputs: &[PathBuf],
+  decoder: &Decoder,
+) -> std::result::Result<Verified, Failure> {
+  let read = |file: &PathBuf| {
+    let reader = open_input(file).map_err(|error| Failure {
+      check: Check::Readable,
+      reason: error.to_string(),
+    })?;
+    verify::read(reader)
+  };
+  if let [input] = inputs {
+    return decoder.verify(&*read(input)?);
+  }
+  let shares = inputs
+    .iter()
+    .map(|file| {
+      read(file)
+        .and_then(|cover| decoder.verify_share(&*cover))
+        .map_err(|failure| Failure {
+          reason: format!("{}: {}", file.display(), failure),
+          ..failure
+        })
+    })
+    .collect::<std::result::Result<Vec<_>, _>>()?;
+  decoder.verify_shares(&shares)
+}
+
+/// Lists the chunks of a PNG file with the length of their data
+pub fn inspect(input: &Path) -> Result<Report> {
+  let mut reader = PngReader::new(open_input(input)?)?;
+  let mut lines = Vec::new();
+  while let Some(header) = reader.next_header()? {
+    lines.push(format!("{} {}", header.chunk_type, header.length));
+    reader.skip_data(header)?;
+  }
+  Ok(Report {
+    output: Some(lines.join("\n")),
+    status: None,
+  })
+}
+
+/// Looks for hidden payloads in a PNG file, without needing the password
+pub fn scan(input: &Path) -> Result<Report> {
+  let png = Png::from_reader(open_input(input)?)?;
+  let findings = cachette::scan(&png);
+  let output = if findings.is_empty() {
+    "No payload found".to_string()
+  } else {
+    let lines = findings.iter().map(ToString::to_string).collect::<Vec<_>>();
+    lines.join("\n")
+  };
+  Ok(Report {
+    output: Some(output),
+    status: None,
+  })
+}
+
+/// Writes a new identity to `output` and prints its recipient. Identity
+/// files are never overwritten, and only their owner can read them.
+pub fn keygen(output: PathBuf) -> Result<()> {
+  let identity = Identity::generate();
+  let recipient = identity.recipient();
+  let contents = format!(
+    "# recipient: {}\n{}\n",
+    recipient,
+    identity.to_secret_string()
+  );
+
+  if is_stdio(&output) {
+    print!("{}", contents);
+    eprintln!("Recipient: {}", recipient);
+    return Ok(());
+  }
+
+  let mut options = std::fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options.open(&output)?.write_all(contents.as_bytes())?;
+  println!("{}", recipient);
   Ok(())
 }
 
-/// Removes a chunk from a PNG file
-pub fn remove(input: std::path::PathBuf, chunk_type: &str) -> Result<()> {
-  let mut png = Png::from_file(&input)?;
-  let chunk_type = ChunkType::from_str(chunk_type)?;
-  match png.remove_chunk(&chunk_type.to_string()) {
-    Some(_) => println!("Chunk removed"),
-    None => println!("Chunk not found"),
+/// Prints a passphrase of `words` random words that scores at least
+/// `min_score`
+pub fn genpass(words: usize, separator: &str, min_score: u8) -> Result<()> {
+  // Unlucky draws, such as a run of short words, are drawn again
+  for _ in 0..GENPASS_ATTEMPTS {
+    let passphrase = strength::generate_passphrase(words, separator);
+    let estimate = strength::estimate(&passphrase);
+    if estimate.check(min_score).is_ok() {
+      println!("{}", passphrase);
+      eprintln!("Strength: {}", estimate);
+      return Ok(());
+    }
   }
-  png.save(input)?;
+  Err(
+    format!("{} words are too few for a score of {}", words, min_score).into(),
+  )
+}
+
+/// Suggests KDF parameters that take about `target_ms` on this machine, and
+/// checks the suggestion by timing it
+pub fn calibrate(target_ms: u64, max_memory_mib: u32) -> Result<()> {
+  let target = std::time::Duration::from_millis(target_ms);
+  let max_memory_kib = max_memory_mib.saturating_mul(1024);
+  let params = cachette::kdf::calibrate(target, max_memory_kib)?;
+  let elapsed = cachette::kdf::measure(&params)?;
+  println!("Suggested parameters: {}", params);
+  println!("Unlocking takes {} ms on this machine", elapsed.as_millis());
+  println!(
+    "--kdf-memory {} --kdf-iterations {} --kdf-parallelism {}",
+    params.memory_kib, params.iterations, params.parallelism
+  );
   Ok(())
 }
 
 /// Prints the chunks of a PNG file
-pub fn print_chunks(input: std::path::PathBuf) -> Result<()> {
-  let png = Png::from_file(&input)?;
-  png.print_chunks();
+pub fn print_chunks(input: PathBuf) -> Result<()> {
+  let mut reader = PngReader::new(open_input(&input)?)?;
+  while let Some(header) = reader.next_header()? {
+    println!("{}", header.chunk_type);
+    reader.skip_data(header)?;
+  }
+  Ok(())
+}
+
+/// Prints how many bytes can be hidden in an image file with the given mode
+pub fn print_capacity(
+  input: PathBuf,
+  mode: Mode,
+  bits_per_channel: u8,
+  ecc: EccLevel,
+) -> Result<()> {
+  let capacity = match mode {
+    Mode::Chunk => chunk_capacity(&input, ecc)?,
+    Mode::Lsb | Mode::Palette | Mode::Deflate | Mode::Robust | Mode::F5 => {
+      let image = cover::from_reader(open_input(&input)?)?;
+      Capacity::of(&*image, mode, bits_per_channel, ecc)?
+    }
+  };
+  // Encode seals with a password unless given recipients or a key file
+  let capacity = capacity.for_session(&Session::password(""));
+  println!("{}", capacity);
   Ok(())
 }
@@ -0,0 +1,74 @@
+use crate::Result;
+
+/// A chunk of a RIFF container: a four-character code and its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RiffChunk {
+  pub fourcc: [u8; 4],
+  pub data: Vec<u8>,
+}
+
+impl RiffChunk {
+  /// The number of bytes this chunk takes once serialised, padding included
+  pub fn byte_len(&self) -> usize {
+    8 + self.data.len().div_ceil(2) * 2
+  }
+}
+
+/// Parses the chunks of a RIFF file whose form type is `form`. `name` is
+/// the name of the format, for errors.
+pub(crate) fn parse(
+  bytes: &[u8],
+  form: &[u8; 4],
+  name: &str,
+) -> Result<Vec<RiffChunk>> {
+  if !bytes.starts_with(b"RIFF") || bytes.get(8..12) != Some(form) {
+    return Err(format!("Invalid {} header", name).into());
+  }
+  let riff_len = u32::from_le_bytes(bytes[4..8].try_into()?) as usize;
+  let end = riff_len.saturating_add(8);
+  let body = bytes
+    .get(12..end)
+    .ok_or_else(|| format!("{} file is truncated", name))?;
+
+  let mut chunks = Vec::new();
+  let mut rest = body;
+  while !rest.is_empty() {
+    let header = rest
+      .get(..8)
+      .ok_or_else(|| format!("{} chunk header is truncated", name))?;
+    let len = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+    let data = rest
+      .get(8..8 + len)
+      .ok_or_else(|| format!("{} chunk is truncated", name))?;
+    let chunk = RiffChunk {
+      fourcc: header[..4].try_into()?,
+      data: data.to_vec(),
+    };
+    rest = rest.get(chunk.byte_len()..).unwrap_or_default();
+    chunks.push(chunk);
+  }
+  Ok(chunks)
+}
+
+/// The number of bytes `chunks` take once serialised as a RIFF file
+pub(crate) fn byte_len(chunks: &[RiffChunk]) -> usize {
+  12 + chunks.iter().map(RiffChunk::byte_len).sum::<usize>()
+}
+
+/// Serialises `chunks` as a RIFF file whose form type is `form`
+pub(crate) fn write(form: &[u8; 4], chunks: &[RiffChunk]) -> Vec<u8> {
+  let len = byte_len(chunks);
+  let mut bytes = Vec::with_capacity(len);
+  bytes.extend_from_slice(b"RIFF");
+  bytes.extend_from_slice(&(len as u32 - 8).to_le_bytes());
+  bytes.extend_from_slice(form);
+  for chunk in chunks {
+    bytes.extend_from_slice(&chunk.fourcc);
+    bytes.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&chunk.data);
+    if chunk.data.len() % 2 == 1 {
+      bytes.push(0);
+    }
+  }
+  bytes
+}
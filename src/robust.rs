@@ -0,0 +1,236 @@
+use crate::png::{
+  ColorType, FilterStrategy, ImageHeader, Palette, Pixels, Png,
+};
+use crate::Result;
+
+/// Where an image stores its colours, in whatever form an optimiser left
+/// them
+struct View {
+  pixels: Pixels,
+  palette: Option<Palette>,
+  /// The samples of the colour tRNS makes fully transparent
+  color_key: Option<Vec<u16>>,
+}
+
+impl View {
+  fn new(png: &Png) -> Result<Self> {
+    let pixels = png.decode_pixels()?;
+    let header = *pixels.header();
+    let palette = match header.color_type {
+      ColorType::Indexed => Some(png.palette()?),
+      _ => None,
+    };
+    let color_key = match (header.color_type, png.chunk_by_type("tRNS")) {
+      (ColorType::Grayscale | ColorType::Rgb, Some(trns)) => Some(
+        trns
+          .data()
+          .chunks_exact(2)
+          .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+          .collect(),
+      ),
+      _ => None,
+    };
+    Ok(Self {
+      pixels,
+      palette,
+      color_key,
+    })
+  }
+
+  fn header(&self) -> &ImageHeader {
+    self.pixels.header()
+  }
+
+  fn pixel_count(&self) -> usize {
+    self.pixels.sample_count() / self.header().color_type.channels()
+  }
+
+  /// The samples of the pixel at `index`
+  fn samples(&self, index: usize) -> Vec<u16> {
+    let channels = self.header().color_type.channels();
+    (index * channels..(index + 1) * channels)
+      .map(|sample| self.pixels.sample(sample))
+      .collect()
+  }
+
+  /// The bit the pixel at `index` carries, or `None` if it is fully
+  /// transparent, as optimisers are free to change the colour of those.
+  ///
+  /// The bit is the parity of the sum of the colour samples, grey counting
+  /// once. It is the same whether the colour is stored as grey, RGB or in
+  /// a palette, at any bit depth, so it survives every lossless reduction.
+  fn carrier_bit(&self, index: usize) -> Option<u8> {
+    let samples = self.samples(index);
+    let color_type = self.header().color_type;
+    if let Some(palette) = &self.palette {
+      let [r, g, b, a] = *palette.entries().get(samples[0] as usize)?;
+      return (a != 0).then_some((r ^ g ^ b) & 1);
+    }
+    if self.color_key.as_ref() == Some(&samples)
+      || color_type.has_alpha() && samples.last() == Some(&0)
+    {
+      return None;
+    }
+    let color_len = samples.len() - color_type.has_alpha() as usize;
+    let parity = samples[..color_len].iter().fold(0, |parity, s| parity ^ s);
+    Some((parity & 1) as u8)
+  }
+
+  /// The indices of the pixels that carry bits, in raster order
+  fn carriers(&self) -> impl Iterator<Item = usize> + '_ {
+    (0..self.pixel_count()).filter(|&index| self.carrier_bit(index).is_some())
+  }
+}
+
+/// The number of whole bytes `png` can carry, one bit in every pixel that
+/// is not fully transparent
+pub fn capacity(png: &Png) -> Result<usize> {
+  Ok(View::new(png)?.carriers().count() / 8)
+}
+
+/// Writes `payload` into the colour parity of the visible pixels of `png`,
+/// most significant bit first, by flipping the lowest bit of the blue or
+/// grey sample where the parity is wrong. The image is filtered with
+/// `filter`.
+pub fn embed(
+  png: &mut Png,
+  payload: &[u8],
+  filter: FilterStrategy,
+) -> Result<()> {
+  let mut view = View::new(png)?;
+  if view.palette.is_some() {
+    return Err(
+      "Robust mode does not support indexed-colour images; use palette mode"
+        .into(),
+    );
+  }
+  if view.color_key.is_some() {
+    return Err(
+      "Robust mode does not support images with a transparent colour".into(),
+    );
+  }
+  let carriers = view.carriers().collect::<Vec<_>>();
+  if payload.len() * 8 > carriers.len() {
+    return Err("Payload does not fit in the image".into());
+  }
+
+  let channels = view.header().color_type.channels();
+  let color_len = channels - view.header().color_type.has_alpha() as usize;
+  let bits = payload
+    .iter()
+    .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+  for (index, bit) in carriers.into_iter().zip(bits) {
+    if view.carrier_bit(index) != Some(bit) {
+      let sample = index * channels + color_len - 1;
+      let value = view.pixels.sample(sample);
+      view.pixels.set_sample(sample, value ^ 1);
+    }
+  }
+  png.encode_pixels(&view.pixels, filter)
+}
+
+/// Reads `len` bytes from the colour parity of the visible pixels of `png`,
+/// whatever colour type and bit depth it was re-encoded with
+pub fn extract(png: &Png, len: usize) -> Result<Vec<u8>> {
+  let view = View::new(png)?;
+  let bits = view
+    .carriers()
+    .take(len * 8)
+    .map(|index| view.carrier_bit(index).expect("carriers have a bit"))
+    .collect::<Vec<_>>();
+  if bits.len() < len * 8 {
+    return Err("Payload is larger than the image capacity".into());
+  }
+  Ok(
+    bits
+      .chunks(8)
+      .map(|bits| bits.iter().fold(0, |byte, bit| (byte << 1) | bit))
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use crate::chunk_type::ChunkType;
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+  }
+
+  fn png_of(pixels: &Pixels, extra: Vec<Chunk>) -> Png {
+    let mut chunks = vec![pixels.header().as_chunk()];
+    chunks.extend(extra);
+    chunks.push(chunk("IDAT", pixels.encode(FilterStrategy::default())));
+    chunks.push(chunk("IEND", Vec::new()));
+    Png::from_chunks(chunks)
+  }
+
+  fn image(color_type: ColorType, bit_depth: u8) -> Pixels {
+    let header = ImageHeader {
+      width: 16,
+      height: 16,
+      bit_depth,
+      color_type,
+      interlaced: false,
+    };
+    let len = header.row_bytes(16) * 16;
+    Pixels::new(header, vec![0; len]).unwrap()
+  }
+
+  #[test]
+  fn test_bit_survives_reductions() {
+    // An RGBA image whose colours are greys, with some pixels invisible
+    let mut rgba = image(ColorType::Rgba, 8);
+    for pixel in 0..256 {
+      let grey = (pixel * 5 % 256) as u16;
+      for channel in 0..3 {
+        rgba.set_sample(pixel * 4 + channel, grey);
+      }
+      rgba.set_sample(pixel * 4 + 3, if pixel % 9 == 0 { 0 } else { 255 });
+    }
+    let mut png = png_of(&rgba, Vec::new());
+    assert_eq!(capacity(&png).unwrap(), (256 - 29) / 8);
+    embed(&mut png, b"robust", FilterStrategy::default()).unwrap();
+    let rgba = png.decode_pixels().unwrap();
+
+    // The same colours stored as 16-bit RGB, with a colour key for the
+    // invisible pixels, and in a palette
+    let mut wide = image(ColorType::Rgb, 16);
+    let key = [1u16, 2, 3];
+    let mut entries = Vec::<[u8; 4]>::new();
+    let mut indexed = image(ColorType::Indexed, 8);
+    for pixel in 0..256 {
+      let rgba = [0, 1, 2, 3].map(|c| rgba.sample(pixel * 4 + c) as u8);
+      for channel in 0..3 {
+        let value = match rgba[3] {
+          0 => key[channel],
+          _ => rgba[channel] as u16 * 257,
+        };
+        wide.set_sample(pixel * 3 + channel, value);
+      }
+      let index = entries.iter().position(|entry| *entry == rgba);
+      let index = index.unwrap_or_else(|| {
+        entries.push(rgba);
+        entries.len() - 1
+      });
+      indexed.set_sample(pixel, index as u16);
+    }
+    let trns = key.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+    let wide = png_of(&wide, vec![chunk("tRNS", trns)]);
+    let plte = entries.iter().flat_map(|entry| &entry[..3]).copied();
+    let alpha = entries.iter().map(|entry| entry[3]).collect();
+    let indexed = png_of(
+      &indexed,
+      vec![chunk("PLTE", plte.collect()), chunk("tRNS", alpha)],
+    );
+    for png in [&png, &wide, &indexed] {
+      assert_eq!(extract(png, 6).unwrap(), b"robust");
+    }
+
+    let mut indexed = indexed;
+    assert!(embed(&mut indexed, b"x", FilterStrategy::default()).is_err());
+  }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+
+use crate::chunk_type::ChunkType;
+use crate::deflate;
+use crate::ecc;
+use crate::envelope::{self, Envelope};
+use crate::ezstego;
+use crate::lsb;
+use crate::png::{Pixels, Png};
+use crate::robust;
+use crate::shamir::{self, Share};
+
+/// How many bytes identify a payload and its length
+pub(crate) const PEEK_LEN: usize = const_max(
+  const_max(envelope::PEEK_LEN, ecc::FRAME_HEADER_LEN),
+  shamir::HEADER_LEN,
+);
+
+const fn const_max(a: usize, b: usize) -> usize {
+  if a > b {
+    a
+  } else {
+    b
+  }
+}
+
+/// Where a payload was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+  /// A chunk of this type
+  Chunk(ChunkType),
+  /// The low bits of the pixels
+  Lsb {
+    /// How many low bits of each sample the payload uses
+    bits_per_channel: u8,
+  },
+  /// The paired colours of the palette
+  Palette,
+  /// The match lengths of the compressed image data
+  Deflate,
+  /// The colour parity of the visible pixels
+  Robust,
+}
+
+/// What a payload holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+  /// A whole encrypted envelope
+  Envelope,
+  /// One share of an envelope split across several images
+  Share,
+  /// An envelope or share wrapped in error correction
+  EccFrame,
+}
+
+impl fmt::Display for PayloadKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      PayloadKind::Envelope => "envelope",
+      PayloadKind::Share => "share",
+      PayloadKind::EccFrame => "ECC frame",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// A payload that looks like it was hidden by cachette. Finding one does not
+/// need the password; opening it does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+  /// Where the payload is hidden
+  pub location: Location,
+  /// What the payload holds
+  pub kind: PayloadKind,
+  /// The length of the payload in bytes
+  pub len: usize,
+}
+
+impl fmt::Display for Finding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.location {
+      Location::Chunk(chunk_type) => write!(f, "{} chunk", chunk_type)?,
+      Location::Lsb { bits_per_channel } => {
+        write!(f, "lsb, {} bits per channel", bits_per_channel)?
+      }
+      Location::Palette => write!(f, "palette")?,
+      Location::Deflate => write!(f, "deflate stream")?,
+      Location::Robust => write!(f, "robust")?,
+    }
+    write!(f, ": {} of {} bytes", self.kind, self.len)
+  }
+}
+
+/// The kind and length of the payload `header` starts, if it starts one
+pub(crate) fn payload_len(header: &[u8]) -> Option<(PayloadKind, usize)> {
+  if Envelope::is_envelope(header) {
+    let len = Envelope::total_len(header).ok()?;
+    Some((PayloadKind::Envelope, len))
+  } else if Share::is_share(header) {
+    Some((PayloadKind::Share, Share::total_len(header).ok()?))
+  } else if ecc::is_protected(header) {
+    Some((PayloadKind::EccFrame, ecc::frame_len(header).ok()?))
+  } else {
+    None
+  }
+}
+
+/// Looks for payloads in every chunk of `png`, in the match lengths of its
+/// image data, in the low bits of its pixels, at every bit depth LSB mode
+/// supports, in the paired colours of its palette and in the colour parity
+/// of its visible pixels
+pub fn scan(png: &Png) -> Vec<Finding> {
+  let mut findings = Vec::new();
+
+  for chunk in png.chunks() {
+    if let Some((kind, len)) = payload_len(chunk.data()) {
+      if len <= chunk.data().len() {
+        findings.push(Finding {
+          location: Location::Chunk(chunk.chunk_type().clone()),
+          kind,
+          len,
+        });
+      }
+    }
+  }
+
+  if let Some((kind, len)) = deflate::extract(png, PEEK_LEN)
+    .ok()
+    .and_then(|header| payload_len(&header))
+  {
+    if deflate::extract(png, len).is_ok() {
+      findings.push(Finding {
+        location: Location::Deflate,
+        kind,
+        len,
+      });
+    }
+  }
+
+  // Images whose pixels cannot be read simply have no LSB payload
+  let Ok(frames) = png.decode_frames() else {
+    return findings;
+  };
+  for bits_per_channel in 1..=8 {
+    let Ok(capacity) = lsb::capacity(&frames, bits_per_channel) else {
+      break;
+    };
+    let Ok(header) = lsb::extract(&frames, bits_per_channel, PEEK_LEN) else {
+      continue;
+    };
+    if let Some((kind, len)) = payload_len(&header) {
+      if len <= capacity {
+        findings.push(Finding {
+          location: Location::Lsb { bits_per_channel },
+          kind,
+          len,
+        });
+      }
+    }
+  }
+  findings.extend(scan_palette(png, &frames));
+  if let Some((kind, len)) = robust::extract(png, PEEK_LEN)
+    .ok()
+    .and_then(|header| payload_len(&header))
+  {
+    if robust::extract(png, len).is_ok() {
+      findings.push(Finding {
+        location: Location::Robust,
+        kind,
+        len,
+      });
+    }
+  }
+
+  findings
+}
+
+/// Looks for a payload in the paired palette colours of an indexed image
+fn scan_palette(png: &Png, frames: &[Pixels]) -> Option<Finding> {
+  let palette = png.palette().ok()?;
+  let capacity = ezstego::capacity(frames, &palette).ok()?;
+  let header = ezstego::extract(frames, &palette, PEEK_LEN).ok()?;
+  let (kind, len) = payload_len(&header)?;
+  (len <= capacity).then_some(Finding {
+    location: Location::Palette,
+    kind,
+    len,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{EccLevel, Encoder, Key, Mode};
+  use std::str::FromStr;
+
+  #[test]
+  fn test_scan() {
+    let png = Png::from_file(&"tests/cat.png".into()).unwrap();
+    // The legacy message in the fixture predates envelopes
+    assert!(scan(&png).is_empty());
+
+    let key = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
+    let mut png = png;
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    Encoder::with_key(key.clone())
+      .chunk_type(chunk_type.clone())
+      .encode(&mut png, "in a chunk")
+      .unwrap();
+    Encoder::with_key(key)
+      .mode(Mode::Lsb)
+      .bits_per_channel(3)
+      .ecc(EccLevel::Low)
+      .encode(&mut png, "in the pixels")
+      .unwrap();
+
+    let findings = scan(&png);
+    assert_eq!(findings.len(), 2);
+    assert_eq!(findings[0].location, Location::Chunk(chunk_type));
+    assert_eq!(findings[0].kind, PayloadKind::Envelope);
+    assert_eq!(findings[0].len, Envelope::sealed_len(10));
+    assert_eq!(
+      findings[1].location,
+      Location::Lsb {
+        bits_per_channel: 3
+      }
+    );
+    assert_eq!(findings[1].kind, PayloadKind::EccFrame);
+    assert_eq!(
+      findings[1].to_string(),
+      format!(
+        "lsb, 3 bits per channel: ECC frame of {} bytes",
+        findings[1].len
+      )
+    );
+  }
+}
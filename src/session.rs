@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::aes::{unwrap_key, wrap_key, Key};
+use crate::envelope::{
+  recipients_key_block_len, Envelope, KeyBlock, Stanza, MAX_RECIPIENTS,
+  PASSWORD_KEY_BLOCK_LEN, PUBLIC_KEY_LEN,
+};
+use crate::kdf::{KdfParams, SALT_LEN};
+use crate::Result;
+
+/// Prefix of the text form of a `Recipient`
+pub const RECIPIENT_PREFIX: &str = "cachette-pk-";
+
+/// Prefix of the text form of an `Identity`
+pub const IDENTITY_PREFIX: &str = "CACHETTE-SK-";
+
+/// Key files shorter than this are refused, since they are not stretched
+/// the way passwords are
+pub const MIN_KEY_FILE_LEN: usize = 32;
+
+/// HKDF info for keys derived from key files
+const KEY_FILE_INFO: &[u8] = b"cachette key file";
+
+/// HKDF info for keys that wrap message keys for a recipient
+const WRAP_INFO: &[u8] = b"cachette x25519 wrap";
+
+/// Decodes the base64 key that follows `prefix` in `text`
+fn decode_key(text: &str, prefix: &str, what: &str) -> Result<[u8; 32]> {
+  let encoded = text
+    .trim()
+    .strip_prefix(prefix)
+    .ok_or_else(|| format!("{} must start with {}", what, prefix))?;
+  let bytes = URL_SAFE_NO_PAD
+    .decode(encoded)
+    .map_err(|_| format!("Invalid {}", what.to_lowercase()))?;
+  bytes
+    .try_into()
+    .map_err(|_| format!("Invalid {}", what.to_lowercase()).into())
+}
+
+/// The public half of an `Identity`, which messages can be encrypted to
+#[derive(Clone, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl fmt::Display for Recipient {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let encoded = URL_SAFE_NO_PAD.encode(self.0.as_bytes());
+    write!(f, "{}{}", RECIPIENT_PREFIX, encoded)
+  }
+}
+
+impl fmt::Debug for Recipient {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Recipient({})", self)
+  }
+}
+
+impl FromStr for Recipient {
+  type Err = crate::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let bytes = decode_key(s, RECIPIENT_PREFIX, "Recipient")?;
+    Ok(Self(PublicKey::from(bytes)))
+  }
+}
+
+/// An X25519 secret key that decrypts messages encrypted to its
+/// `Recipient`
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+  /// A new random identity
+  pub fn generate() -> Self {
+    Self(StaticSecret::random_from_rng(rand::thread_rng()))
+  }
+
+  /// The recipient messages for this identity are encrypted to
+  pub fn recipient(&self) -> Recipient {
+    Recipient(PublicKey::from(&self.0))
+  }
+
+  /// Parses an identity file: the identity on its own line, with blank lines
+  /// and lines starting with `#` ignored
+  pub fn from_file_contents(contents: &str) -> Result<Self> {
+    let mut lines = contents
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    match (lines.next(), lines.next()) {
+      (Some(line), None) => line.parse(),
+      _ => Err("Identity files must hold exactly one identity".into()),
+    }
+  }
+
+  /// The text form of this identity, which must be kept secret
+  pub fn to_secret_string(&self) -> String {
+    let encoded = URL_SAFE_NO_PAD.encode(self.0.as_bytes());
+    format!("{}{}", IDENTITY_PREFIX, encoded)
+  }
+}
+
+/// The secret key is left out so that it never ends up in logs
+impl fmt::Debug for Identity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Identity({})", self.recipient())
+  }
+}
+
+impl FromStr for Identity {
+  type Err = crate::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let bytes = decode_key(s, IDENTITY_PREFIX, "Identity")?;
+    Ok(Self(StaticSecret::from(bytes)))
+  }
+}
+
+/// What the key protecting messages comes from
+#[derive(Clone)]
+pub enum KeyMaterial {
+  /// A password, stretched with Argon2 using the session's `KdfParams`
+  Password(String),
+  /// The contents of a key file, which must be at least
+  /// `MIN_KEY_FILE_LEN` random bytes
+  KeyFile(Vec<u8>),
+  /// Public keys to encrypt messages to. Such a session cannot decrypt.
+  Recipients(Vec<Recipient>),
+  /// A secret key that decrypts messages encrypted to its recipient, and
+  /// encrypts messages to it
+  Identity(Identity),
+}
+
+/// Secrets are left out so that they never end up in logs
+impl fmt::Debug for KeyMaterial {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      KeyMaterial::Password(_) => f.write_str("Password(..)"),
+      KeyMaterial::KeyFile(_) => f.write_str("KeyFile(..)"),
+      KeyMaterial::Recipients(recipients) => {
+        f.debug_tuple("Recipients").field(recipients).finish()
+      }
+      KeyMaterial::Identity(identity) => {
+        f.debug_tuple("Identity").field(identity).finish()
+      }
+    }
+  }
+}
+
+/// The parameters and salt a key was stretched with, the legacy key having
+/// no salt
+type KeyId = (KdfParams, Option<[u8; SALT_LEN]>);
+
+/// A key stretched from a password, or none while it is yet to be derived
+type KeySlot = Arc<Mutex<Option<Key>>>;
+
+/// A password along with the keys stretched from it so far
+#[derive(Clone)]
+struct Password {
+  password: String,
+  /// The parameters and salt new envelopes are sealed with
+  params: KdfParams,
+  salt: [u8; SALT_LEN],
+  keys: Arc<Mutex<HashMap<KeyId, KeySlot>>>,
+}
+
+impl Password {
+  /// The key for `params` and `salt`, derived on first use. Only the slot
+  /// of that key is locked while deriving, so threads needing the same key
+  /// wait for one derivation while those needing other keys carry on.
+  fn key(
+    &self,
+    params: KdfParams,
+    salt: Option<&[u8; SALT_LEN]>,
+  ) -> Result<Key> {
+    let slot = {
+      let mut keys =
+        self.keys.lock().unwrap_or_else(|error| error.into_inner());
+      Arc::clone(keys.entry((params, salt.copied())).or_default())
+    };
+    let mut slot = slot.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(key) = &*slot {
+      return Ok(key.clone());
+    }
+    let key = match salt {
+      Some(salt) => params.derive(&self.password, salt)?,
+      None => Key::derive(&self.password),
+    };
+    *slot = Some(key.clone());
+    Ok(key)
+  }
+
+  fn legacy_key(&self) -> Key {
+    self
+      .key(KdfParams::LEGACY, None)
+      .expect("legacy keys always derive")
+  }
+}
+
+#[derive(Clone)]
+enum Secret {
+  Shared(Key),
+  Password(Password),
+  Recipients(Vec<Recipient>),
+  Identity(Identity),
+}
+
+/// Keys derived from `KeyMaterial`, then used to seal and open any number
+/// of envelopes. Passwords are stretched on first use and the result is
+/// kept, so sharing a session across a batch stretches a password once per
+/// set of parameters and salt rather than once per image.
+#[derive(Clone)]
+pub struct Session {
+  secret: Secret,
+}
+
+impl Session {
+  /// Derives the keys for `material`. Passwords are stretched with the
+  /// default `KdfParams` and a salt picked for this session.
+  pub fn new(material: KeyMaterial) -> Result<Self> {
+    let secret = match material {
+      KeyMaterial::Password(password) => Secret::Password(Password {
+        password,
+        params: KdfParams::default(),
+        salt: rand::random(),
+        keys: Arc::default(),
+      }),
+      KeyMaterial::KeyFile(contents) => {
+        if contents.len() < MIN_KEY_FILE_LEN {
+          return Err(
+            format!("Key files must hold at least {} bytes", MIN_KEY_FILE_LEN)
+              .into(),
+          );
+        }
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &contents)
+          .expand(KEY_FILE_INFO, &mut key)
+          .expect("valid HKDF output length");
+        Secret::Shared(Key::from_bytes(key))
+      }
+      KeyMaterial::Recipients(recipients) => {
+        if recipients.is_empty() {
+          return Err("At least one recipient is needed".into());
+        }
+        if recipients.len() > MAX_RECIPIENTS {
+          return Err(
+            format!("At most {} recipients are supported", MAX_RECIPIENTS)
+              .into(),
+          );
+        }
+        Secret::Recipients(recipients)
+      }
+      KeyMaterial::Identity(identity) => Secret::Identity(identity),
+    };
+    Ok(Self { secret })
+  }
+
+  /// A session for `password`
+  pub fn password(password: &str) -> Self {
+    Self::new(KeyMaterial::Password(password.to_string()))
+      .expect("password sessions always succeed")
+  }
+
+  /// Stretches the password of new envelopes with `params`, which are
+  /// stored in each envelope so that decoding never needs them. Sessions
+  /// without a password ignore them.
+  pub fn kdf(mut self, params: KdfParams) -> Result<Self> {
+    params.validate()?;
+    if let Secret::Password(password) = &mut self.secret {
+      password.params = params;
+    }
+    Ok(self)
+  }
+
+  /// The key shared out of band, if this session has one: the key itself,
+  /// or a password's key as derived before KDF parameters were stored.
+  /// Messages encoded before envelopes existed can only be decrypted with
+  /// it.
+  pub fn shared_key(&self) -> Option<Key> {
+    match &self.secret {
+      Secret::Shared(key) => Some(key.clone()),
+      Secret::Password(password) => Some(password.legacy_key()),
+      _ => None,
+    }
+  }
+
+  /// Length of the key block in the envelopes this session seals, which is
+  /// zero for keys shared out of band
+  pub fn key_block_len(&self) -> usize {
+    match &self.secret {
+      Secret::Shared(_) => 0,
+      Secret::Password(_) => PASSWORD_KEY_BLOCK_LEN,
+      Secret::Recipients(recipients) => {
+        recipients_key_block_len(recipients.len())
+      }
+      Secret::Identity(_) => recipients_key_block_len(1),
+    }
+  }
+
+  /// Encrypts `message` for whoever holds this session's secret, or for
+  /// its recipients
+  pub fn seal(&self, message: &str) -> Envelope {
+    match &self.secret {
+      Secret::Shared(key) => Envelope::seal(message, key),
+      Secret::Password(password) => {
+        let Password { params, salt, .. } = *password;
+        let key = password
+          .key(params, Some(&salt))
+          .expect("validated KDF parameters");
+        Envelope::seal_with(message, &key, KeyBlock::Password { params, salt })
+      }
+      Secret::Recipients(recipients) => seal_for(message, recipients),
+      Secret::Identity(identity) => seal_for(message, &[identity.recipient()]),
+    }
+  }
+
+  /// Decrypts `envelope`
+  pub fn open(&self, envelope: &Envelope) -> Result<String> {
+    match (&self.secret, envelope.key_block()) {
+      (Secret::Shared(key), KeyBlock::Shared) => envelope.open(key),
+      (Secret::Password(password), KeyBlock::Shared) => {
+        envelope.open(&password.legacy_key())
+      }
+      (Secret::Password(password), KeyBlock::Password { params, salt }) => {
+        envelope.open(&password.key(*params, Some(salt))?)
+      }
+      (Secret::Identity(identity), KeyBlock::Recipients(stanzas)) => {
+        let key = stanzas
+          .iter()
+          .find_map(|stanza| unwrap_stanza(identity, stanza).ok())
+          .ok_or("Message was not encrypted to this identity")?;
+        envelope.open(&key)
+      }
+      (Secret::Recipients(_), _) => {
+        Err("Recipients can only encrypt; decrypt with an identity".into())
+      }
+      (Secret::Shared(_) | Secret::Password(_), KeyBlock::Recipients(_)) => {
+        Err(
+          "Message was encrypted to recipients; decrypt it with an identity"
+            .into(),
+        )
+      }
+      (Secret::Shared(_), KeyBlock::Password { .. }) => {
+        Err("Message was encrypted with a password, not a key file".into())
+      }
+      (Secret::Identity(_), KeyBlock::Shared | KeyBlock::Password { .. }) => {
+        Err(
+          "Message was encrypted with a password or key file, not to an \
+         identity"
+            .into(),
+        )
+      }
+    }
+  }
+}
+
+impl From<Key> for Session {
+  fn from(key: Key) -> Self {
+    Self {
+      secret: Secret::Shared(key),
+    }
+  }
+}
+
+/// Secrets are left out so that they never end up in logs
+impl fmt::Debug for Session {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let kind = match &self.secret {
+      Secret::Shared(_) => "shared key",
+      Secret::Password(_) => "password",
+      Secret::Recipients(_) => "recipients",
+      Secret::Identity(_) => "identity",
+    };
+    f.debug_struct("Session").field("kind", &kind).finish()
+  }
+}
+
+/// The key that wraps message keys for `recipient`, agreed from `shared`
+fn wrapping_key(
+  shared: &[u8; 32],
+  ephemeral: &[u8; PUBLIC_KEY_LEN],
+  recipient: &PublicKey,
+) -> Key {
+  let salt = [ephemeral.as_slice(), recipient.as_bytes()].concat();
+  let mut key = [0u8; 32];
+  Hkdf::<Sha256>::new(Some(&salt), shared)
+    .expand(WRAP_INFO, &mut key)
+    .expect("valid HKDF output length");
+  Key::from_bytes(key)
+}
+
+/// Encrypts `message` with a random key, wrapped for each recipient with a
+/// fresh ephemeral key agreement
+fn seal_for(message: &str, recipients: &[Recipient]) -> Envelope {
+  let key = Key::generate();
+  let stanzas = recipients
+    .iter()
+    .map(|Recipient(recipient)| {
+      let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+      let ephemeral = PublicKey::from(&secret).to_bytes();
+      let shared = secret.diffie_hellman(recipient);
+      let wrapping_key = wrapping_key(shared.as_bytes(), &ephemeral, recipient);
+      Stanza {
+        ephemeral,
+        wrapped_key: wrap_key(&key, &wrapping_key),
+      }
+    })
+    .collect();
+  Envelope::seal_with(message, &key, KeyBlock::Recipients(stanzas))
+}
+
+/// Recovers the message key from `stanza` if it was wrapped for `identity`
+fn unwrap_stanza(identity: &Identity, stanza: &Stanza) -> Result<Key> {
+  let shared = identity
+    .0
+    .diffie_hellman(&PublicKey::from(stanza.ephemeral));
+  let recipient = PublicKey::from(&identity.0);
+  let wrapping_key =
+    wrapping_key(shared.as_bytes(), &stanza.ephemeral, &recipient);
+  unwrap_key(&stanza.wrapped_key, &wrapping_key)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PASSWORD: &str = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+
+  #[test]
+  fn test_password_session() {
+    let params = KdfParams::new(64, 1, 1);
+    let session = Session::password(PASSWORD).kdf(params).unwrap();
+    let envelope = session.seal("message");
+    let KeyBlock::Password { params: stored, .. } = envelope.key_block() else {
+      panic!("expected a password key block");
+    };
+    assert_eq!(*stored, params);
+    assert_eq!(session.open(&envelope).unwrap(), "message");
+
+    // Decoding reads the parameters and salt from the envelope
+    let other = Session::new(KeyMaterial::Password(PASSWORD.into())).unwrap();
+    assert_eq!(other.open(&envelope).unwrap(), "message");
+    assert!(Session::password("not the password")
+      .open(&envelope)
+      .is_err());
+
+    // Envelopes sealed with the legacy key still open
+    let legacy = Envelope::seal("legacy", &Key::derive(PASSWORD));
+    assert_eq!(other.open(&legacy).unwrap(), "legacy");
+    assert!(session.kdf(KdfParams::new(64, 0, 1)).is_err());
+  }
+
+  #[test]
+  fn test_derivations_run_side_by_side() {
+    let params = KdfParams::new(64, 1, 1);
+    let session = Session::password(PASSWORD).kdf(params).unwrap();
+    let Secret::Password(password) = &session.secret else {
+      panic!("expected a password session");
+    };
+    // Hold the legacy key's slot, as a thread deriving it would
+    let slot = KeySlot::default();
+    let legacy_id = (KdfParams::LEGACY, None);
+    password
+      .keys
+      .lock()
+      .unwrap()
+      .insert(legacy_id, Arc::clone(&slot));
+    let deriving = slot.lock().unwrap();
+
+    let other = session.clone();
+    let envelope = std::thread::spawn(move || other.seal("message"))
+      .join()
+      .unwrap();
+    drop(deriving);
+    assert_eq!(session.open(&envelope).unwrap(), "message");
+  }
+
+  #[test]
+  fn test_key_file_session() {
+    let contents = (0..64).collect::<Vec<u8>>();
+    let session = Session::new(KeyMaterial::KeyFile(contents)).unwrap();
+    let envelope = session.seal("message");
+    assert_eq!(session.open(&envelope).unwrap(), "message");
+    assert!(Session::password(PASSWORD).open(&envelope).is_err());
+
+    let short = KeyMaterial::KeyFile(vec![0; MIN_KEY_FILE_LEN - 1]);
+    assert!(Session::new(short).is_err());
+  }
+
+  #[test]
+  fn test_recipients_session() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let eve = Identity::generate();
+    let recipients = vec![alice.recipient(), bob.recipient()];
+    let sender = Session::new(KeyMaterial::Recipients(recipients)).unwrap();
+    let envelope = sender.seal("message");
+    assert!(sender.open(&envelope).is_err());
+
+    let bytes = envelope.as_bytes();
+    let envelope = Envelope::try_from(bytes.as_ref()).unwrap();
+    for identity in [alice, bob] {
+      let session = Session::new(KeyMaterial::Identity(identity)).unwrap();
+      assert_eq!(session.open(&envelope).unwrap(), "message");
+    }
+    let eve = Session::new(KeyMaterial::Identity(eve)).unwrap();
+    assert!(eve.open(&envelope).is_err());
+    assert!(Session::password(PASSWORD).open(&envelope).is_err());
+    assert!(Session::new(KeyMaterial::Recipients(Vec::new())).is_err());
+  }
+
+  #[test]
+  fn test_key_text_forms() {
+    let identity = Identity::generate();
+    let recipient = identity.recipient();
+    let parsed = Recipient::from_str(&recipient.to_string()).unwrap();
+    assert_eq!(parsed, recipient);
+
+    let file = format!(
+      "# recipient: {}\n{}\n",
+      recipient,
+      identity.to_secret_string()
+    );
+    let parsed = Identity::from_file_contents(&file).unwrap();
+    assert_eq!(parsed.recipient(), recipient);
+
+    assert!(Recipient::from_str("cachette-pk-short").is_err());
+    assert!(Identity::from_str(&recipient.to_string()).is_err());
+    assert!(!format!("{:?}", identity).contains(IDENTITY_PREFIX));
+  }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::gf256;
+use crate::Result;
+
+/// Magic bytes that start every serialised share
+pub const MAGIC: [u8; 4] = *b"CHTS";
+
+/// The current version of the share layout
+pub const VERSION: u8 = 1;
+
+/// Magic bytes, version, id, threshold, index, total and data length
+pub const HEADER_LEN: usize = 16;
+
+/// Length of the random id shared by the shares of one secret
+const ID_LEN: usize = 4;
+
+/// How many shares a secret is split into, and how many rebuild it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split {
+  /// How many shares rebuild the secret
+  pub threshold: u8,
+  /// How many shares the secret is split into
+  pub shares: u8,
+}
+
+const INVALID_SPLIT: &str =
+  "A split needs a threshold between 1 and the number of shares";
+
+impl Split {
+  /// A `threshold`-of-`shares` split, or an error unless the threshold is
+  /// between 1 and the number of shares
+  pub fn new(threshold: u8, shares: u8) -> Result<Self> {
+    if threshold == 0 || threshold > shares {
+      return Err(INVALID_SPLIT.into());
+    }
+    Ok(Self { threshold, shares })
+  }
+}
+
+/// Parses `K-of-N`, such as `3-of-5`
+impl FromStr for Split {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    let (threshold, shares) =
+      s.split_once("-of-").ok_or("Split must look like 3-of-5")?;
+    let threshold = threshold.parse().map_err(|_| "Invalid threshold")?;
+    let shares = shares.parse().map_err(|_| "Invalid number of shares")?;
+    Self::new(threshold, shares).map_err(|_| INVALID_SPLIT)
+  }
+}
+
+impl fmt::Display for Split {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}-of-{}", self.threshold, self.shares)
+  }
+}
+
+/// One share of a secret split with Shamir's scheme over GF(2^8). Fewer
+/// shares than the threshold reveal nothing about the secret.
+///
+/// | magic (4) | version (1) | id (4) | threshold (1) | index (1) |
+/// total (1) | data length (4) | data |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+  /// Random bytes shared by every share of the same secret
+  pub id: [u8; ID_LEN],
+  /// How many shares were made, and how many rebuild the secret
+  pub split: Split,
+  /// The x coordinate of this share, from 1 to the number of shares
+  pub index: u8,
+  data: Vec<u8>,
+}
+
+impl Share {
+  /// Returns true if `bytes` starts with the share magic bytes
+  pub fn is_share(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+  }
+
+  /// Reads the total length of a serialised share from its header
+  pub fn total_len(header: &[u8]) -> Result<usize> {
+    if header.len() < HEADER_LEN || !Self::is_share(header) {
+      return Err("No share found".into());
+    }
+    if header[4] != VERSION {
+      return Err("Unsupported share version".into());
+    }
+    let length =
+      u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+    Ok(HEADER_LEN + length as usize)
+  }
+
+  /// Serialises this share
+  pub fn as_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&self.id);
+    bytes.push(self.split.threshold);
+    bytes.push(self.index);
+    bytes.push(self.split.shares);
+    bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&self.data);
+    bytes
+  }
+}
+
+/// Parses a serialised share, ignoring bytes past its end
+impl TryFrom<&[u8]> for Share {
+  type Error = crate::Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    let total_len = Self::total_len(bytes)?;
+    if bytes.len() < total_len {
+      return Err("Share is truncated".into());
+    }
+    let split = Split::new(bytes[9], bytes[11])?;
+    let index = bytes[10];
+    if index == 0 || index > split.shares {
+      return Err("Invalid share index".into());
+    }
+    Ok(Self {
+      id: bytes[5..9].try_into().unwrap(),
+      split,
+      index,
+      data: bytes[HEADER_LEN..total_len].to_vec(),
+    })
+  }
+}
+
+/// Splits `secret` into `split.shares` shares, any `split.threshold` of
+/// which rebuild it
+pub fn split(secret: &[u8], split: Split) -> Vec<Share> {
+  let mut rng = rand::thread_rng();
+  let id = rng.gen();
+  let mut shares = (1..=split.shares)
+    .map(|index| Share {
+      id,
+      split,
+      index,
+      data: Vec::with_capacity(secret.len()),
+    })
+    .collect::<Vec<_>>();
+
+  // Each byte is the constant term of its own random polynomial of degree
+  // threshold - 1, and each share holds that polynomial at its index
+  let mut coefficients = vec![0u8; split.threshold as usize];
+  for &byte in secret {
+    coefficients[0] = byte;
+    rng.fill(&mut coefficients[1..]);
+    for share in &mut shares {
+      let y = coefficients
+        .iter()
+        .rev()
+        .fold(0, |y, &c| gf256::mul(y, share.index) ^ c);
+      share.data.push(y);
+    }
+  }
+  shares
+}
+
+/// Rebuilds a secret from its shares. Extra shares and duplicates are
+/// ignored, and the error says how many shares are missing.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>> {
+  let first = shares.first().ok_or("No shares to combine")?;
+  let mut used: Vec<&Share> = Vec::new();
+  for share in shares {
+    if share.id != first.id || share.split != first.split {
+      return Err("Shares come from different secrets".into());
+    }
+    if share.data.len() != first.data.len() {
+      return Err("Shares have different lengths".into());
+    }
+    if !used.iter().any(|other| other.index == share.index) {
+      used.push(share);
+    }
+  }
+
+  let needed = first.split.threshold as usize;
+  if used.len() < needed {
+    let missing = needed - used.len();
+    return Err(
+      format!(
+        "Found {} of the {} shares needed; {} more {} needed",
+        used.len(),
+        needed,
+        missing,
+        if missing == 1 { "is" } else { "are" }
+      )
+      .into(),
+    );
+  }
+  used.truncate(needed);
+
+  // Lagrange interpolation at x = 0, where subtraction is XOR
+  let weights = used
+    .iter()
+    .map(|share| {
+      used.iter().filter(|other| other.index != share.index).fold(
+        1,
+        |weight, other| {
+          gf256::mul(weight, gf256::div(other.index, other.index ^ share.index))
+        },
+      )
+    })
+    .collect::<Vec<_>>();
+  Ok(
+    (0..first.data.len())
+      .map(|i| {
+        used.iter().zip(&weights).fold(0, |byte, (share, &weight)| {
+          byte ^ gf256::mul(share.data[i], weight)
+        })
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_split_combine() {
+    let secret = b"This is a secret message!".to_vec();
+    let shares = split(&secret, "3-of-5".parse().unwrap());
+    assert_eq!(shares.len(), 5);
+
+    for picked in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+      let subset = picked.map(|i| shares[i].clone());
+      assert_eq!(combine(&subset).unwrap(), secret);
+    }
+    assert_eq!(combine(&shares).unwrap(), secret);
+
+    let error = combine(&shares[..2]).unwrap_err().to_string();
+    assert_eq!(error, "Found 2 of the 3 shares needed; 1 more is needed");
+    let duplicates = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+    assert!(combine(&duplicates).is_err());
+
+    let other = split(&secret, "3-of-5".parse().unwrap());
+    let mixed = [shares[0].clone(), shares[1].clone(), other[2].clone()];
+    assert!(combine(&mixed).is_err());
+  }
+
+  #[test]
+  fn test_share_round_trip() {
+    let shares = split(b"secret", Split::new(2, 3).unwrap());
+    let mut bytes = shares[1].as_bytes();
+    assert_eq!(Share::total_len(&bytes).unwrap(), bytes.len());
+    bytes.extend_from_slice(&[0xAA; 8]);
+    assert_eq!(Share::try_from(bytes.as_ref()).unwrap(), shares[1]);
+
+    assert!(Share::try_from(&bytes[..HEADER_LEN + 2]).is_err());
+    bytes[10] = 4;
+    assert!(Share::try_from(bytes.as_ref()).is_err());
+  }
+
+  #[test]
+  fn test_parse_split() {
+    assert_eq!(
+      "3-of-5".parse::<Split>().unwrap(),
+      Split::new(3, 5).unwrap()
+    );
+    assert_eq!(Split::new(2, 2).unwrap().to_string(), "2-of-2");
+    for invalid in ["3of5", "0-of-5", "6-of-5", "3-of-256", "a-of-5"] {
+      assert!(invalid.parse::<Split>().is_err(), "{}", invalid);
+    }
+  }
+}
@@ -0,0 +1,541 @@
+//! Estimating how hard a password is to guess, and generating passphrases
+//! that are hard enough.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use bip39::Language;
+use rand::seq::SliceRandom;
+
+use crate::Result;
+
+/// The score new passwords need unless another minimum is given
+pub const DEFAULT_MIN_SCORE: u8 = 3;
+
+/// The highest score an estimate can have
+pub const MAX_SCORE: u8 = 4;
+
+/// log10 of the guesses below which each score starts: fewer than 10^3
+/// guesses scores 0, fewer than 10^6 scores 1, and so on
+const SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+
+/// Only this many leading characters are analysed, so that the time taken
+/// stays bounded. Any prefix this long that is hard to guess scores the
+/// most, so the rest would not change the score.
+const MAX_ANALYSED_CHARS: usize = 128;
+
+/// Passwords that top leaked password lists, most common first
+const COMMON_PASSWORDS: &[&str] = &[
+  "password",
+  "123456",
+  "qwerty",
+  "letmein",
+  "iloveyou",
+  "admin",
+  "welcome",
+  "monkey",
+  "dragon",
+  "football",
+  "baseball",
+  "master",
+  "shadow",
+  "sunshine",
+  "princess",
+  "trustno",
+  "superman",
+  "batman",
+  "starwars",
+  "freedom",
+  "whatever",
+  "login",
+  "secret",
+  "hello",
+  "passw0rd",
+  "michael",
+  "jordan",
+  "charlie",
+  "hunter",
+  "ranger",
+  "buster",
+  "soccer",
+  "hockey",
+  "killer",
+  "cheese",
+  "computer",
+  "internet",
+  "summer",
+  "winter",
+  "flower",
+  "orange",
+  "pepper",
+  "ginger",
+  "cookie",
+  "chocolate",
+  "banana",
+  "matrix",
+  "mustang",
+  "access",
+  "changeme",
+  "default",
+  "test",
+  "guest",
+  "root",
+  "user",
+  "cachette",
+  "steganography",
+];
+
+/// Rows of a QWERTY keyboard, for spotting keys typed in a row
+const KEYBOARD_ROWS: [&str; 3] = ["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Characters that stand in for letters in "l33t" spellings
+const LEET: [(char, char); 8] = [
+  ('4', 'a'),
+  ('@', 'a'),
+  ('3', 'e'),
+  ('1', 'i'),
+  ('0', 'o'),
+  ('5', 's'),
+  ('$', 's'),
+  ('7', 't'),
+];
+
+/// A weakness found in part of a password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pattern {
+  /// One of the most common passwords
+  CommonPassword,
+  /// A dictionary word, maybe with letters swapped for look-alikes
+  Word,
+  /// A character or run of characters repeated
+  Repeat,
+  /// A run of consecutive letters or digits
+  Sequence,
+  /// A run of neighbouring keys
+  KeyboardRow,
+  /// A recent year
+  Year,
+}
+
+impl Pattern {
+  fn warning(&self) -> &'static str {
+    match self {
+      Pattern::CommonPassword => "it contains a commonly used password",
+      Pattern::Word => "single dictionary words are easy to guess",
+      Pattern::Repeat => "repeats like \"aaa\" or \"abcabc\" are easy to guess",
+      Pattern::Sequence => {
+        "sequences like \"abc\" or \"6543\" are easy to guess"
+      }
+      Pattern::KeyboardRow => "rows of keys like \"qwerty\" are easy to guess",
+      Pattern::Year => "years are easy to guess",
+    }
+  }
+}
+
+/// A part of a password and how many guesses it takes to find it
+#[derive(Debug, Clone)]
+struct Match {
+  start: usize,
+  end: usize,
+  guesses_log10: f64,
+  pattern: Option<Pattern>,
+}
+
+/// How hard a password is to guess
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+  /// log10 of the number of guesses an attacker who knows common patterns
+  /// needs
+  pub guesses_log10: f64,
+  /// 0 (trivial) to 4 (strong)
+  pub score: u8,
+  /// The patterns that made the password easier to guess
+  pub patterns: Vec<Pattern>,
+}
+
+impl Estimate {
+  /// Bits of entropy equivalent to the estimated guesses
+  pub fn entropy_bits(&self) -> f64 {
+    self.guesses_log10 * std::f64::consts::LOG2_10
+  }
+
+  /// Returns an error explaining why the password is weak if it scores
+  /// below `min_score`
+  pub fn check(&self, min_score: u8) -> Result<()> {
+    if self.score >= min_score {
+      return Ok(());
+    }
+    let mut message = format!(
+      "Password is too weak: it scores {} out of {} but {} is needed",
+      self.score, MAX_SCORE, min_score
+    );
+    for pattern in &self.patterns {
+      message.push_str(&format!("; {}", pattern.warning()));
+    }
+    message.push_str(
+      ". Use a longer passphrase of several uncommon words, or generate one \
+       with genpass",
+    );
+    Err(message.into())
+  }
+}
+
+impl fmt::Display for Estimate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "score {} out of {}, about {:.0} bits",
+      self.score,
+      MAX_SCORE,
+      self.entropy_bits()
+    )
+  }
+}
+
+/// The words passphrases are built from
+fn word_list() -> &'static [&'static str; 2048] {
+  Language::English.word_list()
+}
+
+/// How many values a character of the same class as `c` could take
+fn cardinality(c: char) -> f64 {
+  match c {
+    '0'..='9' => 10.0,
+    'a'..='z' | 'A'..='Z' => 26.0,
+    c if c.is_ascii() => 33.0,
+    _ => 100.0,
+  }
+}
+
+/// Extra guesses needed for the capitals in `word`, which are most often
+/// only the first letter, or every letter
+fn uppercase_log10(word: &[char]) -> f64 {
+  let upper = word.iter().filter(|c| c.is_uppercase()).count();
+  if upper == 0 {
+    0.0
+  } else if upper == word.len() || (upper == 1 && word[0].is_uppercase()) {
+    std::f64::consts::LOG10_2
+  } else {
+    upper.min(word.len() - upper).max(1) as f64 * std::f64::consts::LOG10_2
+  }
+}
+
+/// Finds common passwords and wordlist words, including capitalised and
+/// l33t spellings
+fn dictionary_matches(chars: &[char], matches: &mut Vec<Match>) {
+  let words = word_list();
+  let lower = chars
+    .iter()
+    .map(|c| c.to_ascii_lowercase())
+    .collect::<Vec<_>>();
+  let unleet = lower
+    .iter()
+    .map(|c| LEET.iter().find(|(from, _)| from == c).map_or(*c, |l| l.1))
+    .collect::<Vec<_>>();
+  let max_len = words
+    .iter()
+    .chain(COMMON_PASSWORDS)
+    .map(|word| word.len())
+    .max()
+    .unwrap_or(0);
+
+  for start in 0..chars.len() {
+    for end in start + 3..=chars.len().min(start + max_len) {
+      let substituted = lower[start..end] != unleet[start..end];
+      let candidate = unleet[start..end].iter().collect::<String>();
+      let (rank, pattern) = if let Some(rank) =
+        COMMON_PASSWORDS.iter().position(|w| *w == candidate)
+      {
+        (rank + 1, Pattern::CommonPassword)
+      } else if words.binary_search(&candidate.as_str()).is_ok() {
+        // Passphrase words are picked uniformly, so none is likelier
+        (words.len(), Pattern::Word)
+      } else {
+        continue;
+      };
+      let leet_log10 = if substituted {
+        std::f64::consts::LOG10_2 * 2.0
+      } else {
+        0.0
+      };
+      matches.push(Match {
+        start,
+        end,
+        guesses_log10: (rank as f64).log10()
+          + uppercase_log10(&chars[start..end])
+          + leet_log10,
+        pattern: Some(pattern),
+      });
+    }
+  }
+}
+
+/// Finds runs of a repeated character or of a repeated chunk. Each chunk is
+/// estimated once, without looking for repeats inside it.
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+  let mut units = HashMap::new();
+  for start in 0..chars.len() {
+    for unit in 1..=(chars.len() - start) / 2 {
+      let mut end = start + unit;
+      while end + unit <= chars.len()
+        && chars[end..end + unit] == chars[start..start + unit]
+      {
+        end += unit;
+      }
+      let count = (end - start) / unit;
+      if count < 2 || (unit == 1 && count < 3) {
+        continue;
+      }
+      let unit_chars = &chars[start..start + unit];
+      let unit_log10 = *units
+        .entry(unit_chars)
+        .or_insert_with(|| estimate_chars(unit_chars, false).0);
+      matches.push(Match {
+        start,
+        end,
+        guesses_log10: unit_log10 + (count as f64).log10(),
+        pattern: Some(Pattern::Repeat),
+      });
+    }
+  }
+}
+
+/// Finds runs of characters that step by one, like "abc" or "9876", and
+/// runs of keys along a keyboard row
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+  let mut start = 0;
+  while start + 2 < chars.len() {
+    let step = chars[start + 1] as i32 - chars[start] as i32;
+    let mut end = start + 1;
+    while end < chars.len()
+      && chars[end] as i32 - chars[end - 1] as i32 == step
+      && step.abs() == 1
+      && chars[end - 1].is_ascii_alphanumeric()
+      && chars[end].is_ascii_alphanumeric()
+    {
+      end += 1;
+    }
+    if end - start >= 3 {
+      let first = chars[start];
+      let base = if "aAzZ019".contains(first) {
+        4.0
+      } else if first.is_ascii_digit() {
+        10.0
+      } else {
+        26.0
+      };
+      let direction = if step < 0 { 2.0 } else { 1.0 };
+      matches.push(Match {
+        start,
+        end,
+        guesses_log10: (base * direction * (end - start) as f64).log10(),
+        pattern: Some(Pattern::Sequence),
+      });
+      start = end - 1;
+    } else {
+      start += 1;
+    }
+  }
+
+  let lower = chars
+    .iter()
+    .map(|c| c.to_ascii_lowercase())
+    .collect::<String>();
+  for row in KEYBOARD_ROWS {
+    let reversed = row.chars().rev().collect::<String>();
+    for (row, direction) in [(row, 1.0), (reversed.as_str(), 2.0)] {
+      for len in 3..=row.len() {
+        for offset in 0..=row.len() - len {
+          let keys = &row[offset..offset + len];
+          for (start, _) in lower.match_indices(keys) {
+            let start = lower[..start].chars().count();
+            let positions = (KEYBOARD_ROWS.len() * row.len()) as f64;
+            matches.push(Match {
+              start,
+              end: start + len,
+              guesses_log10: (positions * direction * len as f64).log10(),
+              pattern: Some(Pattern::KeyboardRow),
+            });
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Finds years between 1900 and 2099
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+  for start in 0..chars.len().saturating_sub(3) {
+    let year = &chars[start..start + 4];
+    if year.iter().all(char::is_ascii_digit)
+      && matches!((year[0], year[1]), ('1', '9') | ('2', '0'))
+    {
+      matches.push(Match {
+        start,
+        end: start + 4,
+        guesses_log10: 200f64.log10(),
+        pattern: Some(Pattern::Year),
+      });
+    }
+  }
+}
+
+/// Splits `chars` into the patterns that are quickest to guess in turn, with
+/// every character no pattern covers guessed on its own. Repeats are only
+/// looked for with `repeats`. Returns the log10 of the total guesses and the
+/// patterns used.
+fn estimate_chars(chars: &[char], repeats: bool) -> (f64, Vec<Pattern>) {
+  let mut matches = Vec::new();
+  if chars.len() >= 3 {
+    dictionary_matches(chars, &mut matches);
+    if repeats {
+      repeat_matches(chars, &mut matches);
+    }
+    sequence_matches(chars, &mut matches);
+    year_matches(chars, &mut matches);
+  }
+  for (i, c) in chars.iter().enumerate() {
+    matches.push(Match {
+      start: i,
+      end: i + 1,
+      guesses_log10: cardinality(*c).log10(),
+      pattern: None,
+    });
+  }
+
+  // best[i] is the cheapest way to guess the first i characters
+  let mut best: Vec<Option<(f64, usize)>> = vec![None; chars.len() + 1];
+  best[0] = Some((0.0, usize::MAX));
+  for end in 1..=chars.len() {
+    for (index, candidate) in matches.iter().enumerate() {
+      if candidate.end != end {
+        continue;
+      }
+      let Some((before, _)) = best[candidate.start] else {
+        continue;
+      };
+      let total = before + candidate.guesses_log10;
+      if best[end].is_none_or(|(current, _)| total < current) {
+        best[end] = Some((total, index));
+      }
+    }
+  }
+
+  let mut patterns = Vec::new();
+  let mut end = chars.len();
+  while end > 0 {
+    let (_, index) = best[end].expect("every character can be guessed");
+    if let Some(pattern) = matches[index].pattern {
+      if !patterns.contains(&pattern) {
+        patterns.push(pattern);
+      }
+    }
+    end = matches[index].start;
+  }
+  patterns.reverse();
+  (best[chars.len()].map_or(0.0, |(total, _)| total), patterns)
+}
+
+/// Estimates how many guesses `password` takes, by looking for common
+/// passwords, dictionary words, repeats, sequences, keyboard rows and
+/// years, the way zxcvbn does. Passphrases of words picked at random score
+/// by the number of words.
+pub fn estimate(password: &str) -> Estimate {
+  let chars = password
+    .chars()
+    .take(MAX_ANALYSED_CHARS)
+    .collect::<Vec<_>>();
+  let (guesses_log10, mut patterns) = estimate_chars(&chars, true);
+  let score = SCORE_THRESHOLDS
+    .iter()
+    .filter(|threshold| guesses_log10 >= **threshold)
+    .count() as u8;
+  // Passphrase words are only a weakness when there are too few of them
+  if score >= DEFAULT_MIN_SCORE {
+    patterns.retain(|pattern| *pattern != Pattern::Word);
+  }
+  Estimate {
+    guesses_log10,
+    score,
+    patterns,
+  }
+}
+
+/// Generates a passphrase of `words` words picked at random from the
+/// wordlist, joined by `separator`
+pub fn generate_passphrase(words: usize, separator: &str) -> String {
+  let mut rng = rand::thread_rng();
+  (0..words)
+    .map(|_| *word_list().choose(&mut rng).expect("non-empty wordlist"))
+    .collect::<Vec<_>>()
+    .join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_weak_passwords() {
+    for password in [
+      "aaaaaaaaaaaaaaaaaa",
+      "password123456",
+      "qwertyuiopasdfgh",
+      "abcdefghijklmnopqrst",
+      "P@ssw0rd1990",
+      "abcabcabcabcabcabc",
+    ] {
+      let estimate = estimate(password);
+      assert!(
+        estimate.score < DEFAULT_MIN_SCORE,
+        "{}: {}",
+        password,
+        estimate
+      );
+      assert!(!estimate.patterns.is_empty(), "{}", password);
+      assert!(estimate.check(DEFAULT_MIN_SCORE).is_err());
+    }
+    let error = estimate("aaaaaaaaaaaaaaaaaa").check(3).unwrap_err();
+    assert!(error.to_string().contains("\"aaa\""));
+
+    // Long repetitive passwords are still estimated quickly
+    let start = std::time::Instant::now();
+    for password in ["a".repeat(128), "ab".repeat(500), "xyz1".repeat(64)] {
+      assert!(
+        estimate(&password).score < DEFAULT_MIN_SCORE,
+        "{}",
+        password
+      );
+    }
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+  }
+
+  #[test]
+  fn test_strong_passwords() {
+    for password in [
+      "k7#Qv9!mZp2@xW4s",
+      "correct-horse-battery-staple-orbit",
+      "Tr0ub4dor&3xylophone!Quartz",
+    ] {
+      let estimate = estimate(password);
+      assert_eq!(estimate.score, MAX_SCORE, "{}: {}", password, estimate);
+      assert!(estimate.check(DEFAULT_MIN_SCORE).is_ok());
+    }
+    assert!(estimate("").check(0).is_ok());
+  }
+
+  #[test]
+  fn test_passphrases() {
+    let passphrase = generate_passphrase(6, "-");
+    assert_eq!(passphrase.split('-').count(), 6);
+    let estimate = estimate(&passphrase);
+    // Each word is one of 2048, so at least 11 bits
+    assert!(estimate.entropy_bits() >= 66.0, "{}", estimate);
+    assert_eq!(estimate.score, MAX_SCORE);
+
+    // Two words are not enough, however long they are
+    let estimate = super::estimate("AbstractWonder");
+    assert!(estimate.score < DEFAULT_MIN_SCORE);
+    assert_eq!(estimate.patterns, vec![Pattern::Word]);
+  }
+}
@@ -0,0 +1,353 @@
+//! Checking that the message hidden in a file is intact and opens with a
+//! key, without revealing it.
+
+use std::fmt;
+use std::io::Read;
+
+use crate::codec::{unprotect, Decoder};
+use crate::cover::{self, CoverImage, Format};
+use crate::envelope::Envelope;
+use crate::png::Png;
+use crate::scan;
+use crate::shamir::{self, Share, Split};
+
+/// A check made by `Decoder::verify`, from the outermost layer of the file
+/// in. Each has its own exit code in the `verify` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+  /// The file can be parsed in its format
+  Readable,
+  /// Every chunk of a PNG can be read and matches its CRC
+  Crc,
+  /// A payload starts where the decoder looks
+  Present,
+  /// The payload is there in full, and so is every share of a split
+  /// message
+  Complete,
+  /// The ECC frame, share and envelope of the payload are valid
+  WellFormed,
+  /// The tag of the envelope authenticates with the key
+  Authentic,
+}
+
+impl Check {
+  /// The exit code of the `verify` command when this check fails. Codes 1
+  /// and 2 are left to other errors and to invalid arguments.
+  pub fn exit_code(&self) -> u8 {
+    match self {
+      Check::Readable => 3,
+      Check::Crc => 4,
+      Check::Present => 5,
+      Check::Complete => 6,
+      Check::WellFormed => 7,
+      Check::Authentic => 8,
+    }
+  }
+}
+
+/// The first check a payload failed, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+  /// The check that failed
+  pub check: Check,
+  /// What was wrong, for people to read
+  pub reason: String,
+}
+
+impl Failure {
+  fn new(check: Check, reason: impl fmt::Display) -> Self {
+    Self {
+      check,
+      reason: reason.to_string(),
+    }
+  }
+}
+
+impl fmt::Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.reason)
+  }
+}
+
+impl std::error::Error for Failure {}
+
+/// What a payload that passed every check holds. The message itself is
+/// dropped as soon as it authenticates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+  /// The length of the envelope
+  pub envelope_len: usize,
+  /// How many bytes error correction repaired, if the payload had any
+  pub corrected: Option<usize>,
+  /// The split the envelope was rebuilt from, if it was split
+  pub split: Option<Split>,
+}
+
+impl fmt::Display for Verified {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Envelope of {} bytes authenticated", self.envelope_len)?;
+    if let Some(corrected) = self.corrected {
+      write!(f, "; corrected {} errors", corrected)?;
+    }
+    if let Some(split) = self.split {
+      write!(f, "; all {} shares of a {} split", split.shares, split)?;
+    }
+    Ok(())
+  }
+}
+
+/// Reads a whole file to verify, in whichever format it is. PNG files are
+/// read leniently, so that every damaged chunk is reported rather than the
+/// first.
+pub fn read<R: Read>(reader: R) -> Result<Box<dyn CoverImage>, Failure> {
+  let unreadable = |error| Failure::new(Check::Readable, error);
+  let (format, reader) = cover::sniff(reader).map_err(unreadable)?;
+  if format != Some(Format::Png) {
+    return cover::from_reader(reader).map_err(unreadable);
+  }
+  let (png, issues) = Png::from_reader_lenient(reader).map_err(unreadable)?;
+  if !issues.is_empty() {
+    let issues = issues.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let reason = format!("Damaged PNG: {}", issues.join("; "));
+    return Err(Failure::new(Check::Crc, reason));
+  }
+  Ok(Box::new(png))
+}
+
+impl Decoder {
+  /// Checks the payload hidden in `cover` without revealing the message:
+  /// that it is there in full, that its ECC frame and envelope are valid and
+  /// that the envelope authenticates with the key. Shares of a split
+  /// message are checked together with `verify_shares`.
+  pub fn verify(&self, cover: &dyn CoverImage) -> Result<Verified, Failure> {
+    let (sealed, corrected) = self.sealed(cover)?;
+    if Share::is_share(&sealed) {
+      let share = parse_share(&sealed)?;
+      let reason = format!(
+        "This image holds share {} of a {} split; verify it along with the \
+         other shares",
+        share.index, share.split
+      );
+      return Err(Failure::new(Check::Complete, reason));
+    }
+    Ok(Verified {
+      envelope_len: self.authenticate(&sealed)?,
+      corrected,
+      split: None,
+    })
+  }
+
+  /// Reads the share hidden in `cover` by `Encoder::encode_split`, checking
+  /// it like `verify` short of authenticating it
+  pub fn verify_share(&self, cover: &dyn CoverImage) -> Result<Share, Failure> {
+    let (sealed, _) = self.sealed(cover)?;
+    if !Share::is_share(&sealed) {
+      let reason = "Image holds a whole message rather than a share";
+      return Err(Failure::new(Check::WellFormed, reason));
+    }
+    parse_share(&sealed)
+  }
+
+  /// Checks that `shares` are every share of one message, not just enough
+  /// of them to decode it, and that the envelope they rebuild authenticates
+  /// with the key
+  pub fn verify_shares(&self, shares: &[Share]) -> Result<Verified, Failure> {
+    let first = shares
+      .first()
+      .ok_or_else(|| Failure::new(Check::Present, "No shares to verify"))?;
+    if shares.iter().any(|share| share.id != first.id) {
+      let reason = "Shares come from different messages";
+      return Err(Failure::new(Check::WellFormed, reason));
+    }
+    let split = first.split;
+    let mut indices =
+      shares.iter().map(|share| share.index).collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.len() < split.shares as usize {
+      let reason = format!(
+        "Found {} of the {} shares of a {} split",
+        indices.len(),
+        split.shares,
+        split
+      );
+      return Err(Failure::new(Check::Complete, reason));
+    }
+    let sealed = shamir::combine(shares)
+      .map_err(|error| Failure::new(Check::WellFormed, error))?;
+    Ok(Verified {
+      envelope_len: self.authenticate(&sealed)?,
+      corrected: None,
+      split: Some(split),
+    })
+  }
+
+  /// Reads the payload hidden in `cover` and removes its error correction,
+  /// returning the envelope or share inside and how many bytes were
+  /// repaired
+  fn sealed(
+    &self,
+    cover: &dyn CoverImage,
+  ) -> Result<(Vec<u8>, Option<usize>), Failure> {
+    let payload = self
+      .find_payload(cover)
+      .map_err(|error| Failure::new(Check::Readable, error))?
+      .ok_or_else(|| Failure::new(Check::Present, "No payload found"))?;
+    let (_, len) = scan::payload_len(&payload).ok_or_else(|| {
+      let reason = "Payload is not an envelope, share or ECC frame";
+      Failure::new(Check::WellFormed, reason)
+    })?;
+    if payload.len() < len {
+      let reason =
+        format!("Payload is cut short at {} of {} bytes", payload.len(), len);
+      return Err(Failure::new(Check::Complete, reason));
+    }
+    let (sealed, corrected, _) = unprotect(&payload)
+      .map_err(|error| Failure::new(Check::WellFormed, error))?;
+    Ok((sealed, corrected))
+  }
+
+  /// Opens the envelope `sealed` to check its tag, returning its length
+  fn authenticate(&self, sealed: &[u8]) -> Result<usize, Failure> {
+    let envelope = Envelope::try_from(sealed)
+      .map_err(|error| Failure::new(Check::WellFormed, error))?;
+    self
+      .session()
+      .open(&envelope)
+      .map_err(|error| Failure::new(Check::Authentic, error))?;
+    Ok(envelope.as_bytes().len())
+  }
+}
+
+fn parse_share(sealed: &[u8]) -> Result<Share, Failure> {
+  Share::try_from(sealed)
+    .map_err(|error| Failure::new(Check::WellFormed, error))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk_type::ChunkType;
+  use crate::{EccLevel, Encoder, Key};
+  use std::str::FromStr;
+
+  const PASSWORD: &str = "bXR3be-ujj!hP@NDaTUnnBuBQ";
+
+  fn cat() -> Png {
+    Png::from_file(&"tests/cat.png".into()).unwrap()
+  }
+
+  fn encoder() -> Encoder {
+    Encoder::with_key(Key::derive(PASSWORD))
+  }
+
+  fn decoder() -> Decoder {
+    Decoder::with_key(Key::derive(PASSWORD))
+  }
+
+  fn check(result: Result<impl fmt::Debug, Failure>) -> Check {
+    result.unwrap_err().check
+  }
+
+  #[test]
+  fn test_verify() {
+    let mut png = cat();
+    encoder().encode(&mut png, "Archived").unwrap();
+    let verified = decoder().verify(&png).unwrap();
+    assert_eq!(verified.envelope_len, Envelope::sealed_len(8));
+    assert_eq!((verified.corrected, verified.split), (None, None));
+
+    let wrong_key = Decoder::with_key(Key::derive("not the password"));
+    assert_eq!(check(wrong_key.verify(&png)), Check::Authentic);
+    let other_chunk =
+      decoder().chunk_type(ChunkType::from_str("ruSt").unwrap());
+    assert_eq!(check(other_chunk.verify(&png)), Check::Present);
+
+    let chunk_type = ChunkType::from_str(crate::DEFAULT_CHUNK_TYPE).unwrap();
+    let payload = png.message_chunk(&chunk_type).unwrap().unwrap().to_vec();
+    let mut damaged = |change: &dyn Fn(&mut Vec<u8>)| {
+      let mut data = payload.clone();
+      change(&mut data);
+      png.set_message_chunk(&chunk_type, data).unwrap();
+      decoder().verify(&png)
+    };
+    assert_eq!(check(damaged(&|data| data.truncate(30))), Check::Complete);
+    assert_eq!(check(damaged(&|data| data[4] = 9)), Check::WellFormed);
+    let flip_tag = |data: &mut Vec<u8>| *data.last_mut().unwrap() ^= 1;
+    assert_eq!(check(damaged(&flip_tag)), Check::Authentic);
+  }
+
+  #[test]
+  fn test_verify_ecc() {
+    let mut png = cat();
+    let encoder = encoder().ecc(EccLevel::Low);
+    encoder.encode(&mut png, "Archived").unwrap();
+    let chunk_type = ChunkType::from_str(crate::DEFAULT_CHUNK_TYPE).unwrap();
+    let payload = png.message_chunk(&chunk_type).unwrap().unwrap().to_vec();
+
+    let mut damaged = payload.clone();
+    damaged[40] ^= 0xFF;
+    damaged[50] ^= 0xFF;
+    png.set_message_chunk(&chunk_type, damaged).unwrap();
+    assert_eq!(decoder().verify(&png).unwrap().corrected, Some(2));
+
+    let mut damaged = payload;
+    let len = damaged.len();
+    damaged[30..len].iter_mut().for_each(|byte| *byte ^= 0x5A);
+    png.set_message_chunk(&chunk_type, damaged).unwrap();
+    assert_eq!(check(decoder().verify(&png)), Check::WellFormed);
+  }
+
+  #[test]
+  fn test_read() {
+    let mut png = cat();
+    encoder().encode(&mut png, "Archived").unwrap();
+    let bytes = png.as_bytes();
+    let cover = read(bytes.as_slice()).unwrap();
+    assert!(decoder().verify(&*cover).is_ok());
+
+    // Damage the image data, which leaves the payload alone
+    let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+    let mut damaged = bytes.clone();
+    damaged[idat + 10] ^= 1;
+    let failure = read(damaged.as_slice()).err().unwrap();
+    assert_eq!(failure.check, Check::Crc);
+    assert!(failure.reason.contains("IDAT chunk"), "{}", failure);
+
+    let failure = read(&b"GIF89a"[..]).err().unwrap();
+    assert_eq!(failure.check, Check::Readable);
+  }
+
+  #[test]
+  fn test_verify_shares() {
+    let mut pngs = vec![cat(), cat(), cat()];
+    encoder().encode_split(&mut pngs, "Shared", 2).unwrap();
+    assert_eq!(check(decoder().verify(&pngs[0])), Check::Complete);
+
+    let shares = pngs
+      .iter()
+      .map(|png| decoder().verify_share(png).unwrap())
+      .collect::<Vec<_>>();
+    let verified = decoder().verify_shares(&shares).unwrap();
+    assert_eq!(verified.split, Some(Split::new(2, 3).unwrap()));
+    assert_eq!(verified.envelope_len, Envelope::sealed_len(6));
+
+    // Two shares decode the message, but one is still missing
+    let failure = decoder().verify_shares(&shares[1..]).unwrap_err();
+    assert_eq!(failure.check, Check::Complete);
+    assert_eq!(failure.reason, "Found 2 of the 3 shares of a 2-of-3 split");
+
+    let wrong_key = Decoder::with_key(Key::derive("not the password"));
+    assert_eq!(check(wrong_key.verify_shares(&shares)), Check::Authentic);
+
+    let mut others = vec![cat(), cat(), cat()];
+    encoder().encode_split(&mut others, "Shared", 2).unwrap();
+    let mut mixed = shares[..2].to_vec();
+    mixed.push(decoder().verify_share(&others[2]).unwrap());
+    assert_eq!(check(decoder().verify_shares(&mixed)), Check::WellFormed);
+
+    let mut whole = cat();
+    encoder().encode(&mut whole, "Whole").unwrap();
+    assert_eq!(check(decoder().verify_share(&whole)), Check::WellFormed);
+  }
+}
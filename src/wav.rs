@@ -0,0 +1,305 @@
+use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::png::{ColorType, FilterStrategy, ImageHeader, Pixels};
+use crate::riff::{self, RiffChunk};
+use crate::Error;
+use crate::Result;
+
+/// The format tag of integer PCM samples
+const WAVE_FORMAT_PCM: u16 = 1;
+
+/// The format tag of WAVE_FORMAT_EXTENSIBLE, which gives the real format
+/// as a GUID
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The GUID of the PCM subformat of WAVE_FORMAT_EXTENSIBLE
+const PCM_SUBFORMAT: [u8; 16] = [
+  0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00,
+  0x38, 0x9B, 0x71,
+];
+
+/// A WAV file of 8-, 16- or 24-bit PCM audio, kept as the list of chunks of
+/// its RIFF container. Messages go in a chunk of their own, which players
+/// skip, or in the low bits of the samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wav {
+  chunks: Vec<RiffChunk>,
+  channels: u16,
+  sample_rate: u32,
+  bits_per_sample: u16,
+}
+
+impl Wav {
+  /// The number of interleaved channels
+  pub fn channels(&self) -> u16 {
+    self.channels
+  }
+
+  /// The number of sample frames per second
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  /// The size of each sample: 8, 16 or 24 bits
+  pub fn bits_per_sample(&self) -> u16 {
+    self.bits_per_sample
+  }
+
+  fn bytes_per_sample(&self) -> usize {
+    self.bits_per_sample as usize / 8
+  }
+
+  fn data(&self) -> &[u8] {
+    let data = self.chunks.iter().find(|chunk| &chunk.fourcc == b"data");
+    data.map(|chunk| chunk.data.as_slice()).unwrap_or_default()
+  }
+
+  /// The number of whole sample frames, one sample of every channel each
+  fn frame_count(&self) -> usize {
+    self.data().len() / (self.bytes_per_sample() * self.channels as usize)
+  }
+
+  /// The layout of the low bytes of the samples as `Pixels`: a grey image
+  /// with a row for every sample frame and a column for every channel
+  fn image_header(&self) -> Result<ImageHeader> {
+    Ok(ImageHeader {
+      width: self.channels as u32,
+      height: u32::try_from(self.frame_count())
+        .map_err(|_| "WAV file is too long")?,
+      bit_depth: 8,
+      color_type: ColorType::Grayscale,
+      interlaced: false,
+    })
+  }
+}
+
+/// Checks the fmt chunk of a WAV file, returning the channel count, sample
+/// rate and sample size it gives
+fn parse_fmt(data: &[u8]) -> Result<(u16, u32, u16)> {
+  let field = |offset: usize, len: usize| {
+    data
+      .get(offset..offset + len)
+      .ok_or("WAV fmt chunk is truncated")
+  };
+  let u16_at = |offset| -> Result<u16> {
+    Ok(u16::from_le_bytes(field(offset, 2)?.try_into()?))
+  };
+  let u32_at = |offset| -> Result<u32> {
+    Ok(u32::from_le_bytes(field(offset, 4)?.try_into()?))
+  };
+
+  let format = u16_at(0)?;
+  let is_pcm = match format {
+    WAVE_FORMAT_PCM => true,
+    WAVE_FORMAT_EXTENSIBLE => field(24, 16)? == PCM_SUBFORMAT,
+    _ => false,
+  };
+  if !is_pcm {
+    return Err("Only PCM WAV files are supported".into());
+  }
+  let channels = u16_at(2)?;
+  let sample_rate = u32_at(4)?;
+  let byte_rate = u32_at(8)?;
+  let block_align = u16_at(12)?;
+  let bits_per_sample = u16_at(14)?;
+  if !matches!(bits_per_sample, 8 | 16 | 24) {
+    return Err("Only 8-, 16- and 24-bit PCM WAV files are supported".into());
+  }
+  if channels == 0 {
+    return Err("WAV file has no channels".into());
+  }
+  let expected_align = channels as u32 * bits_per_sample as u32 / 8;
+  if block_align as u32 != expected_align
+    || byte_rate as u64 != sample_rate as u64 * expected_align as u64
+  {
+    return Err("WAV fmt chunk is inconsistent".into());
+  }
+  Ok((channels, sample_rate, bits_per_sample))
+}
+
+/// Parses a WAV file, failing on anything but PCM audio with a valid fmt
+/// chunk and a data chunk
+impl TryFrom<&[u8]> for Wav {
+  type Error = Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    let chunks = riff::parse(bytes, b"WAVE", "WAV")?;
+    let fmt = chunks
+      .iter()
+      .find(|chunk| &chunk.fourcc == b"fmt ")
+      .ok_or("WAV file has no fmt chunk")?;
+    let (channels, sample_rate, bits_per_sample) = parse_fmt(&fmt.data)?;
+    if !chunks.iter().any(|chunk| &chunk.fourcc == b"data") {
+      return Err("WAV file has no data chunk".into());
+    }
+    Ok(Self {
+      chunks,
+      channels,
+      sample_rate,
+      bits_per_sample,
+    })
+  }
+}
+
+impl CoverImage for Wav {
+  fn format(&self) -> Format {
+    Format::Wav
+  }
+
+  fn as_bytes(&self) -> Vec<u8> {
+    riff::write(b"WAVE", &self.chunks)
+  }
+
+  fn byte_len(&self) -> usize {
+    riff::byte_len(&self.chunks)
+  }
+
+  fn message_chunk(&self, chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    let fourcc = chunk_type.bytes();
+    let chunk = self.chunks.iter().find(|chunk| chunk.fourcc == fourcc);
+    Ok(chunk.map(|chunk| chunk.data.as_slice()))
+  }
+
+  /// The chunk goes at the end of the file, after the audio
+  fn set_message_chunk(
+    &mut self,
+    chunk_type: &ChunkType,
+    data: Vec<u8>,
+  ) -> Result<()> {
+    let fourcc = chunk_type.bytes();
+    if &fourcc == b"data" {
+      return Err("The data chunk holds the audio of a WAV file".into());
+    }
+    self.chunks.retain(|chunk| chunk.fourcc != fourcc);
+    self.chunks.push(RiffChunk { fourcc, data });
+    Ok(())
+  }
+
+  /// The lowest byte of every sample as a single grey frame. Samples are
+  /// little-endian, so it is the first byte of each.
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    let header = self.image_header()?;
+    let sample_count = self.frame_count() * self.channels as usize;
+    let low_bytes = self
+      .data()
+      .chunks_exact(self.bytes_per_sample())
+      .take(sample_count)
+      .map(|sample| sample[0])
+      .collect();
+    Ok(vec![Pixels::new(header, low_bytes)?])
+  }
+
+  fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    _filter: FilterStrategy,
+  ) -> Result<()> {
+    let [pixels] = frames else {
+      return Err("WAV audio holds a single frame".into());
+    };
+    if *pixels.header() != self.image_header()? {
+      return Err("Samples do not match the WAV audio".into());
+    }
+    let bytes_per_sample = self.bytes_per_sample();
+    let data = self
+      .chunks
+      .iter_mut()
+      .find(|chunk| &chunk.fourcc == b"data")
+      .ok_or("WAV file has no data chunk")?;
+    for (sample, &low_byte) in data
+      .data
+      .chunks_exact_mut(bytes_per_sample)
+      .zip(pixels.data())
+    {
+      sample[0] = low_byte;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Decoder, Encoder, Mode};
+  use std::str::FromStr;
+
+  /// A WAV file of `frames` sample frames in `channels` channels
+  fn wav_bytes(channels: u16, bits: u16, frames: usize) -> Vec<u8> {
+    let align = channels * bits / 8;
+    let mut fmt = WAVE_FORMAT_PCM.to_le_bytes().to_vec();
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&8000u32.to_le_bytes());
+    fmt.extend_from_slice(&(8000 * align as u32).to_le_bytes());
+    fmt.extend_from_slice(&align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    let data = (0..frames * align as usize)
+      .map(|i| (i * 29 % 251) as u8)
+      .collect();
+    let chunks = [
+      RiffChunk {
+        fourcc: *b"fmt ",
+        data: fmt,
+      },
+      RiffChunk {
+        fourcc: *b"data",
+        data,
+      },
+    ];
+    riff::write(b"WAVE", &chunks)
+  }
+
+  #[test]
+  fn test_fmt_validation() {
+    let wav = Wav::try_from(wav_bytes(2, 16, 10).as_slice()).unwrap();
+    assert_eq!((wav.channels(), wav.bits_per_sample()), (2, 16));
+    assert_eq!(wav.sample_rate(), 8000);
+    assert_eq!(wav.as_bytes(), wav_bytes(2, 16, 10));
+
+    assert!(Wav::try_from(wav_bytes(1, 32, 10).as_slice()).is_err());
+    let mut float = wav_bytes(1, 16, 10);
+    float[20] = 3;
+    assert!(Wav::try_from(float.as_slice()).is_err());
+    let mut misaligned = wav_bytes(2, 16, 10);
+    misaligned[32] = 2;
+    assert!(Wav::try_from(misaligned.as_slice()).is_err());
+  }
+
+  #[test]
+  fn test_chunk_round_trip() {
+    let mut wav = Wav::try_from(wav_bytes(1, 8, 100).as_slice()).unwrap();
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    let encoder = Encoder::new("password").chunk_type(chunk_type.clone());
+    encoder.encode(&mut wav, "Voice memo").unwrap();
+    let fourccs = wav.chunks.iter().map(|chunk| &chunk.fourcc);
+    assert_eq!(fourccs.collect::<Vec<_>>(), [b"fmt ", b"data", b"ruSt"]);
+    assert_eq!(wav.data(), &wav_bytes(1, 8, 100)[44..]);
+
+    let wav = crate::cover::from_bytes(wav.as_bytes()).unwrap();
+    let decoder = Decoder::new("password").chunk_type(chunk_type);
+    assert_eq!(decoder.decode(&*wav).unwrap().message, "Voice memo");
+  }
+
+  #[test]
+  fn test_lsb_round_trip() {
+    for (channels, bits) in [(1, 8), (2, 16), (2, 24)] {
+      let before = wav_bytes(channels, bits, 2000);
+      let mut wav = Wav::try_from(before.as_slice()).unwrap();
+      Encoder::new("password")
+        .mode(Mode::Lsb)
+        .encode(&mut wav, "Low bits")
+        .unwrap();
+      let after = wav.as_bytes();
+      assert_eq!(after.len(), before.len());
+      // Only the lowest byte of each sample may change
+      let bytes_per_sample = bits as usize / 8;
+      let samples = before[44..].chunks(bytes_per_sample);
+      for (old, new) in samples.zip(after[44..].chunks(bytes_per_sample)) {
+        assert_eq!(old[1..], new[1..]);
+      }
+
+      let wav = crate::cover::from_bytes(after).unwrap();
+      let decoder = Decoder::new("password").mode(Mode::Lsb);
+      assert_eq!(decoder.decode(&*wav).unwrap().message, "Low bits");
+    }
+  }
+}
@@ -0,0 +1,237 @@
+use std::io::Cursor;
+
+use image_webp::{WebPDecoder, WebPEncoder};
+
+use crate::chunk_type::ChunkType;
+use crate::cover::{CoverImage, Format};
+use crate::png::{ColorType, FilterStrategy, ImageHeader, Pixels};
+use crate::riff::{self, RiffChunk};
+use crate::Error;
+use crate::Result;
+
+/// The VP8X flag telling readers the image has an alpha channel
+const ALPHA_FLAG: u8 = 1 << 4;
+
+/// A WebP image, kept as the list of chunks of its RIFF container. Messages
+/// go in a chunk of their own, which readers skip, or in the pixels of a
+/// lossless image, which is then compressed again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebP {
+  chunks: Vec<RiffChunk>,
+}
+
+impl WebP {
+  fn chunk(&self, fourcc: &[u8; 4]) -> Option<&RiffChunk> {
+    self.chunks.iter().find(|chunk| &chunk.fourcc == fourcc)
+  }
+
+  fn decoder(&self) -> Result<WebPDecoder<Cursor<Vec<u8>>>> {
+    Ok(WebPDecoder::new(Cursor::new(CoverImage::as_bytes(self)))?)
+  }
+
+  /// Turns a simple WebP file, which holds nothing but the image, into an
+  /// extended one, which may hold other chunks. The VP8X chunk that starts
+  /// extended files repeats the canvas size and whether there is alpha.
+  fn make_extended(&mut self) -> Result<()> {
+    if self.chunk(b"VP8X").is_some() {
+      return Ok(());
+    }
+    let decoder = self.decoder()?;
+    let (width, height) = decoder.dimensions();
+    let flags = if decoder.has_alpha() { ALPHA_FLAG } else { 0 };
+    let mut data = vec![flags, 0, 0, 0];
+    data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    self.chunks.insert(
+      0,
+      RiffChunk {
+        fourcc: *b"VP8X",
+        data,
+      },
+    );
+    Ok(())
+  }
+}
+
+/// Parses the RIFF container of a WebP file. The image itself is only
+/// decoded when pixels are needed.
+impl TryFrom<&[u8]> for WebP {
+  type Error = Error;
+
+  fn try_from(bytes: &[u8]) -> Result<Self> {
+    let chunks = riff::parse(bytes, b"WEBP", "WebP")?;
+    Ok(Self { chunks })
+  }
+}
+
+impl CoverImage for WebP {
+  fn format(&self) -> Format {
+    Format::WebP
+  }
+
+  fn as_bytes(&self) -> Vec<u8> {
+    riff::write(b"WEBP", &self.chunks)
+  }
+
+  fn byte_len(&self) -> usize {
+    riff::byte_len(&self.chunks)
+  }
+
+  fn message_chunk(&self, chunk_type: &ChunkType) -> Result<Option<&[u8]>> {
+    let chunk = self.chunk(&chunk_type.bytes());
+    Ok(chunk.map(|chunk| chunk.data.as_slice()))
+  }
+
+  /// The chunk goes at the end of the file, after the image, where the
+  /// container format allows unknown chunks
+  fn set_message_chunk(
+    &mut self,
+    chunk_type: &ChunkType,
+    data: Vec<u8>,
+  ) -> Result<()> {
+    let fourcc = chunk_type.bytes();
+    self.make_extended()?;
+    self.chunks.retain(|chunk| chunk.fourcc != fourcc);
+    self.chunks.push(RiffChunk { fourcc, data });
+    Ok(())
+  }
+
+  /// The single frame of a lossless image, as 8-bit RGB or RGBA
+  fn decode_frames(&self) -> Result<Vec<Pixels>> {
+    let mut decoder = self.decoder()?;
+    if decoder.is_animated() {
+      return Err("Animated WebP images are not supported".into());
+    }
+    if decoder.is_lossy() {
+      return Err("Pixel modes only support lossless WebP images".into());
+    }
+    let (width, height) = decoder.dimensions();
+    let color_type = match decoder.has_alpha() {
+      true => ColorType::Rgba,
+      false => ColorType::Rgb,
+    };
+    let header = ImageHeader {
+      width,
+      height,
+      bit_depth: 8,
+      color_type,
+      interlaced: false,
+    };
+    let len = decoder
+      .output_buffer_size()
+      .ok_or("WebP image is too large")?;
+    let mut data = vec![0; len];
+    decoder.read_image(&mut data)?;
+    Ok(vec![Pixels::new(header, data)?])
+  }
+
+  /// Compresses `frames` losslessly again, replacing the VP8L chunk and
+  /// leaving every other chunk as it was
+  fn encode_frames(
+    &mut self,
+    frames: &[Pixels],
+    _filter: FilterStrategy,
+  ) -> Result<()> {
+    let [pixels] = frames else {
+      return Err("A WebP image holds a single frame here".into());
+    };
+    let header = pixels.header();
+    let color = match (header.color_type, header.bit_depth) {
+      (ColorType::Rgb, 8) => image_webp::ColorType::Rgb8,
+      (ColorType::Rgba, 8) => image_webp::ColorType::Rgba8,
+      _ => return Err("WebP images hold 8-bit RGB or RGBA pixels".into()),
+    };
+    let index = self
+      .chunks
+      .iter()
+      .position(|chunk| &chunk.fourcc == b"VP8L")
+      .ok_or("Pixel modes only support lossless WebP images")?;
+
+    let mut encoded = Vec::new();
+    WebPEncoder::new(&mut encoded).encode(
+      pixels.data(),
+      header.width,
+      header.height,
+      color,
+    )?;
+    let encoded = WebP::try_from(encoded.as_slice())?;
+    let vp8l = encoded
+      .chunk(b"VP8L")
+      .ok_or("Encoder wrote no VP8L chunk")?;
+    self.chunks[index] = vp8l.clone();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Decoder, Encoder, Mode};
+  use std::str::FromStr;
+
+  /// A lossless 32x24 WebP image, with or without alpha
+  fn webp(color: image_webp::ColorType) -> WebP {
+    let channels = match color {
+      image_webp::ColorType::Rgba8 => 4,
+      _ => 3,
+    };
+    let data = (0..32 * 24 * channels)
+      .map(|i| (i * 31 % 253) as u8 | 0x80)
+      .collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    WebPEncoder::new(&mut bytes)
+      .encode(&data, 32, 24, color)
+      .unwrap();
+    WebP::try_from(bytes.as_slice()).unwrap()
+  }
+
+  fn decodes(webp: &WebP) -> bool {
+    let bytes = webp.as_bytes();
+    let mut decoder = WebPDecoder::new(Cursor::new(bytes)).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.dimensions() == (32, 24) && decoder.read_image(&mut data).is_ok()
+  }
+
+  #[test]
+  fn test_chunk_round_trip() {
+    let mut webp = webp(image_webp::ColorType::Rgb8);
+    assert_eq!(WebP::try_from(webp.as_bytes().as_slice()).unwrap(), webp);
+
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    let encoder = Encoder::new("password").chunk_type(chunk_type.clone());
+    encoder.encode(&mut webp, "Odd").unwrap();
+    let fourccs = webp.chunks.iter().map(|chunk| &chunk.fourcc);
+    assert_eq!(fourccs.collect::<Vec<_>>(), [b"VP8X", b"VP8L", b"ruSt"]);
+    assert!(decodes(&webp));
+
+    let webp = crate::cover::from_bytes(webp.as_bytes()).unwrap();
+    let decoder = Decoder::new("password").chunk_type(chunk_type);
+    assert_eq!(decoder.decode(&*webp).unwrap().message, "Odd");
+  }
+
+  #[test]
+  fn test_lsb_round_trip() {
+    for color in [image_webp::ColorType::Rgb8, image_webp::ColorType::Rgba8] {
+      let mut webp = webp(color);
+      let before = webp.decode_frames().unwrap();
+      Encoder::new("password")
+        .mode(Mode::Lsb)
+        .encode(&mut webp, "Lossless")
+        .unwrap();
+      assert!(decodes(&webp));
+      let after = webp.decode_frames().unwrap();
+      assert_eq!(after[0].header(), before[0].header());
+
+      let decoder = Decoder::new("password").mode(Mode::Lsb);
+      assert_eq!(decoder.decode(&webp).unwrap().message, "Lossless");
+      let error = Encoder::new("password")
+        .mode(Mode::Robust)
+        .encode(&mut webp, "Lossless")
+        .unwrap_err();
+      assert_eq!(
+        error.to_string(),
+        "Files in webp format do not support robust mode"
+      );
+    }
+  }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use rand::RngCore;
+
+use crate::chunk_type::ChunkType;
+use crate::deflate;
+use crate::envelope::NONCE_LEN;
+use crate::ezstego;
+use crate::lsb;
+use crate::png::{FilterStrategy, Png};
+use crate::robust;
+use crate::scan::{self, Location};
+use crate::Result;
+
+/// What `wipe` removed from an image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Wiped {
+  /// The types of the chunks that were dropped, in file order
+  pub chunks: Vec<ChunkType>,
+  /// The bits per channel of every LSB payload whose bits were randomised
+  pub lsb: Vec<u8>,
+  /// Whether a payload in the palette colours was randomised
+  pub palette: bool,
+  /// Whether a payload in the compressed image data was randomised
+  pub deflate: bool,
+  /// Whether a payload in the colour parity of the pixels was randomised
+  pub robust: bool,
+}
+
+impl Wiped {
+  /// Returns true if the image held nothing to wipe
+  pub fn is_empty(&self) -> bool {
+    self.chunks.is_empty()
+      && self.lsb.is_empty()
+      && !self.palette
+      && !self.deflate
+      && !self.robust
+  }
+}
+
+impl fmt::Display for Wiped {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.is_empty() {
+      return write!(f, "Nothing to wipe");
+    }
+    let mut parts = self
+      .chunks
+      .iter()
+      .map(|chunk_type| format!("{} chunk", chunk_type))
+      .collect::<Vec<_>>();
+    parts.extend(self.lsb.iter().map(|bits_per_channel| {
+      format!("lsb payload at {} bits per channel", bits_per_channel)
+    }));
+    if self.palette {
+      parts.push("palette payload".to_string());
+    }
+    if self.deflate {
+      parts.push("deflate payload".to_string());
+    }
+    if self.robust {
+      parts.push("robust payload".to_string());
+    }
+    write!(f, "Wiped {}", parts.join(", "))
+  }
+}
+
+/// Removes every trace of cachette from `png`: chunks holding envelopes,
+/// shares or ECC frames, messages encoded before envelopes together with
+/// their nonce chunks, and the chunks of `chunk_types` with their nonce
+/// chunks. Payloads in the pixels, the palette or the compressed image data
+/// are overwritten with random bits, since dropping them is not possible.
+///
+/// Returns an error if `scan` still finds a payload afterwards.
+pub fn wipe(png: &mut Png, chunk_types: &[ChunkType]) -> Result<Wiped> {
+  let mut doomed = scan::scan(png)
+    .into_iter()
+    .filter_map(|finding| match finding.location {
+      Location::Chunk(chunk_type) => Some(chunk_type.to_string()),
+      Location::Lsb { .. }
+      | Location::Palette
+      | Location::Deflate
+      | Location::Robust => None,
+    })
+    .chain(chunk_types.iter().map(ToString::to_string))
+    .collect::<HashSet<_>>();
+
+  // Before envelopes, the nonce of a message went in a chunk of its own,
+  // named after the message chunk with its first letter replaced by `n`,
+  // and stored in base64
+  let types = png
+    .chunks()
+    .iter()
+    .map(|chunk| chunk.chunk_type().to_string())
+    .collect::<HashSet<_>>();
+  for chunk in png.chunks() {
+    let chunk_type = chunk.chunk_type().to_string();
+    let nonce_type = Png::legacy_nonce_chunk_type(&chunk_type);
+    let is_legacy_pair = nonce_type != chunk_type
+      && types.contains(&nonce_type)
+      && png
+        .chunk_by_type(&nonce_type)
+        .and_then(|nonce| {
+          general_purpose::STANDARD_NO_PAD.decode(nonce.data()).ok()
+        })
+        .is_some_and(|nonce| nonce.len() == NONCE_LEN);
+    if is_legacy_pair || doomed.contains(&chunk_type) {
+      doomed.insert(chunk_type);
+      doomed.insert(nonce_type);
+    }
+  }
+
+  let mut wiped = Wiped::default();
+  png.retain_chunks(|chunk| {
+    let remove = doomed.contains(&chunk.chunk_type().to_string());
+    if remove {
+      wiped.chunks.push(chunk.chunk_type().clone());
+    }
+    !remove
+  });
+
+  // Randomising one payload can reveal nothing new, but a payload read at
+  // one depth can hide the header of another, so scan until none is left
+  let mut rng = rand::thread_rng();
+  let mut frames = None;
+  while let Some(finding) = scan::scan(png)
+    .into_iter()
+    .find(|finding| !matches!(finding.location, Location::Chunk(_)))
+  {
+    let mut noise = vec![0; finding.len];
+    rng.fill_bytes(&mut noise);
+    // The pixels stay the same, so decoded frames remain valid
+    if finding.location == Location::Deflate {
+      deflate::embed(png, &noise)?;
+      wiped.deflate = true;
+      continue;
+    }
+    if finding.location == Location::Robust {
+      robust::embed(png, &noise, FilterStrategy::default())?;
+      wiped.robust = true;
+      frames = None;
+      continue;
+    }
+    let mut decoded = match frames.take() {
+      Some(frames) => frames,
+      None => png.decode_frames()?,
+    };
+    match finding.location {
+      Location::Lsb { bits_per_channel } => {
+        lsb::embed(&mut decoded, &noise, bits_per_channel)?;
+        wiped.lsb.push(bits_per_channel);
+      }
+      Location::Palette => {
+        ezstego::embed(&mut decoded, &png.palette()?, &noise)?;
+        wiped.palette = true;
+      }
+      Location::Chunk(_) | Location::Deflate | Location::Robust => {
+        unreachable!("these findings are handled above")
+      }
+    }
+    png.encode_frames(&decoded, FilterStrategy::default())?;
+    frames = Some(decoded);
+  }
+
+  if !scan::scan(png).is_empty() {
+    return Err("Cachette payloads remain after wiping".into());
+  }
+  Ok(wiped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use crate::png::{ColorType, ImageHeader};
+  use crate::{EccLevel, Encoder, Key, Mode};
+  use std::str::FromStr;
+
+  #[test]
+  fn test_wipe() {
+    let mut png = Png::from_file(&"tests/cat.png".into()).unwrap();
+    let original = png.decode_pixels().unwrap();
+    let key = Key::derive("bXR3be-ujj!hP@NDaTUnnBuBQ");
+    Encoder::with_key(key.clone())
+      .chunk_type(ChunkType::from_str("ruSt").unwrap())
+      .encode(&mut png, "in a chunk")
+      .unwrap();
+    Encoder::with_key(key)
+      .mode(Mode::Lsb)
+      .bits_per_channel(2)
+      .ecc(EccLevel::Low)
+      .encode(&mut png, "in the pixels")
+      .unwrap();
+
+    let wiped = wipe(&mut png, &[]).unwrap();
+    // The fixture holds a message from before envelopes, with its nonce
+    let types = wiped.chunks.iter().map(ToString::to_string);
+    let types = types.collect::<HashSet<_>>();
+    assert!(types.contains("teSt") && types.contains("neSt"));
+    assert!(types.contains("ruSt"));
+    assert_eq!(wiped.lsb, vec![2]);
+    assert!(scan::scan(&png).is_empty());
+    assert!(png.chunk_by_type("IHDR").is_some());
+
+    // Only the low bits change
+    let pixels = png.decode_pixels().unwrap();
+    for index in 0..pixels.sample_count() {
+      assert_eq!(pixels.sample(index) >> 2, original.sample(index) >> 2);
+    }
+
+    assert!(wipe(&mut png, &[]).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_image_too_large() {
+    let header = ImageHeader {
+      width: 0x7fff_ffff,
+      height: 0x7fff_ffff,
+      bit_depth: 16,
+      color_type: ColorType::Rgba,
+      interlaced: true,
+    };
+    let mut png = Png::from_chunks(vec![
+      header.as_chunk(),
+      Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![0; 16]),
+      Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+    ]);
+    assert!(scan::scan(&png).is_empty());
+    assert!(wipe(&mut png, &[]).unwrap().is_empty());
+  }
+}
@@ -1,34 +1,70 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
+use std::path::PathBuf;
 use std::process::Command;
+use tempfile::TempDir;
 
 const TEST_FILE_PATH: &str = "tests/cat.png";
+const PASSWORD: &str = "vivid-orbit-canyon-mango-42";
+
+/// The password the message in the fixture was encoded with, which is too
+/// weak to encode new messages with
+const LEGACY_PASSWORD: &str = "test_password_123456789";
+
+/// Copies the test image into a temporary directory, so tests that write to
+/// it neither race each other nor modify the fixture
+fn copy_of_test_file() -> (TempDir, PathBuf) {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("cat.png");
+  std::fs::copy(TEST_FILE_PATH, &path).unwrap();
+  (dir, path)
+}
+
+fn cachette() -> Command {
+  let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+  cmd.env("TEST_PASSWORD", PASSWORD);
+  cmd
+}
+
+/// Runs cachette with `stdin` fed to its standard input
+fn piped(stdin: impl Into<Vec<u8>>) -> assert_cmd::Command {
+  let mut cmd = assert_cmd::Command::from_std(cachette());
+  cmd.write_stdin(stdin);
+  cmd
+}
 
 #[test]
 fn test_encode() -> Result<(), Box<dyn std::error::Error>> {
   let message = "This is a secret message";
   let chunk_type = "teSt";
-  let password = "test_password_123456789";
-  std::env::set_var("TEST_PASSWORD", password);
-  let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+  let (_dir, path) = copy_of_test_file();
 
-  cmd
-    .args(&["encode", TEST_FILE_PATH, chunk_type, message])
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args([chunk_type, message])
     .assert()
     .success();
 
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .arg(chunk_type)
+    .assert()
+    .success()
+    .stdout("This is a secret message\n");
+
   Ok(())
 }
 
 #[test]
 fn test_decode() -> Result<(), Box<dyn std::error::Error>> {
+  // The fixture holds a message encoded before envelopes existed
   let chunk_type = "teSt";
-  let password = "test_password_123456789";
-  std::env::set_var("TEST_PASSWORD", password);
-  let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
 
-  cmd
-    .args(&["decode", TEST_FILE_PATH, chunk_type])
+  cachette()
+    .env("TEST_PASSWORD", LEGACY_PASSWORD)
+    .args(["decode", TEST_FILE_PATH, chunk_type])
     .assert()
     .success()
     .stdout("This is a secret message\n");
@@ -37,11 +73,84 @@ fn test_decode() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
-fn test_print() -> Result<(), Box<dyn std::error::Error>> {
-  let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+fn test_encode_decode_lsb() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
 
-  cmd
-    .args(&["print", TEST_FILE_PATH])
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "Hidden in the pixels"])
+    .args(["--mode", "lsb", "--bits-per-channel", "2"])
+    .assert()
+    .success();
+
+  cachette()
+    .arg("print")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("IDAT"));
+
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .args(["teSt", "--mode", "lsb", "--bits-per-channel", "2"])
+    .assert()
+    .success()
+    .stdout("Hidden in the pixels\n");
+
+  Ok(())
+}
+
+#[test]
+fn test_encode_decode_ecc() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args([
+      "teSt",
+      "Protected message",
+      "--mode",
+      "lsb",
+      "--ecc",
+      "high",
+    ])
+    .assert()
+    .success();
+
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .args(["teSt", "--mode", "lsb"])
+    .assert()
+    .success()
+    .stdout("Protected message\n")
+    .stderr("Corrected 0 errors\n");
+
+  Ok(())
+}
+
+#[test]
+fn test_capacity() -> Result<(), Box<dyn std::error::Error>> {
+  cachette()
+    .args(["capacity", TEST_FILE_PATH, "--mode", "lsb"])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Raw capacity: 230400 bytes"))
+    .stdout(predicate::str::contains(
+      "Envelope overhead: 68 bytes (header 9, key block 31, nonce 12, tag 16)",
+    ))
+    .stdout(predicate::str::contains("Usable capacity: 230332 bytes"));
+
+  Ok(())
+}
+
+#[test]
+fn test_print() -> Result<(), Box<dyn std::error::Error>> {
+  cachette()
+    .args(["print", TEST_FILE_PATH])
     .assert()
     .success()
     .stdout(predicate::str::contains("teSt"));
@@ -52,12 +161,896 @@ fn test_print() -> Result<(), Box<dyn std::error::Error>> {
 #[test]
 fn test_remove() -> Result<(), Box<dyn std::error::Error>> {
   let chunk_type = "teSt";
-  let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+  let (_dir, path) = copy_of_test_file();
 
-  cmd
-    .args(&["remove", TEST_FILE_PATH, chunk_type])
+  cachette()
+    .arg("remove")
+    .arg(&path)
+    .arg(chunk_type)
+    .assert()
+    .success()
+    .stdout("")
+    .stderr("Chunk removed\n");
+
+  Ok(())
+}
+
+#[test]
+fn test_pipe_through_stdio() -> Result<(), Box<dyn std::error::Error>> {
+  let image = std::fs::read(TEST_FILE_PATH)?;
+
+  let encoded = piped(image.clone())
+    .args(["encode", "-", "teXt", "Piped message"])
+    .output()?;
+  assert!(encoded.status.success());
+  assert!(encoded.stdout.starts_with(&image[..8]));
+
+  let removed = piped(encoded.stdout)
+    .args(["remove", "-", "teSt"])
+    .output()?;
+  assert!(removed.status.success());
+  assert_eq!(String::from_utf8(removed.stderr)?, "Chunk removed\n");
+
+  piped(removed.stdout.clone())
+    .args(["decode", "-", "teXt"])
+    .assert()
+    .success()
+    .stdout("Piped message\n");
+
+  piped(removed.stdout)
+    .args(["print", "-"])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("teXt"))
+    .stdout(predicate::str::contains("teSt").not());
+
+  Ok(())
+}
+
+#[test]
+fn test_message_file_and_output() -> Result<(), Box<dyn std::error::Error>> {
+  let (dir, path) = copy_of_test_file();
+  let output = dir.path().join("encoded.png");
+  let original = std::fs::read(&path)?;
+
+  piped("Message from stdin")
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "--file", "-", "-o"])
+    .arg(&output)
+    .assert()
+    .success()
+    .stdout("");
+  assert_eq!(std::fs::read(&path)?, original);
+
+  cachette()
+    .arg("decode")
+    .arg(&output)
+    .arg("teXt")
+    .assert()
+    .success()
+    .stdout("Message from stdin\n");
+
+  cachette()
+    .args(["encode", "-", "teXt", "--file", "-"])
+    .assert()
+    .failure();
+
+  Ok(())
+}
+
+#[test]
+fn test_batch_directory() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  for name in ["a.png", "b.png"] {
+    std::fs::copy(TEST_FILE_PATH, dir.path().join(name))?;
+  }
+
+  cachette()
+    .arg("encode")
+    .arg(dir.path())
+    .args(["teXt", "Batch message"])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("2 files processed, 0 failed"));
+
+  let a = dir.path().join("a.png");
+  let b = dir.path().join("b.png");
+  cachette()
+    .arg("encode")
+    .args([&a, &b])
+    .args(["ruSt", "Listed message"])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("2 files processed, 0 failed"));
+  cachette()
+    .arg("decode")
+    .arg(&b)
+    .arg("ruSt")
+    .assert()
+    .success()
+    .stdout("Listed message\n");
+
+  std::fs::write(dir.path().join("c.png"), b"not a png")?;
+  cachette()
+    .arg("decode")
+    .arg(dir.path())
+    .arg("teXt")
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains(format!(
+      "{}: Batch message\n",
+      a.display()
+    )))
+    .stderr(predicate::str::contains("c.png: failed:"))
+    .stderr(predicate::str::contains("3 files processed, 1 failed"));
+
+  cachette()
+    .arg("remove")
+    .arg(dir.path().join("*.png"))
+    .arg("teXt")
+    .arg("-o")
+    .arg(dir.path().join("out.png"))
+    .assert()
+    .failure();
+
+  Ok(())
+}
+
+#[test]
+fn test_inspect_and_scan() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+
+  cachette()
+    .args(["inspect", TEST_FILE_PATH])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("teSt"));
+
+  cachette()
+    .args(["scan", TEST_FILE_PATH])
+    .assert()
+    .success()
+    .stdout("No payload found\n");
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["ruSt", "Hidden message"])
+    .assert()
+    .success();
+
+  cachette()
+    .arg("scan")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("ruSt chunk: envelope of"));
+
+  Ok(())
+}
+
+#[test]
+fn test_recipients_and_key_files() -> Result<(), Box<dyn std::error::Error>> {
+  let (dir, path) = copy_of_test_file();
+  let identity = dir.path().join("identity.txt");
+
+  let keygen = cachette().arg("keygen").arg(&identity).output()?;
+  assert!(keygen.status.success());
+  let recipient = String::from_utf8(keygen.stdout)?;
+  assert!(recipient.starts_with("cachette-pk-"));
+  cachette().arg("keygen").arg(&identity).assert().failure();
+
+  // No password is needed, so the TEST_PASSWORD fallback is cleared
+  cachette()
+    .env_remove("TEST_PASSWORD")
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "For the identity", "--recipient", recipient.trim()])
+    .assert()
+    .success();
+  cachette()
+    .env_remove("TEST_PASSWORD")
+    .arg("decode")
+    .arg(&path)
+    .arg("teXt")
+    .arg("--identity")
+    .arg(&identity)
+    .assert()
+    .success()
+    .stdout("For the identity\n");
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .arg("teXt")
+    .assert()
+    .failure();
+
+  let key_file = dir.path().join("key.bin");
+  std::fs::write(&key_file, [7u8; 64])?;
+  cachette()
+    .env_remove("TEST_PASSWORD")
+    .arg("encode")
+    .arg(&path)
+    .args(["ruSt", "For the key file", "--key-file"])
+    .arg(&key_file)
+    .assert()
+    .success();
+  cachette()
+    .env_remove("TEST_PASSWORD")
+    .arg("decode")
+    .arg(&path)
+    .args(["ruSt", "--key-file"])
+    .arg(&key_file)
+    .assert()
+    .success()
+    .stdout("For the key file\n");
+
+  Ok(())
+}
+
+#[test]
+fn test_kdf_parameters() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Cheap to unlock", "--kdf-profile", "paranoid"])
+    .args(["--kdf-memory", "1024", "--kdf-iterations", "1"])
+    .assert()
+    .success();
+
+  // The parameters are read back from the payload
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .arg("teXt")
+    .assert()
+    .success()
+    .stdout("Cheap to unlock\n");
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Too cheap", "--kdf-iterations", "0"])
+    .assert()
+    .failure();
+
+  cachette()
+    .args(["calibrate", "--target-ms", "1", "--max-memory-mib", "1"])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("--kdf-memory 1024"));
+
+  Ok(())
+}
+
+#[test]
+fn test_password_strength() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+
+  cachette()
+    .env("TEST_PASSWORD", "aaaaaaaaaaaaaaaaaa")
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Weakly protected"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Password is too weak"))
+    .stderr(predicate::str::contains("repeats like"));
+
+  cachette()
+    .env("TEST_PASSWORD", "aaaaaaaaaaaaaaaaaa")
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Weakly protected", "--min-score", "0"])
+    .assert()
+    .success();
+
+  let genpass = cachette().args(["genpass", "--words", "5"]).output()?;
+  assert!(genpass.status.success());
+  let passphrase = String::from_utf8(genpass.stdout)?;
+  assert_eq!(passphrase.trim().split('-').count(), 5);
+
+  cachette()
+    .env("TEST_PASSWORD", passphrase.trim())
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Strongly protected"])
     .assert()
     .success();
 
+  // Some pairs of words score too low, and are drawn again
+  for _ in 0..20 {
+    cachette()
+      .args(["genpass", "--words", "2"])
+      .assert()
+      .success();
+  }
+  cachette()
+    .args(["genpass", "--words", "1"])
+    .assert()
+    .failure();
+
+  Ok(())
+}
+
+#[test]
+fn test_rekey() -> Result<(), Box<dyn std::error::Error>> {
+  let (dir, path) = copy_of_test_file();
+  let new_password = "amber-quiet-falcon-ridge-77";
+
+  // The legacy message in the fixture moves to a new password
+  cachette()
+    .env("TEST_PASSWORD", LEGACY_PASSWORD)
+    .env("TEST_NEW_PASSWORD", new_password)
+    .arg("rekey")
+    .arg(&path)
+    .arg("teSt")
+    .assert()
+    .success()
+    .stdout("")
+    .stderr("Message rekeyed\n");
+  cachette()
+    .env("TEST_PASSWORD", new_password)
+    .arg("decode")
+    .arg(&path)
+    .arg("teSt")
+    .assert()
+    .success()
+    .stdout("This is a secret message\n");
+  cachette()
+    .env("TEST_PASSWORD", LEGACY_PASSWORD)
+    .arg("decode")
+    .arg(&path)
+    .arg("teSt")
+    .assert()
+    .failure();
+
+  // A protected lsb message moves to a recipient and stays protected
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Pixels", "--mode", "lsb", "--ecc", "low"])
+    .assert()
+    .success();
+  let identity = dir.path().join("identity.txt");
+  let keygen = cachette().arg("keygen").arg(&identity).output()?;
+  let recipient = String::from_utf8(keygen.stdout)?;
+  cachette()
+    .arg("rekey")
+    .arg(&path)
+    .args(["teXt", "--mode", "lsb", "--recipient", recipient.trim()])
+    .assert()
+    .success()
+    .stdout("");
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .args(["teXt", "--mode", "lsb", "--identity"])
+    .arg(&identity)
+    .assert()
+    .success()
+    .stdout("Pixels\n")
+    .stderr("Corrected 0 errors\n");
+
+  // Weak new passwords are refused before anything is written
+  let before = std::fs::read(&path)?;
+  cachette()
+    .env("TEST_NEW_PASSWORD", "aaaaaaaaaaaaaaaaaa")
+    .arg("rekey")
+    .arg(&path)
+    .args(["teXt", "--mode", "lsb", "--identity"])
+    .arg(&identity)
+    .assert()
+    .failure();
+  assert_eq!(std::fs::read(&path)?, before);
+
+  Ok(())
+}
+
+#[test]
+fn test_split() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  let names = ["a.png", "b.png", "c.png", "d.png", "e.png"];
+  for name in names {
+    std::fs::copy(TEST_FILE_PATH, dir.path().join(name))?;
+  }
+
+  cachette()
+    .arg("encode")
+    .arg(dir.path())
+    .args(["teXt", "Split secret", "--split", "3-of-4"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("needs 4 images but 5 were given"));
+  cachette()
+    .current_dir(dir.path())
+    .args(["encode", "--split", "3-of-5"])
+    .args(names)
+    .args(["teXt", "Split secret"])
+    .assert()
+    .success();
+
+  let path = |name: &str| dir.path().join(name);
+  cachette()
+    .arg("decode")
+    .args([path("a.png"), path("c.png"), path("e.png")])
+    .arg("teXt")
+    .assert()
+    .success()
+    .stdout("Split secret\n");
+  cachette()
+    .arg("decode")
+    .args([path("b.png"), path("d.png")])
+    .arg("teXt")
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("1 more is needed"));
+  cachette()
+    .arg("decode")
+    .arg(path("b.png"))
+    .arg("teXt")
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("2 more are needed"));
+
+  cachette()
+    .arg("scan")
+    .arg(path("a.png"))
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("teXt chunk: share of"));
+
+  Ok(())
+}
+
+#[test]
+fn test_wipe() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["ruSt", "In the pixels", "--mode", "lsb"])
+    .assert()
+    .success();
+
+  cachette()
+    .arg("wipe")
+    .arg(&path)
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("teSt chunk, neSt chunk"))
+    .stderr(predicate::str::contains(
+      "lsb payload at 1 bits per channel",
+    ))
+    .stderr(predicate::str::contains("no payload left"));
+
+  cachette()
+    .arg("scan")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout("No payload found\n");
+  cachette()
+    .arg("inspect")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("neSt").not());
+
+  cachette()
+    .arg("wipe")
+    .arg(&path)
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("Nothing to wipe"));
+
+  Ok(())
+}
+
+#[test]
+fn test_strip() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+  let mut bytes = std::fs::read(&path)?;
+  bytes.extend_from_slice(b"data after IEND");
+  std::fs::write(&path, bytes)?;
+
+  cachette()
+    .arg("strip")
+    .arg(&path)
+    .args(["--keep", "pHYs", "--merge-idat"])
+    .assert()
+    .success()
+    // The message chunks of the fixture and the extra data follow IEND
+    .stderr("Removed zTXt; dropped 95 bytes after IEND\n");
+  cachette()
+    .arg("inspect")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::starts_with("IHDR 13\npHYs 9\nIDAT"))
+    .stdout(predicate::str::ends_with("IEND 0\n"));
+
+  cachette()
+    .arg("strip")
+    .arg(&path)
+    .args(["--keep", ""])
+    .assert()
+    .success()
+    .stderr("Removed pHYs\n");
+
+  Ok(())
+}
+
+#[test]
+fn test_lenient_decode_and_repair() -> Result<(), Box<dyn std::error::Error>> {
+  let (_dir, path) = copy_of_test_file();
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["ruSt", "Survives damage"])
+    .assert()
+    .success();
+
+  // Damage the data of an unrelated chunk
+  let mut bytes = std::fs::read(&path)?;
+  let offset = bytes.windows(4).position(|w| w == b"zTXt").unwrap();
+  bytes[offset + 6] ^= 0xFF;
+  std::fs::write(&path, bytes)?;
+
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .arg("ruSt")
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Invalid CRC"));
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .args(["ruSt", "--lenient"])
+    .assert()
+    .success()
+    .stdout("Survives damage\n")
+    .stderr(predicate::str::starts_with(
+      "Read past zTXt chunk at offset",
+    ));
+
+  cachette()
+    .arg("repair")
+    .arg(&path)
+    .assert()
+    .success()
+    .stderr(predicate::str::starts_with("Repaired zTXt chunk at offset"));
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .arg("ruSt")
+    .assert()
+    .success()
+    .stdout("Survives damage\n");
+  cachette()
+    .arg("repair")
+    .arg(&path)
+    .assert()
+    .success()
+    .stderr("No damage found\n");
+
+  Ok(())
+}
+
+/// A 64x64 uncompressed 24-bit bitmap
+fn bmp_bytes() -> Vec<u8> {
+  let pixels_len = 64 * 64 * 3;
+  let mut bytes = b"BM".to_vec();
+  bytes.extend_from_slice(&(54 + pixels_len as u32).to_le_bytes());
+  bytes.extend_from_slice(&[0; 4]);
+  bytes.extend_from_slice(&54u32.to_le_bytes());
+  bytes.extend_from_slice(&40u32.to_le_bytes());
+  bytes.extend_from_slice(&64i32.to_le_bytes());
+  bytes.extend_from_slice(&64i32.to_le_bytes());
+  bytes.extend_from_slice(&1u16.to_le_bytes());
+  bytes.extend_from_slice(&24u16.to_le_bytes());
+  bytes.extend_from_slice(&[0; 24]);
+  bytes.extend((0..pixels_len).map(|i| (i * 7 % 251) as u8));
+  bytes
+}
+
+#[test]
+fn test_bmp() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("cover.bmp");
+  std::fs::write(&path, bmp_bytes())?;
+
+  cachette()
+    .args(["capacity", "--mode", "lsb"])
+    .arg(&path)
+    .assert()
+    .success();
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "In a bitmap"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("use lsb mode"));
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "In a bitmap", "--mode", "lsb"])
+    .assert()
+    .success();
+  let encoded = std::fs::read(&path)?;
+  assert_eq!(encoded.len(), bmp_bytes().len());
+  assert!(encoded.starts_with(b"BM"));
+
+  piped(encoded)
+    .args(["decode", "-", "teSt", "--mode", "lsb"])
+    .assert()
+    .success()
+    .stdout("In a bitmap\n");
+
+  // Directories are searched for every format, not only PNG
+  std::fs::copy(TEST_FILE_PATH, dir.path().join("cover.png"))?;
+  cachette()
+    .arg("encode")
+    .arg(dir.path())
+    .args(["ruSt", "In the folder", "--mode", "lsb"])
+    .assert()
+    .success()
+    .stderr(predicate::str::contains("2 files processed, 0 failed"));
+  cachette()
+    .arg("decode")
+    .arg(dir.path())
+    .args(["ruSt", "--mode", "lsb"])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("cover.bmp: In the folder"));
+
+  Ok(())
+}
+
+#[test]
+fn test_jpeg() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("cat.jpg");
+  std::fs::copy("tests/cat.jpg", &path)?;
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "In the coefficients"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("use f5 mode"));
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "In the coefficients", "--mode", "f5"])
+    .assert()
+    .success();
+  assert!(std::fs::read(&path)?.starts_with(&[0xFF, 0xD8]));
+
+  cachette()
+    .arg("decode")
+    .arg(&path)
+    .args(["teSt", "--mode", "f5"])
+    .assert()
+    .success()
+    .stdout("In the coefficients\n");
+
+  cachette()
+    .args(["encode", TEST_FILE_PATH, "teSt", "x", "--mode", "f5"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("do not support f5 mode"));
+
+  Ok(())
+}
+
+/// One second of 16-bit stereo PCM audio at 8 kHz
+fn wav_bytes() -> Vec<u8> {
+  let samples = (0..8000 * 2)
+    .flat_map(|i: i32| ((i * 37 % 2000 - 1000) as i16).to_le_bytes())
+    .collect::<Vec<_>>();
+  let mut bytes = b"RIFF".to_vec();
+  bytes.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+  bytes.extend_from_slice(b"WAVEfmt ");
+  bytes.extend_from_slice(&16u32.to_le_bytes());
+  bytes.extend_from_slice(&1u16.to_le_bytes());
+  bytes.extend_from_slice(&2u16.to_le_bytes());
+  bytes.extend_from_slice(&8000u32.to_le_bytes());
+  bytes.extend_from_slice(&32000u32.to_le_bytes());
+  bytes.extend_from_slice(&4u16.to_le_bytes());
+  bytes.extend_from_slice(&16u16.to_le_bytes());
+  bytes.extend_from_slice(b"data");
+  bytes.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+  bytes.extend(samples);
+  bytes
+}
+
+#[test]
+fn test_wav() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("memo.wav");
+
+  for mode in ["chunk", "lsb"] {
+    std::fs::write(&path, wav_bytes())?;
+    cachette()
+      .arg("encode")
+      .arg(&path)
+      .args(["teSt", "In a voice memo", "--mode", mode])
+      .assert()
+      .success();
+    let encoded = std::fs::read(&path)?;
+    assert!(encoded.starts_with(b"RIFF"));
+    if mode == "lsb" {
+      assert_eq!(encoded.len(), wav_bytes().len());
+    }
+
+    cachette()
+      .arg("decode")
+      .arg(&path)
+      .args(["teSt", "--mode", mode])
+      .assert()
+      .success()
+      .stdout("In a voice memo\n");
+  }
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "x", "--mode", "robust"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains(
+      "Files in wav format do not support robust mode",
+    ));
+
+  Ok(())
+}
+
+/// A one-page PDF document with a classic cross-reference table
+fn pdf_bytes() -> Vec<u8> {
+  let objects = [
+    "<< /Type /Catalog /Pages 2 0 R >>",
+    "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>",
+  ];
+  let mut bytes = b"%PDF-1.4\n".to_vec();
+  let mut table = String::from("xref\n0 4\n0000000000 65535 f\r\n");
+  for (i, object) in objects.iter().enumerate() {
+    table.push_str(&format!("{:010} 00000 n\r\n", bytes.len()));
+    let object = format!("{} 0 obj\n{}\nendobj\n", i + 1, object);
+    bytes.extend_from_slice(object.as_bytes());
+  }
+  let xref = bytes.len();
+  bytes.extend_from_slice(table.as_bytes());
+  let trailer = format!(
+    "trailer\n<< /Size 4 /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+    xref
+  );
+  bytes.extend_from_slice(trailer.as_bytes());
+  bytes
+}
+
+#[test]
+fn test_pdf() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("report.pdf");
+  std::fs::write(&path, pdf_bytes())?;
+
+  for message in ["In the minutes", "In the amended minutes"] {
+    cachette()
+      .arg("encode")
+      .arg(&path)
+      .args(["teSt", message])
+      .assert()
+      .success();
+    cachette()
+      .arg("decode")
+      .arg(&path)
+      .arg("teSt")
+      .assert()
+      .success()
+      .stdout(format!("{}\n", message));
+  }
+  let encoded = std::fs::read(&path)?;
+  assert!(encoded.starts_with(&pdf_bytes()));
+  // Encoding again replaced the update rather than adding another
+  let sections = encoded.windows(6).filter(|w| w == b"\nxref\n").count();
+  assert_eq!(sections, 2);
+
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teSt", "x", "--mode", "lsb"])
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("only hide messages in chunk mode"));
+
+  cachette()
+    .arg("capacity")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Mode: chunk"));
+
+  Ok(())
+}
+
+#[test]
+fn test_verify() -> Result<(), Box<dyn std::error::Error>> {
+  let (dir, path) = copy_of_test_file();
+  cachette()
+    .arg("encode")
+    .arg(&path)
+    .args(["teXt", "Kept for the records"])
+    .assert()
+    .success();
+
+  cachette()
+    .arg("verify")
+    .arg(&path)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("authenticated"))
+    .stdout(predicate::str::contains("Kept for the records").not());
+  cachette()
+    .env("TEST_PASSWORD", "not-the-right-password-at-all")
+    .arg("verify")
+    .arg(&path)
+    .assert()
+    .code(8);
+  cachette()
+    .arg("verify")
+    .arg(&path)
+    .args(["--label", "ruSt"])
+    .assert()
+    .code(5)
+    .stderr("Error: No payload found\n");
+
+  let bytes = std::fs::read(&path)?;
+  let offset = bytes.windows(4).position(|w| w == b"zTXt").unwrap();
+  let mut damaged = bytes.clone();
+  damaged[offset + 6] ^= 0xFF;
+  let damaged_path = dir.path().join("damaged.png");
+  std::fs::write(&damaged_path, damaged)?;
+  cachette().arg("verify").arg(&damaged_path).assert().code(4);
+
+  let garbage_path = dir.path().join("garbage.png");
+  std::fs::write(&garbage_path, b"GIF89a")?;
+  cachette().arg("verify").arg(&garbage_path).assert().code(3);
+
+  let split_dir = dir.path().join("split");
+  std::fs::create_dir(&split_dir)?;
+  let shares = ["a.png", "b.png", "c.png"].map(|name| split_dir.join(name));
+  for share in &shares {
+    std::fs::copy(TEST_FILE_PATH, share)?;
+  }
+  cachette()
+    .arg("encode")
+    .arg(&split_dir)
+    .args(["teXt", "Split record", "--split", "2-of-3"])
+    .assert()
+    .success();
+  cachette()
+    .arg("verify")
+    .args(&shares)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("all 3 shares of a 2-of-3 split"));
+  cachette()
+    .arg("verify")
+    .args(&shares[..2])
+    .assert()
+    .code(6)
+    .stderr(predicate::str::contains("Found 2 of the 3 shares"));
+
   Ok(())
 }
@@ -0,0 +1,72 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const HEADER_PATH: &str = "include/cachette.h";
+
+fn generate_header() -> String {
+  let crate_dir = env!("CARGO_MANIFEST_DIR");
+  let config =
+    cbindgen::Config::from_file(Path::new(crate_dir).join("cbindgen.toml"))
+      .unwrap();
+  let mut header = Vec::new();
+  cbindgen::Builder::new()
+    .with_src(Path::new(crate_dir).join("src/ffi.rs"))
+    .with_config(config)
+    .generate()
+    .unwrap()
+    .write(&mut header);
+  String::from_utf8(header).unwrap()
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+  let header = generate_header();
+  if std::env::var_os("CACHETTE_BLESS").is_some() {
+    std::fs::write(HEADER_PATH, &header).unwrap();
+  }
+  let committed = std::fs::read_to_string(HEADER_PATH).unwrap();
+  assert!(
+    header == committed,
+    "{} is stale, rerun with CACHETTE_BLESS=1 to regenerate it",
+    HEADER_PATH
+  );
+}
+
+/// The directory holding the test binary, where cargo also puts the cdylib
+/// it built for this run
+fn artifact_dir() -> PathBuf {
+  let exe = std::env::current_exe().unwrap();
+  exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+#[cfg(unix)]
+fn test_c_harness() {
+  let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let artifacts = artifact_dir();
+  let build_dir = tempfile::tempdir().unwrap();
+  let harness = build_dir.path().join("harness");
+
+  let status = Command::new(std::env::var("CC").unwrap_or("cc".into()))
+    .arg(crate_dir.join("tests/ffi/harness.c"))
+    .arg("-I")
+    .arg(crate_dir.join("include"))
+    .arg("-L")
+    .arg(&artifacts)
+    .arg(format!("-Wl,-rpath,{}", artifacts.display()))
+    .args(["-lcachette", "-Wall", "-Werror", "-o"])
+    .arg(&harness)
+    .status()
+    .expect("a C compiler is needed to run the FFI tests");
+  assert!(status.success(), "failed to compile the C harness");
+
+  let output = Command::new(&harness)
+    .arg(crate_dir.join("tests/cat.png"))
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "C harness failed: {}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+}
@@ -0,0 +1,75 @@
+/* Exercises the C API the way a C or C++ caller would. Built and run by
+ * tests/ffi.rs; takes the path of a PNG as its only argument. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "cachette.h"
+
+#define CHECK(condition)                                            \
+  do {                                                              \
+    if (!(condition)) {                                             \
+      fprintf(stderr, "%s:%d: %s failed (%s)\n", __FILE__, __LINE__, \
+              #condition, cachette_last_error());                   \
+      return 1;                                                     \
+    }                                                               \
+  } while (0)
+
+static const char *PASSWORD = "correct horse battery staple";
+
+static unsigned char *read_file(const char *path, size_t *len) {
+  FILE *file = fopen(path, "rb");
+  if (file == NULL) {
+    return NULL;
+  }
+  fseek(file, 0, SEEK_END);
+  *len = (size_t)ftell(file);
+  fseek(file, 0, SEEK_SET);
+  unsigned char *bytes = malloc(*len);
+  if (bytes != NULL && fread(bytes, 1, *len, file) != *len) {
+    free(bytes);
+    bytes = NULL;
+  }
+  fclose(file);
+  return bytes;
+}
+
+int main(int argc, char **argv) {
+  size_t png_len = 0;
+  unsigned char *png = argc > 1 ? read_file(argv[1], &png_len) : NULL;
+  CHECK(png != NULL);
+
+  CachetteBuffer encoded = {0};
+  CachetteBuffer decoded = {0};
+  CachetteBuffer report = {0};
+
+  CachetteOptions options = cachette_default_options();
+  options.mode = CACHETTE_MODE_LSB;
+  options.ecc = CACHETTE_ECC_LOW;
+  CHECK(cachette_encode_buffer(png, png_len, "ruSt", "Hello from C", PASSWORD,
+                               &options, &encoded) == CACHETTE_STATUS_OK);
+  CHECK(cachette_decode_buffer(encoded.data, encoded.len, "ruSt", PASSWORD,
+                               &options, &decoded) == CACHETTE_STATUS_OK);
+  CHECK(strcmp((const char *)decoded.data, "Hello from C") == 0);
+  CHECK(decoded.len == strlen("Hello from C"));
+
+  CHECK(cachette_inspect(encoded.data, encoded.len, &report) ==
+        CACHETTE_STATUS_OK);
+  CHECK(strstr((const char *)report.data, "IHDR 13\n") != NULL);
+
+  CHECK(cachette_decode_buffer(png, png_len, "ru5t", PASSWORD, NULL,
+                               &decoded) ==
+        CACHETTE_STATUS_INVALID_CHUNK_TYPE);
+  CHECK(strlen(cachette_last_error()) > 0);
+  CHECK(cachette_inspect(png, 4, &report) == CACHETTE_STATUS_INVALID_PNG);
+
+  cachette_free(&encoded);
+  cachette_free(&decoded);
+  cachette_free(&report);
+  cachette_free(NULL);
+  CHECK(encoded.data == NULL && encoded.len == 0);
+
+  free(png);
+  return 0;
+}
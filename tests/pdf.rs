@@ -0,0 +1,130 @@
+//! Hides messages in documents written by `lopdf` and checks that it still
+//! reads them back intact.
+
+use cachette::{ChunkType, CoverImage, Decoder, Encoder, Pdf};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, SaveOptions, Stream};
+use std::str::FromStr;
+
+const MESSAGE: &str = "Filed between the pages";
+
+/// A one-page document, saved with a classic cross-reference table or,
+/// with `compact`, with object and cross-reference streams
+fn document(compact: bool) -> Vec<u8> {
+  let mut doc = Document::with_version("1.5");
+  let pages_id = doc.new_object_id();
+  let font_id = doc.add_object(dictionary! {
+    "Type" => "Font",
+    "Subtype" => "Type1",
+    "BaseFont" => "Courier",
+  });
+  let resources_id = doc.add_object(dictionary! {
+    "Font" => dictionary! { "F1" => font_id },
+  });
+  let content = Content {
+    operations: vec![
+      Operation::new("BT", vec![]),
+      Operation::new("Tf", vec!["F1".into(), 48.into()]),
+      Operation::new("Td", vec![100.into(), 600.into()]),
+      Operation::new("Tj", vec![Object::string_literal("Quarterly report")]),
+      Operation::new("ET", vec![]),
+    ],
+  };
+  let content_id =
+    doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+  let page_id = doc.add_object(dictionary! {
+    "Type" => "Page",
+    "Parent" => pages_id,
+    "Contents" => content_id,
+  });
+  doc.objects.insert(
+    pages_id,
+    Object::Dictionary(dictionary! {
+      "Type" => "Pages",
+      "Kids" => vec![page_id.into()],
+      "Count" => 1,
+      "Resources" => resources_id,
+      "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+    }),
+  );
+  let catalog_id = doc.add_object(dictionary! {
+    "Type" => "Catalog",
+    "Pages" => pages_id,
+  });
+  doc.trailer.set("Root", catalog_id);
+
+  let mut bytes = Vec::new();
+  if compact {
+    let options = SaveOptions::builder()
+      .use_object_streams(true)
+      .use_xref_streams(true)
+      .build();
+    doc.save_with_options(&mut bytes, options).unwrap();
+  } else {
+    doc.save_to(&mut bytes).unwrap();
+  }
+  bytes
+}
+
+#[test]
+fn test_document_stays_valid() {
+  let chunk_type = ChunkType::from_str("teSt").unwrap();
+  for compact in [false, true] {
+    let original = document(compact);
+    let mut pdf = Pdf::try_from(original.as_slice()).unwrap();
+    Encoder::new("password")
+      .chunk_type(chunk_type.clone())
+      .encode(&mut pdf, MESSAGE)
+      .unwrap();
+    let encoded = pdf.as_bytes();
+    assert!(encoded.starts_with(&original));
+
+    let doc = Document::load_mem(&encoded).unwrap();
+    let before = Document::load_mem(&original).unwrap();
+    assert_eq!(doc.get_pages(), before.get_pages());
+    let root = |doc: &Document| doc.trailer.get(b"Root").unwrap().clone();
+    assert_eq!(root(&doc), root(&before));
+    let text = doc.extract_text(&[1]).unwrap();
+    assert!(text.contains("Quarterly report"));
+
+    // The message is a stream object of its own that nothing refers to
+    let size = before.trailer.get(b"Size").unwrap().as_i64().unwrap();
+    let object = doc.get_object((size as u32, 0)).unwrap();
+    let stream = object.as_stream().unwrap();
+    assert_eq!(
+      stream.dict.get(b"Type").unwrap().as_name().unwrap(),
+      b"teSt"
+    );
+    let new_size = doc.trailer.get(b"Size").unwrap().as_i64().unwrap();
+    assert_eq!(new_size, size + 1);
+
+    let cover = cachette::cover::from_bytes(encoded).unwrap();
+    let decoded = Decoder::new("password")
+      .chunk_type(chunk_type.clone())
+      .decode(&*cover)
+      .unwrap();
+    assert_eq!(decoded.message, MESSAGE);
+  }
+}
+
+#[test]
+fn test_message_survives_resave() {
+  let chunk_type = ChunkType::from_str("teSt").unwrap();
+  let mut pdf = Pdf::try_from(document(false).as_slice()).unwrap();
+  Encoder::new("password")
+    .chunk_type(chunk_type.clone())
+    .encode(&mut pdf, MESSAGE)
+    .unwrap();
+
+  // Saving the whole document again keeps the object, though not the
+  // incremental update it came in
+  let mut doc = Document::load_mem(&pdf.as_bytes()).unwrap();
+  let mut resaved = Vec::new();
+  doc.save_to(&mut resaved).unwrap();
+  let pdf = Pdf::try_from(resaved.as_slice()).unwrap();
+  let decoded = Decoder::new("password")
+    .chunk_type(chunk_type)
+    .decode(&pdf)
+    .unwrap();
+  assert_eq!(decoded.message, MESSAGE);
+}
@@ -0,0 +1,214 @@
+//! Re-saves encoded images through the `png` crate, as an optimiser would,
+//! and checks which embedding modes survive.
+
+use cachette::{Decoder, Encoder, Key, Mode, Png};
+use png::{AdaptiveFilterType, BitDepth, ColorType, Compression, FilterType};
+
+const TEST_FILE_PATH: &str = "tests/cat.png";
+const MESSAGE: &str = "Still here after re-encoding";
+
+/// An image decoded by the `png` crate, samples untouched
+struct Image {
+  width: u32,
+  height: u32,
+  color_type: ColorType,
+  bit_depth: BitDepth,
+  data: Vec<u8>,
+  palette: Option<Vec<u8>>,
+  trns: Option<Vec<u8>>,
+}
+
+impl Image {
+  fn decode(bytes: &[u8]) -> Self {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut data).unwrap();
+    data.truncate(frame.buffer_size());
+    let info = reader.info();
+    Self {
+      width: frame.width,
+      height: frame.height,
+      color_type: frame.color_type,
+      bit_depth: frame.bit_depth,
+      data,
+      palette: info.palette.as_ref().map(|palette| palette.to_vec()),
+      trns: info.trns.as_ref().map(|trns| trns.to_vec()),
+    }
+  }
+
+  /// Writes this image with the `png` crate, which keeps no ancillary chunk
+  /// but tRNS
+  fn encode(&self, filter: FilterType, compression: Compression) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+    encoder.set_color(self.color_type);
+    encoder.set_depth(self.bit_depth);
+    encoder.set_filter(filter);
+    encoder.set_adaptive_filter(AdaptiveFilterType::NonAdaptive);
+    encoder.set_compression(compression);
+    if let Some(palette) = &self.palette {
+      encoder.set_palette(palette.clone());
+    }
+    if let Some(trns) = &self.trns {
+      encoder.set_trns(trns.clone());
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&self.data).unwrap();
+    writer.finish().unwrap();
+    bytes
+  }
+
+  /// The same colours as RGBA with 16-bit samples
+  fn to_rgba16(&self) -> Self {
+    assert_eq!(
+      (self.color_type, self.bit_depth),
+      (ColorType::Rgb, BitDepth::Eight)
+    );
+    let data = self
+      .data
+      .chunks(3)
+      .flat_map(|rgb| {
+        [rgb[0], rgb[0], rgb[1], rgb[1], rgb[2], rgb[2], 255, 255]
+      })
+      .collect();
+    Self {
+      color_type: ColorType::Rgba,
+      bit_depth: BitDepth::Sixteen,
+      data,
+      ..self.clone_header()
+    }
+  }
+
+  /// The same colours in a palette, if there are no more than 256
+  fn to_indexed(&self) -> Option<Self> {
+    assert_eq!(
+      (self.color_type, self.bit_depth),
+      (ColorType::Rgb, BitDepth::Eight)
+    );
+    let mut colors = Vec::<&[u8]>::new();
+    let mut data = Vec::new();
+    for rgb in self.data.chunks(3) {
+      let index = colors.iter().position(|color| *color == rgb);
+      let index = index.unwrap_or_else(|| {
+        colors.push(rgb);
+        colors.len() - 1
+      });
+      data.push(u8::try_from(index).ok()?);
+    }
+    Some(Self {
+      color_type: ColorType::Indexed,
+      data,
+      palette: Some(colors.concat()),
+      ..self.clone_header()
+    })
+  }
+
+  fn clone_header(&self) -> Self {
+    Self {
+      width: self.width,
+      height: self.height,
+      color_type: self.color_type,
+      bit_depth: self.bit_depth,
+      data: Vec::new(),
+      palette: None,
+      trns: None,
+    }
+  }
+}
+
+fn key() -> Key {
+  Key::derive("vivid-orbit-canyon-mango-42")
+}
+
+fn encoded(png: Png, mode: Mode) -> Vec<u8> {
+  let mut png = png;
+  Encoder::with_key(key())
+    .mode(mode)
+    .encode(&mut png, MESSAGE)
+    .unwrap();
+  png.as_bytes()
+}
+
+fn decodes(bytes: &[u8], mode: Mode) -> bool {
+  let png = Png::try_from(bytes).unwrap();
+  Decoder::with_key(key())
+    .mode(mode)
+    .decode(&png)
+    .is_ok_and(|decoded| decoded.message == MESSAGE)
+}
+
+/// A 48x48 RGB image of a few colours in no particular order
+fn few_colors() -> Png {
+  let mut state = 7u32;
+  let mut data = Vec::new();
+  for _ in 0..48 * 48 {
+    state ^= state << 13;
+    state ^= state >> 17;
+    state ^= state << 5;
+    let level = (state % 6) as u8 * 40;
+    data.extend_from_slice(&[level, 255 - level, level / 2]);
+  }
+  let image = Image {
+    width: 48,
+    height: 48,
+    color_type: ColorType::Rgb,
+    bit_depth: BitDepth::Eight,
+    data,
+    palette: None,
+    trns: None,
+  };
+  Png::try_from(
+    image
+      .encode(FilterType::NoFilter, Compression::Fast)
+      .as_slice(),
+  )
+  .unwrap()
+}
+
+#[test]
+fn test_robust_survives_recompression() {
+  let png = Png::from_file(&TEST_FILE_PATH.into()).unwrap();
+  let bytes = encoded(png, Mode::Robust);
+  assert!(decodes(&bytes, Mode::Robust));
+
+  let image = Image::decode(&bytes);
+  for (filter, compression) in [
+    (FilterType::NoFilter, Compression::Fast),
+    (FilterType::Paeth, Compression::Best),
+    (FilterType::Avg, Compression::Default),
+  ] {
+    let resaved = image.encode(filter, compression);
+    assert!(decodes(&resaved, Mode::Robust), "{:?}", filter);
+  }
+}
+
+#[test]
+fn test_robust_survives_colour_conversions() {
+  let png = Png::from_file(&TEST_FILE_PATH.into()).unwrap();
+  let image = Image::decode(&encoded(png, Mode::Robust));
+  let wide = image
+    .to_rgba16()
+    .encode(FilterType::Sub, Compression::Default);
+  assert!(decodes(&wide, Mode::Robust));
+
+  let image = Image::decode(&encoded(few_colors(), Mode::Robust));
+  let indexed = image.to_indexed().expect("few colours fit in a palette");
+  let indexed = indexed.encode(FilterType::NoFilter, Compression::Best);
+  assert!(decodes(&indexed, Mode::Robust));
+}
+
+#[test]
+fn test_other_modes_against_reencoding() {
+  // Chunks are dropped and the IDAT stream is compressed from scratch
+  for (mode, survives) in [
+    (Mode::Chunk, false),
+    (Mode::Deflate, false),
+    (Mode::Lsb, true),
+  ] {
+    let image = Image::decode(&encoded(few_colors(), mode));
+    let resaved = image.encode(FilterType::Up, Compression::Best);
+    assert_eq!(decodes(&resaved, mode), survives, "{}", mode);
+  }
+}